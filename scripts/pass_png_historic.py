@@ -8,7 +8,7 @@ Recognises two filename schemes produced by the strf toolkit:
       All files sharing the same datetime prefix (i.e. from the same rffft
       recording session) are grouped together and processed as one pass-png run.
 
-  rsmedfilt output — mf_YYYY-MM-DDTHH:MM:SS_NNNNNN.bin
+  rsmedfilt --per-file output — mf_YYYY-MM-DDTHH:MM:SS_NNNNNN.bin
       Each file is treated as its own group (no grouping needed).
 
 For each group the TLE whose epoch most closely matches the start time of the
@@ -23,6 +23,7 @@ Usage example:
 """
 
 import argparse
+import json
 import os
 import re
 import subprocess
@@ -31,7 +32,6 @@ import tempfile
 from datetime import datetime, timedelta, timezone
 from pathlib import Path
 
-HEADER_SIZE = 256
 # rffft output: YYYY-MM-DDTHH:MM:SS_NNNNNN.bin — group by the datetime prefix.
 RFFFT_RE = re.compile(r"^(\d{4}-\d{2}-\d{2}T\d{2}:\d{2}:\d{2})_\d+\.bin$")
 
@@ -41,21 +41,18 @@ RFFFT_RE = re.compile(r"^(\d{4}-\d{2}-\d{2}T\d{2}:\d{2}:\d{2})_\d+\.bin$")
 # ---------------------------------------------------------------------------
 
 
-def read_first_start_time(path: Path) -> datetime:
-    """Return UTC_START from the first spectrum header in a .bin file."""
-    with path.open("rb") as f:
-        header_bytes = f.read(HEADER_SIZE)
-    header = header_bytes.decode("ascii", errors="replace")
-    m = re.search(r"UTC_START\s+(\S+)", header)
-    if not m:
-        raise ValueError(f"No UTC_START in first header of {path}")
-    ts = m.group(1).rstrip("Z")
-    for fmt in ("%Y-%m-%dT%H:%M:%S.%f", "%Y-%m-%dT%H:%M:%S"):
-        try:
-            return datetime.strptime(ts, fmt).replace(tzinfo=timezone.utc)
-        except ValueError:
-            pass
-    raise ValueError(f"Cannot parse UTC_START {ts!r} in {path}")
+def read_start_time(rsinfo: str, paths: list[Path]) -> datetime:
+    """Return the start time of a group of .bin files, as reported by rsinfo."""
+    result = subprocess.run(
+        [rsinfo, "--json"] + [str(p) for p in paths],
+        check=True,
+        capture_output=True,
+        text=True,
+    )
+    start = json.loads(result.stdout)["start"]
+    return datetime.strptime(start, "%Y-%m-%dT%H:%M:%S.%f").replace(
+        tzinfo=timezone.utc
+    )
 
 
 # ---------------------------------------------------------------------------
@@ -188,6 +185,12 @@ def main() -> None:
         metavar="PATH",
         help="Path to rstrf binary [default: rstrf]",
     )
+    parser.add_argument(
+        "--rsinfo",
+        default="rsinfo",
+        metavar="PATH",
+        help="Path to rsinfo binary, used to read the spectrogram headers [default: rsinfo]",
+    )
     parser.add_argument(
         "-n",
         "--dry-run",
@@ -211,13 +214,23 @@ def main() -> None:
         exit_code = 0
         for group_key, files in sorted(groups.items()):
             files_sorted = sorted(files)
-            first_file = files_sorted[0]
 
             try:
-                start_time = read_first_start_time(first_file)
-            except Exception as exc:
+                start_time = read_start_time(args.rsinfo, files_sorted)
+            except (
+                subprocess.CalledProcessError,
+                OSError,
+                ValueError,
+                KeyError,
+            ) as exc:
+                reason = (
+                    exc.stderr.strip()
+                    if isinstance(exc, subprocess.CalledProcessError)
+                    else exc
+                )
                 print(
-                    f"\nWARNING: Skipping group {group_key!r}: {exc}", file=sys.stderr
+                    f"\nWARNING: Skipping group {group_key!r}: {reason}",
+                    file=sys.stderr,
                 )
                 continue
 
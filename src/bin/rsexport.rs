@@ -0,0 +1,77 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use clap::Parser;
+use rstrf::{
+    spectrogram::{self, ExportFormat, LoadOptions},
+    util::parse_utc,
+};
+use std::path::PathBuf;
+
+/// Exports rffft spectrograms as FITS or NumPy (.npy/.npz) files, e.g. for analysis with astropy.
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    /// Spectrogram files to load (rffft format)
+    #[arg(value_name = "INPUT", required = true)]
+    input: Vec<PathBuf>,
+    /// File to export to
+    #[arg(value_name = "OUTPUT", required = true)]
+    output: PathBuf,
+    /// Output format [default: guessed from the extension of OUTPUT]
+    #[arg(long)]
+    format: Option<ExportFormat>,
+    /// Frequency range to export in Hz: MIN MAX (channels outside this range are skipped)
+    #[arg(long, value_name = "FREQ", num_args = 2)]
+    freq_range: Option<Vec<f64>>,
+    /// Time range to export in UTC: START END (spectra outside this range are skipped)
+    #[arg(long, value_names = ["START", "END"], num_args = 2, value_parser = parse_utc)]
+    time_range: Option<Vec<DateTime<Utc>>>,
+    /// Skip truncated and damaged spectra with a warning instead of failing
+    #[arg(long)]
+    lenient: bool,
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let args = Args::parse();
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+    let format = args
+        .format
+        .or_else(|| ExportFormat::from_path(&args.output))
+        .with_context(|| {
+            format!(
+                "Can't guess the format of {}, pass --format",
+                args.output.display()
+            )
+        })?;
+
+    let freq_range = args
+        .freq_range
+        .map(|v| (v[0].round() as u64, v[1].round() as u64));
+    // The crop is applied while loading, and the export streams from the memory-mapped files
+    let options = LoadOptions {
+        freq_range,
+        time_range: args.time_range.map(|v| (v[0], v[1])),
+        lenient: args.lenient,
+        mmap: true,
+        ..Default::default()
+    };
+    let spectrogram = spectrogram::load(&args.input, options)
+        .await
+        .context("Failed to load input spectrogram")?;
+
+    log::info!(
+        "Exporting {} spectra with {} channels as {}",
+        spectrogram.nslices,
+        spectrogram.nchan,
+        format
+    );
+    let output = args.output.clone();
+    tokio::task::spawn_blocking(move || spectrogram::export(&spectrogram, &output, format))
+        .await?
+        .with_context(|| format!("Failed to export to {}", args.output.display()))?;
+
+    Ok(())
+}
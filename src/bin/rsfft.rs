@@ -0,0 +1,176 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use anyhow::Context;
+use chrono::{DateTime, Duration, Utc};
+use clap::Parser;
+use rstrf::{
+    iq::{FftParams, Integrator, IqReader, SampleFormat},
+    spectrogram::{self, RawStrfSpectrum, Spectrogram},
+    util::parse_utc,
+};
+use std::{
+    io::SeekFrom,
+    path::{Path, PathBuf},
+};
+use tokio::io::AsyncSeekExt;
+
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    /// Raw IQ files to read. Multiple files are treated as one continuous recording.
+    #[arg(value_name = "INPUT", required = true)]
+    input: Vec<PathBuf>,
+    /// Output path prefix. Files are written as OUTPUT_000000.bin, OUTPUT_000001.bin, ...
+    #[arg(value_name = "OUTPUT", required = true)]
+    output: PathBuf,
+    /// Sample rate in Hz
+    #[arg(short = 's', long, value_name = "SAMP_RATE")]
+    samp_rate: f64,
+    /// Center frequency in Hz
+    #[arg(short = 'f', long, value_name = "FREQ")]
+    freq: f64,
+    /// UTC start time of the recording, e.g. 2026-02-19T00:00:01.000
+    #[arg(short = 'T', long, value_name = "START_TIME")]
+    start_time: String,
+    /// Channel width in Hz, like rffft's -c
+    #[arg(short = 'c', long, value_name = "CHANSIZE", default_value = "100")]
+    chansize: f64,
+    /// Number of frequency channels (instead of the channel width)
+    #[arg(long, value_name = "NCHAN", conflicts_with = "chansize")]
+    nchan: Option<usize>,
+    /// Integration time per spectrum in seconds
+    #[arg(short = 't', long, value_name = "TINT", default_value = "1.0")]
+    tint: f32,
+    /// Number of spectra per output file
+    #[arg(short = 'n', long, value_name = "NSUB", default_value = "60")]
+    nsub: usize,
+    /// Sample format of the input files
+    #[arg(short = 'F', long, value_name = "FORMAT", default_value = "cf32")]
+    format: SampleFormat,
+    /// Only process spectra overlapping this UTC time range (samples before it are skipped)
+    #[arg(long, value_names = ["START", "END"], num_args = 2, value_parser = parse_utc)]
+    time_range: Option<Vec<DateTime<Utc>>>,
+}
+
+fn output_path(prefix: &Path, index: usize) -> PathBuf {
+    let mut name = prefix.as_os_str().to_owned();
+    name.push(format!("_{:06}.bin", index));
+    PathBuf::from(name)
+}
+
+async fn write_file(
+    spectra: Vec<RawStrfSpectrum>,
+    params: &FftParams,
+    path: &Path,
+) -> anyhow::Result<()> {
+    let params = params.spectrogram_params();
+    let spectrogram =
+        tokio::task::spawn_blocking(move || Spectrogram::from_raw(spectra, params)).await??;
+    spectrogram::save_strf(&spectrogram, path)
+        .await
+        .context(format!("Failed to write {}", path.display()))?;
+    log::info!(
+        "Wrote {} spectra to {}",
+        spectrogram.nslices,
+        path.display()
+    );
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+    let args = Args::parse();
+    anyhow::ensure!(args.nsub > 0, "Number of spectra per file must be positive");
+
+    let nchan = match args.nchan {
+        Some(nchan) => nchan,
+        None => {
+            anyhow::ensure!(args.chansize > 0.0, "Channel width must be positive");
+            (args.samp_rate / args.chansize).round() as usize
+        }
+    };
+    anyhow::ensure!(nchan > 0, "Number of channels must be positive");
+
+    let mut start_time = parse_utc(&args.start_time)?;
+    let params = FftParams {
+        freq: args.freq,
+        samp_rate: args.samp_rate,
+        nchan,
+        tint: args.tint,
+    };
+    let time_range = args.time_range.map(|v| (v[0], v[1]));
+    if let Some((from, to)) = time_range {
+        anyhow::ensure!(from < to, "Time range ends before it starts: {from} - {to}");
+    }
+
+    // Skip the samples of all spectra that end before the time range
+    let spectrum_samples = (params.nchan * params.nint()) as u64;
+    let mut skip_samples = 0;
+    if let Some((from, _)) = time_range {
+        let skip = ((from - start_time).as_seconds_f64() / params.spectrum_length())
+            .floor()
+            .max(0.0) as u64;
+        skip_samples = skip * spectrum_samples;
+        start_time +=
+            Duration::microseconds((skip as f64 * params.spectrum_length() * 1e6).round() as i64);
+    }
+    let mut integrator = Integrator::new(params.clone(), start_time)?;
+    log::info!(
+        "Averaging {} FFTs of {} channels per spectrum ({:.3} s)",
+        params.nint(),
+        params.nchan,
+        params.spectrum_length()
+    );
+
+    let mut pending = Vec::with_capacity(args.nsub);
+    let mut file_index = 0;
+    let mut done = false;
+    'files: for path in &args.input {
+        let mut file = tokio::fs::File::open(path)
+            .await
+            .context(format!("Failed to open {}", path.display()))?;
+        if skip_samples > 0 {
+            let file_samples = file.metadata().await?.len() / args.format.sample_size() as u64;
+            let skip = skip_samples.min(file_samples);
+            skip_samples -= skip;
+            if skip == file_samples {
+                log::debug!("Skipping {} (before the time range)", path.display());
+                continue;
+            }
+            file.seek(SeekFrom::Start(skip * args.format.sample_size() as u64))
+                .await?;
+        }
+        let mut reader = IqReader::new(file, args.format, spectrum_samples as usize);
+        while let Some(samples) = reader
+            .next_block()
+            .await
+            .context(format!("Failed to read {}", path.display()))?
+        {
+            for spectrum in integrator.push(&samples) {
+                if time_range.is_some_and(|(_, to)| spectrum.time >= to) {
+                    done = true;
+                    break;
+                }
+                if spectrogram::overlaps_time_range(time_range, spectrum.time, spectrum.length_s) {
+                    pending.push(spectrum);
+                }
+            }
+            while pending.len() >= args.nsub {
+                let rest = pending.split_off(args.nsub);
+                let spectra = std::mem::replace(&mut pending, rest);
+                write_file(spectra, &params, &output_path(&args.output, file_index)).await?;
+                file_index += 1;
+            }
+            if done {
+                break 'files;
+            }
+        }
+    }
+
+    if !pending.is_empty() {
+        write_file(pending, &params, &output_path(&args.output, file_index)).await?;
+    }
+
+    Ok(())
+}
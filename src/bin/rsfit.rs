@@ -0,0 +1,150 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{collections::HashMap, path::PathBuf};
+
+use anyhow::Context;
+use clap::Parser;
+use rstrf::orbit::{self, FitParameter, Measurement};
+
+/// Fits the elements of a satellite's TLE and its transmitter frequency to Doppler measurements
+/// (.dat files as written by rstrf or rfplot), like STRF's rffit. Prints the fit and writes the
+/// updated TLE.
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    /// Measurement files (MJD, frequency in Hz, significance and site ID per line)
+    #[arg(value_name = "INPUT", required = true)]
+    input: Vec<PathBuf>,
+    /// TLE catalog file
+    #[arg(short = 'c', long)]
+    catalog: PathBuf,
+    /// Satellite to fit
+    #[arg(short = 'i', long)]
+    norad_id: u64,
+    /// Initial transmitter frequency (Hz), defaults to the first transmitter of the satellite
+    #[arg(short = 'f', long)]
+    freq: Option<f64>,
+    /// Path to frequencies.txt
+    #[arg(short = 'F', long, value_name = "FREQLIST")]
+    freqs: Option<PathBuf>,
+    /// Path to STRF's sites.txt (defaults to $ST_SITES_TXT or $ST_DATADIR/data/sites.txt)
+    #[arg(long, value_name = "SITES")]
+    sites: Option<PathBuf>,
+    /// Parameters to fit: incl, raan, ecc, argp, ma, mm and freq
+    #[arg(
+        short = 'p',
+        long = "fit",
+        value_delimiter = ',',
+        default_values_t = FitParameter::DEFAULT
+    )]
+    parameters: Vec<FitParameter>,
+    /// Write the fitted TLE to this file instead of stdout
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+    /// Print the residual of each measurement
+    #[arg(long)]
+    residuals: bool,
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let args = Args::parse();
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+
+    let mut measurements: Vec<Measurement> = Vec::new();
+    for path in &args.input {
+        measurements.extend(
+            orbit::load_measurements(path)
+                .await
+                .with_context(|| format!("Failed to load {path:?}"))?,
+        );
+    }
+    measurements.sort_by_key(|m| m.time);
+    log::info!(
+        "Loaded {} measurements from {} files",
+        measurements.len(),
+        args.input.len()
+    );
+
+    let transmitters = match &args.freqs {
+        Some(path) => orbit::load_frequencies(path)
+            .await
+            .context("Failed to load frequencies")?,
+        None => HashMap::new(),
+    };
+    let satellite = orbit::load_tles(&args.catalog, transmitters)
+        .await
+        .context("Failed to load TLEs")?
+        .into_iter()
+        .find(|sat| sat.norad_id() == args.norad_id)
+        .with_context(|| format!("Satellite {} not found in catalog", args.norad_id))?;
+    let frequency = args
+        .freq
+        .or_else(|| satellite.transmitters.first().copied())
+        .with_context(|| {
+            format!(
+                "No frequency given and satellite {} has no transmitters",
+                args.norad_id
+            )
+        })?;
+
+    let sites_path = match args.sites {
+        Some(path) => path,
+        None => orbit::strf_sites_path().context("No sites.txt given")?,
+    };
+    let sites = orbit::load_strf_sites(&sites_path)
+        .await
+        .with_context(|| format!("Failed to load {sites_path:?}"))?;
+
+    let initial = orbit::residuals(&satellite, frequency, &measurements, &sites)?;
+    let parameters = args.parameters.clone();
+    let fit = tokio::task::spawn_blocking(move || {
+        orbit::fit_orbit(&satellite, frequency, &measurements, &sites, &parameters)
+            .map(|fit| (satellite, measurements, fit))
+    })
+    .await??;
+    let (satellite, measurements, fit) = fit;
+    let rms = |residuals: &[f64]| {
+        (residuals.iter().map(|r| r * r).sum::<f64>() / residuals.len() as f64).sqrt()
+    };
+    log::info!(
+        "RMS {:.1} Hz -> {:.1} Hz after {} iterations",
+        rms(&initial),
+        fit.rms,
+        fit.iterations
+    );
+    for parameter in &args.parameters {
+        log::info!(
+            "{:<16} {:>16.8} -> {:>16.8}",
+            parameter.to_string(),
+            parameter.get(&satellite.elements, frequency),
+            parameter.get(&fit.satellite.elements, fit.frequency)
+        );
+    }
+
+    if args.residuals {
+        println!(
+            "{:<23}  {:>16}  {:>10}  {:>7}",
+            "TIME", "FREQ (MHz)", "RES Hz", "SITE"
+        );
+        for (m, residual) in measurements.iter().zip(&fit.residuals) {
+            println!(
+                "{:<23}  {:>16.6}  {:>+10.1}  {:>7}",
+                m.time.format("%Y-%m-%dT%H:%M:%S%.3f"),
+                m.freq / 1e6,
+                residual,
+                m.site_id
+            );
+        }
+    }
+
+    let tle = orbit::format_tle_with_name(&fit.satellite);
+    match args.output {
+        Some(path) => tokio::fs::write(&path, tle)
+            .await
+            .with_context(|| format!("Failed to write {path:?}"))?,
+        None => print!("{tle}"),
+    }
+
+    Ok(())
+}
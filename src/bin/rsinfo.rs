@@ -0,0 +1,219 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{ops::Range, path::PathBuf};
+
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use clap::Parser;
+use rstrf::spectrogram::{FileSummary, SetSummary};
+use serde::Serialize;
+
+/// Summarizes rffft spectrograms by reading only their headers, and checks them for gaps, overlaps,
+/// inconsistent parameters and damaged spectra.
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    /// Spectrogram files to inspect (rffft format)
+    #[arg(value_name = "INPUT", required = true)]
+    input: Vec<PathBuf>,
+    /// Print JSON instead of a human-readable summary
+    #[arg(long)]
+    json: bool,
+}
+
+fn format_time(time: DateTime<Utc>) -> String {
+    time.format("%Y-%m-%dT%H:%M:%S%.3f").to_string()
+}
+
+#[derive(Serialize)]
+struct TimeRangeJson {
+    start: String,
+    end: String,
+    seconds: f64,
+}
+
+impl From<&Range<DateTime<Utc>>> for TimeRangeJson {
+    fn from(range: &Range<DateTime<Utc>>) -> Self {
+        Self {
+            start: format_time(range.start),
+            end: format_time(range.end),
+            seconds: (range.end - range.start).as_seconds_f64(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct FileJson {
+    path: PathBuf,
+    start: String,
+    end: String,
+    freq: f32,
+    bw: f32,
+    nchan: usize,
+    nspectra: usize,
+    spectrum_length: f32,
+    gaps: Vec<TimeRangeJson>,
+    problems: Vec<ProblemJson>,
+}
+
+#[derive(Serialize)]
+struct ProblemJson {
+    offset: Option<u64>,
+    message: String,
+}
+
+#[derive(Serialize)]
+struct OverlapJson {
+    first: PathBuf,
+    second: PathBuf,
+    #[serde(flatten)]
+    range: TimeRangeJson,
+}
+
+#[derive(Serialize)]
+struct SetJson {
+    files: Vec<FileJson>,
+    start: String,
+    end: String,
+    gaps: Vec<TimeRangeJson>,
+    overlaps: Vec<OverlapJson>,
+    inconsistent: Vec<PathBuf>,
+}
+
+impl From<&SetSummary> for SetJson {
+    fn from(set: &SetSummary) -> Self {
+        Self {
+            files: set
+                .files
+                .iter()
+                .map(|file| FileJson {
+                    path: file.path.clone(),
+                    start: format_time(file.start),
+                    end: format_time(file.end),
+                    freq: file.params.freq,
+                    bw: file.params.bw,
+                    nchan: file.params.nchan,
+                    nspectra: file.nspectra,
+                    spectrum_length: file.spectrum_length,
+                    gaps: file.gaps.iter().map(Into::into).collect(),
+                    problems: file
+                        .problems
+                        .iter()
+                        .map(|problem| ProblemJson {
+                            offset: problem.offset(),
+                            message: problem.to_string(),
+                        })
+                        .collect(),
+                })
+                .collect(),
+            start: format_time(set.start),
+            end: format_time(set.end),
+            gaps: set.gaps.iter().map(Into::into).collect(),
+            overlaps: set
+                .overlaps
+                .iter()
+                .map(|overlap| OverlapJson {
+                    first: overlap.first.clone(),
+                    second: overlap.second.clone(),
+                    range: (&overlap.range).into(),
+                })
+                .collect(),
+            inconsistent: set.inconsistent.clone(),
+        }
+    }
+}
+
+fn print_file(file: &FileSummary) {
+    println!("{}", file.path.display());
+    println!("  {} - {}", format_time(file.start), format_time(file.end));
+    println!(
+        "  {:.6} MHz, {:.3} kHz, {} channels ({:.2} Hz)",
+        file.params.freq / 1e6,
+        file.params.bw / 1e3,
+        file.params.nchan,
+        file.params.bw / file.params.nchan as f32
+    );
+    println!(
+        "  {} spectra of {:.3} s, {} gaps",
+        file.nspectra,
+        file.spectrum_length,
+        file.gaps.len()
+    );
+    for problem in &file.problems {
+        println!("  Problem: {problem}");
+    }
+}
+
+fn print_set(set: &SetSummary) {
+    for file in &set.files {
+        print_file(file);
+    }
+    println!();
+    println!("{} files", set.files.len());
+    println!(
+        "  {} - {} ({:.0} s)",
+        format_time(set.start),
+        format_time(set.end),
+        (set.end - set.start).as_seconds_f64()
+    );
+    let missing: f64 = set
+        .gaps
+        .iter()
+        .map(|gap| (gap.end - gap.start).as_seconds_f64())
+        .sum();
+    println!("  {} gaps ({:.0} s missing)", set.gaps.len(), missing);
+    for gap in &set.gaps {
+        println!(
+            "    {} - {} ({:.0} s)",
+            format_time(gap.start),
+            format_time(gap.end),
+            (gap.end - gap.start).as_seconds_f64()
+        );
+    }
+    println!("  {} overlaps", set.overlaps.len());
+    for overlap in &set.overlaps {
+        println!(
+            "    {} and {}: {} - {}",
+            overlap.first.display(),
+            overlap.second.display(),
+            format_time(overlap.range.start),
+            format_time(overlap.range.end)
+        );
+    }
+    if !set.inconsistent.is_empty() {
+        println!(
+            "  {} files with different parameters than {}:",
+            set.inconsistent.len(),
+            set.files[0].path.display()
+        );
+        for path in &set.inconsistent {
+            println!("    {}", path.display());
+        }
+    }
+    let problems: usize = set.files.iter().map(|file| file.problems.len()).sum();
+    println!("  {problems} damaged spectra");
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let args = Args::parse();
+    // Damaged spectra are part of the summary, so don't log them as well
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("error")).init();
+
+    let mut files = Vec::with_capacity(args.input.len());
+    for path in &args.input {
+        files.push(
+            FileSummary::read(path)
+                .await
+                .with_context(|| format!("Failed to read {}", path.display()))?,
+        );
+    }
+    let set = SetSummary::new(files).context("No files to summarize")?;
+
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&SetJson::from(&set))?);
+    } else {
+        print_set(&set);
+    }
+    Ok(())
+}
@@ -1,8 +1,15 @@
-use anyhow::Context;
+use anyhow::{Context, ensure};
+use chrono::{DateTime, Utc};
 use clap::Parser;
-use rstrf::spectrogram;
-use scirs2_ndimage::{BorderMode, filters::median_filter};
-use std::path::PathBuf;
+use ndarray::{Axis, s};
+use rstrf::{
+    spectrogram::{
+        self, FloorEstimator, FloorUnits, LoadOptions, NoiseFloor, STREAM_CHUNK_SLICES,
+        Spectrogram, StrfWriter,
+    },
+    util::parse_utc,
+};
+use std::path::{Path, PathBuf};
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
@@ -10,45 +17,229 @@ struct Args {
     /// Spectrogram files to load (rffft format)
     #[arg(value_name = "INPUT", required = true)]
     input: Vec<PathBuf>,
-    /// Spectrogram file to output (rffft format)
+    /// Spectrogram file to output (rffft format), or the output directory with `--per-file`
     #[arg(value_name = "OUTPUT", required = true)]
     output: PathBuf,
+    /// Filter each input file separately and write the results to the OUTPUT directory under the
+    /// same names, instead of joining all inputs into one file. Spectra from neighbouring files are
+    /// used for the time window, so there are no edges at file boundaries.
+    #[arg(long)]
+    per_file: bool,
+    /// Prefix for the names of the output files with `--per-file`
+    #[arg(long, default_value = "mf_", requires = "per_file")]
+    prefix: String,
     /// Window size in Hz
     #[arg(short = 'w', long, value_name = "WINDOW_SIZE", default_value = "20000")]
     window_size: f32,
+    /// Window size in seconds (by default, each spectrum is filtered on its own)
+    #[arg(short = 't', long, value_name = "SECONDS", default_value = "0")]
+    time_window: f32,
+    /// How the noise floor is estimated from the window
+    #[arg(short, long, default_value_t)]
+    estimator: FloorEstimator,
+    /// Percentile (0 to 100) for `--estimator percentile`
+    #[arg(long, default_value = "25")]
+    percentile: f32,
+    /// Rejection threshold in standard deviations for `--estimator sigma-clip`
+    #[arg(long, default_value = "3")]
+    clip_sigma: f32,
+    /// Units of the output: dB above the floor, or the local noise sigma above the floor (so the
+    /// same colour scale works for different recordings)
+    #[arg(short, long, default_value_t)]
+    units: FloorUnits,
     /// Frequency range to load in Hz: MIN MAX (channels outside this range are skipped)
     #[arg(long, value_name = "FREQ", num_args = 2)]
     freq_range: Option<Vec<f64>>,
+    /// Time range to load in UTC: START END (spectra outside this range are skipped)
+    #[arg(long, value_names = ["START", "END"], num_args = 2, value_parser = parse_utc)]
+    time_range: Option<Vec<DateTime<Utc>>>,
+    /// Skip truncated and damaged spectra with a warning instead of failing
+    #[arg(long)]
+    lenient: bool,
 }
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     let args = Args::parse();
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+    ensure!(
+        args.window_size > 0.0 && args.time_window >= 0.0,
+        "The window sizes must be positive"
+    );
 
     let freq_range = args
         .freq_range
+        .as_ref()
         .map(|v| (v[0].round() as u64, v[1].round() as u64));
-    let mut spectrogram = spectrogram::load(&args.input, freq_range)
+    // Memory-map the input and filter it in chunks, so recordings larger than RAM work as well.
+    let options = LoadOptions {
+        freq_range,
+        time_range: args.time_range.as_ref().map(|v| (v[0], v[1])),
+        lenient: args.lenient,
+        mmap: true,
+        ..Default::default()
+    };
+
+    if args.per_file {
+        return filter_files(&args, options, &args.output).await;
+    }
+    let spectrogram = spectrogram::load(&args.input, options)
         .await
         .context("Failed to load input spectrogram")?;
+    let floor = noise_floor(&args, &spectrogram)?;
+    filter(&spectrogram, [None, None], &floor, args.units, &args.output).await
+}
 
-    let window_size =
-        (spectrogram.nchan as f32 * args.window_size / spectrogram.bw).round() as usize;
+/// Sets up the noise floor estimate from the arguments for the resolution of `spectrogram`.
+fn noise_floor(args: &Args, spectrogram: &Spectrogram) -> anyhow::Result<NoiseFloor> {
+    let mean_length = spectrogram.lengths.iter().sum::<f32>() / spectrogram.nslices.max(1) as f32;
+    let floor = NoiseFloor {
+        estimator: args.estimator,
+        half_chans: (spectrogram.nchan as f32 * args.window_size / spectrogram.bw / 2.0).round()
+            as usize,
+        half_slices: (args.time_window / mean_length / 2.0).round() as usize,
+        percentile: args.percentile,
+        clip_sigma: args.clip_sigma,
+    };
+    floor.validate()?;
+    log::info!(
+        "Subtracting the {} over {} channels and {} spectra",
+        args.estimator,
+        2 * floor.half_chans + 1,
+        2 * floor.half_slices + 1
+    );
+    Ok(floor)
+}
+
+/// Filters each input file on its own, with at most three files (the current one and its
+/// neighbours) loaded at a time.
+async fn filter_files(args: &Args, options: LoadOptions, output_dir: &Path) -> anyhow::Result<()> {
+    tokio::fs::create_dir_all(output_dir)
+        .await
+        .with_context(|| format!("Failed to create {}", output_dir.display()))?;
 
-    let median = median_filter(
-        &spectrogram.data().to_owned(),
-        &[1, window_size],
-        Some(BorderMode::Nearest),
-    )
-    .context("Failed to apply median filter")?;
+    let mut inputs = args.input.iter();
+    let mut previous: Option<(&PathBuf, Spectrogram)> = None;
+    let mut current = load_next(&mut inputs, options).await?;
+    ensure!(
+        current.is_some(),
+        "None of the files have spectra in the time range"
+    );
+    while let Some((path, spectrogram)) = current.take() {
+        let next = load_next(&mut inputs, options).await?;
 
-    let result = &spectrogram.data() - &median;
+        let floor = noise_floor(args, &spectrogram)?;
+        let neighbours = [previous.as_ref(), next.as_ref()].map(|neighbour| {
+            neighbour
+                .map(|(_, spec)| spec)
+                .filter(|neighbour| is_adjacent(&spectrogram, neighbour, args.time_window))
+        });
+        let name = path.file_name().context("Input is not a file")?;
+        let output = output_dir.join(format!("{}{}", args.prefix, name.to_string_lossy()));
+        ensure!(
+            !same_file(&output, path),
+            "{} would overwrite its input, use a different --prefix or output directory",
+            output.display()
+        );
+        log::info!("Filtering {} into {}", path.display(), output.display());
+        filter(&spectrogram, neighbours, &floor, args.units, &output).await?;
 
-    spectrogram.set_data(result.into())?;
+        previous = Some((path, spectrogram));
+        current = next;
+    }
+    Ok(())
+}
+
+/// Loads the next input file that has spectra in the time range.
+async fn load_next<'a>(
+    inputs: &mut impl Iterator<Item = &'a PathBuf>,
+    options: LoadOptions,
+) -> anyhow::Result<Option<(&'a PathBuf, Spectrogram)>> {
+    for path in inputs {
+        if let Some(spectrogram) = spectrogram::load_single(path.clone(), options).await? {
+            return Ok(Some((path, spectrogram)));
+        }
+    }
+    Ok(None)
+}
 
-    spectrogram::save_strf(&spectrogram, &args.output)
+/// Whether the spectra of `neighbour` can be used for the time window at the edges of
+/// `spectrogram`, i.e. it has the same parameters and starts or ends within `time_window` seconds.
+fn is_adjacent(spectrogram: &Spectrogram, neighbour: &Spectrogram, time_window: f32) -> bool {
+    let gap = if neighbour.start_time() < spectrogram.start_time() {
+        spectrogram.start_time() - neighbour.end_time()
+    } else {
+        neighbour.start_time() - spectrogram.end_time()
+    };
+    if gap.as_seconds_f32() > time_window {
+        return false;
+    }
+    if neighbour.params() != spectrogram.params() {
+        log::warn!(
+            "Not using spectra from a neighbouring file with different parameters for the time \
+             window: {:?}",
+            neighbour.params()
+        );
+        return false;
+    }
+    true
+}
+
+fn same_file(a: &Path, b: &Path) -> bool {
+    match (a.canonicalize(), b.canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+/// Filters `spectrogram` and writes the result to `output`. The spectra before and after it in
+/// `neighbours` are used for the time window at its edges, but not written.
+async fn filter(
+    spectrogram: &Spectrogram,
+    neighbours: [Option<&Spectrogram>; 2],
+    floor: &NoiseFloor,
+    units: FloorUnits,
+    output: &Path,
+) -> anyhow::Result<()> {
+    let [before, after] = neighbours;
+    let nslices = spectrogram.nslices;
+    let channels = 0..spectrogram.nchan;
+    let half = floor.half_slices;
+
+    let mut writer = StrfWriter::create(output, &spectrogram.params(), nslices)
         .await
-        .context("Failed to save filtered spectrogram")?;
+        .context("Failed to create output file")?;
+    // Each chunk of spectra is filtered together with the spectra around it that are part of the
+    // time window.
+    for start in (0..nslices).step_by(STREAM_CHUNK_SLICES) {
+        let slices = start..(start + STREAM_CHUNK_SLICES).min(nslices);
+        let context = slices.start.saturating_sub(half)..(slices.end + half).min(nslices);
+        let mut tiles = Vec::with_capacity(3);
+        if let Some(before) = before {
+            let missing = (half - (slices.start - context.start)).min(before.nslices);
+            tiles.push(before.tile(before.nslices - missing..before.nslices, channels.clone()));
+        }
+        let offset = tiles.first().map_or(0, |tile| tile.nrows()) + slices.start - context.start;
+        tiles.push(spectrogram.tile(context.clone(), channels.clone()));
+        if let Some(after) = after {
+            let missing = (half - (context.end - slices.end)).min(after.nslices);
+            tiles.push(after.tile(0..missing, channels.clone()));
+        }
+        let views: Vec<_> = tiles.iter().map(|tile| tile.view()).collect();
+        let data = ndarray::concatenate(Axis(0), &views).context("Mismatching channels")?;
 
-    Ok(())
+        let result = floor.subtract(data.view(), units);
+        let result = result.slice(s![offset..offset + slices.len(), ..]);
+
+        for (i, slice) in slices.zip(result.outer_iter()) {
+            writer
+                .write_spectrum(spectrogram.timestamps[i], spectrogram.lengths[i], slice)
+                .await
+                .context("Failed to save filtered spectrogram")?;
+        }
+    }
+    writer
+        .finish()
+        .await
+        .context("Failed to save filtered spectrogram")
 }
@@ -0,0 +1,95 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use anyhow::{Context, ensure};
+use chrono::{DateTime, Utc};
+use clap::Parser;
+use rstrf::{
+    spectrogram::{self, ExportFormat, LoadOptions, Pipeline, Stage, save_strf_blanked},
+    util::parse_utc,
+};
+use std::path::PathBuf;
+
+/// Applies a chain of processing stages to rffft spectrograms, e.g.
+/// "bandpass, background(window=20000), snr".
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None, after_help = Stage::HELP)]
+struct Args {
+    /// Spectrogram files to load (rffft format)
+    #[arg(value_name = "INPUT", required = true)]
+    input: Vec<PathBuf>,
+    /// File to output (rffft format, or .fits/.npy/.npz as with rsexport)
+    #[arg(value_name = "OUTPUT", required = true)]
+    output: PathBuf,
+    /// Processing stages, see below
+    #[arg(short, long, conflicts_with = "pipeline_file")]
+    pipeline: Option<Pipeline>,
+    /// File with the processing stages (`#` starts a comment)
+    #[arg(long, value_name = "FILE")]
+    pipeline_file: Option<PathBuf>,
+    /// Frequency range to load in Hz: MIN MAX (channels outside this range are skipped)
+    #[arg(long, value_name = "FREQ", num_args = 2)]
+    freq_range: Option<Vec<f64>>,
+    /// Time range to load in UTC: START END (spectra outside this range are skipped)
+    #[arg(long, value_names = ["START", "END"], num_args = 2, value_parser = parse_utc)]
+    time_range: Option<Vec<DateTime<Utc>>>,
+    /// Skip truncated and damaged spectra with a warning instead of failing
+    #[arg(long)]
+    lenient: bool,
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let args = Args::parse();
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+
+    let pipeline = match (args.pipeline, &args.pipeline_file) {
+        (Some(pipeline), _) => pipeline,
+        (None, Some(path)) => tokio::fs::read_to_string(path)
+            .await
+            .with_context(|| format!("Failed to read {}", path.display()))?
+            .parse()
+            .with_context(|| format!("Invalid pipeline in {}", path.display()))?,
+        (None, None) => Pipeline::default(),
+    };
+    ensure!(
+        !pipeline.is_empty(),
+        "Nothing to do, pass the processing stages with --pipeline or --pipeline-file"
+    );
+
+    let freq_range = args
+        .freq_range
+        .map(|v| (v[0].round() as u64, v[1].round() as u64));
+    let options = LoadOptions {
+        freq_range,
+        time_range: args.time_range.map(|v| (v[0], v[1])),
+        lenient: args.lenient,
+        mmap: true,
+        ..Default::default()
+    };
+    let spectrogram = spectrogram::load(&args.input, options)
+        .await
+        .context("Failed to load input spectrogram")?;
+
+    log::info!(
+        "Processing {} spectra with {} channels: {}",
+        spectrogram.nslices,
+        spectrogram.nchan,
+        pipeline
+    );
+    let processed = tokio::task::spawn_blocking(move || pipeline.apply(&spectrogram))
+        .await?
+        .context("Failed to process spectrogram")?;
+
+    match ExportFormat::from_path(&args.output) {
+        Some(format) => {
+            let output = args.output.clone();
+            tokio::task::spawn_blocking(move || spectrogram::export(&processed, &output, format))
+                .await?
+        }
+        // The rffft format has no place for the RFI mask of the `flag` stage
+        None => save_strf_blanked(&processed, &args.output).await,
+    }
+    .context("Failed to save processed spectrogram")?;
+
+    Ok(())
+}
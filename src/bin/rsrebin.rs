@@ -0,0 +1,96 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use anyhow::{Context, ensure};
+use chrono::{DateTime, Utc};
+use clap::Parser;
+use rstrf::{
+    spectrogram::{self, LoadOptions, save_strf},
+    util::parse_utc,
+};
+use std::path::PathBuf;
+
+/// Integrates spectra and merges channels of rffft spectrograms, averaging in linear power.
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    /// Spectrogram files to load (rffft format)
+    #[arg(value_name = "INPUT", required = true)]
+    input: Vec<PathBuf>,
+    /// Spectrogram file to output (rffft format)
+    #[arg(value_name = "OUTPUT", required = true)]
+    output: PathBuf,
+    /// Number of consecutive spectra to integrate
+    #[arg(short = 't', long, value_name = "FACTOR", conflicts_with = "tint")]
+    time_factor: Option<usize>,
+    /// Number of neighbouring channels to merge
+    #[arg(
+        short = 'f',
+        long,
+        value_name = "FACTOR",
+        conflicts_with = "chan_width"
+    )]
+    freq_factor: Option<usize>,
+    /// Target integration time per spectrum in seconds (rounded to a multiple of the input's)
+    #[arg(long, value_name = "SECONDS")]
+    tint: Option<f32>,
+    /// Target channel width in Hz (rounded to a multiple of the input's)
+    #[arg(long, value_name = "HZ")]
+    chan_width: Option<f32>,
+    /// Frequency range to load in Hz: MIN MAX (channels outside this range are skipped)
+    #[arg(long, value_name = "FREQ", num_args = 2)]
+    freq_range: Option<Vec<f64>>,
+    /// Time range to load in UTC: START END (spectra outside this range are skipped)
+    #[arg(long, value_names = ["START", "END"], num_args = 2, value_parser = parse_utc)]
+    time_range: Option<Vec<DateTime<Utc>>>,
+    /// Skip truncated and damaged spectra with a warning instead of failing
+    #[arg(long)]
+    lenient: bool,
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let args = Args::parse();
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+    ensure!(
+        args.time_factor.is_some()
+            || args.freq_factor.is_some()
+            || args.tint.is_some()
+            || args.chan_width.is_some(),
+        "Nothing to do, pass at least one of --time-factor, --freq-factor, --tint or --chan-width"
+    );
+
+    let freq_range = args
+        .freq_range
+        .map(|v| (v[0].round() as u64, v[1].round() as u64));
+    // Only the rebinned spectrogram needs to fit into memory
+    let options = LoadOptions {
+        freq_range,
+        time_range: args.time_range.map(|v| (v[0], v[1])),
+        lenient: args.lenient,
+        mmap: true,
+        ..Default::default()
+    };
+    let spectrogram = spectrogram::load(&args.input, options)
+        .await
+        .context("Failed to load input spectrogram")?;
+
+    let (time_factor, freq_factor) = spectrogram.rebin_factors(args.tint, args.chan_width);
+    let time_factor = args.time_factor.unwrap_or(time_factor);
+    let freq_factor = args.freq_factor.unwrap_or(freq_factor);
+    log::info!(
+        "Integrating {} spectra and merging {} channels of {} spectra with {} channels",
+        time_factor,
+        freq_factor,
+        spectrogram.nslices,
+        spectrogram.nchan
+    );
+    let rebinned = tokio::task::spawn_blocking(move || spectrogram.rebin(time_factor, freq_factor))
+        .await?
+        .context("Failed to rebin spectrogram")?;
+
+    save_strf(&rebinned, &args.output)
+        .await
+        .context("Failed to save rebinned spectrogram")?;
+
+    Ok(())
+}
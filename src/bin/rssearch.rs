@@ -0,0 +1,163 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use anyhow::Context;
+use chrono::{DateTime, Duration, Utc};
+use clap::Parser;
+use rstrf::{
+    signal::{self, BlindSearchOptions, Candidate},
+    spectrogram::{self, LoadOptions, Pipeline, Spectrogram, Stage},
+    util::parse_utc,
+};
+use serde::Serialize;
+use std::path::PathBuf;
+
+/// Searches rffft spectrograms for signal traces without knowing where to look, and lists their
+/// start and end times, frequency spans and drift rates.
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None, after_help = Stage::HELP)]
+struct Args {
+    /// Spectrogram files to search (rffft format)
+    #[arg(value_name = "INPUT", required = true)]
+    input: Vec<PathBuf>,
+    /// Detection threshold in standard deviations above the local noise
+    #[arg(short, long, default_value_t = BlindSearchOptions::default().threshold)]
+    threshold: f32,
+    /// Channels on each side of a cell that are left out of its noise estimate
+    #[arg(long, default_value_t = BlindSearchOptions::default().guard)]
+    guard: usize,
+    /// Channels on each side of a cell (beyond the guard channels) to estimate its noise from
+    #[arg(long, default_value_t = BlindSearchOptions::default().train)]
+    train: usize,
+    /// Longest gap in seconds that is bridged when linking detections into traces
+    #[arg(long, default_value_t = BlindSearchOptions::default().max_gap)]
+    max_gap: f32,
+    /// How far in channels detections after a gap may be from the extrapolated trace
+    #[arg(long, default_value_t = BlindSearchOptions::default().max_jump)]
+    max_jump: f32,
+    /// Drop traces with fewer detections
+    #[arg(long, default_value_t = BlindSearchOptions::default().min_cells)]
+    min_cells: usize,
+    /// Processing stages to apply before searching, see below (e.g. "flag" to skip RFI)
+    #[arg(short, long)]
+    pipeline: Option<Pipeline>,
+    /// Frequency range to load in Hz: MIN MAX (channels outside this range are skipped)
+    #[arg(long, value_name = "FREQ", num_args = 2)]
+    freq_range: Option<Vec<f64>>,
+    /// Time range to load in UTC: START END (spectra outside this range are skipped)
+    #[arg(long, value_names = ["START", "END"], num_args = 2, value_parser = parse_utc)]
+    time_range: Option<Vec<DateTime<Utc>>>,
+    /// Skip truncated and damaged spectra with a warning instead of failing
+    #[arg(long)]
+    lenient: bool,
+    /// Print JSON instead of a table
+    #[arg(long)]
+    json: bool,
+}
+
+fn format_time(time: DateTime<Utc>) -> String {
+    time.format("%Y-%m-%dT%H:%M:%S%.3f").to_string()
+}
+
+#[derive(Serialize)]
+struct CandidateJson {
+    start: String,
+    end: String,
+    /// Absolute frequencies in Hz
+    freq_min: f64,
+    freq_max: f64,
+    /// Hz/s
+    drift: f32,
+    sigma: f32,
+    cells: usize,
+}
+
+impl CandidateJson {
+    fn new(candidate: &Candidate, spectrogram: &Spectrogram) -> Self {
+        let time = |t: f32| spectrogram.start_time() + Duration::microseconds((t * 1e6) as i64);
+        let freq = |f: f32| spectrogram.freq as f64 + f as f64;
+        Self {
+            start: format_time(time(candidate.start)),
+            end: format_time(time(candidate.end)),
+            freq_min: freq(candidate.freq_min),
+            freq_max: freq(candidate.freq_max),
+            drift: candidate.drift,
+            sigma: candidate.sigma,
+            cells: candidate.cells,
+        }
+    }
+}
+
+fn print_candidates(candidates: &[CandidateJson]) {
+    println!(
+        "{:<23}  {:<23}  {:>14}  {:>10}  {:>10}  {:>6}  {:>6}",
+        "START", "END", "FREQ (MHz)", "SPAN (kHz)", "DRIFT Hz/s", "SIGMA", "CELLS"
+    );
+    for candidate in candidates {
+        println!(
+            "{:<23}  {:<23}  {:>14.6}  {:>10.3}  {:>10.1}  {:>6.1}  {:>6}",
+            candidate.start,
+            candidate.end,
+            (candidate.freq_min + candidate.freq_max) / 2e6,
+            (candidate.freq_max - candidate.freq_min) / 1e3,
+            candidate.drift,
+            candidate.sigma,
+            candidate.cells
+        );
+    }
+    println!("{} candidates", candidates.len());
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let args = Args::parse();
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+
+    let freq_range = args
+        .freq_range
+        .map(|v| (v[0].round() as u64, v[1].round() as u64));
+    let options = LoadOptions {
+        freq_range,
+        time_range: args.time_range.map(|v| (v[0], v[1])),
+        lenient: args.lenient,
+        mmap: true,
+        ..Default::default()
+    };
+    let spectrogram = spectrogram::load(&args.input, options)
+        .await
+        .context("Failed to load input spectrogram")?;
+
+    let options = BlindSearchOptions {
+        threshold: args.threshold,
+        guard: args.guard,
+        train: args.train,
+        max_gap: args.max_gap,
+        max_jump: args.max_jump,
+        min_cells: args.min_cells,
+    };
+    let pipeline = args.pipeline.unwrap_or_default();
+    log::info!(
+        "Searching {} spectra with {} channels",
+        spectrogram.nslices,
+        spectrogram.nchan
+    );
+    let (spectrogram, candidates) = tokio::task::spawn_blocking(move || {
+        let spectrogram = pipeline
+            .apply(&spectrogram)
+            .context("Failed to process spectrogram")?;
+        let candidates = signal::blind_search(&spectrogram, &options);
+        anyhow::Ok((spectrogram, candidates))
+    })
+    .await??;
+
+    let candidates: Vec<CandidateJson> = candidates
+        .iter()
+        .map(|candidate| CandidateJson::new(candidate, &spectrogram))
+        .collect();
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&candidates)?);
+    } else {
+        print_candidates(&candidates);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,213 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{collections::HashMap, path::PathBuf};
+
+use anyhow::Context;
+use chrono::{DateTime, Duration, Utc};
+use clap::{ArgGroup, Parser};
+use rstrf::{
+    orbit::{self, Site},
+    signal::{self, StackedSpectrum},
+    spectrogram::{self, LoadOptions, Pipeline, Stage},
+    util::parse_utc,
+};
+use serde::Serialize;
+
+/// Stacks rffft spectrograms along the predicted Doppler curve of a satellite, so that signals too
+/// weak to be seen in single spectra add up. Prints the offset of the transmitter from its
+/// predicted frequency and the SNR of the stacked spectrum for each pass.
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None, after_help = Stage::HELP)]
+#[command(group(ArgGroup::new("freq_source").required(true).args(["freq", "freqs"])))]
+struct Args {
+    /// Spectrogram files to stack (rffft format)
+    #[arg(value_name = "INPUT", required = true)]
+    input: Vec<PathBuf>,
+    /// TLE catalog file
+    #[arg(short = 'c', long)]
+    catalog: PathBuf,
+    /// Satellite to stack along
+    #[arg(short = 'i', long)]
+    norad_id: u64,
+    /// Transmitter frequency (Hz), may be specified multiple times
+    #[arg(short = 'f', long)]
+    freq: Vec<f64>,
+    /// Path to frequencies.txt
+    #[arg(short = 'F', long, value_name = "FREQLIST")]
+    freqs: Option<PathBuf>,
+    /// Site ID in STRF's sites.txt (defaults to $ST_COSPAR)
+    #[arg(short = 'C', long, value_name = "SITE_ID")]
+    site_id: Option<i32>,
+    /// Path to STRF's sites.txt (defaults to $ST_SITES_TXT or $ST_DATADIR/data/sites.txt)
+    #[arg(long, value_name = "SITES")]
+    sites: Option<PathBuf>,
+    /// Width in Hz of the stacked spectrum around the predicted frequency
+    #[arg(short, long, default_value_t = 20e3)]
+    span: f32,
+    /// Processing stages to apply before stacking, see below (e.g. "flag" to skip RFI)
+    #[arg(short, long)]
+    pipeline: Option<Pipeline>,
+    /// Frequency range to load in Hz: MIN MAX (channels outside this range are skipped)
+    #[arg(long, value_name = "FREQ", num_args = 2)]
+    freq_range: Option<Vec<f64>>,
+    /// Time range to load in UTC: START END (spectra outside this range are skipped)
+    #[arg(long, value_names = ["START", "END"], num_args = 2, value_parser = parse_utc)]
+    time_range: Option<Vec<DateTime<Utc>>>,
+    /// Skip truncated and damaged spectra with a warning instead of failing
+    #[arg(long)]
+    lenient: bool,
+    /// Print JSON (including the stacked spectra) instead of a table
+    #[arg(long)]
+    json: bool,
+}
+
+/// Looks up the site like rstrf's "Follow STRF site" preference.
+async fn load_site(site_id: Option<i32>, sites: Option<PathBuf>) -> anyhow::Result<Site> {
+    let site_id = match site_id {
+        Some(site_id) => site_id,
+        None => std::env::var("ST_COSPAR")
+            .context("No site ID given and ST_COSPAR is not set")?
+            .parse()
+            .context("Failed to parse ST_COSPAR")?,
+    };
+    let path = match sites {
+        Some(path) => path,
+        None => orbit::strf_sites_path().context("No sites.txt given")?,
+    };
+    orbit::load_strf_sites(&path)
+        .await
+        .with_context(|| format!("Failed to load {path:?}"))?
+        .remove(&site_id)
+        .with_context(|| format!("Site ID {site_id} not found in {path:?}"))
+}
+
+fn format_time(time: DateTime<Utc>) -> String {
+    time.format("%Y-%m-%dT%H:%M:%S%.3f").to_string()
+}
+
+#[derive(Serialize)]
+struct PassJson {
+    start: String,
+    end: String,
+    /// Transmitter frequency in Hz
+    transmitter: f64,
+    /// Offset of the peak from the predicted frequency in Hz
+    offset: Option<f32>,
+    snr: Option<f32>,
+    sigma: Option<f32>,
+    #[serde(flatten)]
+    stacked: StackedSpectrum,
+}
+
+fn print_passes(passes: &[PassJson]) {
+    println!(
+        "{:<23}  {:<23}  {:>14}  {:>10}  {:>14}  {:>7}  {:>6}  {:>7}",
+        "START", "END", "TX (MHz)", "OFFSET Hz", "FREQ (MHz)", "SNR dB", "SIGMA", "SPECTRA"
+    );
+    for pass in passes {
+        let (Some(offset), Some(snr), Some(sigma)) = (pass.offset, pass.snr, pass.sigma) else {
+            continue;
+        };
+        println!(
+            "{:<23}  {:<23}  {:>14.6}  {:>+10.1}  {:>14.6}  {:>7.1}  {:>6.1}  {:>7}",
+            pass.start,
+            pass.end,
+            pass.transmitter / 1e6,
+            offset,
+            (pass.transmitter + offset as f64) / 1e6,
+            snr,
+            sigma,
+            pass.stacked.spectra
+        );
+    }
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let args = Args::parse();
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+
+    let transmitters = match &args.freqs {
+        Some(path) => orbit::load_frequencies(path)
+            .await
+            .context("Failed to load frequencies")?,
+        None => HashMap::from([(args.norad_id, args.freq.clone())]),
+    };
+    let satellite = orbit::load_tles(&args.catalog, transmitters)
+        .await
+        .context("Failed to load TLEs")?
+        .into_iter()
+        .find(|sat| sat.norad_id() == args.norad_id)
+        .with_context(|| format!("Satellite {} not found in catalog", args.norad_id))?;
+    anyhow::ensure!(
+        !satellite.transmitters.is_empty(),
+        "Satellite {} has no transmitters",
+        args.norad_id
+    );
+    let site = load_site(args.site_id, args.sites).await?;
+
+    let freq_range = args
+        .freq_range
+        .map(|v| (v[0].round() as u64, v[1].round() as u64));
+    let options = LoadOptions {
+        freq_range,
+        time_range: args.time_range.map(|v| (v[0], v[1])),
+        lenient: args.lenient,
+        mmap: true,
+        ..Default::default()
+    };
+    let spectrogram = spectrogram::load(&args.input, options)
+        .await
+        .context("Failed to load input spectrogram")?;
+
+    let pipeline = args.pipeline.unwrap_or_default();
+    let span = args.span;
+    let (spectrogram, passes) = tokio::task::spawn_blocking(move || {
+        let spectrogram = pipeline
+            .apply(&spectrogram)
+            .context("Failed to process spectrogram")?;
+        let start = spectrogram.start_time();
+        let predictions = orbit::predict_satellites(
+            std::slice::from_ref(&satellite),
+            start..start + spectrogram.length(),
+            &site,
+        );
+        let time = |t: f64| format_time(start + Duration::milliseconds((t * 1e3) as i64));
+        let mut passes = Vec::new();
+        for pass in predictions.for_id(satellite.norad_id()) {
+            let times = predictions.times.view();
+            for (transmitter, &freq) in satellite.transmitters.iter().enumerate() {
+                let Some(stacked) =
+                    signal::stack_pass(&spectrogram, times, pass, transmitter, span)
+                else {
+                    continue;
+                };
+                let peak = stacked.peak();
+                passes.push(PassJson {
+                    start: time(times[pass.time_range.start]),
+                    end: time(times[pass.time_range.end - 1]),
+                    transmitter: freq,
+                    offset: peak.map(|peak| peak.offset),
+                    snr: peak.map(|peak| peak.snr),
+                    sigma: peak.map(|peak| peak.sigma),
+                    stacked,
+                });
+            }
+        }
+        anyhow::Ok((spectrogram, passes))
+    })
+    .await??;
+    log::info!(
+        "Stacked {} passes in {} spectra",
+        passes.len(),
+        spectrogram.nslices
+    );
+
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&passes)?);
+    } else {
+        print_passes(&passes);
+    }
+
+    Ok(())
+}
@@ -2,6 +2,7 @@
 
 use crate::config::Config;
 use crate::pass_png::{self, PassPngMode};
+use crate::windows::orbit_fit::OrbitFitter;
 use crate::windows::rfplot::{InitialView, RFPlot};
 use crate::windows::sat_manager::SatManager;
 use crate::windows::{self, AnyWindow};
@@ -14,7 +15,7 @@ use iced::{Daemon, window};
 use iced::{Element, Program, Subscription, Task, Theme};
 use rstrf::menu::{MenuItem, view_menu};
 use rstrf::orbit::{Satellite, Site, Transmitters};
-use rstrf::spectrogram::SpectrogramBounds;
+use rstrf::spectrogram::{IqOptions, LoadOptions, SpectrogramBounds};
 use space_track::SpaceTrack;
 use std::collections::HashMap;
 use std::fmt::Debug;
@@ -38,8 +39,8 @@ pub struct AppShared {
     ///
     /// Also used for location if `config.follow_strf_site` is true.
     pub site_id: Option<i32>,
-    /// Frequency range to load in Hz (channels outside this range are skipped)
-    pub freq_range: Option<(u64, u64)>,
+    /// Options for loading spectrograms (frequency range, SigMF channelization)
+    pub load_options: LoadOptions,
 }
 
 impl AppShared {
@@ -88,6 +89,8 @@ pub enum Message {
     WindowOpenedSatManager(window::Id),
     OpenPreferences,
     WindowOpenedPreferences(window::Id),
+    OpenOrbitFit,
+    WindowOpenedOrbitFit(window::Id),
     WindowClosed(window::Id),
     #[allow(clippy::enum_variant_names)]
     WindowMessage(window::Id, windows::Message),
@@ -193,9 +196,20 @@ impl AppModel {
         let app = AppModel {
             config_path,
             shared_state: AppShared {
-                freq_range: flags
-                    .freq_range
-                    .map(|v| (v[0].round() as u64, v[1].round() as u64)),
+                load_options: LoadOptions {
+                    freq_range: flags
+                        .freq_range
+                        .map(|v| (v[0].round() as u64, v[1].round() as u64)),
+                    time_range: flags.time_range.map(|v| (v[0], v[1])),
+                    iq: IqOptions {
+                        nchan: flags.nchan,
+                        tint: flags.tint,
+                    },
+                    mmap: flags.mmap,
+                    stitch: flags.stitch,
+                    // Show damaged files instead of refusing to open the whole set
+                    lenient: true,
+                },
                 ..Default::default()
             },
             windows: HashMap::default(),
@@ -238,6 +252,10 @@ impl AppModel {
                     label: "Open new SatManager window".to_string(),
                     msg: Some(Message::OpenSatManager),
                 },
+                MenuItem::Button {
+                    label: "Open new Orbit Fit window".to_string(),
+                    msg: Some(Message::OpenOrbitFit),
+                },
                 MenuItem::Button {
                     label: "Open Preferences".to_string(),
                     msg: Some(Message::OpenPreferences),
@@ -296,7 +314,11 @@ impl AppModel {
                     zmin: args.zmin,
                     zmax: args.zmax,
                 };
-                let rfplot_task = self.open_rfplot_with(id, args.spectrograms.clone(), view);
+                let rfplot = match &args.follow {
+                    Some(dir) => RFPlot::following(dir.clone(), view),
+                    None => RFPlot::with_initial_view(args.spectrograms.clone(), view),
+                };
+                let rfplot_task = self.open_rfplot_with(id, rfplot);
                 if self.shared_state.config.follow_strf_site {
                     let strf_site_task = self.update_strf_site();
                     Task::batch([rfplot_task, strf_site_task])
@@ -313,7 +335,8 @@ impl AppModel {
                     zmin: args.zmin,
                     zmax: args.zmax,
                 };
-                let task = self.open_rfplot_with(id, args.spectrograms.clone(), view);
+                let rfplot = RFPlot::with_initial_view(args.spectrograms.clone(), view);
+                let task = self.open_rfplot_with(id, rfplot);
                 self.pass_png = Some(PassPngMode::new(id, *args));
                 task
             }
@@ -330,6 +353,12 @@ impl AppModel {
                     .insert(id, AnyWindow::SatManager(Box::new(SatManager::new())));
                 Task::none()
             }
+            Message::OpenOrbitFit => Self::open_window(None).map(Message::WindowOpenedOrbitFit),
+            Message::WindowOpenedOrbitFit(id) => {
+                self.windows
+                    .insert(id, AnyWindow::OrbitFit(Box::new(OrbitFitter::new())));
+                Task::none()
+            }
             Message::OpenPreferences => {
                 Self::open_window(None).map(Message::WindowOpenedPreferences)
             }
@@ -479,15 +508,7 @@ impl AppModel {
             log::error!("no site ID provided for STRF site lookup");
             return Task::done(Message::Event(AppEvent::ConfigUpdated));
         };
-        let sites_path = std::env::var("ST_SITES_TXT")
-            .map(PathBuf::from)
-            .or_else(|_| {
-                std::env::var("ST_DATADIR").map(|dir| {
-                    [dir, "data".to_string(), "sites.txt".to_string()]
-                        .iter()
-                        .collect()
-                })
-            });
+        let sites_path = rstrf::orbit::strf_sites_path();
         let sites_path = match sites_path {
             Ok(path) => path,
             Err(err) => {
@@ -541,13 +562,7 @@ impl AppModel {
         open
     }
 
-    fn open_rfplot_with(
-        &mut self,
-        id: window::Id,
-        spectrograms: Vec<PathBuf>,
-        view: InitialView,
-    ) -> Task<Message> {
-        let rfplot = RFPlot::with_initial_view(spectrograms, view);
+    fn open_rfplot_with(&mut self, id: window::Id, rfplot: RFPlot) -> Task<Message> {
         self.windows.insert(id, AnyWindow::RFPlot(Box::new(rfplot)));
         let task = self
             .windows
@@ -1,36 +1,60 @@
-use std::{path::PathBuf, pin::Pin};
+use std::{
+    path::{Path, PathBuf},
+    pin::Pin,
+    time::Duration,
+};
 
+use chrono::{DateTime, Utc};
 use futures_util::{SinkExt, Stream, StreamExt, stream};
 use iced::Subscription;
-use rstrf::spectrogram::Spectrogram;
+use rstrf::spectrogram::{Follower, LoadOptions, Spectrogram};
+
+/// How often a followed directory is checked for new spectra.
+const FOLLOW_POLL_INTERVAL: Duration = Duration::from_secs(1);
 
 pub enum Event {
-    Progress { loaded: usize, total: usize },
+    Progress {
+        loaded: usize,
+        total: usize,
+        /// Files loaded so far that had damaged spectra, which were skipped
+        degraded: Vec<PathBuf>,
+    },
     Done(Result<(Vec<PathBuf>, Spectrogram), String>),
+    /// New spectra were written to a followed directory. The spectrogram only holds the new
+    /// spectra, which start after the previous ones.
+    Extended((Vec<PathBuf>, Spectrogram)),
 }
 
 // run_with(D, fn(&D) -> S) requires fn(&Vec<PathBuf>) since D == Vec<PathBuf>
 #[allow(clippy::ptr_arg)]
 fn load_worker(
     paths: &Vec<PathBuf>,
-    freq_range: Option<(u64, u64)>,
+    options: LoadOptions,
 ) -> Pin<Box<dyn Stream<Item = Event> + Send>> {
-    let paths = paths.clone();
+    let paths = rstrf::sigmf::dedup_recordings(paths);
     Box::pin(iced::stream::channel(16, async move |mut sender| {
         let total = paths.len();
         let mut file_stream = stream::iter(paths.clone())
-            .map(|path| rstrf::spectrogram::load_single(path, freq_range))
+            .map(|path| rstrf::spectrogram::load_single(path, options))
             .buffer_unordered(8);
         let mut spectrograms = Vec::with_capacity(total);
+        let mut loaded = 0;
+        let mut degraded = Vec::new();
 
         while let Some(result) = file_stream.next().await {
             match result {
                 Ok(spec) => {
-                    spectrograms.push(spec);
+                    if let Some(spec) = &spec {
+                        degraded.extend(spec.degraded_files().into_iter().map(Path::to_path_buf));
+                    }
+                    // Files outside of the time range are skipped
+                    spectrograms.extend(spec);
+                    loaded += 1;
                     sender
                         .send(Event::Progress {
-                            loaded: spectrograms.len(),
+                            loaded,
                             total,
+                            degraded: degraded.clone(),
                         })
                         .await
                         .ok();
@@ -42,18 +66,117 @@ fn load_worker(
             }
         }
 
+        if spectrograms.is_empty() {
+            let error = "None of the files have spectra in the time range".to_string();
+            sender.send(Event::Done(Err(error))).await.ok();
+            return;
+        }
         spectrograms.sort_by_key(|s| s.start_time());
-        let result = Spectrogram::concatenate(spectrograms)
-            .map(|s| (paths, s))
-            .map_err(|e| format!("{e:?}"));
+        let result = match Spectrogram::combine(spectrograms, options.stitch) {
+            Ok(mut spec) => tokio::task::spawn_blocking(move || {
+                spec.build_pyramid();
+                (paths, spec)
+            })
+            .await
+            .map_err(|e| format!("{e:?}")),
+            Err(e) => Err(format!("{e:?}")),
+        };
         sender.send(Event::Done(result)).await.ok();
         std::future::pending::<()>().await;
     }))
 }
 
-pub fn load_subscription(
-    paths: Vec<PathBuf>,
-    freq_range: Option<(u64, u64)>,
-) -> Subscription<Event> {
-    Subscription::run_with((paths, freq_range), |(p, f)| load_worker(p, *f))
+pub fn load_subscription(paths: Vec<PathBuf>, options: LoadOptions) -> Subscription<Event> {
+    Subscription::run_with((paths, options), |(p, o)| load_worker(p, *o))
+}
+
+// See load_worker
+#[allow(clippy::ptr_arg)]
+fn follow_worker(dir: &PathBuf, options: LoadOptions) -> Pin<Box<dyn Stream<Item = Event> + Send>> {
+    let dir = dir.clone();
+    Box::pin(iced::stream::channel(16, async move |mut sender| {
+        if options.mmap {
+            log::warn!("Memory-mapping is not supported while following a directory, ignoring");
+        }
+        let mut follower = Follower::new(dir, options);
+        // Start of the last spectrum that was sent. Only the new spectra are sent, so the
+        // receiver can append them without copying what it has.
+        let mut end: Option<DateTime<Utc>> = None;
+        let mut interval = tokio::time::interval(FOLLOW_POLL_INTERVAL);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+        loop {
+            interval.tick().await;
+            let mut spectra = match follower.poll().await {
+                Ok(spectra) if spectra.is_empty() => continue,
+                Ok(spectra) => spectra,
+                Err(e) => {
+                    if end.is_none() {
+                        sender.send(Event::Done(Err(format!("{e:?}")))).await.ok();
+                    } else {
+                        log::error!("Stopped following {}: {e:?}", follower.dir().display());
+                    }
+                    break;
+                }
+            };
+            if let Some(end) = end {
+                let total = spectra.len();
+                spectra.retain(|spec| spec.time > end);
+                if spectra.len() < total {
+                    log::warn!(
+                        "Skipping {} spectra that don't start after the end of the spectrogram",
+                        total - spectra.len()
+                    );
+                }
+                if spectra.is_empty() {
+                    continue;
+                }
+            }
+            let params = follower
+                .params()
+                .cloned()
+                .expect("Follower returned spectra without parameters");
+            let first = end.is_none();
+            let result = tokio::task::spawn_blocking(move || {
+                Spectrogram::from_raw(spectra, params).map(|mut spec| {
+                    if first {
+                        spec.build_pyramid();
+                    }
+                    spec
+                })
+            })
+            .await
+            .map_err(anyhow::Error::from)
+            .flatten();
+
+            match result {
+                Ok(spec) => {
+                    end = spec.timestamps.last().copied();
+                    let files = follower.files();
+                    let event = if first {
+                        Event::Done(Ok((files, spec)))
+                    } else {
+                        Event::Extended((files, spec))
+                    };
+                    sender.send(event).await.ok();
+                }
+                Err(e) if first => {
+                    sender.send(Event::Done(Err(format!("{e:?}")))).await.ok();
+                    break;
+                }
+                Err(e) => {
+                    log::error!("Stopped following {}: {e:?}", follower.dir().display());
+                    break;
+                }
+            }
+        }
+        std::future::pending::<()>().await;
+    }))
+}
+
+/// Watches `dir` for strf `.bin` files that are being written, e.g. by rffft running on a live
+/// capture. The first batch of spectra is reported as [`Event::Done`], later ones as
+/// [`Event::Extended`].
+pub fn follow_subscription(dir: PathBuf, options: LoadOptions) -> Subscription<Event> {
+    Subscription::run_with((dir, options), |(d, o)| follow_worker(d, *o))
 }
@@ -7,7 +7,9 @@ mod pass_png;
 mod widgets;
 mod windows;
 
+use chrono::{DateTime, Utc};
 use clap::{ArgGroup, Args, Parser, Subcommand};
+use rstrf::{spectrogram::Overlap, util::parse_utc};
 use std::path::PathBuf;
 
 use crate::app::AppModel;
@@ -23,6 +25,34 @@ pub struct CliArgs {
     /// Frequency range to load in Hz: MIN MAX (channels outside this range are skipped)
     #[arg(long, value_name = "FREQ", num_args = 2, global = true)]
     pub freq_range: Option<Vec<f64>>,
+    /// Time range to load in UTC: START END (spectra outside this range are skipped)
+    #[arg(
+        long,
+        value_names = ["START", "END"],
+        num_args = 2,
+        value_parser = parse_utc,
+        global = true
+    )]
+    pub time_range: Option<Vec<DateTime<Utc>>>,
+    /// Number of frequency channels when channelizing SigMF recordings
+    #[arg(long, value_name = "NCHAN", default_value_t = 4096, global = true)]
+    pub nchan: usize,
+    /// Integration time per spectrum in seconds when channelizing SigMF recordings
+    #[arg(long, value_name = "TINT", default_value_t = 1.0, global = true)]
+    pub tint: f32,
+    /// Memory-map .bin files instead of reading them into RAM (for very large recordings)
+    #[arg(long, global = true)]
+    pub mmap: bool,
+    /// Stitch files of neighbouring frequency bands side by side. Channels covered by several
+    /// bands are averaged or blanked.
+    #[arg(
+        long,
+        value_name = "OVERLAP",
+        num_args = 0..=1,
+        default_missing_value = "average",
+        global = true
+    )]
+    pub stitch: Option<Overlap>,
     /// Window width in pixels
     #[arg(short = 'W', long, default_value_t = 800, global = true)]
     pub width: u32,
@@ -41,9 +71,12 @@ pub enum Command {
 
 #[derive(Args, Debug, Clone)]
 pub struct PlotArgs {
-    /// Spectrogram files to display
-    #[arg(value_name = "SPECTROGRAMS", required = true)]
+    /// Spectrogram files to display (.bin or SigMF)
+    #[arg(value_name = "SPECTROGRAMS", required_unless_present = "follow")]
     pub spectrograms: Vec<PathBuf>,
+    /// Watch a directory that rffft is writing .bin files to and add new spectra as they appear
+    #[arg(long, value_name = "DIR", conflicts_with = "spectrograms")]
+    pub follow: Option<PathBuf>,
     /// TLE catalog file
     #[arg(short = 'c', long)]
     pub catalog: Option<PathBuf>,
@@ -77,7 +110,7 @@ pub struct PlotArgs {
 #[derive(Args, Debug, Clone)]
 #[command(group(ArgGroup::new("freq_source").required(true).args(["freq", "freqs"])))]
 pub struct PassPngArgs {
-    /// Spectrogram files to display
+    /// Spectrogram files to display (.bin or SigMF)
     #[arg(value_name = "SPECTROGRAMS", required = true)]
     pub spectrograms: Vec<PathBuf>,
     /// TLE catalog file
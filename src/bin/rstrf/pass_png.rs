@@ -134,9 +134,9 @@ impl PassPngMode {
                     predict_task,
                     Task::done(app::Message::WindowMessage(
                         self.window_id,
-                        windows::Message::RFPlot(windows::rfplot::Message::Control(
+                        windows::Message::RFPlot(Box::new(windows::rfplot::Message::Control(
                             windows::rfplot::control::Message::SetControlsVisible(false),
-                        )),
+                        ))),
                     )),
                 ])
             }
@@ -224,9 +224,9 @@ impl PassPngMode {
                 self.state = State::WaitingForCapture(path.clone(), queue.clone());
                 Task::done(app::Message::WindowMessage(
                     id,
-                    windows::Message::RFPlot(windows::rfplot::Message::CaptureScreenshot(Some(
-                        path.clone(),
-                    ))),
+                    windows::Message::RFPlot(Box::new(
+                        windows::rfplot::Message::CaptureScreenshot(Some(path.clone())),
+                    )),
                 ))
             }
             Message::ScreenshotSaved(saved_path) => {
@@ -255,7 +255,7 @@ impl PassPngMode {
         self.state = State::WaitingForView(path, queue, 1);
         Task::done(app::Message::WindowMessage(
             id,
-            windows::Message::RFPlot(windows::rfplot::Message::SetView(view)),
+            windows::Message::RFPlot(Box::new(windows::rfplot::Message::SetView(view))),
         ))
     }
 }
@@ -42,6 +42,7 @@ pub enum Icon {
     Delete,
     Save,
     Screenshot,
+    Info,
     Colormap(Colormap),
 }
 
@@ -98,6 +99,9 @@ impl From<Icon> for svg::Handle {
                     "../../../../resources/icons/material-symbols--screenshot-monitor-outline-rounded.svg"
                 )
             }
+            Icon::Info => {
+                include_bytes!("../../../../resources/icons/material-symbols--info-outline.svg")
+            }
             Icon::Colormap(colormap) => match colormap {
                 Colormap::Magma => include_bytes!("../../../../resources/icons/cmap-magma.svg"),
                 Colormap::Inferno => include_bytes!("../../../../resources/icons/cmap-inferno.svg"),
@@ -3,9 +3,10 @@ use rstrf::menu::MenuItem;
 
 use crate::{
     app::{self, AppEvent, AppShared},
-    windows::{rfplot::RFPlot, sat_manager::SatManager},
+    windows::{orbit_fit::OrbitFitter, rfplot::RFPlot, sat_manager::SatManager},
 };
 
+pub mod orbit_fit;
 pub mod preferences;
 pub mod rfplot;
 pub mod sat_manager;
@@ -13,15 +14,16 @@ pub mod sat_manager;
 #[derive(Debug, Clone)]
 pub enum Message {
     ToApp(Box<app::Message>),
-    RFPlot(rfplot::Message),
+    RFPlot(Box<rfplot::Message>),
     SatManager(sat_manager::Message),
     Preferences(preferences::Message),
+    OrbitFit(orbit_fit::Message),
 }
 
 impl From<WindowOut<rfplot::Message>> for Message {
     fn from(out: WindowOut<rfplot::Message>) -> Self {
         match out {
-            WindowOut::Msg(msg) => Message::RFPlot(msg),
+            WindowOut::Msg(msg) => Message::RFPlot(Box::new(msg)),
             WindowOut::Effect(effect) => match effect {
                 WindowEffect::ToApp(app_msg) => Message::ToApp(Box::new(app_msg)),
             },
@@ -51,6 +53,17 @@ impl From<WindowOut<preferences::Message>> for Message {
     }
 }
 
+impl From<WindowOut<orbit_fit::Message>> for Message {
+    fn from(out: WindowOut<orbit_fit::Message>) -> Self {
+        match out {
+            WindowOut::Msg(msg) => Message::OrbitFit(msg),
+            WindowOut::Effect(effect) => match effect {
+                WindowEffect::ToApp(app_msg) => Message::ToApp(Box::new(app_msg)),
+            },
+        }
+    }
+}
+
 /// A cross-cutting effect that escapes a window's own message type and must be handled by the parent.
 #[derive(Debug, Clone)]
 pub enum WindowEffect {
@@ -85,6 +98,12 @@ impl From<preferences::Message> for WindowOut<preferences::Message> {
     }
 }
 
+impl From<orbit_fit::Message> for WindowOut<orbit_fit::Message> {
+    fn from(message: orbit_fit::Message) -> Self {
+        WindowOut::Msg(message)
+    }
+}
+
 pub trait Window<M: Clone> {
     fn title(&self) -> String;
     fn menu_bar(&self) -> Vec<MenuItem<WindowOut<M>>> {
@@ -104,6 +123,7 @@ pub enum AnyWindow {
     SatManager(Box<SatManager>),
     RFPlot(Box<RFPlot>),
     Preferences(Box<preferences::Window>),
+    OrbitFit(Box<OrbitFitter>),
 }
 
 impl AnyWindow {
@@ -112,6 +132,7 @@ impl AnyWindow {
             AnyWindow::SatManager(w) => w.title(),
             AnyWindow::RFPlot(w) => w.title(),
             AnyWindow::Preferences(w) => w.title(),
+            AnyWindow::OrbitFit(w) => w.title(),
         }
     }
 
@@ -132,6 +153,11 @@ impl AnyWindow {
                 .into_iter()
                 .map(|i| i.map_msg(Message::from))
                 .collect(),
+            AnyWindow::OrbitFit(w) => w
+                .menu_bar()
+                .into_iter()
+                .map(|i| i.map_msg(Message::from))
+                .collect(),
         }
     }
 
@@ -140,6 +166,7 @@ impl AnyWindow {
             AnyWindow::SatManager(w) => w.view(app).map(Message::from),
             AnyWindow::RFPlot(w) => w.view(app).map(Message::from),
             AnyWindow::Preferences(w) => w.view(app).map(Message::from),
+            AnyWindow::OrbitFit(w) => w.view(app).map(Message::from),
         }
     }
 
@@ -148,6 +175,7 @@ impl AnyWindow {
             AnyWindow::RFPlot(w) => w.init(id, app).map(Message::from),
             AnyWindow::SatManager(w) => w.init(id, app).map(Message::from),
             AnyWindow::Preferences(w) => w.init(id, app).map(Message::from),
+            AnyWindow::OrbitFit(w) => w.init(id, app).map(Message::from),
         }
     }
 
@@ -157,11 +185,14 @@ impl AnyWindow {
                 w.update(id, msg, app).map(Message::from)
             }
             (AnyWindow::RFPlot(w), Message::RFPlot(msg)) => {
-                w.update(id, msg, app).map(Message::from)
+                w.update(id, *msg, app).map(Message::from)
             }
             (AnyWindow::Preferences(w), Message::Preferences(msg)) => {
                 w.update(id, msg, app).map(Message::from)
             }
+            (AnyWindow::OrbitFit(w), Message::OrbitFit(msg)) => {
+                w.update(id, msg, app).map(Message::from)
+            }
             _ => Task::none(),
         }
     }
@@ -171,6 +202,7 @@ impl AnyWindow {
             AnyWindow::RFPlot(w) => w.subscription(app).map(Message::from),
             AnyWindow::SatManager(w) => w.subscription(app).map(Message::from),
             AnyWindow::Preferences(w) => w.subscription(app).map(Message::from),
+            AnyWindow::OrbitFit(w) => w.subscription(app).map(Message::from),
         }
     }
 
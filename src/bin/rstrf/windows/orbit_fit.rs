@@ -0,0 +1,445 @@
+//! This module contains the orbit fit window, which fits the elements of a satellite and its
+//! transmitter frequency to Doppler measurements, like STRF's `rffit` (see
+//! [`rstrf::orbit::fit_orbit`]).
+
+use std::{collections::HashMap, path::PathBuf};
+
+use iced::{
+    Element, Font, Length, Task,
+    alignment::Vertical,
+    widget::{Row, button, checkbox, column, container, pick_list, row, text},
+    window,
+};
+use plotters::prelude::*;
+use plotters_iced2::{Chart, ChartWidget};
+use rfd::AsyncFileDialog;
+use rstrf::orbit::{self, FitParameter, Measurement, OrbitFit, Satellite, Site};
+use strum::VariantArray;
+
+use crate::{
+    app::{self, AppShared},
+    widgets::form::number_input,
+    windows::{Window, WindowEffect, WindowOut},
+};
+
+#[derive(Debug, Clone)]
+pub enum Message {
+    AddMeasurements,
+    MeasurementsLoaded(Vec<PathBuf>, Result<Vec<Measurement>, String>),
+    ClearMeasurements,
+    SelectSatellite(SatelliteChoice),
+    UpdateFrequency(f64),
+    ToggleParameter(FitParameter, bool),
+    Fit,
+    /// RMS of the residuals before the fit, and the fit
+    Fitted(Result<(f64, Box<OrbitFit>), String>),
+    /// Replace the satellite's elements with the fitted ones
+    Apply,
+    SaveTle,
+}
+
+/// A satellite in the satellite pick list.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SatelliteChoice {
+    norad_id: u64,
+    name: Option<String>,
+}
+
+impl std::fmt::Display for SatelliteChoice {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.name {
+            Some(name) => write!(f, "{:06} {}", self.norad_id, name),
+            None => write!(f, "{:06}", self.norad_id),
+        }
+    }
+}
+
+pub struct OrbitFitter {
+    files: Vec<PathBuf>,
+    measurements: Vec<Measurement>,
+    satellite: Option<SatelliteChoice>,
+    /// Initial transmitter frequency in Hz
+    frequency: f64,
+    parameters: Vec<FitParameter>,
+    /// RMS of the residuals before the fit, and the fit
+    result: Option<(f64, ResidualPlot)>,
+    running: bool,
+}
+
+impl OrbitFitter {
+    pub fn new() -> Self {
+        Self {
+            files: Vec::new(),
+            measurements: Vec::new(),
+            satellite: None,
+            frequency: 0.0,
+            parameters: FitParameter::DEFAULT.to_vec(),
+            result: None,
+            running: false,
+        }
+    }
+
+    fn selected<'a>(&self, app: &'a AppShared) -> Option<(usize, &'a (Satellite, bool))> {
+        let norad_id = self.satellite.as_ref()?.norad_id;
+        app.satellites
+            .iter()
+            .enumerate()
+            .find(|(_, (sat, _))| sat.norad_id() == norad_id)
+    }
+
+    /// The sites in STRF's sites.txt, plus the site from the preferences as `site_id` if sites.txt
+    /// doesn't have it.
+    async fn load_sites(site_id: Option<i32>, site: Option<Site>) -> HashMap<i32, Site> {
+        let sites = match orbit::strf_sites_path() {
+            Ok(path) => orbit::load_strf_sites(&path).await,
+            Err(err) => Err(err),
+        };
+        let mut sites = sites.unwrap_or_else(|err| {
+            log::warn!("Failed to load STRF sites.txt: {err:?}");
+            HashMap::new()
+        });
+        if let (Some(site_id), Some(site)) = (site_id, site) {
+            sites.entry(site_id).or_insert(site);
+        }
+        sites
+    }
+}
+
+impl Window<Message> for OrbitFitter {
+    fn title(&self) -> String {
+        "Orbit Fit".into()
+    }
+
+    fn view<'a>(&'a self, app: &'a AppShared) -> Element<'a, WindowOut<Message>> {
+        let measurements = row![
+            button("Add measurements")
+                .style(button::primary)
+                .on_press(Message::AddMeasurements.into()),
+            button("Clear").style(button::secondary).on_press_maybe(
+                (!self.files.is_empty()).then(|| Message::ClearMeasurements.into())
+            ),
+            text(format!(
+                "{} measurements from {} files",
+                self.measurements.len(),
+                self.files.len()
+            )),
+        ]
+        .spacing(8)
+        .align_y(Vertical::Center);
+
+        let choices: Vec<SatelliteChoice> = app
+            .satellites
+            .iter()
+            .map(|(sat, _)| SatelliteChoice {
+                norad_id: sat.norad_id(),
+                name: sat.elements.object_name.clone(),
+            })
+            .collect();
+        let satellite = row![
+            pick_list(choices, self.satellite.clone(), |choice| {
+                Message::SelectSatellite(choice).into()
+            })
+            .placeholder("Satellite")
+            .width(Length::FillPortion(3)),
+            text("Frequency (MHz)"),
+            number_input(
+                "",
+                self.frequency / 1e6,
+                6,
+                Some(|freq: f64| Message::UpdateFrequency(freq * 1e6).into())
+            )
+            .width(Length::FillPortion(1)),
+        ]
+        .spacing(8)
+        .align_y(Vertical::Center);
+
+        let parameters = Row::with_children(FitParameter::VARIANTS.iter().map(|&parameter| {
+            checkbox(self.parameters.contains(&parameter))
+                .label(parameter.to_string())
+                .on_toggle(move |fit| Message::ToggleParameter(parameter, fit).into())
+                .into()
+        }))
+        .spacing(12);
+
+        let can_fit = !self.running
+            && !self.measurements.is_empty()
+            && !self.parameters.is_empty()
+            && self.selected(app).is_some();
+        let fitted = self.result.is_some() && !self.running;
+        let actions = row![
+            button(if self.running { "Fitting..." } else { "Fit" })
+                .style(button::primary)
+                .on_press_maybe(can_fit.then(|| Message::Fit.into())),
+            button("Apply to satellite")
+                .style(button::secondary)
+                .on_press_maybe(fitted.then(|| Message::Apply.into())),
+            button("Save TLE")
+                .style(button::secondary)
+                .on_press_maybe(fitted.then(|| Message::SaveTle.into())),
+        ]
+        .spacing(8);
+
+        let mut content = column![measurements, satellite, parameters, actions]
+            .spacing(8)
+            .padding(8);
+        if let Some((initial_rms, plot)) = &self.result {
+            let fit = &plot.fit;
+            let [line1, line2] = orbit::format_tle(&fit.satellite.elements);
+            content = content
+                .push(text(format!(
+                    "RMS {:.1} Hz -> {:.1} Hz after {} iterations, frequency {:.6} MHz",
+                    initial_rms,
+                    fit.rms,
+                    fit.iterations,
+                    fit.frequency / 1e6
+                )))
+                .push(
+                    container(text(format!("{line1}\n{line2}")).font(Font::MONOSPACE))
+                        .padding(4)
+                        .style(container::bordered_box),
+                )
+                .push(
+                    ChartWidget::new(plot)
+                        .width(Length::Fill)
+                        .height(Length::Fill),
+                );
+        }
+        content.into()
+    }
+
+    fn update(
+        &mut self,
+        _id: window::Id,
+        message: Message,
+        app: &AppShared,
+    ) -> Task<WindowOut<Message>> {
+        match message {
+            Message::AddMeasurements => Task::future(async {
+                let files = AsyncFileDialog::new()
+                    .add_filter("Doppler measurements", &["dat"])
+                    .add_filter("All files", &["*"])
+                    .pick_files()
+                    .await;
+                let paths: Vec<PathBuf> = files
+                    .unwrap_or_default()
+                    .iter()
+                    .map(|f| f.path().to_path_buf())
+                    .collect();
+                let mut measurements = Vec::new();
+                for path in &paths {
+                    match orbit::load_measurements(path).await {
+                        Ok(loaded) => measurements.extend(loaded),
+                        Err(err) => {
+                            let err = format!("Failed to load {path:?}: {err:?}");
+                            return Message::MeasurementsLoaded(paths, Err(err));
+                        }
+                    }
+                }
+                Message::MeasurementsLoaded(paths, Ok(measurements))
+            })
+            .map(WindowOut::Msg),
+            Message::MeasurementsLoaded(paths, Ok(measurements)) => {
+                log::info!(
+                    "Loaded {} measurements from {} files",
+                    measurements.len(),
+                    paths.len()
+                );
+                self.files.extend(paths);
+                self.measurements.extend(measurements);
+                self.measurements.sort_by_key(|m| m.time);
+                self.result = None;
+                Task::none()
+            }
+            Message::MeasurementsLoaded(_, Err(err)) => {
+                log::error!("{err}");
+                Task::none()
+            }
+            Message::ClearMeasurements => {
+                self.files.clear();
+                self.measurements.clear();
+                self.result = None;
+                Task::none()
+            }
+            Message::SelectSatellite(choice) => {
+                self.satellite = Some(choice);
+                if let Some((_, (sat, _))) = self.selected(app)
+                    && let Some(&freq) = sat.transmitters.first()
+                {
+                    self.frequency = freq;
+                }
+                self.result = None;
+                Task::none()
+            }
+            Message::UpdateFrequency(freq) => {
+                self.frequency = freq;
+                Task::none()
+            }
+            Message::ToggleParameter(parameter, fit) => {
+                self.parameters.retain(|&p| p != parameter);
+                if fit {
+                    self.parameters.push(parameter);
+                }
+                Task::none()
+            }
+            Message::Fit => {
+                let Some((_, (satellite, _))) = self.selected(app) else {
+                    return Task::none();
+                };
+                self.running = true;
+                let satellite = satellite.clone();
+                let frequency = self.frequency;
+                let measurements = self.measurements.clone();
+                let parameters = self.parameters.clone();
+                let (site_id, site) = (app.site_id, app.site());
+                Task::future(async move {
+                    let sites = Self::load_sites(site_id, site).await;
+                    let result = tokio::task::spawn_blocking(move || {
+                        let initial =
+                            orbit::residuals(&satellite, frequency, &measurements, &sites)?;
+                        let fit = orbit::fit_orbit(
+                            &satellite,
+                            frequency,
+                            &measurements,
+                            &sites,
+                            &parameters,
+                        )?;
+                        anyhow::Ok((initial, fit))
+                    })
+                    .await
+                    .map_err(anyhow::Error::from)
+                    .and_then(|result| result);
+                    match result {
+                        Ok((initial, fit)) => {
+                            let rms = (initial.iter().map(|r| r * r).sum::<f64>()
+                                / initial.len() as f64)
+                                .sqrt();
+                            log::info!(
+                                "Fit RMS {:.1} Hz -> {:.1} Hz after {} iterations",
+                                rms,
+                                fit.rms,
+                                fit.iterations
+                            );
+                            Message::Fitted(Ok((rms, Box::new(fit))))
+                        }
+                        Err(err) => Message::Fitted(Err(format!("{err:?}"))),
+                    }
+                })
+                .map(WindowOut::Msg)
+            }
+            Message::Fitted(result) => {
+                self.running = false;
+                match result {
+                    Ok((initial_rms, fit)) => {
+                        self.result = Some((
+                            initial_rms,
+                            ResidualPlot {
+                                times: self.measurements.iter().map(|m| m.time).collect(),
+                                fit: *fit,
+                            },
+                        ));
+                    }
+                    Err(err) => log::error!("Failed to fit orbit: {err}"),
+                }
+                Task::none()
+            }
+            Message::Apply => {
+                let (Some((_, plot)), Some((idx, (satellite, active)))) =
+                    (&self.result, self.selected(app))
+                else {
+                    return Task::none();
+                };
+                let mut transmitters = satellite.transmitters.clone();
+                if let Some(tx) = transmitters.iter_mut().find(|f| **f == self.frequency) {
+                    *tx = plot.fit.frequency;
+                }
+                let satellite = Satellite {
+                    transmitters,
+                    ..plot.fit.satellite.clone()
+                };
+                Task::done(WindowOut::Effect(WindowEffect::ToApp(
+                    app::Message::SatelliteChanged(idx, Box::new((satellite, *active))),
+                )))
+            }
+            Message::SaveTle => {
+                let Some((_, plot)) = &self.result else {
+                    return Task::none();
+                };
+                let tle = orbit::format_tle_with_name(&plot.fit.satellite);
+                let file_name = format!("{:06}.tle", plot.fit.satellite.norad_id());
+                Task::future(async move {
+                    let Some(file) = AsyncFileDialog::new()
+                        .add_filter("TLEs", &["tle", "txt"])
+                        .add_filter("All files", &["*"])
+                        .set_file_name(file_name)
+                        .save_file()
+                        .await
+                    else {
+                        return;
+                    };
+                    match tokio::fs::write(file.path(), tle).await {
+                        Ok(()) => log::info!("Saved TLE to {:?}", file.path()),
+                        Err(err) => log::error!("Failed to save TLE to {:?}: {err}", file.path()),
+                    }
+                })
+                .discard()
+            }
+        }
+    }
+}
+
+/// Plot of the residuals of a fit over time
+struct ResidualPlot {
+    times: Vec<chrono::DateTime<chrono::Utc>>,
+    fit: OrbitFit,
+}
+
+impl Chart<WindowOut<Message>> for ResidualPlot {
+    type State = ();
+
+    fn build_chart<DB: DrawingBackend>(&self, _state: &Self::State, mut chart: ChartBuilder<DB>) {
+        let Some(&start) = self.times.first() else {
+            return;
+        };
+        let points: Vec<(f64, f64)> = self
+            .times
+            .iter()
+            .zip(&self.fit.residuals)
+            .map(|(t, &r)| ((*t - start).as_seconds_f64() / 60.0, r))
+            .collect();
+        let x_max = points.last().map_or(1.0, |&(t, _)| t.max(1.0));
+        let y_max = points.iter().fold(1.0f64, |max, &(_, r)| max.max(r.abs())) * 1.1;
+        let chart = chart
+            .x_label_area_size(30)
+            .y_label_area_size(60)
+            .margin(8)
+            .build_cartesian_2d(0.0..x_max, -y_max..y_max);
+        let mut chart = match chart {
+            Ok(chart) => chart,
+            Err(e) => {
+                log::error!("Could not build residual chart: {:?}", e);
+                return;
+            }
+        };
+        let mesh = chart
+            .configure_mesh()
+            .x_desc(format!(
+                "Minutes since {}",
+                start.format("%Y-%m-%d %H:%M:%S")
+            ))
+            .y_desc("Residual (Hz)")
+            .max_light_lines(0)
+            .axis_style(WHITE)
+            .label_style(&WHITE)
+            .bold_line_style(WHITE.mix(0.4))
+            .draw();
+        if let Err(e) = mesh {
+            log::error!("Could not draw residual axes: {:?}", e);
+        }
+        let series = points
+            .into_iter()
+            .map(|point| Circle::new(point, 2, CYAN.filled()));
+        if let Err(e) = chart.draw_series(series) {
+            log::error!("Could not draw residuals: {:?}", e);
+        }
+    }
+}
@@ -0,0 +1,223 @@
+//! This module contains the auto-track panel for RFPlot, which follows a signal along the predicted
+//! Doppler curve of a satellite (see [`signal::follow_signal`]) instead of manual track points.
+
+use iced::{
+    Element, Length,
+    alignment::Vertical,
+    widget::{self, button, pick_list, slider, text},
+};
+use rstrf::{
+    coord::data_absolute,
+    orbit::Predictions,
+    signal::{self, FollowOptions, SignalDetectionMethod},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::windows::rfplot::{self, control::Controls};
+
+const OFFSET_MAX_HZ: f32 = 50e3;
+const MAX_DRIFT_MAX: f32 = 1000.0;
+const LOCK_TIMEOUT_MAX_S: f32 = 300.0;
+
+#[derive(Debug, Clone)]
+pub enum Message {
+    SelectTarget(Target),
+    UpdateOffset(f32),
+    UpdateMaxDrift(f32),
+    UpdateLockTimeout(f32),
+    /// Follow the signal of the selected target, handled by the overlay
+    Track,
+}
+
+/// A transmitter of a satellite whose predicted frequency curve can be followed.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Target {
+    pub norad_id: u64,
+    /// Index into the satellite's transmitters
+    pub transmitter: usize,
+    /// Predicted frequency in the middle of the first pass, to tell the transmitters apart
+    freq: f64,
+}
+
+impl PartialEq for Target {
+    fn eq(&self, other: &Self) -> bool {
+        self.norad_id == other.norad_id && self.transmitter == other.transmitter
+    }
+}
+
+impl Target {
+    /// The predicted frequency curves of this transmitter, one per pass, relative to
+    /// `center_freq`. `None` if it has no passes.
+    pub fn passes(
+        &self,
+        predictions: &Predictions,
+        center_freq: f32,
+    ) -> Option<Vec<Vec<data_absolute::Point>>> {
+        let passes: Vec<_> = predictions
+            .for_id(self.norad_id)
+            .iter()
+            .filter_map(|pass| {
+                pass.curve(
+                    predictions.times.view(),
+                    self.transmitter,
+                    center_freq as f64,
+                )
+            })
+            .collect();
+        (!passes.is_empty()).then_some(passes)
+    }
+}
+
+impl std::fmt::Display for Target {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:06} at {:.3} MHz", self.norad_id, self.freq / 1e6)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct AutoTrack {
+    target: Option<Target>,
+    /// Offset of the signal from the predicted frequency in Hz
+    offset: f32,
+    /// See [`FollowOptions::max_drift`]
+    max_drift: f32,
+    /// See [`FollowOptions::lock_timeout`]
+    lock_timeout: f32,
+}
+
+impl Default for AutoTrack {
+    fn default() -> Self {
+        Self {
+            target: None,
+            offset: 0.0,
+            max_drift: 50.0,
+            lock_timeout: 30.0,
+        }
+    }
+}
+
+impl AutoTrack {
+    pub fn update(&mut self, message: Message) {
+        match message {
+            Message::SelectTarget(target) => self.target = Some(target),
+            Message::UpdateOffset(offset) => self.offset = offset,
+            Message::UpdateMaxDrift(max_drift) => self.max_drift = max_drift,
+            Message::UpdateLockTimeout(timeout) => self.lock_timeout = timeout,
+            // Handled by the overlay, see `passes`
+            Message::Track => (),
+        }
+    }
+
+    pub fn offset(&self) -> f32 {
+        self.offset
+    }
+
+    pub fn options(&self, method: SignalDetectionMethod, search_bw: f32) -> FollowOptions {
+        FollowOptions {
+            method,
+            search_bw,
+            max_drift: self.max_drift,
+            lock_timeout: self.lock_timeout,
+        }
+    }
+
+    /// The predicted frequency curves of the selected target, see [`Target::passes`]. `None` if
+    /// no target is selected or it has no passes.
+    pub fn passes(
+        &self,
+        predictions: &Predictions,
+        center_freq: f32,
+    ) -> Option<Vec<Vec<data_absolute::Point>>> {
+        self.target?.passes(predictions, center_freq)
+    }
+
+    /// The transmitters of all satellites that pass during the spectrogram.
+    pub fn targets(predictions: &Predictions) -> Vec<Target> {
+        let mut targets: Vec<Target> = predictions
+            .iter_satellites()
+            .filter_map(|(norad_id, passes)| Some((norad_id, passes.first()?)))
+            .flat_map(|(norad_id, pass)| {
+                pass.frequencies
+                    .iter()
+                    .enumerate()
+                    .map(move |(transmitter, freqs)| Target {
+                        norad_id,
+                        transmitter,
+                        freq: freqs[freqs.len() / 2],
+                    })
+            })
+            .collect();
+        targets.sort_by_key(|target| (target.norad_id, target.transmitter));
+        targets
+    }
+
+    pub fn view(&self, targets: Vec<Target>) -> Element<'_, rfplot::Message> {
+        widget::column![
+            widget::row![
+                text("Auto-track").width(Length::FillPortion(3)),
+                pick_list(targets, self.target, |target| {
+                    Message::SelectTarget(target).into()
+                })
+                .placeholder("Satellite")
+                .width(Length::FillPortion(7)),
+                button("Track")
+                    .style(button::primary)
+                    .on_press_maybe(self.target.map(|_| Message::Track.into())),
+            ]
+            .spacing(4)
+            .align_y(Vertical::Center),
+            widget::grid![
+                Controls::control(
+                    "Offset",
+                    slider(-OFFSET_MAX_HZ..=OFFSET_MAX_HZ, self.offset, |offset| {
+                        Message::UpdateOffset(offset).into()
+                    })
+                    .step(100.0)
+                    .width(Length::Fill),
+                    format!("{:.1} kHz", self.offset / 1e3),
+                ),
+                Controls::control(
+                    "Max Drift",
+                    slider(1.0..=MAX_DRIFT_MAX, self.max_drift, |drift| {
+                        Message::UpdateMaxDrift(drift).into()
+                    })
+                    .width(Length::Fill),
+                    format!("{:.0} Hz/s", self.max_drift),
+                ),
+                Controls::control(
+                    "Lock Timeout",
+                    slider(1.0..=LOCK_TIMEOUT_MAX_S, self.lock_timeout, |timeout| {
+                        Message::UpdateLockTimeout(timeout).into()
+                    })
+                    .width(Length::Fill),
+                    format!("{:.0} s", self.lock_timeout),
+                ),
+            ]
+            .columns(2)
+            .spacing(8)
+            .height(Length::Shrink),
+        ]
+        .spacing(8)
+        .into()
+    }
+}
+
+impl From<Message> for rfplot::Message {
+    fn from(message: Message) -> Self {
+        rfplot::overlay::Message::AutoTrack(message).into()
+    }
+}
+
+/// Follows the signal along each pass, see [`signal::follow_signal`].
+pub fn follow_passes(
+    spectrogram: &rstrf::spectrogram::Spectrogram,
+    passes: &[Vec<data_absolute::Point>],
+    offset: f32,
+    options: FollowOptions,
+) -> anyhow::Result<Vec<signal::Signal>> {
+    let mut signals = Vec::new();
+    for pass in passes {
+        signals.extend(signal::follow_signal(spectrogram, pass, offset, options)?);
+    }
+    Ok(signals)
+}
@@ -0,0 +1,168 @@
+//! This module contains the blind search panel for RFPlot, which searches the whole spectrogram for
+//! signal traces (see [`signal::blind_search`]) and lists them so the view can jump to each one.
+
+use iced::{
+    Element, Length,
+    alignment::Vertical,
+    widget::{self, button, scrollable, slider, text},
+};
+use rstrf::{
+    coord::{DataAbsoluteToDataNormalized, data_absolute},
+    signal::{self, BlindSearchOptions, Candidate},
+    spectrogram::Spectrogram,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::windows::rfplot::{
+    self,
+    control::{self, Controls},
+};
+
+const THRESHOLD_MIN: f32 = 3.0;
+const THRESHOLD_MAX: f32 = 20.0;
+/// Height of the candidate list
+const LIST_HEIGHT: f32 = 160.0;
+
+#[derive(Debug, Clone)]
+pub enum Message {
+    UpdateThreshold(f32),
+    /// Search the spectrogram, handled by the overlay
+    Search,
+    Found(Vec<Candidate>),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct BlindSearch {
+    /// See [`BlindSearchOptions::threshold`]
+    threshold: f32,
+    #[serde(skip)]
+    candidates: Vec<Candidate>,
+    #[serde(skip)]
+    running: bool,
+}
+
+impl Default for BlindSearch {
+    fn default() -> Self {
+        Self {
+            threshold: BlindSearchOptions::default().threshold,
+            candidates: Vec::new(),
+            running: false,
+        }
+    }
+}
+
+impl BlindSearch {
+    pub fn update(&mut self, message: Message) {
+        match message {
+            Message::UpdateThreshold(threshold) => self.threshold = threshold,
+            // Handled by the overlay, see `start`
+            Message::Search => (),
+            Message::Found(candidates) => {
+                log::info!("Found {} candidate signals", candidates.len());
+                self.candidates = candidates;
+                self.running = false;
+            }
+        }
+    }
+
+    /// Marks the search as running and returns its options.
+    pub fn start(&mut self) -> BlindSearchOptions {
+        self.running = true;
+        BlindSearchOptions {
+            threshold: self.threshold,
+            ..Default::default()
+        }
+    }
+
+    pub fn candidates(&self) -> &[Candidate] {
+        &self.candidates
+    }
+
+    pub fn clear(&mut self) {
+        self.candidates.clear();
+    }
+
+    pub fn view<'a>(&'a self, spectrogram: &Spectrogram) -> Element<'a, rfplot::Message> {
+        let search = button(if self.running {
+            "Searching..."
+        } else {
+            "Search"
+        })
+        .style(button::primary)
+        .on_press_maybe((!self.running).then(|| Message::Search.into()));
+        let header = widget::row![
+            Controls::control(
+                "Blind Search",
+                slider(THRESHOLD_MIN..=THRESHOLD_MAX, self.threshold, |t| {
+                    Message::UpdateThreshold(t).into()
+                })
+                .step(0.5)
+                .width(Length::Fill),
+                format!("{:.1}", self.threshold),
+            )
+            .width(Length::Fill),
+            search,
+        ]
+        .spacing(4)
+        .align_y(Vertical::Center);
+        if self.candidates.is_empty() {
+            return header.into();
+        }
+
+        let da_to_dn = DataAbsoluteToDataNormalized::new(&spectrogram.bounds());
+        let entries = self.candidates.iter().map(|candidate| {
+            let start = spectrogram.start_time()
+                + chrono::Duration::milliseconds((candidate.start * 1e3) as i64);
+            let freq = spectrogram.freq + (candidate.freq_min + candidate.freq_max) / 2.0;
+            let label = format!(
+                "{}  {:.4} MHz  {:.0} s  {:+.1} Hz/s  {:.0} sigma",
+                start.format("%H:%M:%S"),
+                freq / 1e6,
+                candidate.duration(),
+                candidate.drift,
+                candidate.sigma,
+            );
+            button(text(label).size(12))
+                .style(button::text)
+                .width(Length::Fill)
+                .on_press(
+                    control::Message::ZoomToRect(view_of(candidate, spectrogram) * da_to_dn).into(),
+                )
+                .into()
+        });
+        widget::column![
+            header,
+            scrollable(widget::column(entries)).height(Length::Fixed(LIST_HEIGHT)),
+        ]
+        .spacing(4)
+        .into()
+    }
+}
+
+/// The part of the spectrogram to show for a candidate: its bounds with a margin of half their
+/// size (and at least 10 spectra/channels) on each side.
+fn view_of(candidate: &Candidate, spectrogram: &Spectrogram) -> data_absolute::Rectangle {
+    let bounds = spectrogram.bounds();
+    let margin_x =
+        (candidate.duration() / 2.0).max(10.0 * bounds.0.width / spectrogram.nslices as f32);
+    let margin_y =
+        (candidate.freq_span() / 2.0).max(10.0 * spectrogram.bw / spectrogram.nchan as f32);
+    data_absolute::Rectangle::new(
+        data_absolute::Point::new(candidate.start - margin_x, candidate.freq_min - margin_y),
+        data_absolute::Size::new(
+            candidate.duration() + 2.0 * margin_x,
+            candidate.freq_span() + 2.0 * margin_y,
+        ),
+    )
+}
+
+impl From<Message> for rfplot::Message {
+    fn from(message: Message) -> Self {
+        rfplot::overlay::Message::BlindSearch(message).into()
+    }
+}
+
+/// Runs the search, see [`signal::blind_search`].
+pub fn search(spectrogram: &Spectrogram, options: BlindSearchOptions) -> Message {
+    Message::Found(signal::blind_search(spectrogram, &options))
+}
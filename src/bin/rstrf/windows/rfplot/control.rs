@@ -2,7 +2,7 @@ use glam::Vec2;
 use iced::{
     Element, Length, Task,
     alignment::Vertical,
-    widget::{self, Row, slider, text},
+    widget::{self, Row, button, pick_list, slider, text},
 };
 use rstrf::{
     colormap::Colormap,
@@ -10,10 +10,11 @@ use rstrf::{
         DataAbsoluteToDataNormalized, DataNormalizedToDataAbsolute, PlotAreaToDataNormalized,
         data_absolute, data_normalized, plot_area,
     },
-    spectrogram::Spectrogram,
+    signal::{SignalDetectionKind, SignalDetectionMethod, ToneOptions, TrackInterpolation},
+    spectrogram::{Decimation, Spectrogram},
 };
 use serde::{Deserialize, Serialize};
-use strum::IntoEnumIterator;
+use strum::{IntoEnumIterator, VariantArray};
 
 use crate::{
     widgets::{Icon, ToolbarButton, toolbar},
@@ -31,6 +32,13 @@ const ZOOM_WHEEL_SCALE: f32 = 0.2;
 const SIGMA_MIN: f32 = 0.1;
 const SIGMA_MAX: f32 = 20.0;
 
+const OUTLIER_SIGMA_MIN: f32 = 1.0;
+const OUTLIER_SIGMA_MAX: f32 = 10.0;
+
+const TONES_MAX: u8 = 4;
+const TONE_SEPARATION_MIN: f32 = 100.0;
+const TONE_SEPARATION_MAX: f32 = 20e3;
+
 const TRACK_BW_MIN: f32 = 1e3;
 const TRACK_BW_MAX: f32 = 100e3;
 
@@ -46,10 +54,34 @@ pub struct Controls {
     power_range: (f32, f32),
     /// Threshold for signal detection
     signal_sigma: f32,
+    /// How the frequency of detected signals is estimated
+    #[serde(default)]
+    signal_method: SignalDetectionKind,
+    /// Threshold for rejecting found signals as outliers
+    #[serde(default = "default_outlier_sigma")]
+    outlier_sigma: f32,
+    /// Number of tones to look for in each spectrum
+    #[serde(default = "default_tones")]
+    tones: u8,
+    /// Minimum distance between the tones in Hz
+    #[serde(default = "default_tone_separation")]
+    tone_separation: f32,
+    /// Threshold for all but the strongest tone
+    #[serde(default = "default_signal_sigma")]
+    tone_sigma: f32,
     /// Bandwidth around track points
     track_bw: f32,
+    /// How the track is interpolated between track points
+    #[serde(default)]
+    track_interpolation: TrackInterpolation,
     show_controls: bool,
+    /// Whether the spectrogram metadata panel is shown
+    #[serde(default)]
+    show_info: bool,
     colormap: Colormap,
+    /// How zoomed-out views combine cells
+    #[serde(default)]
+    decimation: Decimation,
 }
 
 #[derive(Debug, Clone)]
@@ -65,9 +97,17 @@ pub enum Message {
     UpdateMinPower(f32),
     UpdateMaxPower(f32),
     UpdateSignalSigma(f32),
+    UpdateSignalMethod(SignalDetectionKind),
+    UpdateOutlierSigma(f32),
+    UpdateTones(u8),
+    UpdateToneSeparation(f32),
+    UpdateToneSigma(f32),
     UpdateTrackBW(f32),
+    UpdateTrackInterpolation(TrackInterpolation),
     SetControlsVisible(bool),
+    SetInfoVisible(bool),
     UpdateColormap(Colormap),
+    UpdateDecimation(Decimation),
 }
 
 impl Controls {
@@ -77,6 +117,38 @@ impl Controls {
         self.set_data_bounds(data.0.width, data.0.height);
     }
 
+    /// Updates the controls after `spec` grew from `old_bounds` (e.g. while following a directory),
+    /// keeping the view on the same data. A view that reaches the end of the spectrogram keeps
+    /// following it, and a fully zoomed-out view stays zoomed out.
+    pub fn extend_spectrogram(
+        &mut self,
+        old_bounds: &data_absolute::Rectangle,
+        spec: &Spectrogram,
+    ) {
+        self.set_power_bounds(spec.power_bounds);
+        self.extend_data_bounds(old_bounds, &spec.bounds());
+    }
+
+    fn extend_data_bounds(
+        &mut self,
+        old_bounds: &data_absolute::Rectangle,
+        new_bounds: &data_absolute::Rectangle,
+    ) {
+        let view = self.bounds();
+        let zoomed_out = view.0.width >= 1.0 - 1e-6;
+        let at_end = view.0.x + view.0.width >= 1.0 - 1e-6;
+        let mut rect = view * DataNormalizedToDataAbsolute::new(old_bounds);
+
+        self.set_data_bounds(new_bounds.0.width, new_bounds.0.height);
+        if zoomed_out {
+            return;
+        }
+        if at_end {
+            rect.0.x = new_bounds.0.x + new_bounds.0.width - rect.0.width;
+        }
+        self.set_view_from_rect_da(&rect, new_bounds);
+    }
+
     fn set_power_bounds(&mut self, bounds: (f32, f32)) {
         self.power_bounds = bounds;
         self.power_range = if self.power_range == (0.0, 0.0) {
@@ -128,19 +200,43 @@ impl Controls {
         self.power_range
     }
 
-    pub fn signal_sigma(&self) -> f32 {
-        self.signal_sigma
+    pub fn signal_method(&self) -> SignalDetectionMethod {
+        self.signal_method.with_sigma(self.signal_sigma)
+    }
+
+    pub fn outlier_sigma(&self) -> f32 {
+        self.outlier_sigma
+    }
+
+    pub fn tones(&self) -> ToneOptions {
+        ToneOptions {
+            count: self.tones as usize,
+            min_separation: self.tone_separation,
+            secondary_sigma: Some(self.tone_sigma),
+        }
     }
 
     pub fn track_bw(&self) -> f32 {
         self.track_bw
     }
 
+    pub fn track_interpolation(&self) -> TrackInterpolation {
+        self.track_interpolation
+    }
+
     pub fn colormap(&self) -> Colormap {
         self.colormap
     }
 
-    fn control<'a>(
+    pub fn show_info(&self) -> bool {
+        self.show_info
+    }
+
+    pub fn decimation(&self) -> Decimation {
+        self.decimation
+    }
+
+    pub(super) fn control<'a>(
         label: &'static str,
         control: impl Into<Element<'a, rfplot::Message>>,
         value: impl Into<String>,
@@ -154,7 +250,11 @@ impl Controls {
         .align_y(Vertical::Center)
     }
 
-    pub fn view(&self, shared: &super::SharedState) -> Element<'_, rfplot::Message> {
+    pub(super) fn view<'a>(
+        &'a self,
+        shared: &'a super::SharedState,
+        overlay: &'a rfplot::overlay::Overlay,
+    ) -> Element<'a, rfplot::Message> {
         let colormaps = Colormap::iter()
             .map(|c| ToolbarButton::LabeledIcon {
                 icon: Icon::Colormap(c),
@@ -171,6 +271,12 @@ impl Controls {
                 msg: Message::SetControlsVisible(!self.show_controls).into(),
                 style: widget::button::primary,
             },
+            ToolbarButton::Icon {
+                icon: Icon::Info,
+                tooltip: "Toggle spectrogram info",
+                msg: Message::SetInfoVisible(!self.show_info).into(),
+                style: widget::button::primary,
+            },
             ToolbarButton::Icon {
                 icon: Icon::ZoomReset,
                 tooltip: "Reset view & clear marks",
@@ -297,6 +403,55 @@ impl Controls {
                         .width(Length::Fill),
                         format!("{:.1}", self.signal_sigma),
                     ),
+                    Self::control(
+                        "Signal Peak",
+                        pick_list(
+                            SignalDetectionKind::VARIANTS,
+                            Some(self.signal_method),
+                            |m| Message::UpdateSignalMethod(m).into(),
+                        )
+                        .width(Length::Fill),
+                        "",
+                    ),
+                    Self::control(
+                        "Outlier Thresh",
+                        slider(
+                            OUTLIER_SIGMA_MIN..=OUTLIER_SIGMA_MAX,
+                            self.outlier_sigma,
+                            |s| Message::UpdateOutlierSigma(s).into(),
+                        )
+                        .step(0.1)
+                        .width(Length::Fill),
+                        format!("{:.1}", self.outlier_sigma),
+                    ),
+                    Self::control(
+                        "Tones",
+                        slider(1..=TONES_MAX, self.tones, |n| {
+                            Message::UpdateTones(n).into()
+                        })
+                        .width(Length::Fill),
+                        format!("{}", self.tones),
+                    ),
+                    Self::control(
+                        "Tone Sep",
+                        slider(
+                            TONE_SEPARATION_MIN..=TONE_SEPARATION_MAX,
+                            self.tone_separation,
+                            |s| Message::UpdateToneSeparation(s).into(),
+                        )
+                        .step(100.0)
+                        .width(Length::Fill),
+                        format!("{:.1} kHz", self.tone_separation / 1000.0),
+                    ),
+                    Self::control(
+                        "Tone Thresh",
+                        slider(SIGMA_MIN..=SIGMA_MAX, self.tone_sigma, |s| {
+                            Message::UpdateToneSigma(s).into()
+                        })
+                        .step(0.1)
+                        .width(Length::Fill),
+                        format!("{:.1}", self.tone_sigma),
+                    ),
                     Self::control(
                         "Track BW",
                         slider(TRACK_BW_MIN..=TRACK_BW_MAX, self.track_bw, |b| {
@@ -306,11 +461,51 @@ impl Controls {
                         .width(Length::Fill),
                         format!("{:.1} kHz", self.track_bw / 1000.0),
                     ),
+                    Self::control(
+                        "Track Interp",
+                        pick_list(
+                            TrackInterpolation::VARIANTS,
+                            Some(self.track_interpolation),
+                            |i| Message::UpdateTrackInterpolation(i).into(),
+                        )
+                        .width(Length::Fill),
+                        "",
+                    ),
+                    Self::control(
+                        "Decimation",
+                        pick_list(Decimation::VARIANTS, Some(self.decimation), |d| {
+                            Message::UpdateDecimation(d).into()
+                        })
+                        .width(Length::Fill),
+                        "",
+                    ),
                 ]
                 .columns(2)
                 .spacing(8)
                 .height(Length::Shrink),
             );
+            result = result.push(
+                widget::row![
+                    text("Outliers").width(Length::FillPortion(3)),
+                    button("Reject")
+                        .style(button::primary)
+                        .on_press(rfplot::overlay::Message::RejectOutliers.into()),
+                    button("Remove")
+                        .style(button::danger)
+                        .on_press(rfplot::overlay::Message::RemoveRejected.into()),
+                    button("Restore")
+                        .style(button::secondary)
+                        .on_press(rfplot::overlay::Message::RestoreRejected.into()),
+                ]
+                .spacing(4)
+                .align_y(Vertical::Center),
+            );
+            result = result.push(shared.processing.view());
+            result = result.push(overlay.auto_track_view());
+            result = result.push(overlay.stacking_view());
+            if let Some(spectrogram) = &shared.spectrogram {
+                result = result.push(overlay.blind_search_view(spectrogram));
+            }
         }
         widget::container(result)
             .padding(8)
@@ -370,11 +565,21 @@ impl Controls {
             Message::UpdateSignalSigma(sigma) => {
                 self.signal_sigma = sigma;
             }
+            Message::UpdateSignalMethod(method) => self.signal_method = method,
+            Message::UpdateOutlierSigma(sigma) => self.outlier_sigma = sigma,
+            Message::UpdateTones(tones) => self.tones = tones,
+            Message::UpdateToneSeparation(separation) => self.tone_separation = separation,
+            Message::UpdateToneSigma(sigma) => self.tone_sigma = sigma,
             Message::UpdateTrackBW(bw) => {
                 self.track_bw = bw;
             }
+            Message::UpdateTrackInterpolation(interpolation) => {
+                self.track_interpolation = interpolation;
+            }
             Message::SetControlsVisible(visible) => self.show_controls = visible,
+            Message::SetInfoVisible(visible) => self.show_info = visible,
             Message::UpdateColormap(colormap) => self.colormap = colormap,
+            Message::UpdateDecimation(decimation) => self.decimation = decimation,
         }
         self.snap_to_bounds();
         Task::none()
@@ -431,6 +636,22 @@ impl Controls {
     }
 }
 
+fn default_signal_sigma() -> f32 {
+    5.0
+}
+
+fn default_outlier_sigma() -> f32 {
+    3.0
+}
+
+fn default_tones() -> u8 {
+    1
+}
+
+fn default_tone_separation() -> f32 {
+    ToneOptions::default().min_separation
+}
+
 impl Default for Controls {
     fn default() -> Self {
         Self {
@@ -438,10 +659,18 @@ impl Default for Controls {
             center: data_normalized::Point::new(0.5, 0.5),
             power_bounds: (0.0, 0.0),
             power_range: (0.0, 0.0),
-            signal_sigma: 5.0,
+            signal_sigma: default_signal_sigma(),
+            signal_method: Default::default(),
+            outlier_sigma: default_outlier_sigma(),
+            tones: default_tones(),
+            tone_separation: default_tone_separation(),
+            tone_sigma: default_signal_sigma(),
             track_bw: 10e3,
+            track_interpolation: Default::default(),
             show_controls: true,
+            show_info: false,
             colormap: Default::default(),
+            decimation: Default::default(),
             zoom_max: Vec2::splat(ZOOM_MAX),
         }
     }
@@ -482,6 +711,49 @@ mod tests {
         assert!((c.size().0.height - 1.0).abs() < 1e-6);
     }
 
+    fn data_bounds(length_s: f32) -> data_absolute::Rectangle {
+        data_absolute::Rectangle::new(
+            data_absolute::Point::new(0.0, -50e3),
+            data_absolute::Size::new(length_s, 100e3),
+        )
+    }
+
+    #[test]
+    fn extend_keeps_view_on_same_data() {
+        let mut c = Controls::default();
+        let (old, new) = (data_bounds(3600.0), data_bounds(7200.0));
+        c.set_data_bounds(3600.0, 100e3);
+        c.set_view_from_rect_dn(&data_normalized::Rectangle::new(
+            data_normalized::Point::new(0.25, 0.0),
+            data_normalized::Size::new(0.25, 1.0),
+        ));
+        c.extend_data_bounds(&old, &new);
+        let b = c.bounds();
+        assert!((b.0.x - 0.125).abs() < 1e-4);
+        assert!((b.0.width - 0.125).abs() < 1e-4);
+    }
+
+    #[test]
+    fn extend_follows_end_of_spectrogram() {
+        let mut c = Controls::default();
+        let (old, new) = (data_bounds(3600.0), data_bounds(7200.0));
+        c.set_data_bounds(3600.0, 100e3);
+        c.set_view_from_rect_dn(&data_normalized::Rectangle::new(
+            data_normalized::Point::new(0.75, 0.0),
+            data_normalized::Size::new(0.25, 1.0),
+        ));
+        c.extend_data_bounds(&old, &new);
+        let b = c.bounds();
+        assert!((b.0.x + b.0.width - 1.0).abs() < 1e-4);
+        assert!((b.0.width - 0.125).abs() < 1e-4);
+
+        // Fully zoomed out views stay zoomed out
+        let mut c = Controls::default();
+        c.set_data_bounds(3600.0, 100e3);
+        c.extend_data_bounds(&old, &new);
+        assert!((c.bounds().0.width - 1.0).abs() < 1e-6);
+    }
+
     #[test]
     fn reset_view_restores_full_view() {
         let mut c = Controls::default();
@@ -521,8 +793,8 @@ mod tests {
         c.set_power_bounds((-50.0, -10.0));
         c.set_power_bounds((-30.0, -20.0));
         let (lo, hi) = c.power_range();
-        assert!(lo >= -30.0 && lo <= -20.0, "lo={}", lo);
-        assert!(hi >= -30.0 && hi <= -20.0, "hi={}", hi);
+        assert!((-30.0..=-20.0).contains(&lo), "lo={}", lo);
+        assert!((-30.0..=-20.0).contains(&hi), "hi={}", hi);
     }
 
     #[test]
@@ -0,0 +1,143 @@
+//! This module contains the metadata panel for RFPlot, which summarizes the loaded spectrogram and
+//! lists gaps in the data and damaged spectra that were skipped while loading.
+
+use chrono::{DateTime, Utc};
+use iced::{
+    Element, Length,
+    alignment::Vertical,
+    widget::{self, Row, button, text},
+};
+use rstrf::{coord::data_normalized, spectrogram::Spectrogram};
+
+use super::{Message, SharedState};
+
+/// Recordings with many dropouts can have thousands of gaps, so only list the first few.
+const MAX_LISTED_GAPS: usize = 10;
+/// Same for damaged spectra in the loaded files.
+const MAX_LISTED_WARNINGS: usize = 10;
+
+fn field<'a>(label: &'static str, value: String) -> Row<'a, Message> {
+    widget::row![
+        text(label).width(Length::FillPortion(1)),
+        text(value).width(Length::FillPortion(2)),
+    ]
+    .spacing(4)
+    .align_y(Vertical::Center)
+}
+
+fn format_time(time: DateTime<Utc>) -> String {
+    time.format("%Y-%m-%d %H:%M:%S").to_string()
+}
+
+/// Zooms the time axis to the gap (and as much around it again), keeping the frequency axis.
+fn zoom_to_gap(
+    spectrogram: &Spectrogram,
+    shared: &SharedState,
+    gap: &std::ops::Range<DateTime<Utc>>,
+) -> Message {
+    let length = spectrogram.length().as_seconds_f32();
+    let start = (gap.start - spectrogram.start_time()).as_seconds_f32() / length;
+    let end = (gap.end - spectrogram.start_time()).as_seconds_f32() / length;
+    let margin = (end - start) / 2.0;
+    let view = shared.controls.bounds();
+    Message::SetView(data_normalized::Rectangle::new(
+        data_normalized::Point::new(start - margin, view.0.y),
+        data_normalized::Size::new(end - start + 2.0 * margin, view.0.height),
+    ))
+}
+
+pub fn view(shared: &SharedState) -> Option<Element<'_, Message>> {
+    let spectrogram = shared.spectrogram.as_ref()?;
+
+    let gaps = spectrogram.gaps();
+    let missing: f32 = gaps
+        .iter()
+        .map(|gap| (gap.end - gap.start).as_seconds_f32())
+        .sum();
+    let storage = if spectrogram.is_mapped() {
+        "memory-mapped"
+    } else {
+        "in memory"
+    };
+
+    let fields = widget::grid![
+        field("Files", shared.spectrogram_files.len().to_string()),
+        field("Storage", storage.to_string()),
+        field("Start", format_time(spectrogram.start_time())),
+        field("End", format_time(spectrogram.end_time())),
+        field("Frequency", format!("{:.3} MHz", spectrogram.freq / 1e6)),
+        field("Bandwidth", format!("{:.1} kHz", spectrogram.bw / 1e3)),
+        field(
+            "Channels",
+            format!(
+                "{} ({:.1} Hz)",
+                spectrogram.nchan,
+                spectrogram.bw / spectrogram.nchan as f32
+            )
+        ),
+        field(
+            "Spectra",
+            format!("{} ({:.1} s)", spectrogram.nslices, spectrogram.lengths[0])
+        ),
+        field("Gaps", format!("{} ({:.0} s missing)", gaps.len(), missing)),
+        field(
+            "Skipped",
+            format!(
+                "{} damaged spectra in {} files",
+                spectrogram.load_warnings().len(),
+                spectrogram.degraded_files().len()
+            )
+        ),
+        field(
+            "RFI",
+            match spectrogram.mask().map(|mask| mask.count()) {
+                Some((slices, channels)) => format!("{slices} spectra, {channels} channels"),
+                None => "not flagged".to_string(),
+            }
+        ),
+    ]
+    .columns(2)
+    .spacing(8)
+    .height(Length::Shrink);
+
+    let mut gap_list = widget::column![].spacing(2);
+    for gap in gaps.iter().take(MAX_LISTED_GAPS) {
+        gap_list = gap_list.push(
+            button(text(format!(
+                "{} - {} ({:.0} s)",
+                format_time(gap.start),
+                gap.end.format("%H:%M:%S"),
+                (gap.end - gap.start).as_seconds_f32()
+            )))
+            .style(button::text)
+            .padding(0)
+            .on_press(zoom_to_gap(spectrogram, shared, gap)),
+        );
+    }
+    if gaps.len() > MAX_LISTED_GAPS {
+        gap_list = gap_list.push(text(format!(
+            "... and {} more",
+            gaps.len() - MAX_LISTED_GAPS
+        )));
+    }
+
+    let warnings = spectrogram.load_warnings();
+    let mut warning_list = widget::column![].spacing(2);
+    for warning in warnings.iter().take(MAX_LISTED_WARNINGS) {
+        warning_list = warning_list.push(text(warning.to_string()).size(12));
+    }
+    if warnings.len() > MAX_LISTED_WARNINGS {
+        warning_list = warning_list.push(text(format!(
+            "... and {} more",
+            warnings.len() - MAX_LISTED_WARNINGS
+        )));
+    }
+
+    Some(
+        widget::container(widget::column![fields, gap_list, warning_list].spacing(8))
+            .padding(8)
+            .width(Length::Fill)
+            .style(widget::container::bordered_box)
+            .into(),
+    )
+}
@@ -1,4 +1,8 @@
-use std::{path::PathBuf, pin::Pin, sync::Arc};
+use std::{
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::Arc,
+};
 
 use futures_util::{SinkExt, Stream};
 use iced::{
@@ -25,18 +29,29 @@ use crate::{
     windows::{Window, WindowEffect, WindowOut, rfplot::control::Controls},
 };
 
+mod autotrack;
+mod blind;
 pub mod control;
+mod info;
 pub mod overlay;
+mod processing;
 mod shader;
+mod stacking;
 
 #[derive(Debug, Clone)]
 pub enum Message {
     Control(control::Message),
     Overlay(overlay::Message),
+    Processing(processing::Message),
     PickSpectrogram,
     LoadSpectrogram(Vec<PathBuf>),
     SpectrogramLoaded(Result<(Vec<PathBuf>, Spectrogram), String>),
-    LoadProgress { loaded: usize, total: usize },
+    SpectrogramExtended((Vec<PathBuf>, Spectrogram)),
+    LoadProgress {
+        loaded: usize,
+        total: usize,
+        degraded: Vec<PathBuf>,
+    },
     GpuUploadDone,
     SetView(data_normalized::Rectangle),
     CaptureScreenshot(Option<PathBuf>),
@@ -88,6 +103,12 @@ pub(crate) struct SharedState {
     pub spectrogram_files: Vec<PathBuf>,
     #[serde(skip)]
     pub spectrogram: Option<Spectrogram>,
+    /// Directory that is watched for new spectra, see `--follow`
+    #[serde(default)]
+    pub follow_dir: Option<PathBuf>,
+    /// Processing that is applied to the spectrogram after loading it
+    #[serde(default)]
+    pub processing: processing::Processing,
     /// The margin on the left/bottom of the plot area (for axes/labels)
     pub plot_area_margin: f32,
 }
@@ -111,7 +132,13 @@ enum LoadingState {
     LoadingFiles {
         loaded: usize,
         total: usize,
+        /// Files that were loaded with some spectra skipped
+        degraded: Vec<PathBuf>,
     },
+    /// Following a directory that doesn't contain any spectra yet
+    WaitingForSpectra,
+    /// Applying the processing pipeline to the loaded spectrogram
+    Processing,
     GpuUploading,
 }
 
@@ -194,6 +221,14 @@ impl RFPlot {
         rfplot
     }
 
+    /// Creates a plot that follows `dir`, adding spectra as they are written to it.
+    pub fn following(dir: PathBuf, view: InitialView) -> Self {
+        let mut rfplot = Self::new();
+        rfplot.shared.follow_dir = Some(dir);
+        rfplot.initial_view = Some(Box::new(view));
+        rfplot
+    }
+
     // TODO
     pub fn app_event(&mut self, _event: AppEvent, app: &AppShared) -> Task<WindowOut<Message>> {
         // Trigger a prediction cache check
@@ -204,6 +239,90 @@ impl RFPlot {
     }
 }
 
+impl RFPlot {
+    /// Shows a newly loaded (and processed) spectrogram, uploading it to the GPU.
+    fn show_spectrogram(&mut self, spec: Spectrogram, app: &AppShared) -> Task<Message> {
+        self.shared.controls.set_spectrogram(&spec);
+        if let Some(iv) = self.initial_view.take() {
+            apply_initial_view(&mut self.shared.controls, &spec, &iv);
+        }
+        let spec_id = spec.id;
+        let uploaded = self
+            .shared
+            .spectrogram
+            .as_ref()
+            .is_some_and(|old| old.id == spec_id);
+        self.shared.spectrogram = Some(spec);
+
+        if uploaded {
+            self.loading_state = LoadingState::Idle;
+        } else {
+            let notify = Arc::new(tokio::sync::Notify::new());
+            self.gpu_notify = Some(notify.clone());
+            self.gpu_watcher = Some(GpuDoneWatcher { spec_id, notify });
+            self.loading_state = LoadingState::GpuUploading;
+        }
+
+        self.overlay
+            .update(overlay::Message::SpectrogramUpdated, &self.shared, app)
+            .map(Message::Overlay)
+    }
+
+    /// Shows a spectrogram that grew at the end, keeping the view on the same data.
+    fn extend_spectrogram(&mut self, spec: Spectrogram, app: &AppShared) -> Task<Message> {
+        let Some(old) = &self.shared.spectrogram else {
+            return Task::none();
+        };
+        log::debug!(
+            "Spectrogram grew from {} to {} spectra",
+            old.nslices,
+            spec.nslices
+        );
+        self.shared
+            .controls
+            .extend_spectrogram(&old.bounds(), &spec);
+        self.shared.spectrogram = Some(spec);
+        self.overlay
+            .update(overlay::Message::SpectrogramExtended, &self.shared, app)
+            .map(Message::Overlay)
+    }
+}
+
+fn log_load_warnings(spec: &Spectrogram) {
+    let degraded = spec.degraded_files();
+    if !degraded.is_empty() {
+        log::warn!(
+            "Skipped {} damaged spectra in {} file(s), see the info panel",
+            spec.load_warnings().len(),
+            degraded.len()
+        );
+    }
+}
+
+fn log_gaps(spec: &Spectrogram) {
+    let gaps = spec.gaps();
+    if gaps.is_empty() {
+        return;
+    }
+    let missing: f32 = gaps
+        .iter()
+        .map(|gap| (gap.end - gap.start).as_seconds_f32())
+        .sum();
+    log::warn!(
+        "Spectrogram has {} gaps ({:.0} s missing)",
+        gaps.len(),
+        missing
+    );
+    for gap in gaps {
+        log::info!(
+            "Gap from {} to {} ({:.0} s)",
+            gap.start,
+            gap.end,
+            (gap.end - gap.start).as_seconds_f32()
+        );
+    }
+}
+
 fn apply_initial_view(controls: &mut Controls, spec: &Spectrogram, iv: &InitialView) {
     let spec_bounds = spec.bounds();
     let length_secs = spec_bounds.0.width as f64;
@@ -235,7 +354,11 @@ impl Window<Message> for RFPlot {
                 app.config.default_colormap,
             ))
             .map(WindowOut::Msg);
-        let spec_task = if self.shared.spectrogram_files.is_empty() {
+        let spec_task = if self.shared.follow_dir.is_some() {
+            // The follow subscription delivers the spectrogram
+            self.loading_state = LoadingState::WaitingForSpectra;
+            Task::none()
+        } else if self.shared.spectrogram_files.is_empty() {
             Task::none()
         } else {
             self.update(
@@ -260,13 +383,41 @@ impl Window<Message> for RFPlot {
 
     fn view(&self, app: &AppShared) -> Element<'_, WindowOut<Message>> {
         match &self.loading_state {
-            LoadingState::LoadingFiles { loaded, total } => {
-                return container(widget::text(format!(
+            LoadingState::LoadingFiles {
+                loaded,
+                total,
+                degraded,
+            } => {
+                let mut column = widget::column![widget::text(format!(
                     "Loading spectrograms... {loaded}/{total}"
+                ))]
+                .spacing(4)
+                .align_x(Horizontal::Center);
+                if !degraded.is_empty() {
+                    column = column.push(widget::text(format!(
+                        "Skipped damaged spectra in {} file(s):",
+                        degraded.len()
+                    )));
+                    for path in degraded {
+                        column = column.push(widget::text(path.display().to_string()).size(12));
+                    }
+                }
+                return container(column).center(Length::Fill).into();
+            }
+            LoadingState::WaitingForSpectra => {
+                let dir = self.shared.follow_dir.as_deref().unwrap_or(Path::new(""));
+                return container(widget::text(format!(
+                    "Waiting for spectra in {}...",
+                    dir.display()
                 )))
                 .center(Length::Fill)
                 .into();
             }
+            LoadingState::Processing => {
+                return container(widget::text("Processing spectrogram..."))
+                    .center(Length::Fill)
+                    .into();
+            }
             LoadingState::GpuUploading => {
                 // The shader must be in the tree so prepare() fires and creates GPU buffers.
                 // The text overlay communicates loading status on top.
@@ -299,7 +450,11 @@ impl Window<Message> for RFPlot {
             .into();
         }
 
-        let controls = self.shared.controls.view(&self.shared).map(Message::from);
+        let controls = self
+            .shared
+            .controls
+            .view(&self.shared, &self.overlay)
+            .map(Message::from);
 
         let spectrogram: Element<'_, Message> = container(
             widget::shader(self)
@@ -343,7 +498,15 @@ impl Window<Message> for RFPlot {
         }
         let plot_area: Element<'_, Message> = stack.into();
 
-        let contents: Element<'_, Message> = widget::column![controls, plot_area]
+        let info = self
+            .shared
+            .controls
+            .show_info()
+            .then(|| info::view(&self.shared))
+            .flatten();
+        let contents: Element<'_, Message> = widget::column![controls]
+            .push(info)
+            .push(plot_area)
             .padding(8)
             .spacing(4)
             .width(Length::Fill)
@@ -389,45 +552,98 @@ impl Window<Message> for RFPlot {
                 .map(Message::Overlay),
             Message::LoadSpectrogram(paths) => {
                 let total = paths.len();
+                self.shared.follow_dir = None;
                 self.pending_paths = paths;
-                self.loading_state = LoadingState::LoadingFiles { loaded: 0, total };
+                self.loading_state = LoadingState::LoadingFiles {
+                    loaded: 0,
+                    total,
+                    degraded: Vec::new(),
+                };
                 Task::none()
             }
-            Message::LoadProgress { loaded, total } => {
-                self.loading_state = LoadingState::LoadingFiles { loaded, total };
+            Message::LoadProgress {
+                loaded,
+                total,
+                degraded,
+            } => {
+                self.loading_state = LoadingState::LoadingFiles {
+                    loaded,
+                    total,
+                    degraded,
+                };
                 Task::none()
             }
             Message::SpectrogramLoaded(result) => match result {
                 Ok((paths, spec)) => {
                     log::info!("Loaded spectrogram: {spec:?}");
-                    self.shared.controls.set_spectrogram(&spec);
-                    if let Some(iv) = self.initial_view.take() {
-                        apply_initial_view(&mut self.shared.controls, &spec, &iv);
-                    }
-                    let spec_id = spec.id;
-                    self.shared.spectrogram = Some(spec);
+                    log_gaps(&spec);
+                    log_load_warnings(&spec);
                     self.shared.spectrogram_files = paths;
-
-                    let notify = Arc::new(tokio::sync::Notify::new());
-                    self.gpu_notify = Some(notify.clone());
-                    self.gpu_watcher = Some(GpuDoneWatcher { spec_id, notify });
-                    self.loading_state = LoadingState::GpuUploading;
-
-                    self.overlay
-                        .update(overlay::Message::SpectrogramUpdated, &self.shared, app)
-                        .map(Message::Overlay)
+                    match self.shared.processing.process(spec.clone()) {
+                        Some(task) => {
+                            self.loading_state = LoadingState::Processing;
+                            task.map(Message::Processing)
+                        }
+                        None => self.show_spectrogram(spec, app),
+                    }
                 }
                 Err(err) => {
                     log::error!("Failed to load spectrogram: {err}");
                     self.loading_state = LoadingState::Idle;
+                    self.shared.follow_dir = None;
                     Task::none()
                 }
             },
+            Message::SpectrogramExtended((paths, tail)) => {
+                self.shared.spectrogram_files = paths;
+                match self.shared.processing.extend(&tail) {
+                    Ok(Some(task)) => task.map(Message::Processing),
+                    Ok(None) => match self.shared.processing.raw().cloned() {
+                        Some(spec) => self.extend_spectrogram(spec, app),
+                        None => Task::none(),
+                    },
+                    Err(e) => {
+                        log::error!("Failed to add new spectra: {e:?}");
+                        Task::none()
+                    }
+                }
+            }
+            Message::Processing(processing::Message::Done(ticket, result)) => {
+                let shown =
+                    self.shared
+                        .processing
+                        .finish(ticket, result, self.shared.spectrogram.as_ref());
+                let catch_up = self.shared.processing.catch_up().map(Message::Processing);
+                let Some(spec) = shown else {
+                    return catch_up.map(WindowOut::Msg);
+                };
+                let task = match ticket.run {
+                    processing::Run::Load => self.show_spectrogram(spec, app),
+                    processing::Run::Apply => {
+                        // The pipeline may have changed the units, so show the full range
+                        let (min, max) = spec.power_bounds;
+                        let task = self.show_spectrogram(spec, app);
+                        self.shared.controls.set_power_range(Some(min), Some(max));
+                        task
+                    }
+                    processing::Run::Extend => self.extend_spectrogram(spec, app),
+                };
+                Task::batch([task, catch_up])
+            }
+            Message::Processing(message) => self
+                .shared
+                .processing
+                .update(message)
+                .map(Message::Processing),
             Message::PickSpectrogram => Task::future(async {
                 let files = AsyncFileDialog::new()
-                    .add_filter("Supported spectrogram formats", &["rstrf", "bin"])
+                    .add_filter(
+                        "Supported spectrogram formats",
+                        &["rstrf", "bin", "sigmf-meta"],
+                    )
                     .add_filter("rSTRF spectrograms", &["rstrf"])
                     .add_filter("STRF spectrograms", &["bin"])
+                    .add_filter("SigMF recordings", &["sigmf-meta"])
                     .add_filter("All files", &["*"])
                     .pick_files()
                     .await;
@@ -483,17 +699,46 @@ impl Window<Message> for RFPlot {
 
         if matches!(self.loading_state, LoadingState::LoadingFiles { .. }) {
             subs.push(
-                io_service::load_subscription(self.pending_paths.clone(), app.freq_range).map(
+                io_service::load_subscription(self.pending_paths.clone(), app.load_options).map(
                     |e| match e {
-                        io_service::Event::Progress { loaded, total } => {
-                            WindowOut::Msg(Message::LoadProgress { loaded, total })
-                        }
+                        io_service::Event::Progress {
+                            loaded,
+                            total,
+                            degraded,
+                        } => WindowOut::Msg(Message::LoadProgress {
+                            loaded,
+                            total,
+                            degraded,
+                        }),
                         io_service::Event::Done(r) => WindowOut::Msg(Message::SpectrogramLoaded(r)),
+                        io_service::Event::Extended(r) => {
+                            WindowOut::Msg(Message::SpectrogramExtended(r))
+                        }
                     },
                 ),
             );
         }
 
+        if let Some(dir) = &self.shared.follow_dir {
+            subs.push(
+                io_service::follow_subscription(dir.clone(), app.load_options).map(|e| match e {
+                    io_service::Event::Progress {
+                        loaded,
+                        total,
+                        degraded,
+                    } => WindowOut::Msg(Message::LoadProgress {
+                        loaded,
+                        total,
+                        degraded,
+                    }),
+                    io_service::Event::Done(r) => WindowOut::Msg(Message::SpectrogramLoaded(r)),
+                    io_service::Event::Extended(r) => {
+                        WindowOut::Msg(Message::SpectrogramExtended(r))
+                    }
+                }),
+            );
+        }
+
         if let Some(watcher) = &self.gpu_watcher {
             subs.push(Subscription::run_with(watcher.clone(), gpu_done_stream));
         }
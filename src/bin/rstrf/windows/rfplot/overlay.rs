@@ -28,7 +28,8 @@ use rstrf::{
         PlotAreaToDataAbsolute, ScreenToPlotArea, data_absolute, plot_area, screen,
     },
     orbit::{self, Site},
-    signal,
+    signal::{self, Signal, Track},
+    spectrogram::Spectrogram,
     util::{clip_line, is_modifier},
 };
 use serde::{Deserialize, Serialize};
@@ -38,7 +39,13 @@ use rfd::AsyncFileDialog;
 use crate::{app::AppShared, windows::rfplot::MarkAction};
 use rstrf::async_cache::AsyncCache;
 
-use super::{MouseState, RFPlot, RectAction, SharedState, control};
+use super::{
+    MouseState, RFPlot, RectAction, SharedState,
+    autotrack::{self, AutoTrack},
+    blind::{self, BlindSearch},
+    control,
+    stacking::{self, Stacking},
+};
 
 /// All inputs that determine the satellite pass predictions.
 ///
@@ -59,6 +66,10 @@ pub(crate) struct PredictionKey {
     site: Site,
 }
 
+/// While following a growing spectrogram, predictions extend to the next multiple of this many
+/// seconds, so they aren't recomputed for every new spectrum.
+const FOLLOW_PREDICTION_STEP_S: i64 = 900;
+
 fn prediction_key(shared: &SharedState, app: &AppShared) -> Option<PredictionKey> {
     let spectrogram = shared.spectrogram.as_ref()?;
     let site = app.site()?;
@@ -66,17 +77,39 @@ fn prediction_key(shared: &SharedState, app: &AppShared) -> Option<PredictionKey
     if satellites.is_empty() {
         return None;
     }
+    let mut time_range = spectrogram.absolute_bounds().time_range;
+    if shared.follow_dir.is_some() {
+        let steps = (time_range.end - time_range.start).num_seconds() / FOLLOW_PREDICTION_STEP_S;
+        time_range.end =
+            time_range.start + chrono::Duration::seconds((steps + 1) * FOLLOW_PREDICTION_STEP_S);
+    }
     Some(PredictionKey {
         satellites,
-        time_range: spectrogram.absolute_bounds().time_range,
+        time_range,
         site,
     })
 }
 
 /// Maximum cursor-to-mark distance (in screen pixels) for a right-click to delete a mark. Marks
-/// render as radius-5 circles, so this gives a comfortable grab radius around them.
+/// render as circles with a radius of up to 8 px, so this gives a comfortable grab radius around
+/// them.
 const DELETE_TOLERANCE_PX: f32 = 15.0;
 
+/// Radius of signal marks for the weakest and strongest signals. Marks grow by 1 px per doubling
+/// of the significance, from the minimum at 2 sigma.
+const SIGNAL_RADIUS_PX: (f32, f32) = (3.0, 8.0);
+/// Radius of signals that were marked by hand.
+const MARKED_SIGNAL_RADIUS_PX: i32 = 5;
+
+/// Written to the sigma column of `.dat` files for signals that were marked by hand.
+const MARKED_SIGNAL_SIGMA: f32 = 5.0;
+
+/// Number of points the track between the trackpoints is drawn with.
+const TRACK_SAMPLES: usize = 200;
+
+/// Colours of found signals by tone, see [`signal::ToneOptions`]
+const TONE_COLORS: [RGBColor; 4] = [WHITE, CYAN, MAGENTA, RGBColor(255, 165, 0)];
+
 #[derive(Debug, Clone)]
 pub enum Message {
     MarkTrackpoints,
@@ -86,9 +119,11 @@ pub enum Message {
     DeleteMark(MarkAction, data_absolute::Point),
     ClearAll,
     FindSignals,
-    FoundSignals(Vec<data_absolute::Point>),
+    FoundSignals(Vec<Signal>),
     UpdateCrosshair(Option<plot_area::Point>),
     SpectrogramUpdated,
+    /// New spectra were appended. Unlike `SpectrogramUpdated`, this keeps the marks.
+    SpectrogramExtended,
     /// Force a prediction cache check without any other side effects.
     RefreshCache,
     PredictionsReady(PredictionKey, orbit::Predictions),
@@ -99,8 +134,19 @@ pub enum Message {
     ToggleAbsoluteAxes,
     DeleteInRect(data_absolute::Rectangle),
     UpdateRectPreview(Option<plot_area::Point>),
+    /// Flag the found signals that don't follow a Doppler curve
+    RejectOutliers,
+    /// Delete the rejected signals
+    RemoveRejected,
+    /// Un-reject all signals
+    RestoreRejected,
+    ToggleRejected(data_absolute::Point),
     SaveSignals,
-    WriteSignals(String, Option<std::path::PathBuf>),
+    /// The contents of one file per tone, and the path chosen for them
+    WriteSignals(Vec<String>, Option<std::path::PathBuf>),
+    AutoTrack(autotrack::Message),
+    BlindSearch(blind::Message),
+    Stacking(stacking::Message),
 }
 
 fn clamp_line_to_plot(
@@ -123,7 +169,14 @@ pub(super) struct Overlay {
     show_crosshair: bool,
     absolute_axes: bool,
     track_points: Vec<data_absolute::Point>,
-    signals: Vec<data_absolute::Point>,
+    #[serde(deserialize_with = "deserialize_signals")]
+    signals: Vec<Signal>,
+    #[serde(default)]
+    auto_track: AutoTrack,
+    #[serde(default)]
+    blind_search: BlindSearch,
+    #[serde(default)]
+    stacking: Stacking,
     #[serde(skip)]
     crosshair: Option<data_absolute::Point>,
     #[serde(skip)]
@@ -144,6 +197,9 @@ impl Default for Overlay {
             absolute_axes: true,
             track_points: Default::default(),
             signals: Default::default(),
+            auto_track: Default::default(),
+            blind_search: Default::default(),
+            stacking: Default::default(),
             crosshair: Default::default(),
             rect_preview: Default::default(),
             mouse_state: Cell::new(MouseState::Idle),
@@ -275,6 +331,21 @@ impl Overlay {
             .draw()
             .map_err(|e| format!("Failed to draw mesh: {:?}", e))?;
 
+        // The shader leaves gaps in the data blank, so shade them to tell them apart from a quiet
+        // band
+        chart
+            .draw_series(spectrogram.gaps().iter().filter_map(|gap| {
+                let left = (gap.start - start_time).as_seconds_f32().max(x.start);
+                let right = (gap.end - start_time).as_seconds_f32().min(x.end);
+                (left < right).then(|| {
+                    plotters::element::Rectangle::new(
+                        [(left, y.start), (right, y.end)],
+                        RGBColor(128, 128, 128).mix(0.35).filled(),
+                    )
+                })
+            }))
+            .map_err(|e| format!("Could not draw gaps: {:?}", e))?;
+
         if self.show_predictions
             && let Some((_, predictions)) = self.prediction_cache.get_stored()
         {
@@ -331,51 +402,56 @@ impl Overlay {
                 }
             }))
             .map_err(|e| format!("Could not draw track points: {:?}", e))?;
-        chart
-            .draw_series(LineSeries::new(
-                clamp_line_to_plot(
-                    &bounds,
-                    self.track_points.iter().map(|pos| {
-                        data_absolute::Point::new(
-                            pos.0.x,
-                            pos.0.y + shared.controls.track_bw() / 2.0,
+        if let Some(track) = Track::new(&self.track_points, shared.controls.track_interpolation()) {
+            let track_bw = shared.controls.track_bw();
+            for (offset, side) in [(track_bw / 2.0, "above"), (-track_bw / 2.0, "below")] {
+                chart
+                    .draw_series(LineSeries::new(
+                        clamp_line_to_plot(
+                            &bounds,
+                            track
+                                .sample(TRACK_SAMPLES)
+                                .map(|p| data_absolute::Point::new(p.0.x, p.0.y + offset)),
                         )
-                    }),
-                )
-                .map(|v| v.into()),
-                &YELLOW,
-            ))
-            .map_err(|e| {
-                format!(
-                    "Could not draw lines connecting track points (above): {:?}",
-                    e
-                )
-            })?;
+                        .map(|v| v.into()),
+                        &YELLOW,
+                    ))
+                    .map_err(|e| format!("Could not draw track ({side}): {:?}", e))?;
+            }
+        }
+
         chart
-            .draw_series(LineSeries::new(
-                clamp_line_to_plot(
-                    &bounds,
-                    self.track_points.iter().map(|pos| {
-                        data_absolute::Point::new(
-                            pos.0.x,
-                            pos.0.y - shared.controls.track_bw() / 2.0,
-                        )
+            .draw_series(
+                self.blind_search
+                    .candidates()
+                    .iter()
+                    .filter_map(|candidate| {
+                        let left = candidate.start.max(x.start);
+                        let right = candidate.end.min(x.end);
+                        let bottom = candidate.freq_min.max(y.start);
+                        let top = candidate.freq_max.min(y.end);
+                        (left <= right && bottom <= top).then(|| {
+                            plotters::element::Rectangle::new(
+                                [(left, bottom), (right, top)],
+                                CYAN.stroke_width(1),
+                            )
+                        })
                     }),
-                )
-                .map(|v| v.into()),
-                &YELLOW,
-            ))
-            .map_err(|e| {
-                format!(
-                    "Could not draw lines connecting track points (below): {:?}",
-                    e
-                )
-            })?;
-
+            )
+            .map_err(|e| format!("Could not draw candidates: {:?}", e))?;
         chart
-            .draw_series(self.signals.iter().filter_map(|pos| {
-                if bounds.contains(*pos) {
-                    Some(Circle::new(pos.into(), 5, WHITE.filled()))
+            .draw_series(self.signals.iter().filter_map(|signal| {
+                if bounds.contains(signal.point) {
+                    let style = if signal.rejected {
+                        RED.stroke_width(2)
+                    } else {
+                        TONE_COLORS[signal.tone % TONE_COLORS.len()].filled()
+                    };
+                    Some(Circle::new(
+                        (&signal.point).into(),
+                        signal_radius(signal),
+                        style,
+                    ))
                 } else {
                     None
                 }
@@ -416,11 +492,11 @@ impl Overlay {
                 .map_err(|e| format!("Could not draw crosshair horizontal line: {:?}", e))?;
             let crosshair_norm =
                 *crosshair * DataAbsoluteToDataNormalized::new(&spectrogram.bounds());
-            let dim = spectrogram.data().dim();
-            let power = spectrogram.data()[(
+            let dim = (spectrogram.nslices, spectrogram.nchan);
+            let power = spectrogram.value(
                 ((crosshair_norm.0.x * (dim.0 as f32)).floor() as usize).clamp(0, dim.0 - 1),
                 ((crosshair_norm.0.y * (dim.1 as f32)).floor() as usize).clamp(0, dim.1 - 1),
-            )];
+            );
             let crosshair_pos = plot_area::Point::new(0.01, 0.99)
                 * PlotAreaToDataAbsolute::new(&shared.controls.bounds(), &spectrogram.bounds());
             let crosshair_text = if self.absolute_axes {
@@ -545,11 +621,9 @@ impl Overlay {
 
         match self.mouse_state.get() {
             MouseState::Idle => match event {
-                mouse::Event::ButtonPressed(mouse::Button::Left) => {
-                    if cursor.is_over(bounds) {
-                        self.mouse_state.set(MouseState::Panning(plot_pos));
-                        return (Status::Captured, None);
-                    }
+                mouse::Event::ButtonPressed(mouse::Button::Left) if cursor.is_over(bounds) => {
+                    self.mouse_state.set(MouseState::Panning(plot_pos));
+                    return (Status::Captured, None);
                 }
                 mouse::Event::ButtonPressed(mouse::Button::Right) => {
                     if cursor.is_over(bounds)
@@ -565,10 +639,12 @@ impl Overlay {
                             &self.signals,
                         )
                     {
-                        return (
-                            Status::Captured,
-                            Some(Message::DeleteMark(action, point).into()),
-                        );
+                        let msg = if modifiers.shift() && action == MarkAction::Signal {
+                            Message::ToggleRejected(point)
+                        } else {
+                            Message::DeleteMark(action, point)
+                        };
+                        return (Status::Captured, Some(msg.into()));
                     }
                     return (Status::Captured, None);
                 }
@@ -713,6 +789,9 @@ impl Overlay {
             keyboard::Key::Character("f") => {
                 return (Status::Captured, Some(Message::FindSignals.into()));
             }
+            keyboard::Key::Character("a") => {
+                return (Status::Captured, Some(autotrack::Message::Track.into()));
+            }
             keyboard::Key::Character("p") => {
                 return (Status::Captured, Some(Message::TogglePredictions.into()));
             }
@@ -780,6 +859,31 @@ impl Overlay {
         }
     }
 
+    pub(super) fn auto_track_view(&self) -> iced::Element<'_, super::Message> {
+        let targets = self
+            .prediction_cache
+            .get_stored()
+            .map(|(_, predictions)| AutoTrack::targets(predictions))
+            .unwrap_or_default();
+        self.auto_track.view(targets)
+    }
+
+    pub(super) fn stacking_view(&self) -> iced::Element<'_, super::Message> {
+        let targets = self
+            .prediction_cache
+            .get_stored()
+            .map(|(_, predictions)| AutoTrack::targets(predictions))
+            .unwrap_or_default();
+        self.stacking.view(targets)
+    }
+
+    pub(super) fn blind_search_view<'a>(
+        &'a self,
+        spectrogram: &Spectrogram,
+    ) -> iced::Element<'a, super::Message> {
+        self.blind_search.view(spectrogram)
+    }
+
     pub(super) fn status(&self, app: &AppShared) -> Option<&str> {
         if !self.show_predictions {
             return None;
@@ -797,6 +901,30 @@ impl Overlay {
         }
     }
 
+    /// Flags the found signals that are outliers from a Doppler curve through all signals of their
+    /// tone, see [`signal::reject_outliers`]. Signals that were marked by hand are never rejected.
+    fn reject_outliers(&mut self, shared: &SharedState) {
+        let Some(spectrogram) = &shared.spectrogram else {
+            return;
+        };
+        let channel_width = spectrogram.bw / spectrogram.nchan as f32;
+        let tones = self.signals.iter().map(|s| s.tone).unique().collect_vec();
+        for tone in tones {
+            let indices = (0..self.signals.len())
+                .filter(|&i| self.signals[i].tone == tone)
+                .collect_vec();
+            let points = indices.iter().map(|&i| self.signals[i].point).collect_vec();
+            let rejected =
+                signal::reject_outliers(&points, shared.controls.outlier_sigma(), channel_width);
+            for (i, rejected) in indices.into_iter().zip(rejected) {
+                let signal = &mut self.signals[i];
+                signal.rejected = rejected && signal.sigma.is_some();
+            }
+        }
+        let n = self.signals.iter().filter(|s| s.rejected).count();
+        log::info!("Rejected {n} of {} signals as outliers", self.signals.len());
+    }
+
     /// Checks whether the prediction cache is stale for the current inputs. If so, starts an async
     /// recomputation. Called at the top of every `update()` so any incoming message acts as a
     /// trigger.
@@ -858,17 +986,22 @@ impl Overlay {
             }
             Message::AddSignal(pos) => {
                 log::debug!("Manually adding signal at position: {:?}", pos);
-                self.signals.push(pos);
+                self.signals.push(Signal::marked(pos));
                 Task::none()
             }
             Message::DeleteMark(action, point) => {
                 log::debug!("Deleting {:?} mark at position: {:?}", action, point);
-                let collection = match action {
-                    MarkAction::Trackpoint => &mut self.track_points,
-                    MarkAction::Signal => &mut self.signals,
-                };
-                if let Some(idx) = collection.iter().position(|p| *p == point) {
-                    collection.remove(idx);
+                match action {
+                    MarkAction::Trackpoint => {
+                        if let Some(idx) = self.track_points.iter().position(|p| *p == point) {
+                            self.track_points.remove(idx);
+                        }
+                    }
+                    MarkAction::Signal => {
+                        if let Some(idx) = self.signals.iter().position(|s| s.point == point) {
+                            self.signals.remove(idx);
+                        }
+                    }
                 }
                 Task::none()
             }
@@ -887,15 +1020,19 @@ impl Overlay {
                     };
                     let spectrogram = spectrogram.clone();
                     let track_points = self.track_points.clone();
-                    let sigma = shared.controls.signal_sigma();
+                    let method = shared.controls.signal_method();
                     let track_bw = shared.controls.track_bw();
+                    let interpolation = shared.controls.track_interpolation();
+                    let tones = shared.controls.tones();
                     Task::future(async move {
                         tokio::task::spawn_blocking(move || {
                             let signals = signal::find_signals(
                                 &spectrogram,
                                 &track_points,
                                 track_bw,
-                                signal::SignalDetectionMethod::FitTrace { sigma },
+                                interpolation,
+                                method,
+                                tones,
                             );
                             let signals = match signals {
                                 Err(e) => {
@@ -914,8 +1051,117 @@ impl Overlay {
                     })
                 }
             }
+            Message::AutoTrack(autotrack::Message::Track) => {
+                let Some(spectrogram) = &shared.spectrogram else {
+                    log::error!("No spectrogram loaded, cannot track signals");
+                    return Task::none();
+                };
+                let Some(passes) =
+                    self.prediction_cache
+                        .get_stored()
+                        .and_then(|(_, predictions)| {
+                            self.auto_track.passes(predictions, spectrogram.freq)
+                        })
+                else {
+                    log::warn!("No predicted passes to track");
+                    return Task::none();
+                };
+                let spectrogram = spectrogram.clone();
+                let offset = self.auto_track.offset();
+                let options = self
+                    .auto_track
+                    .options(shared.controls.signal_method(), shared.controls.track_bw());
+                Task::future(async move {
+                    tokio::task::spawn_blocking(move || {
+                        let signals =
+                            autotrack::follow_passes(&spectrogram, &passes, offset, options);
+                        let signals = match signals {
+                            Err(e) => {
+                                log::error!("Error tracking signals: {}", e);
+                                Vec::new()
+                            }
+                            Ok(signals) => {
+                                log::info!("Tracked {} signal peaks", signals.len());
+                                signals
+                            }
+                        };
+                        Message::FoundSignals(signals)
+                    })
+                    .await
+                    .unwrap()
+                })
+            }
+            Message::AutoTrack(message) => {
+                self.auto_track.update(message);
+                Task::none()
+            }
+            Message::BlindSearch(blind::Message::Search) => {
+                let Some(spectrogram) = &shared.spectrogram else {
+                    log::error!("No spectrogram loaded, cannot search for signals");
+                    return Task::none();
+                };
+                let spectrogram = spectrogram.clone();
+                let options = self.blind_search.start();
+                Task::future(async move {
+                    let message =
+                        tokio::task::spawn_blocking(move || blind::search(&spectrogram, options))
+                            .await
+                            .unwrap();
+                    Message::BlindSearch(message)
+                })
+            }
+            Message::BlindSearch(message) => {
+                self.blind_search.update(message);
+                Task::none()
+            }
+            Message::Stacking(stacking::Message::Stack) => {
+                let Some(spectrogram) = &shared.spectrogram else {
+                    log::error!("No spectrogram loaded, cannot stack spectra");
+                    return Task::none();
+                };
+                let Some(passes) = self.stacking.target().and_then(|target| {
+                    let (_, predictions) = self.prediction_cache.get_stored()?;
+                    target.passes(predictions, spectrogram.freq)
+                }) else {
+                    log::warn!("No predicted passes to stack along");
+                    return Task::none();
+                };
+                let spectrogram = spectrogram.clone();
+                let span = self.stacking.start();
+                Task::future(async move {
+                    let message = tokio::task::spawn_blocking(move || {
+                        stacking::stack(&spectrogram, &passes, span)
+                    })
+                    .await
+                    .unwrap();
+                    Message::Stacking(message)
+                })
+            }
+            Message::Stacking(message) => {
+                self.stacking.update(message);
+                Task::none()
+            }
             Message::FoundSignals(signals) => {
                 self.signals = signals;
+                self.reject_outliers(shared);
+                Task::none()
+            }
+            Message::RejectOutliers => {
+                self.reject_outliers(shared);
+                Task::none()
+            }
+            Message::RemoveRejected => {
+                self.signals.retain(|s| !s.rejected);
+                Task::none()
+            }
+            Message::RestoreRejected => {
+                self.signals.iter_mut().for_each(|s| s.rejected = false);
+                Task::none()
+            }
+            Message::ToggleRejected(point) => {
+                if let Some(signal) = self.signals.iter_mut().find(|s| s.point == point) {
+                    signal.rejected = !signal.rejected;
+                }
                 Task::none()
             }
             Message::UpdateCrosshair(plot_pos) => {
@@ -932,9 +1178,12 @@ impl Overlay {
             Message::SpectrogramUpdated => {
                 self.track_points.clear();
                 self.signals.clear();
+                self.blind_search.clear();
                 self.crosshair = None;
                 Task::none()
             }
+            // The prediction cache check at the top of `update()` is all that's needed
+            Message::SpectrogramExtended => Task::none(),
             Message::RefreshCache => {
                 self.prediction_cache.reset();
                 Task::none()
@@ -967,7 +1216,7 @@ impl Overlay {
             Message::DeleteInRect(rect) => {
                 self.rect_preview = None;
                 self.track_points.retain(|p| !rect.contains(*p));
-                self.signals.retain(|p| !rect.contains(*p));
+                self.signals.retain(|s| !rect.contains(s.point));
                 Task::none()
             }
             Message::UpdateRectPreview(corner2) => {
@@ -986,13 +1235,22 @@ impl Overlay {
                 let start_time = spectrogram.start_time();
                 let start_mjd = start_time.timestamp_millis() as f64 / 86_400_000.0 + 40587.0;
                 let center_freq = spectrogram.freq as f64;
-                let suggested = signals_filename(start_time, center_freq, &self.signals)
+                let signals: Vec<Signal> = self
+                    .signals
+                    .iter()
+                    .filter(|s| !s.rejected)
+                    .copied()
+                    .collect();
+                let suggested = signals_filename(start_time, center_freq, &signals)
                     .unwrap_or_else(|| "out.dat".to_owned());
-                let mut output = String::new();
-                for sig in &self.signals {
-                    let mjd = start_mjd + sig.0.x as f64 / 86400.0;
-                    let freq = center_freq + sig.0.y as f64;
-                    output.push_str(&format!("{mjd:.6} {freq:.6} 5.000000 {site_id}\n"));
+                let tones = signals.iter().map(|s| s.tone + 1).max().unwrap_or(1);
+                let mut outputs = vec![String::new(); tones];
+                for sig in &signals {
+                    let mjd = start_mjd + sig.point.0.x as f64 / 86400.0;
+                    let freq = center_freq + sig.point.0.y as f64;
+                    let sigma = sig.sigma.unwrap_or(MARKED_SIGNAL_SIGMA);
+                    outputs[sig.tone]
+                        .push_str(&format!("{mjd:.6} {freq:.6} {sigma:.6} {site_id}\n"));
                 }
                 Task::future(async move {
                     let path = AsyncFileDialog::new()
@@ -1000,15 +1258,22 @@ impl Overlay {
                         .save_file()
                         .await
                         .map(|f| f.path().to_path_buf());
-                    Message::WriteSignals(output, path)
+                    Message::WriteSignals(outputs, path)
                 })
             }
             Message::WriteSignals(_, None) => Task::none(),
-            Message::WriteSignals(output, Some(path)) => {
-                let n = output.lines().count();
-                match std::fs::write(&path, &output) {
-                    Ok(()) => log::info!("Wrote {n} signals to {path:?}"),
-                    Err(e) => log::error!("Failed to write {path:?}: {e}"),
+            Message::WriteSignals(outputs, Some(path)) => {
+                for (tone, output) in outputs.iter().enumerate() {
+                    let path = if outputs.len() > 1 {
+                        tone_path(&path, tone)
+                    } else {
+                        path.clone()
+                    };
+                    let n = output.lines().count();
+                    match std::fs::write(&path, output) {
+                        Ok(()) => log::info!("Wrote {n} signals to {path:?}"),
+                        Err(e) => log::error!("Failed to write {path:?}: {e}"),
+                    }
                 }
                 Task::none()
             }
@@ -1025,14 +1290,14 @@ impl Overlay {
 fn signals_filename(
     start_time: DateTime<Utc>,
     center_freq: f64,
-    signals: &[data_absolute::Point],
+    signals: &[Signal],
 ) -> Option<String> {
     if signals.is_empty() {
         return None;
     }
     let n = signals.len() as f64;
-    let mean_secs = signals.iter().map(|s| s.0.x as f64).sum::<f64>() / n;
-    let mean_freq = center_freq + signals.iter().map(|s| s.0.y as f64).sum::<f64>() / n;
+    let mean_secs = signals.iter().map(|s| s.point.0.x as f64).sum::<f64>() / n;
+    let mean_freq = center_freq + signals.iter().map(|s| s.point.0.y as f64).sum::<f64>() / n;
     let mean_time = start_time + Duration::milliseconds((mean_secs * 1000.0) as i64);
     Some(format!(
         "{}_{:.0}k.dat",
@@ -1041,6 +1306,16 @@ fn signals_filename(
     ))
 }
 
+/// The file for one tone of a multi-tone signal set: `path` with `_tone{n}` before the extension.
+fn tone_path(path: &std::path::Path, tone: usize) -> std::path::PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let mut name = format!("{stem}_tone{tone}");
+    if let Some(ext) = path.extension() {
+        name = format!("{name}.{}", ext.to_string_lossy());
+    }
+    path.with_file_name(name)
+}
+
 /// Finds the mark (track point or signal) nearest to `pos`, measured in screen pixels via
 /// `da_to_screen`, and returns it tagged with which collection it belongs to. Returns `None` if
 /// there are no marks, or the nearest is farther than [`DELETE_TOLERANCE_PX`].
@@ -1048,12 +1323,12 @@ fn closest_mark(
     pos: screen::Point,
     da_to_screen: &DataAbsoluteToScreen,
     track_points: &[data_absolute::Point],
-    signals: &[data_absolute::Point],
+    signals: &[Signal],
 ) -> Option<(MarkAction, data_absolute::Point)> {
     track_points
         .iter()
         .map(|p| (MarkAction::Trackpoint, p))
-        .chain(signals.iter().map(|p| (MarkAction::Signal, p)))
+        .chain(signals.iter().map(|s| (MarkAction::Signal, &s.point)))
         .map(|(action, &point)| {
             let offset = point * *da_to_screen - pos;
             let dist = offset.0.x.hypot(offset.0.y);
@@ -1064,6 +1339,36 @@ fn closest_mark(
         .map(|(action, point, _)| (action, point))
 }
 
+fn signal_radius(signal: &Signal) -> i32 {
+    match signal.sigma {
+        Some(sigma) => (SIGNAL_RADIUS_PX.0 + (sigma / 2.0).log2())
+            .clamp(SIGNAL_RADIUS_PX.0, SIGNAL_RADIUS_PX.1)
+            .round() as i32,
+        None => MARKED_SIGNAL_RADIUS_PX,
+    }
+}
+
+/// Reads the signals of a saved workspace, which only had their positions before the significance
+/// was kept.
+fn deserialize_signals<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<Signal>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Saved {
+        Signal(Signal),
+        Point(data_absolute::Point),
+    }
+    let saved = Vec::<Saved>::deserialize(deserializer)?;
+    Ok(saved
+        .into_iter()
+        .map(|saved| match saved {
+            Saved::Signal(signal) => signal,
+            Saved::Point(point) => Signal::marked(point),
+        })
+        .collect())
+}
+
 impl PartialEq for Overlay {
     fn eq(&self, other: &Self) -> bool {
         self.track_points == other.track_points
@@ -1171,6 +1476,43 @@ mod tests {
             .unwrap()
     }
 
+    fn sig(x: f32, y: f32) -> Signal {
+        Signal::marked(pt(x, y))
+    }
+
+    #[test]
+    fn signals_of_old_workspaces_load_as_marked() {
+        let found = Signal {
+            point: pt(1.0, 2.0),
+            sigma: Some(7.0),
+            tone: 0,
+            rejected: false,
+        };
+        // Signals saved before outlier rejection have no `rejected` flag
+        let unflagged = serde_json::json!({ "point": pt(1.0, 2.0), "sigma": 7.0 });
+        let saved = serde_json::json!([pt(3.0, 4.0), found, unflagged]);
+        assert_eq!(
+            deserialize_signals(saved).unwrap(),
+            vec![sig(3.0, 4.0), found, found]
+        );
+    }
+
+    #[test]
+    fn signal_radius_grows_with_sigma() {
+        let radius = |sigma| {
+            signal_radius(&Signal {
+                point: pt(0.0, 0.0),
+                sigma,
+                tone: 0,
+                rejected: false,
+            })
+        };
+        assert_eq!(radius(Some(1.0)), 3);
+        assert_eq!(radius(Some(8.0)), 5);
+        assert_eq!(radius(Some(1e6)), 8);
+        assert_eq!(radius(None), MARKED_SIGNAL_RADIUS_PX);
+    }
+
     #[test]
     fn empty_signals_returns_none() {
         assert_eq!(
@@ -1182,21 +1524,25 @@ mod tests {
     #[test]
     fn single_signal_at_center() {
         // One signal exactly at the center: mean time = start, mean freq = center_freq
-        let name = signals_filename(utc(2024, 6, 15, 12, 30), 145_900_000.0, &[pt(0.0, 0.0)]);
+        let name = signals_filename(utc(2024, 6, 15, 12, 30), 145_900_000.0, &[sig(0.0, 0.0)]);
         assert_eq!(name.as_deref(), Some("2024-06-15T12:30_145900k.dat"));
     }
 
     #[test]
     fn single_signal_with_offsets() {
         // 60 s into the observation, +1000 Hz from center → 145901 kHz
-        let name = signals_filename(utc(2024, 6, 15, 12, 30), 145_900_000.0, &[pt(60.0, 1000.0)]);
+        let name = signals_filename(
+            utc(2024, 6, 15, 12, 30),
+            145_900_000.0,
+            &[sig(60.0, 1000.0)],
+        );
         assert_eq!(name.as_deref(), Some("2024-06-15T12:31_145901k.dat"));
     }
 
     #[test]
     fn mean_of_multiple_signals() {
         // Two signals 120 s apart → mean at +60 s → :31; freqs +0 and +200 → mean +100 Hz → 145900 kHz
-        let signals = [pt(0.0, 0.0), pt(120.0, 200.0)];
+        let signals = [sig(0.0, 0.0), sig(120.0, 200.0)];
         let name = signals_filename(utc(2024, 6, 15, 12, 30), 145_900_000.0, &signals);
         assert_eq!(name.as_deref(), Some("2024-06-15T12:31_145900k.dat"));
     }
@@ -1204,17 +1550,29 @@ mod tests {
     #[test]
     fn minute_boundary_rollover() {
         // Start at 12:59; 61 s offset rolls over to 13:00
-        let name = signals_filename(utc(2024, 6, 15, 12, 59), 437_525_000.0, &[pt(61.0, 0.0)]);
+        let name = signals_filename(utc(2024, 6, 15, 12, 59), 437_525_000.0, &[sig(61.0, 0.0)]);
         assert_eq!(name.as_deref(), Some("2024-06-15T13:00_437525k.dat"));
     }
 
     #[test]
     fn negative_freq_offset() {
         // Negative offset: center 437.525 MHz, −525 Hz → 437524.475 kHz → 437524k
-        let name = signals_filename(utc(2024, 1, 1, 0, 0), 437_525_000.0, &[pt(0.0, -525.0)]);
+        let name = signals_filename(utc(2024, 1, 1, 0, 0), 437_525_000.0, &[sig(0.0, -525.0)]);
         assert_eq!(name.as_deref(), Some("2024-01-01T00:00_437524k.dat"));
     }
 
+    #[test]
+    fn tone_path_goes_before_extension() {
+        assert_eq!(
+            tone_path(std::path::Path::new("/tmp/2024-01-01T00:00_437524k.dat"), 1),
+            std::path::PathBuf::from("/tmp/2024-01-01T00:00_437524k_tone1.dat")
+        );
+        assert_eq!(
+            tone_path(std::path::Path::new("signals"), 0),
+            std::path::PathBuf::from("signals_tone0")
+        );
+    }
+
     #[test]
     fn identity_transform_maps_data_to_pixels() {
         // Sanity check that the test fixture really is an identity x/y mapping.
@@ -1244,7 +1602,7 @@ mod tests {
     #[test]
     fn closest_mark_picks_nearest_across_collections() {
         let track_points = [pt(10.0, 10.0)];
-        let signals = [pt(12.0, 12.0)];
+        let signals = [sig(12.0, 12.0)];
         // Cursor nearer the signal than the track point.
         assert_eq!(
             closest_mark(
@@ -1269,7 +1627,7 @@ mod tests {
 
     #[test]
     fn closest_mark_tolerance_is_inclusive() {
-        let signals = [pt(DELETE_TOLERANCE_PX, 0.0)];
+        let signals = [sig(DELETE_TOLERANCE_PX, 0.0)];
         // Exactly DELETE_TOLERANCE_PX away → still deleted.
         assert_eq!(
             closest_mark(sp(0.0, 0.0), &identity_da_to_screen(), &[], &signals),
@@ -0,0 +1,286 @@
+//! This module contains the processing panel for RFPlot, which applies a [`Pipeline`] (the same
+//! as `rsproc --pipeline`) to the loaded spectrogram.
+
+use iced::{
+    Element, Font, Length, Task,
+    alignment::Vertical,
+    widget::{self, button, container, text, text_input, tooltip},
+};
+use rstrf::spectrogram::{Pipeline, Spectrogram, Stage};
+use serde::{Deserialize, Serialize};
+
+use crate::windows::rfplot;
+
+#[derive(Debug, Clone)]
+#[allow(clippy::large_enum_variant)]
+pub enum Message {
+    Edit(String),
+    Apply,
+    /// A run of the pipeline finished, see [`Processing::finish`]
+    Done(Ticket, Result<Spectrogram, String>),
+}
+
+/// Why the pipeline was run, which decides how the result is shown.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Run {
+    /// A spectrogram was loaded
+    Load,
+    /// The pipeline was changed
+    Apply,
+    /// New spectra arrived in a followed directory
+    Extend,
+}
+
+/// Identifies a run of the pipeline, see [`Processing::finish`].
+#[derive(Debug, Clone, Copy)]
+pub struct Ticket {
+    pub run: Run,
+    /// Increases with every run, so that results older than the shown one can be dropped
+    seq: u64,
+    /// First slice of the unprocessed spectrogram that the run processed. Only runs for new
+    /// spectra of a per-spectrum pipeline start after 0.
+    first: usize,
+    /// Number of slices of the unprocessed spectrogram when the run started
+    end: usize,
+}
+
+#[derive(Serialize, Deserialize, Default, Clone, PartialEq)]
+pub struct Processing {
+    /// The pipeline as typed into the panel
+    definition: String,
+    /// The pipeline that is applied to the spectrogram
+    pipeline: Pipeline,
+    #[serde(skip)]
+    error: Option<String>,
+    /// The spectrogram as it was loaded
+    #[serde(skip)]
+    raw: Option<Spectrogram>,
+    /// Sequence number of the last run that was started
+    #[serde(skip)]
+    seq: u64,
+    /// Sequence number of the run whose result is shown
+    #[serde(skip)]
+    shown_seq: u64,
+    /// Number of slices of the unprocessed spectrogram that the shown result covers
+    #[serde(skip)]
+    shown_slices: usize,
+    #[serde(skip)]
+    running: usize,
+    /// Runs in progress that process the whole spectrogram
+    #[serde(skip)]
+    full_runs: usize,
+    /// New spectra arrived while the whole spectrogram was being processed
+    #[serde(skip)]
+    stale: bool,
+}
+
+impl Processing {
+    /// Keeps the unprocessed spectrogram and starts processing it. Returns `None` if the pipeline
+    /// is empty, in which case `raw` can be shown as it is.
+    pub fn process(&mut self, raw: Spectrogram) -> Option<Task<Message>> {
+        self.raw = Some(raw);
+        self.stale = false;
+        if self.pipeline.is_empty() {
+            self.show_raw();
+            return None;
+        }
+        self.start(Run::Load)
+    }
+
+    /// Appends new spectra of a followed directory to the unprocessed spectrogram, and starts
+    /// processing them. Returns `None` if the pipeline is empty, in which case
+    /// [`Processing::raw`] can be shown as it is.
+    ///
+    /// If the pipeline works on each spectrum on its own, only the spectra that aren't shown yet
+    /// are processed. Otherwise the whole spectrogram is processed again. While that runs, new
+    /// spectra are only collected, and processed afterwards by [`Processing::catch_up`].
+    pub fn extend(&mut self, tail: &Spectrogram) -> anyhow::Result<Option<Task<Message>>> {
+        let raw = self
+            .raw
+            .as_mut()
+            .ok_or_else(|| anyhow::anyhow!("No spectrogram to add the spectra to"))?;
+        raw.splice_from(raw.nslices, tail)?;
+        if self.pipeline.is_empty() {
+            self.show_raw();
+            return Ok(None);
+        }
+        if self.full_runs > 0 {
+            self.stale = true;
+            return Ok(Some(Task::none()));
+        }
+        Ok(self.start(Run::Extend))
+    }
+
+    /// Processes the spectra that arrived while the whole spectrogram was being processed, see
+    /// [`Processing::extend`].
+    pub fn catch_up(&mut self) -> Task<Message> {
+        if !self.stale || self.full_runs > 0 {
+            return Task::none();
+        }
+        self.stale = false;
+        self.start(Run::Extend).unwrap_or_else(Task::none)
+    }
+
+    /// The spectrogram as it was loaded, before processing.
+    pub fn raw(&self) -> Option<&Spectrogram> {
+        self.raw.as_ref()
+    }
+
+    pub fn update(&mut self, message: Message) -> Task<Message> {
+        match message {
+            Message::Edit(definition) => {
+                self.definition = definition;
+                Task::none()
+            }
+            Message::Apply => match self.definition.parse::<Pipeline>() {
+                Ok(pipeline) => {
+                    log::info!("Processing spectrogram with: {pipeline}");
+                    self.pipeline = pipeline;
+                    self.error = None;
+                    self.start(Run::Apply).unwrap_or_else(Task::none)
+                }
+                Err(e) => {
+                    self.error = Some(format!("{e:#}"));
+                    Task::none()
+                }
+            },
+            // Handled by RFPlot, see `finish`
+            Message::Done(..) => Task::none(),
+        }
+    }
+
+    /// Marks the unprocessed spectrogram as shown, so that runs that are still in progress are
+    /// dropped.
+    fn show_raw(&mut self) {
+        self.seq += 1;
+        self.shown_seq = self.seq;
+        self.shown_slices = self.raw.as_ref().map_or(0, |raw| raw.nslices);
+    }
+
+    fn start(&mut self, run: Run) -> Option<Task<Message>> {
+        let raw = self.raw.as_ref()?;
+        let first = match run {
+            Run::Extend if self.pipeline.is_per_spectrum() => self.shown_slices,
+            _ => 0,
+        };
+        let input = if first == 0 {
+            raw.clone()
+        } else {
+            raw.slices_from(first)
+        };
+        self.seq += 1;
+        let ticket = Ticket {
+            run,
+            seq: self.seq,
+            first,
+            end: raw.nslices,
+        };
+        let pipeline = self.pipeline.clone();
+        self.running += 1;
+        if first == 0 {
+            self.full_runs += 1;
+        }
+        Some(Task::future(async move {
+            let result = tokio::task::spawn_blocking(move || {
+                pipeline.apply(&input).map(|mut spec| {
+                    // Results for new spectra are added to the shown spectrogram's pyramid
+                    if first == 0 && spec.pyramid().is_none() {
+                        spec.build_pyramid();
+                    }
+                    spec
+                })
+            })
+            .await
+            .map_err(anyhow::Error::from)
+            .flatten()
+            .map_err(|e| format!("{e:?}"));
+            Message::Done(ticket, result)
+        }))
+    }
+
+    /// Returns the spectrogram to show after a run finished, or `None` if a newer result is
+    /// already shown. If processing failed, this is the unprocessed spectrogram.
+    ///
+    /// Results for new spectra are added to a copy of `shown`, which only copies its last block
+    /// of data (see [`Spectrogram::splice_from`]).
+    pub fn finish(
+        &mut self,
+        ticket: Ticket,
+        result: Result<Spectrogram, String>,
+        shown: Option<&Spectrogram>,
+    ) -> Option<Spectrogram> {
+        self.running = self.running.saturating_sub(1);
+        if ticket.first == 0 {
+            self.full_runs = self.full_runs.saturating_sub(1);
+        }
+        if ticket.seq <= self.shown_seq {
+            log::debug!("Dropping outdated result of processing run {}", ticket.seq);
+            return None;
+        }
+        let spec = match result {
+            Ok(spec) if ticket.first == 0 => spec,
+            Ok(tail) => {
+                let mut spec = shown?.clone();
+                if let Err(e) = spec.splice_from(ticket.first, &tail) {
+                    log::error!("Failed to add processed spectra: {e:?}");
+                    return None;
+                }
+                spec
+            }
+            Err(e) => {
+                log::error!("Failed to process spectrogram: {e}");
+                self.error = Some(e);
+                self.show_raw();
+                return self.raw.clone();
+            }
+        };
+        self.error = None;
+        self.shown_seq = ticket.seq;
+        self.shown_slices = ticket.end;
+        Some(spec)
+    }
+
+    pub fn view(&self) -> Element<'_, rfplot::Message> {
+        let input = text_input(
+            "e.g. bandpass, background(window=20000), snr",
+            &self.definition,
+        )
+        .on_input(|definition| Message::Edit(definition).into())
+        .on_submit(Message::Apply.into())
+        .width(Length::FillPortion(7));
+        let help = tooltip(
+            text("?"),
+            container(text(Stage::HELP).font(Font::MONOSPACE))
+                .padding(5)
+                .style(container::dark),
+            tooltip::Position::Bottom,
+        );
+        let status = if self.running > 0 {
+            "Processing...".to_string()
+        } else if let Some(error) = &self.error {
+            error.clone()
+        } else {
+            String::new()
+        };
+
+        widget::column![
+            widget::row![
+                text("Processing").width(Length::FillPortion(3)),
+                input,
+                button("Apply").on_press(Message::Apply.into()),
+                help,
+            ]
+            .spacing(4)
+            .align_y(Vertical::Center),
+            text(status).size(12),
+        ]
+        .spacing(2)
+        .into()
+    }
+}
+
+impl From<Message> for rfplot::Message {
+    fn from(message: Message) -> Self {
+        rfplot::Message::Processing(message)
+    }
+}
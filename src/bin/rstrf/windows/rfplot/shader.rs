@@ -1,7 +1,8 @@
 //! This module contains the WGPU shader implementation for the RFPlot widget. The shader is
 //! responsible for rendering the spectrogram itself.
-use std::{collections::HashMap, sync::Arc};
+use std::{collections::HashMap, ops::Range, sync::Arc};
 
+use chrono::{DateTime, Utc};
 use glam::{Vec2, vec2};
 use iced::{
     Rectangle, mouse,
@@ -9,21 +10,34 @@ use iced::{
     widget::shader,
 };
 use itertools::{Itertools, izip};
-use rstrf::{colormap::Colormap, spectrogram::Spectrogram};
+use rstrf::{colormap::Colormap, coord::data_normalized, spectrogram::Spectrogram};
 use uuid::Uuid;
 
 use super::{Controls, Message, RFPlot};
 
+/// Chunks appended while following a growing spectrogram are small, so once there are this many
+/// the window is uploaded again in one piece.
+const MAX_CHUNKS: usize = 64;
+
+/// Value uploaded for cells that are flagged as RFI, which the shader draws in grey. Must match
+/// `MASKED` in `shader.wgsl`.
+const MASKED: f32 = -1e30;
+
 #[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
 #[repr(C)]
 pub struct Uniforms {
     power_bounds: Vec2,
+    /// Seconds since the start of the spectrogram
     time_bounds: Vec2,
     freq_bounds: Vec2,
     pixel_height: f32,
     viewport_width: f32,
     nslices: u32,
     nchan: u32,
+    /// First uploaded channel
+    chan_offset: u32,
+    /// Total number of channels of the uploaded spectrogram level
+    nchan_total: u32,
 }
 
 struct SpectrogramChunk {
@@ -41,9 +55,87 @@ struct Buffers {
     spectrogram: Vec<SpectrogramChunk>,
 }
 
+/// The part of a spectrogram (or one of its pyramid levels) that is uploaded to the GPU.
+#[derive(Debug, Clone, PartialEq)]
+struct Window {
+    spectrogram_id: Uuid,
+    /// ID of the full resolution spectrogram, which changes with its RFI mask while the pyramid
+    /// levels stay the same
+    source_id: Uuid,
+    slices: Range<usize>,
+    channels: Range<usize>,
+    /// Number of slices in the level and the full resolution spectrogram when the window was
+    /// last uploaded, to notice when they grew
+    level_nslices: usize,
+    source_nslices: usize,
+}
+
+impl Window {
+    /// The slices and channels of `level` that are visible in `bounds`.
+    ///
+    /// `spectrogram` is the full resolution spectrogram, which defines the normalized time axis.
+    fn visible(
+        spectrogram: &Spectrogram,
+        level: &Spectrogram,
+        bounds: &data_normalized::Rectangle,
+    ) -> Window {
+        let start_time = spectrogram.start_time();
+        let length = spectrogram.length().as_seconds_f32();
+        let x = |t: &DateTime<Utc>| (*t - start_time).as_seconds_f32() / length;
+        let xmin = bounds.0.x;
+        let xmax = bounds.0.x + bounds.0.width;
+        let first_slice = level
+            .timestamps
+            .partition_point(|t| x(t) <= xmin)
+            .saturating_sub(1);
+        let last_slice = level
+            .timestamps
+            .partition_point(|t| x(t) < xmax)
+            .clamp(first_slice + 1, level.nslices);
+
+        let nchan = level.nchan as f32;
+        let first_chan = ((bounds.0.y * nchan).floor().max(0.0) as usize).min(level.nchan - 1);
+        let last_chan = (((bounds.0.y + bounds.0.height) * nchan).ceil() as usize)
+            .clamp(first_chan + 1, level.nchan);
+
+        Window {
+            spectrogram_id: level.id,
+            source_id: spectrogram.id,
+            slices: first_slice..last_slice,
+            channels: first_chan..last_chan,
+            level_nslices: level.nslices,
+            source_nslices: spectrogram.nslices,
+        }
+    }
+
+    /// Grows the window by its own size on each side, so panning doesn't immediately require
+    /// another upload.
+    fn with_margin(self, level: &Spectrogram) -> Window {
+        let grow = |range: Range<usize>, len: usize| {
+            range.start.saturating_sub(range.len())..(range.end + range.len()).min(len)
+        };
+        Window {
+            slices: grow(self.slices, level.nslices),
+            channels: grow(self.channels, level.nchan),
+            ..self
+        }
+    }
+
+    fn contains(&self, other: &Window) -> bool {
+        self.spectrogram_id == other.spectrogram_id
+            && self.source_id == other.source_id
+            && self.slices.start <= other.slices.start
+            && self.slices.end >= other.slices.end
+            && self.channels.start <= other.channels.start
+            && self.channels.end >= other.channels.end
+    }
+}
+
 struct PrimitiveData {
     buffers: Buffers,
+    /// ID of the full resolution spectrogram, to signal when it was first uploaded
     spectrogram_id: Uuid,
+    window: Option<Window>,
     colormap: Colormap,
 }
 
@@ -117,23 +209,107 @@ impl Pipeline {
             return;
         };
 
-        let is_new_entry = !self.instances.contains_key(&primitive.id);
         let primitive_data = self.instances.entry(primitive.id).or_insert_with_key(|id| {
-            Self::create_buffers(
+            Self::create_buffers(device, &self.pipeline, id, primitive.controls.colormap())
+        });
+
+        let bounds = primitive.controls.bounds();
+
+        // Render from the coarsest pyramid level that still has a cell per pixel, so zoomed-out
+        // views don't need the full spectrogram on the GPU.
+        let selected = spectrogram.pyramid().and_then(|pyramid| {
+            pyramid.select(
+                bounds.0.width * spectrogram.nslices as f32 / viewport_bounds.width,
+                bounds.0.height * spectrogram.nchan as f32 / viewport_bounds.height,
+            )
+        });
+        let (level, factors) = selected.map_or((spectrogram, (1, 1)), |level| {
+            (
+                level.spectrogram(primitive.controls.decimation()),
+                (level.time_factor, level.freq_factor),
+            )
+        });
+
+        let visible = Window::visible(spectrogram, level, &bounds);
+        if let Some(window) = primitive_data.window.as_mut()
+            && window.spectrogram_id == level.id
+            && window.source_id == spectrogram.id
+            && window.source_nslices != spectrogram.nslices
+        {
+            // The spectrogram grew. If the uploaded window reaches the old end, upload the new
+            // slices as extra chunks. The last uploaded slice may have changed in a decimated
+            // level, so it is uploaded again; later chunks are drawn over earlier ones.
+            if window.slices.end == window.level_nslices
+                && level.nslices >= window.slices.end
+                && primitive_data.buffers.spectrogram.len() < MAX_CHUNKS
+            {
+                let appended = Window {
+                    slices: window.slices.end.saturating_sub(1)..level.nslices,
+                    ..window.clone()
+                };
+                log::debug!(
+                    "Uploading slices {:?} of a grown {}x{} spectrogram",
+                    appended.slices,
+                    level.nslices,
+                    level.nchan
+                );
+                primitive_data
+                    .buffers
+                    .spectrogram
+                    .extend(Self::create_spectrogram_buffers(
+                        device,
+                        queue,
+                        &self.pipeline,
+                        spectrogram,
+                        level,
+                        factors,
+                        &appended,
+                    ));
+                window.slices.end = level.nslices;
+            } else if window.slices.end == window.level_nslices {
+                // Can't extend the window, so upload it again
+                primitive_data.window = None;
+            }
+            if let Some(window) = primitive_data.window.as_mut() {
+                window.level_nslices = level.nslices;
+                window.source_nslices = spectrogram.nslices;
+            }
+        }
+        if !primitive_data
+            .window
+            .as_ref()
+            .is_some_and(|window| window.contains(&visible))
+        {
+            let window = visible.with_margin(level);
+            log::debug!(
+                "Uploading slices {:?} and channels {:?} of a {}x{} spectrogram",
+                window.slices,
+                window.channels,
+                level.nslices,
+                level.nchan
+            );
+            // Free the previous window before allocating the next one
+            primitive_data.buffers.spectrogram.clear();
+            primitive_data.buffers.spectrogram = Self::create_spectrogram_buffers(
                 device,
                 queue,
                 &self.pipeline,
-                id,
                 spectrogram,
-                primitive.controls.colormap(),
-            )
-        });
+                level,
+                factors,
+                &window,
+            );
+            primitive_data.window = Some(window);
+        }
+        let Some(window) = &primitive_data.window else {
+            return;
+        };
 
-        let bounds = primitive.controls.bounds();
-        let pixel_height = bounds.0.height / viewport_bounds.height * spectrogram.nchan as f32;
+        let pixel_height = bounds.0.height / viewport_bounds.height * level.nchan as f32;
 
-        let xmin = bounds.0.x;
-        let xmax = bounds.0.x + bounds.0.width;
+        let length = spectrogram.length().as_seconds_f32();
+        let xmin = bounds.0.x * length;
+        let xmax = (bounds.0.x + bounds.0.width) * length;
         let vmin = bounds.0.y;
         let vmax = bounds.0.y + bounds.0.height;
 
@@ -143,22 +319,16 @@ impl Pipeline {
                 time_bounds: vec2(xmin, xmax),
                 freq_bounds: vec2(vmin, vmax),
                 nslices: chunk.nslices,
-                nchan: spectrogram.nchan as u32,
+                nchan: window.channels.len() as u32,
+                chan_offset: window.channels.start as u32,
+                nchan_total: level.nchan as u32,
                 pixel_height,
                 viewport_width: viewport_bounds.width,
             };
             queue.write_buffer(&chunk.uniform, 0, bytemuck::bytes_of(&uniforms));
         }
 
-        // TODO: do we need to track primitive ID & spectrogram ID separately?
-        if is_new_entry {
-            // Buffers were already created inside create_buffers; just signal upload done.
-            if let Some(notify) = &primitive.gpu_notify {
-                notify.notify_one();
-            }
-        } else if primitive_data.spectrogram_id != spectrogram.id {
-            primitive_data.buffers.spectrogram =
-                Self::create_spectrogram_buffers(device, queue, &self.pipeline, spectrogram);
+        if primitive_data.spectrogram_id != spectrogram.id {
             primitive_data.spectrogram_id = spectrogram.id;
             if let Some(notify) = &primitive.gpu_notify {
                 notify.notify_one();
@@ -177,10 +347,8 @@ impl Pipeline {
 
     fn create_buffers(
         device: &wgpu::Device,
-        queue: &wgpu::Queue,
         pipeline: &wgpu::RenderPipeline,
         id: &Uuid,
-        spectrogram: &Spectrogram,
         colormap: Colormap,
     ) -> PrimitiveData {
         let prefix = format!("spectrogram.{}", id);
@@ -200,63 +368,80 @@ impl Pipeline {
             }],
         });
 
-        let spectrogram_id = spectrogram.id;
-        let spectrogram = Self::create_spectrogram_buffers(device, queue, pipeline, spectrogram);
-
         PrimitiveData {
             buffers: Buffers {
                 colormap: colormap_buffer,
                 colormap_bind: colormap_bind_group,
-                spectrogram,
+                spectrogram: Vec::new(),
             },
-            spectrogram_id,
+            spectrogram_id: Uuid::nil(),
+            window: None,
             colormap,
         }
     }
 
+    /// Uploads `window` of `level`, which is either `spectrogram` or one of its pyramid levels
+    /// with the given time and frequency decimation factors.
     fn create_spectrogram_buffers(
         device: &wgpu::Device,
         queue: &wgpu::Queue,
         pipeline: &wgpu::RenderPipeline,
         spectrogram: &Spectrogram,
+        level: &Spectrogram,
+        (time_factor, freq_factor): (usize, usize),
+        window: &Window,
     ) -> Vec<SpectrogramChunk> {
+        let nchan = window.channels.len();
         let limits = device.limits();
         let max_buf_size =
             (limits.max_storage_buffer_binding_size as u64).min(limits.max_buffer_size) as usize;
-        let data = spectrogram.data.as_slice().unwrap();
-        let chunk_len = (max_buf_size / (std::mem::size_of::<f32>() * spectrogram.nchan))
-            .min(data.len() / spectrogram.nchan);
+        let chunk_len =
+            (max_buf_size / (std::mem::size_of::<f32>() * nchan)).min(window.slices.len());
         if chunk_len == 0 {
             log::error!(
                 "Spectrogram is too large to render ({} bytes per slice, max buffer size is {})",
-                spectrogram.nchan * std::mem::size_of::<f32>(),
+                nchan * std::mem::size_of::<f32>(),
                 max_buf_size
             );
             return Vec::new();
         }
 
-        let prefix = format!("spectrogram.{}", spectrogram.id);
+        let prefix = format!("spectrogram.{}", level.id);
+        // Pyramid levels are positioned on the time axis of the full resolution spectrogram. The
+        // ranges are in seconds since its start rather than normalized, so they stay valid when
+        // the spectrogram grows.
         let start_time = spectrogram.start_time();
-        let timestamps = spectrogram
-            .timestamps
+        let timestamps = level.timestamps[window.slices.clone()]
             .iter()
             .map(|t| (*t - start_time).as_seconds_f32());
-        let length = spectrogram.length().as_seconds_f32();
-        let x_ranges = izip!(timestamps, spectrogram.lengths.iter())
-            .map(|(t, len)| {
-                let left = t / length;
-                let right = (t + len) / length;
-                vec2(left, right)
-            })
+        let x_ranges = izip!(timestamps, level.lengths[window.slices.clone()].iter())
+            .map(|(t, len)| vec2(t, t + len))
             .collect_vec();
+        let mask = spectrogram
+            .mask()
+            .map(|mask| mask.decimate(time_factor, freq_factor));
 
         izip!(
-            data.chunks(chunk_len * spectrogram.nchan),
+            window.slices.clone().step_by(chunk_len),
             x_ranges.chunks(chunk_len),
         )
-        .enumerate()
-        .map(|(i, (chunk, x_ranges_chunk))| {
-            let prefix = format!("{}.chunk{}", prefix, i);
+        .map(|(first_slice, x_ranges_chunk)| {
+            // Only convert one chunk at a time, so memory-mapped spectrograms never need to be
+            // fully loaded.
+            let slices = first_slice..first_slice + x_ranges_chunk.len();
+            let tile = level.tile(slices.clone(), window.channels.clone());
+            let mut tile = tile.as_standard_layout();
+            if let Some(mask) = &mask {
+                let mut masked = tile.into_owned();
+                for ((i, f), value) in masked.indexed_iter_mut() {
+                    if mask.is_masked(slices.start + i, window.channels.start + f) {
+                        *value = MASKED;
+                    }
+                }
+                tile = masked.into();
+            }
+            let chunk = tile.as_slice().unwrap();
+            let prefix = format!("{}.slice{}", prefix, first_slice);
             log::debug!(
                 "Creating chunk {} ({} bytes)",
                 prefix,
@@ -274,7 +459,7 @@ impl Pipeline {
                     vec2(0.0, 1.0),
                 ]),
             });
-            let nslices = (chunk.len() / spectrogram.nchan) as u64;
+            let nslices = (chunk.len() / nchan) as u64;
             let instance_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
                 label: Some(format!("{prefix}.buffer.instance").as_str()),
                 contents: bytemuck::cast_slice(&(0..nslices as u32).collect::<Vec<_>>()),
@@ -1,3 +1,7 @@
+// Value of cells that are flagged as RFI, see `MASKED` in `shader.rs`
+const MASKED: f32 = -1e30;
+const MASKED_COLOR: vec4f = vec4f(0.5, 0.5, 0.5, 1.0);
+
 struct Uniforms {
     power_bounds: vec2f,
     time_bounds: vec2f,
@@ -6,6 +10,8 @@ struct Uniforms {
     viewport_width: f32,
     nslices: u32,
     nchan: u32,
+    chan_offset: u32,
+    nchan_total: u32,
 }
 
 @group(0) @binding(0) var<storage, read> color_map: array<vec4f>;
@@ -47,6 +53,9 @@ fn vs_main(in: VertexIn) -> VertexOut {
 @fragment
 fn fs_main(in: VertexOut) -> FragOut {
     let value = get_value(in.u, in.v);
+    if value <= MASKED {
+        return FragOut(1.0, MASKED_COLOR);
+    }
 
     let normalized = clamp((value - uniforms.power_bounds.x) / (uniforms.power_bounds.y - uniforms.power_bounds.x), 0.0, 1.0);
 
@@ -65,13 +74,20 @@ fn fs_main(in: VertexOut) -> FragOut {
 
 fn get_value(u: u32, v: f32) -> f32 {
     let time_idx = clamp(u, 0u, uniforms.nslices - 1u);
-    let freq_idx = v * f32(uniforms.nchan);
+    // Only a window of the channels is uploaded
+    let freq_idx = max(v * f32(uniforms.nchan_total) - f32(uniforms.chan_offset), 0.0);
     var value = uniforms.power_bounds.x;
+    var masked = true;
     let n_y = u32(ceil(uniforms.pixel_height));
     for (var f = 0u; f < n_y; f++) {
         let freq_idx = clamp(u32(freq_idx) + f, 0u, uniforms.nchan - 1u);
         let idx = time_idx * uniforms.nchan + freq_idx;
-        value = max(value, spec_data[idx]);
+        let cell = spec_data[idx];
+        // Pixels covering masked and unmasked cells show the unmasked ones
+        if cell > MASKED {
+            value = max(value, cell);
+            masked = false;
+        }
     }
-    return value;
+    return select(value, MASKED, masked);
 }
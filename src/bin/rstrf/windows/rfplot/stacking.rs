@@ -0,0 +1,203 @@
+//! This module contains the stacking panel for RFPlot, which stacks the spectra along the predicted
+//! Doppler curve of a satellite (see [`signal::stack_along`]) to find signals that are too weak to
+//! be seen in single spectra.
+
+use iced::{
+    Element, Length,
+    alignment::Vertical,
+    widget::{self, button, pick_list, slider, text},
+};
+use plotters::prelude::*;
+use plotters_iced2::{Chart, ChartWidget};
+use rstrf::{
+    coord::data_absolute,
+    signal::{self, StackedSpectrum},
+    spectrogram::Spectrogram,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::windows::rfplot::{self, autotrack::Target, control::Controls};
+
+const SPAN_MIN_HZ: f32 = 1e3;
+const SPAN_MAX_HZ: f32 = 100e3;
+/// Height of the stacked spectrum plot
+const PLOT_HEIGHT: f32 = 120.0;
+
+#[derive(Debug, Clone)]
+pub enum Message {
+    SelectTarget(Target),
+    UpdateSpan(f32),
+    /// Stack the spectra along the passes of the selected target, handled by the overlay
+    Stack,
+    Stacked(Option<StackedSpectrum>),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Stacking {
+    target: Option<Target>,
+    /// Width of the stacked spectrum in Hz
+    span: f32,
+    #[serde(skip)]
+    result: Option<StackPlot>,
+    #[serde(skip)]
+    running: bool,
+}
+
+impl Default for Stacking {
+    fn default() -> Self {
+        Self {
+            target: None,
+            span: 20e3,
+            result: None,
+            running: false,
+        }
+    }
+}
+
+impl Stacking {
+    pub fn update(&mut self, message: Message) {
+        match message {
+            Message::SelectTarget(target) => self.target = Some(target),
+            Message::UpdateSpan(span) => self.span = span,
+            // Handled by the overlay, see `start`
+            Message::Stack => (),
+            Message::Stacked(result) => {
+                match result.as_ref().and_then(StackedSpectrum::peak) {
+                    Some(peak) => log::info!(
+                        "Stacked spectrum peaks at {:+.1} Hz with {:.1} dB SNR",
+                        peak.offset,
+                        peak.snr
+                    ),
+                    None => log::warn!("No spectra to stack"),
+                }
+                self.result = result.map(StackPlot);
+                self.running = false;
+            }
+        }
+    }
+
+    /// Marks the stacking as running and returns the span.
+    pub fn start(&mut self) -> f32 {
+        self.running = true;
+        self.span
+    }
+
+    pub fn target(&self) -> Option<Target> {
+        self.target
+    }
+
+    pub fn view(&self, targets: Vec<Target>) -> Element<'_, rfplot::Message> {
+        let stack = button(if self.running { "Stacking..." } else { "Stack" })
+            .style(button::primary)
+            .on_press_maybe(
+                (self.target.is_some() && !self.running).then(|| Message::Stack.into()),
+            );
+        let mut result = widget::column![
+            widget::row![
+                text("Stacking").width(Length::FillPortion(3)),
+                pick_list(targets, self.target, |target| {
+                    Message::SelectTarget(target).into()
+                })
+                .placeholder("Satellite")
+                .width(Length::FillPortion(7)),
+                stack,
+            ]
+            .spacing(4)
+            .align_y(Vertical::Center),
+            Controls::control(
+                "Span",
+                slider(SPAN_MIN_HZ..=SPAN_MAX_HZ, self.span, |span| {
+                    Message::UpdateSpan(span).into()
+                })
+                .step(100.0)
+                .width(Length::Fill),
+                format!("{:.1} kHz", self.span / 1e3),
+            ),
+        ]
+        .spacing(8);
+        if let Some(plot) = &self.result {
+            let stacked = &plot.0;
+            let summary = match stacked.peak() {
+                Some(peak) => format!(
+                    "Offset {:+.1} Hz  SNR {:.1} dB  {:.0} sigma  ({} spectra)",
+                    peak.offset, peak.snr, peak.sigma, stacked.spectra
+                ),
+                None => "No data".to_owned(),
+            };
+            result = result.push(text(summary).size(12)).push(
+                ChartWidget::new(plot)
+                    .width(Length::Fill)
+                    .height(Length::Fixed(PLOT_HEIGHT)),
+            );
+        }
+        result.into()
+    }
+}
+
+/// Plot of the stacked spectrum
+#[derive(Debug, Clone, PartialEq)]
+struct StackPlot(StackedSpectrum);
+
+impl Chart<rfplot::Message> for StackPlot {
+    type State = ();
+
+    fn build_chart<DB: DrawingBackend>(&self, _state: &Self::State, mut chart: ChartBuilder<DB>) {
+        let stacked = &self.0;
+        let points: Vec<(f32, f32)> = stacked
+            .power
+            .iter()
+            .enumerate()
+            .filter(|(_, p)| !p.is_nan())
+            .map(|(i, &p)| (stacked.offset(i as f32) / 1e3, p))
+            .collect();
+        let (Some(&(x_min, _)), Some(&(x_max, _))) = (points.first(), points.last()) else {
+            return;
+        };
+        let (y_min, y_max) = points
+            .iter()
+            .fold((f32::INFINITY, f32::NEG_INFINITY), |(lo, hi), &(_, p)| {
+                (lo.min(p), hi.max(p))
+            });
+        let margin = ((y_max - y_min) * 0.1).max(0.1);
+        let chart = chart
+            .x_label_area_size(20)
+            .y_label_area_size(40)
+            .build_cartesian_2d(x_min..x_max, (y_min - margin)..(y_max + margin));
+        let mut chart = match chart {
+            Ok(chart) => chart,
+            Err(e) => {
+                log::error!("Could not build stacked spectrum chart: {:?}", e);
+                return;
+            }
+        };
+        let mesh = chart
+            .configure_mesh()
+            .x_desc("Offset (kHz)")
+            .max_light_lines(0)
+            .axis_style(WHITE)
+            .label_style(&WHITE)
+            .bold_line_style(WHITE.mix(0.4))
+            .draw();
+        if let Err(e) = mesh {
+            log::error!("Could not draw stacked spectrum axes: {:?}", e);
+        }
+        if let Err(e) = chart.draw_series(LineSeries::new(points, CYAN.stroke_width(1))) {
+            log::error!("Could not draw stacked spectrum: {:?}", e);
+        }
+    }
+}
+
+impl From<Message> for rfplot::Message {
+    fn from(message: Message) -> Self {
+        rfplot::overlay::Message::Stacking(message).into()
+    }
+}
+
+/// Stacks the spectra along the passes, see [`signal::stack_along`].
+pub fn stack(
+    spectrogram: &Spectrogram,
+    passes: &[Vec<data_absolute::Point>],
+    span: f32,
+) -> Message {
+    Message::Stacked(signal::stack_along(spectrogram, passes, span))
+}
@@ -75,7 +75,7 @@ mod tests {
             for (i, entry) in cm.buffer().iter().enumerate() {
                 for (c, &channel) in entry.iter().enumerate() {
                     assert!(
-                        channel >= 0.0 && channel <= 1.0,
+                        (0.0..=1.0).contains(&channel),
                         "{:?}[{}][{}] = {} out of [0, 1]",
                         cm,
                         i,
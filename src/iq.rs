@@ -0,0 +1,328 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Turning raw IQ recordings into spectra.
+
+use std::sync::Arc;
+
+use anyhow::{Result, ensure};
+use chrono::{DateTime, Duration, Utc};
+use clap::ValueEnum;
+use rustfft::{Fft, FftPlanner, num_complex::Complex32};
+use strum::Display;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use crate::spectrogram::{RawStrfSpectrum, SpectrogramParams};
+
+/// Sample formats for raw interleaved IQ data (little endian).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Display)]
+#[strum(serialize_all = "lowercase")]
+pub enum SampleFormat {
+    /// 32-bit float I/Q
+    Cf32,
+    /// Signed 16-bit integer I/Q
+    Cs16,
+    /// Signed 8-bit integer I/Q
+    Cs8,
+    /// Unsigned 8-bit integer I/Q (e.g. RTL-SDR)
+    Cu8,
+}
+
+impl SampleFormat {
+    /// Size of one complex sample in bytes.
+    pub fn sample_size(&self) -> usize {
+        match self {
+            SampleFormat::Cf32 => 8,
+            SampleFormat::Cs16 => 4,
+            SampleFormat::Cs8 | SampleFormat::Cu8 => 2,
+        }
+    }
+
+    /// Maps a SigMF `core:datatype` (e.g. `ci16_le`) to a sample format.
+    ///
+    /// Returns `None` for real-valued, big-endian or otherwise unsupported types.
+    pub fn from_sigmf(datatype: &str) -> Option<Self> {
+        match datatype {
+            "cf32_le" => Some(SampleFormat::Cf32),
+            "ci16_le" => Some(SampleFormat::Cs16),
+            "ci8" => Some(SampleFormat::Cs8),
+            "cu8" => Some(SampleFormat::Cu8),
+            _ => None,
+        }
+    }
+
+    /// Decodes complete samples from `bytes`, scaled to roughly [-1, 1].
+    ///
+    /// Trailing bytes that don't form a complete sample are ignored.
+    pub fn decode(&self, bytes: &[u8]) -> Vec<Complex32> {
+        let size = self.sample_size();
+        let half = size / 2;
+        let component = |b: &[u8]| -> f32 {
+            match self {
+                SampleFormat::Cf32 => f32::from_le_bytes([b[0], b[1], b[2], b[3]]),
+                SampleFormat::Cs16 => i16::from_le_bytes([b[0], b[1]]) as f32 / 32768.0,
+                SampleFormat::Cs8 => b[0] as i8 as f32 / 128.0,
+                SampleFormat::Cu8 => (b[0] as f32 - 127.5) / 127.5,
+            }
+        };
+        bytes
+            .chunks_exact(size)
+            .map(|s| Complex32::new(component(&s[..half]), component(&s[half..])))
+            .collect()
+    }
+}
+
+/// Parameters for turning IQ samples into spectra.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FftParams {
+    /// Center frequency in Hz
+    pub freq: f64,
+    /// Sample rate in Hz
+    pub samp_rate: f64,
+    /// Number of frequency channels (FFT size)
+    pub nchan: usize,
+    /// Integration time per spectrum in seconds
+    pub tint: f32,
+}
+
+impl FftParams {
+    /// Number of FFTs that are averaged into one spectrum.
+    pub fn nint(&self) -> usize {
+        ((self.tint as f64 * self.samp_rate / self.nchan as f64).round() as usize).max(1)
+    }
+
+    /// Actual length of one spectrum in seconds, after rounding to a whole number of FFTs.
+    pub fn spectrum_length(&self) -> f64 {
+        (self.nint() * self.nchan) as f64 / self.samp_rate
+    }
+
+    pub fn spectrogram_params(&self) -> SpectrogramParams {
+        SpectrogramParams {
+            freq: self.freq as f32,
+            bw: self.samp_rate as f32,
+            nchan: self.nchan,
+        }
+    }
+}
+
+/// Averages FFTs of consecutive IQ samples into integrated power spectra, like STRF's `rffft`.
+///
+/// Spectra are FFT-shifted so that channel 0 is at the lowest frequency.
+pub struct Integrator {
+    params: FftParams,
+    nint: usize,
+    fft: Arc<dyn Fft<f32>>,
+    start_time: DateTime<Utc>,
+    /// Samples that don't yet fill a whole FFT
+    pending: Vec<Complex32>,
+    scratch: Vec<Complex32>,
+    accumulator: Vec<f32>,
+    /// Number of FFTs in `accumulator`
+    nfft: usize,
+    /// Number of spectra emitted so far
+    nspectra: usize,
+}
+
+impl Integrator {
+    pub fn new(params: FftParams, start_time: DateTime<Utc>) -> Result<Self> {
+        ensure!(params.nchan > 0, "Number of channels must be positive");
+        ensure!(params.samp_rate > 0.0, "Sample rate must be positive");
+        ensure!(params.tint > 0.0, "Integration time must be positive");
+        let fft = FftPlanner::new().plan_fft_forward(params.nchan);
+        Ok(Self {
+            nint: params.nint(),
+            scratch: vec![Complex32::default(); fft.get_inplace_scratch_len()],
+            accumulator: vec![0.0; params.nchan],
+            pending: Vec::with_capacity(params.nchan),
+            params,
+            fft,
+            start_time,
+            nfft: 0,
+            nspectra: 0,
+        })
+    }
+
+    pub fn params(&self) -> &FftParams {
+        &self.params
+    }
+
+    /// Feeds samples into the integrator, returning all spectra that were completed by them.
+    pub fn push(&mut self, samples: &[Complex32]) -> Vec<RawStrfSpectrum> {
+        let nchan = self.params.nchan;
+        let mut spectra = Vec::new();
+        let mut samples = samples;
+        while !samples.is_empty() {
+            let take = (nchan - self.pending.len()).min(samples.len());
+            self.pending.extend_from_slice(&samples[..take]);
+            samples = &samples[take..];
+            if self.pending.len() < nchan {
+                break;
+            }
+
+            self.fft
+                .process_with_scratch(&mut self.pending, &mut self.scratch);
+            for (i, v) in self.pending.iter().enumerate() {
+                self.accumulator[(i + nchan / 2) % nchan] += v.norm_sqr();
+            }
+            self.pending.clear();
+            self.nfft += 1;
+
+            if self.nfft == self.nint {
+                spectra.push(self.emit());
+            }
+        }
+        spectra
+    }
+
+    fn emit(&mut self) -> RawStrfSpectrum {
+        let norm = (self.nint * self.params.nchan) as f32;
+        let power_linear = self.accumulator.iter().map(|v| v / norm).collect();
+        self.accumulator.fill(0.0);
+        self.nfft = 0;
+
+        let length = self.params.spectrum_length();
+        let offset = Duration::microseconds((self.nspectra as f64 * length * 1e6).round() as i64);
+        self.nspectra += 1;
+        RawStrfSpectrum {
+            time: self.start_time + offset,
+            length_s: length as f32,
+            power_linear,
+        }
+    }
+}
+
+/// Reads raw IQ data in blocks of whole samples.
+pub struct IqReader<R> {
+    reader: R,
+    format: SampleFormat,
+    buf: Vec<u8>,
+}
+
+impl<R: AsyncRead + Unpin> IqReader<R> {
+    /// Creates a reader that returns up to `block_samples` samples per call.
+    pub fn new(reader: R, format: SampleFormat, block_samples: usize) -> Self {
+        Self {
+            reader,
+            format,
+            buf: vec![0u8; block_samples.max(1) * format.sample_size()],
+        }
+    }
+
+    /// Reads the next block of samples. Returns `None` at the end of the input.
+    pub async fn next_block(&mut self) -> Result<Option<Vec<Complex32>>> {
+        let mut filled = 0;
+        while filled < self.buf.len() {
+            let n = self.reader.read(&mut self.buf[filled..]).await?;
+            if n == 0 {
+                break;
+            }
+            filled += n;
+        }
+        if filled < self.format.sample_size() {
+            return Ok(None);
+        }
+        Ok(Some(self.format.decode(&self.buf[..filled])))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    fn test_start() -> DateTime<Utc> {
+        NaiveDate::from_ymd_opt(2024, 1, 1)
+            .unwrap()
+            .and_hms_opt(0, 0, 0)
+            .unwrap()
+            .and_utc()
+    }
+
+    fn tone(samp_rate: f64, freq: f64, n: usize) -> Vec<Complex32> {
+        (0..n)
+            .map(|i| {
+                let phase = 2.0 * std::f64::consts::PI * freq * i as f64 / samp_rate;
+                Complex32::new(phase.cos() as f32, phase.sin() as f32)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn decode_scales_integer_formats() {
+        let cs16 = SampleFormat::Cs16.decode(&[0x00, 0x40, 0x00, 0xc0]);
+        assert_eq!(cs16, vec![Complex32::new(0.5, -0.5)]);
+
+        let cs8 = SampleFormat::Cs8.decode(&[0x40, 0xc0]);
+        assert_eq!(cs8, vec![Complex32::new(0.5, -0.5)]);
+
+        let cu8 = SampleFormat::Cu8.decode(&[255, 0]);
+        assert_eq!(cu8, vec![Complex32::new(1.0, -1.0)]);
+    }
+
+    #[test]
+    fn decode_ignores_incomplete_trailing_sample() {
+        let mut bytes = Vec::new();
+        bytes.extend(1.0f32.to_le_bytes());
+        bytes.extend(2.0f32.to_le_bytes());
+        bytes.extend([0, 0, 0]);
+        assert_eq!(
+            SampleFormat::Cf32.decode(&bytes),
+            vec![Complex32::new(1.0, 2.0)]
+        );
+    }
+
+    #[test]
+    fn integrator_rounds_to_whole_ffts() {
+        let params = FftParams {
+            freq: 437e6,
+            samp_rate: 48000.0,
+            nchan: 1000,
+            tint: 1.0,
+        };
+        assert_eq!(params.nint(), 48);
+        assert!((params.spectrum_length() - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn integrator_puts_tone_in_matching_channel() {
+        let params = FftParams {
+            freq: 0.0,
+            samp_rate: 1024.0,
+            nchan: 64,
+            tint: 0.25,
+        };
+        let mut integrator = Integrator::new(params, test_start()).unwrap();
+        // One channel is 16 Hz wide and channel 32 is centered on 0 Hz
+        let spectra = integrator.push(&tone(1024.0, 160.0, 1024));
+        assert_eq!(spectra.len(), 4);
+
+        for spec in &spectra {
+            let peak = spec
+                .power_linear
+                .iter()
+                .enumerate()
+                .max_by(|a, b| a.1.total_cmp(b.1))
+                .unwrap()
+                .0;
+            assert_eq!(peak, 42);
+        }
+    }
+
+    #[test]
+    fn integrator_timestamps_are_contiguous_across_pushes() {
+        let params = FftParams {
+            freq: 0.0,
+            samp_rate: 1000.0,
+            nchan: 100,
+            tint: 0.5,
+        };
+        let mut integrator = Integrator::new(params, test_start()).unwrap();
+        let samples = tone(1000.0, 0.0, 1000);
+        let mut spectra = integrator.push(&samples[..333]);
+        spectra.extend(integrator.push(&samples[333..]));
+
+        assert_eq!(spectra.len(), 2);
+        assert_eq!(spectra[0].time, test_start());
+        assert_eq!(spectra[1].time, test_start() + Duration::milliseconds(500));
+        assert!(spectra.iter().all(|s| (s.length_s - 0.5).abs() < 1e-6));
+    }
+}
@@ -3,8 +3,10 @@
 pub mod async_cache;
 pub mod colormap;
 pub mod coord;
+pub mod iq;
 pub mod menu;
 pub mod orbit;
+pub mod sigmf;
 pub mod signal;
 pub mod spectrogram;
 pub mod util;
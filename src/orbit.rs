@@ -9,10 +9,16 @@ use serde::{Deserialize, Serialize};
 use sgp4::Prediction;
 use tokio::io::AsyncBufReadExt;
 
-use crate::util::pred_ranges;
+use crate::{coord::data_absolute, util::pred_ranges};
 
 use super::util::minmax;
 
+mod fit;
+pub use fit::{
+    FitParameter, Measurement, OrbitFit, fit_orbit, format_tle, format_tle_with_name,
+    load_measurements, parse_measurement, residuals,
+};
+
 pub type Transmitters = HashMap<u64, Vec<f64>>;
 
 /// Loads frequencies from a strf-style frequencies.txt file
@@ -102,6 +108,17 @@ pub async fn load_tles(
     Ok(elements)
 }
 
+/// The path to STRF's sites.txt: `$ST_SITES_TXT`, or `$ST_DATADIR/data/sites.txt`
+pub fn strf_sites_path() -> anyhow::Result<PathBuf> {
+    std::env::var("ST_SITES_TXT")
+        .map(PathBuf::from)
+        .or_else(|_| {
+            std::env::var("ST_DATADIR")
+                .map(|dir| [dir.as_str(), "data", "sites.txt"].iter().collect())
+                .context("Neither ST_SITES_TXT nor ST_DATADIR are set")
+        })
+}
+
 /// Loads sites from an STRF sites.txt file
 pub async fn load_strf_sites(path: &PathBuf) -> anyhow::Result<HashMap<i32, Site>> {
     let file = tokio::fs::File::open(path).await?;
@@ -137,6 +154,11 @@ pub async fn load_strf_sites(path: &PathBuf) -> anyhow::Result<HashMap<i32, Site
 const RADIUS_EARTH: f64 = 6378.137; // km
 const SPEED_OF_LIGHT: f64 = 299792.458; // km/s
 
+/// The received frequency of a transmitter at `tx_freq` with range rate `range_rate` (in km/s).
+fn doppler_shift(range_rate: f64, tx_freq: f64) -> f64 {
+    (1.0 - range_rate / SPEED_OF_LIGHT) * tx_freq
+}
+
 #[derive(Debug, Clone, Serialize)]
 pub struct Satellite {
     pub elements: sgp4::Elements,
@@ -199,6 +221,29 @@ impl Satellite {
         Ok(prediction)
     }
 
+    /// Range rate (in km/s) and zenith angle (in radians) of the satellite as seen from `site`.
+    pub fn observe(&self, time: &NaiveDateTime, site: &Site) -> anyhow::Result<(f64, f64)> {
+        let prediction = self.predict(time)?;
+        let site_prediction = site.at_time(time);
+        let site_pos = arr1(&site_prediction.position);
+        let delta_pos = arr1(&prediction.position) - &site_pos;
+        let range = delta_pos.norm();
+        let angle = (delta_pos.dot(&site_pos) / (range * RADIUS_EARTH)).acos();
+        let delta_vel = arr1(&prediction.velocity) - arr1(&site_prediction.velocity);
+        Ok((delta_pos.dot(&delta_vel) / range, angle))
+    }
+
+    /// The frequency of a transmitter at `tx_freq` as received at `site`.
+    pub fn doppler_frequency(
+        &self,
+        time: &NaiveDateTime,
+        site: &Site,
+        tx_freq: f64,
+    ) -> anyhow::Result<f64> {
+        let (range_rate, _) = self.observe(time, site)?;
+        Ok(doppler_shift(range_rate, tx_freq))
+    }
+
     pub fn predict_passes(
         &self,
         start: DateTime<Utc>,
@@ -223,8 +268,8 @@ impl Satellite {
             .for_each(|&t, rr, angle| {
                 let t = (start + chrono::Duration::milliseconds((t * 1000.0).round() as i64))
                     .naive_utc();
-                let prediction = match self.predict(&t) {
-                    Ok(prediction) => prediction,
+                let (range_rate, zenith_angle) = match self.observe(&t, site) {
+                    Ok(observation) => observation,
                     Err(e) => {
                         if !warned {
                             log::warn!(
@@ -240,17 +285,11 @@ impl Satellite {
                         return;
                     }
                 };
-                let site_prediction = site.at_time(&t);
-                let site_pos = arr1(&site_prediction.position);
-                let delta_pos = arr1(&prediction.position) - &site_pos;
-                let range = delta_pos.norm();
-                *angle = (delta_pos.dot(&site_pos) / (range * RADIUS_EARTH)).acos();
+                *angle = zenith_angle;
                 if below_horizon(*angle) {
                     return;
                 }
-
-                let delta_vel = arr1(&prediction.velocity) - arr1(&site_prediction.velocity);
-                *rr = delta_pos.dot(&delta_vel) / range;
+                *rr = range_rate;
             });
         let passes = pred_ranges(&angles, |a| !below_horizon(a));
         passes
@@ -263,7 +302,7 @@ impl Satellite {
                     .map(|&tx_freq| {
                         range_rates
                             .slice(s![time_range.clone()])
-                            .mapv(|rr| (1.0 - rr / SPEED_OF_LIGHT) * tx_freq)
+                            .mapv(|rr| doppler_shift(rr, tx_freq))
                     })
                     .collect(),
                 za: angles.slice(s![time_range.clone()]).to_owned(),
@@ -286,6 +325,27 @@ pub struct PassPrediction {
     pub za: Array1<f64>,
 }
 
+impl PassPrediction {
+    /// The predicted frequency of a transmitter during the pass, relative to `center_freq`, at the
+    /// prediction `times`. `None` if the satellite has no such transmitter.
+    pub fn curve(
+        &self,
+        times: ArrayView1<f64>,
+        transmitter: usize,
+        center_freq: f64,
+    ) -> Option<Vec<data_absolute::Point>> {
+        let freqs = self.frequencies.get(transmitter)?;
+        let times = times.slice(s![self.time_range.clone()]);
+        Some(
+            times
+                .iter()
+                .zip(freqs)
+                .map(|(&t, &f)| data_absolute::Point::new(t as f32, (f - center_freq) as f32))
+                .collect(),
+        )
+    }
+}
+
 impl std::fmt::Debug for PassPrediction {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("PassPrediction")
@@ -0,0 +1,573 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Fitting of orbital elements and the transmitter frequency to Doppler measurements, like STRF's
+//! `rffit`.
+//!
+//! The measurements are the `.dat` files written by rstrf (or `rfplot`). The selected elements are
+//! adjusted by Levenberg-Marquardt least squares until the SGP4 Doppler curve matches the measured
+//! frequencies, with the Jacobian taken by finite differences.
+
+use std::collections::HashMap;
+
+use anyhow::Context;
+use chrono::{DateTime, Datelike, NaiveDateTime, Timelike, Utc};
+use serde::{Deserialize, Serialize};
+use strum::{Display, EnumString, VariantArray};
+use tokio::io::AsyncBufReadExt;
+
+use super::{Satellite, Site};
+
+/// Give up on a fit after this many iterations
+const MAX_ITERATIONS: usize = 100;
+/// A fit has converged when an iteration improves the sum of squares by less than this fraction
+const CONVERGENCE: f64 = 1e-10;
+/// Largest damping of the Levenberg-Marquardt steps before a fit is considered stuck
+const MAX_DAMPING: f64 = 1e12;
+
+/// A Doppler measurement from a `.dat` file.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Measurement {
+    pub time: DateTime<Utc>,
+    /// Received frequency in Hz
+    pub freq: f64,
+    /// Significance of the signal, see [`Signal::sigma`](crate::signal::Signal::sigma)
+    pub sigma: f32,
+    /// STRF site ID of the receiver
+    pub site_id: i32,
+}
+
+/// Parses a line of a `.dat` file: MJD, frequency in Hz, significance and site ID. Empty lines and
+/// comments give `None`.
+pub fn parse_measurement(line: &str) -> anyhow::Result<Option<Measurement>> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return Ok(None);
+    }
+    let parts: Vec<&str> = line.split_whitespace().collect();
+    anyhow::ensure!(
+        parts.len() == 4,
+        "Expected 4 columns in .dat file, got: {}",
+        line
+    );
+    let mjd: f64 = parts[0]
+        .parse()
+        .with_context(|| format!("Failed to parse MJD from {}", parts[0]))?;
+    let time = DateTime::from_timestamp_millis(((mjd - 40587.0) * 86_400_000.0).round() as i64)
+        .with_context(|| format!("MJD {mjd} is out of range"))?;
+    Ok(Some(Measurement {
+        time,
+        freq: parts[1]
+            .parse()
+            .with_context(|| format!("Failed to parse frequency from {}", parts[1]))?,
+        sigma: parts[2]
+            .parse()
+            .with_context(|| format!("Failed to parse sigma from {}", parts[2]))?,
+        site_id: parts[3]
+            .parse()
+            .with_context(|| format!("Failed to parse site ID from {}", parts[3]))?,
+    }))
+}
+
+/// Loads the measurements of a `.dat` file
+pub async fn load_measurements(path: &std::path::Path) -> anyhow::Result<Vec<Measurement>> {
+    let file = tokio::fs::File::open(path).await?;
+    let reader = tokio::io::BufReader::new(file);
+    let mut measurements = Vec::new();
+    let mut lines = reader.lines();
+    while let Some(line) = lines.next_line().await? {
+        measurements.extend(parse_measurement(&line)?);
+    }
+    Ok(measurements)
+}
+
+/// The quantities that [`fit_orbit`] can adjust.
+#[derive(
+    Debug,
+    Display,
+    EnumString,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    Hash,
+    Serialize,
+    Deserialize,
+    VariantArray,
+)]
+#[strum(ascii_case_insensitive)]
+pub enum FitParameter {
+    #[strum(to_string = "Inclination", serialize = "incl")]
+    Inclination,
+    #[strum(to_string = "RAAN", serialize = "raan")]
+    RightAscension,
+    #[strum(to_string = "Eccentricity", serialize = "ecc")]
+    Eccentricity,
+    #[strum(to_string = "Arg. of perigee", serialize = "argp")]
+    ArgumentOfPerigee,
+    #[strum(to_string = "Mean anomaly", serialize = "ma")]
+    MeanAnomaly,
+    #[strum(to_string = "Mean motion", serialize = "mm")]
+    MeanMotion,
+    /// Frequency of the transmitter
+    #[strum(to_string = "Frequency", serialize = "freq")]
+    Frequency,
+}
+
+impl FitParameter {
+    /// What is fitted by default: where the satellite is along its orbit, and the frequency.
+    pub const DEFAULT: [FitParameter; 3] = [
+        FitParameter::MeanAnomaly,
+        FitParameter::MeanMotion,
+        FitParameter::Frequency,
+    ];
+
+    /// Step for the finite differences, in the units of the TLE (degrees, revolutions per day) or
+    /// Hz.
+    fn step(self) -> f64 {
+        match self {
+            FitParameter::Inclination
+            | FitParameter::RightAscension
+            | FitParameter::ArgumentOfPerigee
+            | FitParameter::MeanAnomaly => 1e-4,
+            FitParameter::Eccentricity => 1e-6,
+            FitParameter::MeanMotion => 1e-7,
+            FitParameter::Frequency => 1.0,
+        }
+    }
+
+    pub fn get(self, elements: &sgp4::Elements, frequency: f64) -> f64 {
+        match self {
+            FitParameter::Inclination => elements.inclination,
+            FitParameter::RightAscension => elements.right_ascension,
+            FitParameter::Eccentricity => elements.eccentricity,
+            FitParameter::ArgumentOfPerigee => elements.argument_of_perigee,
+            FitParameter::MeanAnomaly => elements.mean_anomaly,
+            FitParameter::MeanMotion => elements.mean_motion,
+            FitParameter::Frequency => frequency,
+        }
+    }
+
+    fn set(self, elements: &mut sgp4::Elements, frequency: &mut f64, value: f64) {
+        match self {
+            FitParameter::Inclination => elements.inclination = value,
+            FitParameter::RightAscension => elements.right_ascension = value,
+            FitParameter::Eccentricity => elements.eccentricity = value,
+            FitParameter::ArgumentOfPerigee => elements.argument_of_perigee = value,
+            FitParameter::MeanAnomaly => elements.mean_anomaly = value,
+            FitParameter::MeanMotion => elements.mean_motion = value,
+            FitParameter::Frequency => *frequency = value,
+        }
+    }
+}
+
+/// The result of [`fit_orbit`].
+#[derive(Debug, Clone)]
+pub struct OrbitFit {
+    /// The satellite with the fitted elements
+    pub satellite: Satellite,
+    /// The fitted transmitter frequency in Hz
+    pub frequency: f64,
+    /// Measured minus predicted frequency of each measurement in Hz
+    pub residuals: Vec<f64>,
+    /// Root mean square of the residuals in Hz
+    pub rms: f64,
+    pub iterations: usize,
+}
+
+/// Measured minus predicted frequency of each measurement in Hz, for a transmitter at `frequency`.
+/// Fails if the site of a measurement isn't in `sites` or SGP4 fails.
+pub fn residuals(
+    satellite: &Satellite,
+    frequency: f64,
+    measurements: &[Measurement],
+    sites: &HashMap<i32, Site>,
+) -> anyhow::Result<Vec<f64>> {
+    measurements
+        .iter()
+        .map(|m| {
+            let site = sites
+                .get(&m.site_id)
+                .with_context(|| format!("Site {} is unknown", m.site_id))?;
+            let predicted = satellite.doppler_frequency(&m.time.naive_utc(), site, frequency)?;
+            Ok(m.freq - predicted)
+        })
+        .collect()
+}
+
+/// Fits `parameters` of the satellite's elements and the transmitter frequency (starting at
+/// `frequency`) to the measurements by least squares. The other elements and the epoch are kept.
+///
+/// All measurements are weighted equally, as in `rffit`: [`Measurement::sigma`] is how far the
+/// signal stood out of the noise when it was picked, not an uncertainty of its frequency, so it
+/// gives no meaningful weight.
+pub fn fit_orbit(
+    satellite: &Satellite,
+    frequency: f64,
+    measurements: &[Measurement],
+    sites: &HashMap<i32, Site>,
+    parameters: &[FitParameter],
+) -> anyhow::Result<OrbitFit> {
+    anyhow::ensure!(!measurements.is_empty(), "No measurements to fit");
+    let with_values = |values: &[f64]| -> anyhow::Result<(Satellite, f64)> {
+        let mut elements = satellite.elements.clone();
+        let mut frequency = frequency;
+        for (parameter, &value) in parameters.iter().zip(values) {
+            parameter.set(&mut elements, &mut frequency, value);
+        }
+        anyhow::ensure!(
+            (0.0..1.0).contains(&elements.eccentricity) && elements.mean_motion > 0.0,
+            "Elements are out of range"
+        );
+        let constants = sgp4::Constants::from_elements(&elements)?;
+        let satellite = Satellite {
+            elements,
+            constants,
+            transmitters: satellite.transmitters.clone(),
+        };
+        Ok((satellite, frequency))
+    };
+    let residuals_at = |values: &[f64]| -> anyhow::Result<Vec<f64>> {
+        let (satellite, frequency) = with_values(values)?;
+        residuals(&satellite, frequency, measurements, sites)
+    };
+    let sum_of_squares = |residuals: &[f64]| residuals.iter().map(|r| r * r).sum::<f64>();
+
+    let mut values: Vec<f64> = parameters
+        .iter()
+        .map(|p| p.get(&satellite.elements, frequency))
+        .collect();
+    let mut current = residuals_at(&values)?;
+    let mut cost = sum_of_squares(&current);
+    let mut damping = 1e-3;
+    let mut iterations = 0;
+    while !parameters.is_empty() && iterations < MAX_ITERATIONS {
+        iterations += 1;
+        // Columns of the Jacobian of the residuals
+        let jacobian = parameters
+            .iter()
+            .enumerate()
+            .map(|(i, parameter)| {
+                let mut stepped = values.clone();
+                stepped[i] += parameter.step();
+                let residuals = residuals_at(&stepped)?;
+                Ok(residuals
+                    .iter()
+                    .zip(&current)
+                    .map(|(r, r0)| (r - r0) / parameter.step())
+                    .collect::<Vec<f64>>())
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let dot = |a: &[f64], b: &[f64]| a.iter().zip(b).map(|(a, b)| a * b).sum::<f64>();
+        let normal: Vec<Vec<f64>> = jacobian
+            .iter()
+            .map(|a| jacobian.iter().map(|b| dot(a, b)).collect())
+            .collect();
+        let gradient: Vec<f64> = jacobian.iter().map(|a| -dot(a, &current)).collect();
+
+        let mut improvement = None;
+        while improvement.is_none() && damping < MAX_DAMPING {
+            let mut damped = normal.clone();
+            for (i, row) in damped.iter_mut().enumerate() {
+                row[i] += damping * normal[i][i].max(f64::EPSILON);
+            }
+            let trial = solve(damped, gradient.clone()).and_then(|step| {
+                let trial: Vec<f64> = values.iter().zip(&step).map(|(v, s)| v + s).collect();
+                let residuals = residuals_at(&trial).ok()?;
+                (sum_of_squares(&residuals) < cost).then_some((trial, residuals))
+            });
+            match trial {
+                Some((trial, residuals)) => {
+                    let trial_cost = sum_of_squares(&residuals);
+                    improvement = Some((cost - trial_cost) / cost);
+                    values = trial;
+                    current = residuals;
+                    cost = trial_cost;
+                    damping /= 10.0;
+                }
+                None => damping *= 10.0,
+            }
+        }
+        if improvement.is_none_or(|improvement| improvement < CONVERGENCE) {
+            break;
+        }
+    }
+
+    let (mut satellite, frequency) = with_values(&values)?;
+    let elements = &mut satellite.elements;
+    for angle in [
+        &mut elements.inclination,
+        &mut elements.right_ascension,
+        &mut elements.argument_of_perigee,
+        &mut elements.mean_anomaly,
+    ] {
+        *angle = angle.rem_euclid(360.0);
+    }
+    satellite.constants = sgp4::Constants::from_elements(&satellite.elements)?;
+    Ok(OrbitFit {
+        satellite,
+        frequency,
+        rms: (cost / current.len() as f64).sqrt(),
+        residuals: current,
+        iterations,
+    })
+}
+
+/// Solves `a x = b` by Gaussian elimination with partial pivoting. `None` if `a` is singular.
+fn solve(mut a: Vec<Vec<f64>>, mut b: Vec<f64>) -> Option<Vec<f64>> {
+    let n = b.len();
+    for col in 0..n {
+        let pivot = (col..n).max_by(|&i, &j| a[i][col].abs().total_cmp(&a[j][col].abs()))?;
+        if a[pivot][col] == 0.0 || !a[pivot][col].is_finite() {
+            return None;
+        }
+        a.swap(col, pivot);
+        b.swap(col, pivot);
+        let (pivot_rows, rows) = a.split_at_mut(col + 1);
+        let pivot_row = &pivot_rows[col];
+        for (row, b_row) in rows.iter_mut().zip(col + 1..n) {
+            let factor = row[col] / pivot_row[col];
+            for (value, pivot) in row[col..].iter_mut().zip(&pivot_row[col..]) {
+                *value -= factor * pivot;
+            }
+            b[b_row] -= factor * b[col];
+        }
+    }
+    let mut x = vec![0.0; n];
+    for row in (0..n).rev() {
+        let sum: f64 = (row + 1..n).map(|k| a[row][k] * x[k]).sum();
+        x[row] = (b[row] - sum) / a[row][row];
+    }
+    Some(x)
+}
+
+/// Formats the elements as the two lines of a TLE.
+pub fn format_tle(elements: &sgp4::Elements) -> [String; 2] {
+    let classification = match elements.classification {
+        sgp4::Classification::Unclassified => 'U',
+        sgp4::Classification::Classified => 'C',
+        sgp4::Classification::Secret => 'S',
+    };
+    let line1 = format!(
+        "1 {:05}{} {:<8} {} {} {} {} {} {:>4}",
+        elements.norad_id,
+        classification,
+        elements.international_designator.as_deref().unwrap_or(""),
+        format_epoch(&elements.datetime),
+        format_decimal(elements.mean_motion_dot),
+        format_exponential(elements.mean_motion_ddot),
+        format_exponential(elements.drag_term),
+        elements.ephemeris_type,
+        elements.element_set_number % 10_000,
+    );
+    let line2 = format!(
+        "2 {:05} {:8.4} {:8.4} {:07} {:8.4} {:8.4} {:11.8}{:>5}",
+        elements.norad_id,
+        elements.inclination,
+        elements.right_ascension,
+        (elements.eccentricity * 1e7).round() as u64,
+        elements.argument_of_perigee,
+        elements.mean_anomaly,
+        elements.mean_motion,
+        elements.revolution_number % 100_000,
+    );
+    [line1, line2].map(|line| {
+        let checksum = tle_checksum(&line);
+        format!("{line}{checksum}")
+    })
+}
+
+/// Formats the satellite as a TLE with its name (if known) in the title line.
+pub fn format_tle_with_name(satellite: &Satellite) -> String {
+    let [line1, line2] = format_tle(&satellite.elements);
+    match &satellite.elements.object_name {
+        Some(name) => format!("{name}\n{line1}\n{line2}\n"),
+        None => format!("{line1}\n{line2}\n"),
+    }
+}
+
+/// Epoch as `YYDDD.DDDDDDDD`
+fn format_epoch(datetime: &NaiveDateTime) -> String {
+    let seconds = datetime.num_seconds_from_midnight() as f64 + datetime.nanosecond() as f64 / 1e9;
+    format!(
+        "{:02}{:012.8}",
+        datetime.year() % 100,
+        datetime.ordinal() as f64 + seconds / 86400.0
+    )
+}
+
+/// `value` (below 1) as ` .NNNNNNNN`
+fn format_decimal(value: f64) -> String {
+    let sign = if value < 0.0 { '-' } else { ' ' };
+    let digits = format!("{:.8}", value.abs());
+    format!("{sign}{}", digits.trim_start_matches('0'))
+}
+
+/// `value` in the assumed decimal point notation of TLEs, ` NNNNN-N` for ` 0.NNNNN * 10^-N`
+fn format_exponential(value: f64) -> String {
+    let sign = if value < 0.0 { '-' } else { ' ' };
+    if value == 0.0 {
+        return format!("{sign}00000-0");
+    }
+    let mut exponent = value.abs().log10().floor() as i32 + 1;
+    let mut mantissa = (value.abs() / 10f64.powi(exponent) * 1e5).round() as u32;
+    if mantissa >= 100_000 {
+        mantissa /= 10;
+        exponent += 1;
+    }
+    let exponent_sign = if exponent < 0 { '-' } else { '+' };
+    format!("{sign}{mantissa:05}{exponent_sign}{}", exponent.abs())
+}
+
+/// Sum of the digits, with 1 for each minus sign, modulo 10
+fn tle_checksum(line: &str) -> u32 {
+    line.chars()
+        .map(|c| match c {
+            '-' => 1,
+            c => c.to_digit(10).unwrap_or(0),
+        })
+        .sum::<u32>()
+        % 10
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, NaiveDate};
+
+    const ISS_LINE1: &str = "1 25544U 98067A   08264.51782528 -.00002182  00000-0 -11606-4 0  2927";
+    const ISS_LINE2: &str = "2 25544  51.6416 247.4627 0006703 130.5360 325.0288 15.72125391563537";
+
+    fn iss() -> Satellite {
+        Satellite::from_tle(None, ISS_LINE1, ISS_LINE2, &HashMap::new()).unwrap()
+    }
+
+    #[test]
+    fn formats_tle_like_the_original() {
+        let elements = sgp4::Elements {
+            object_name: None,
+            international_designator: Some("98067A".to_owned()),
+            norad_id: 25544,
+            classification: sgp4::Classification::Unclassified,
+            datetime: NaiveDate::from_ymd_opt(2008, 9, 20)
+                .unwrap()
+                .and_hms_micro_opt(12, 25, 40, 104_192)
+                .unwrap(),
+            mean_motion_dot: -0.00002182,
+            mean_motion_ddot: 0.0,
+            drag_term: -0.11606e-4,
+            element_set_number: 292,
+            inclination: 51.6416,
+            right_ascension: 247.4627,
+            eccentricity: 0.0006703,
+            argument_of_perigee: 130.536,
+            mean_anomaly: 325.0288,
+            mean_motion: 15.72125391,
+            revolution_number: 56353,
+            ephemeris_type: 0,
+        };
+        assert_eq!(format_tle(&elements), [ISS_LINE1, ISS_LINE2]);
+    }
+
+    #[test]
+    fn formatted_tle_parses_back() {
+        let satellite = iss();
+        let [line1, line2] = format_tle(&satellite.elements);
+        let parsed = Satellite::from_tle(None, &line1, &line2, &HashMap::new()).unwrap();
+        assert_eq!(parsed.elements.datetime, satellite.elements.datetime);
+        assert_eq!(parsed.elements.mean_motion, satellite.elements.mean_motion);
+        assert_eq!(
+            parsed.elements.eccentricity,
+            satellite.elements.eccentricity
+        );
+    }
+
+    #[test]
+    fn parses_dat_lines() {
+        let measurement = parse_measurement("54729.518000 437525123.500000 7.250000 4171")
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            measurement.time,
+            DateTime::from_timestamp(1_221_913_555, 200_000_000).unwrap()
+        );
+        assert_eq!(measurement.freq, 437525123.5);
+        assert_eq!(measurement.sigma, 7.25);
+        assert_eq!(measurement.site_id, 4171);
+        assert!(parse_measurement("# comment").unwrap().is_none());
+        assert!(parse_measurement("54729.5 437525123.5").is_err());
+    }
+
+    #[test]
+    fn fit_recovers_elements_and_frequency() {
+        let truth = iss();
+        let frequency = 437.525e6 + 1234.0;
+        let site_id = 4171;
+        let sites = HashMap::from([(
+            site_id,
+            Site {
+                latitude: 52f64.to_radians(),
+                longitude: 4f64.to_radians(),
+                altitude: 0.01,
+            },
+        )]);
+        let epoch = truth.elements.datetime.and_utc();
+        let measurements: Vec<Measurement> = (0..60)
+            .map(|i| {
+                let time = epoch + Duration::seconds(20 * i);
+                let freq = truth
+                    .doppler_frequency(&time.naive_utc(), &sites[&site_id], frequency)
+                    .unwrap();
+                Measurement {
+                    time,
+                    freq,
+                    sigma: 10.0,
+                    site_id,
+                }
+            })
+            .collect();
+
+        let mut guess = truth.clone();
+        guess.elements.mean_anomaly += 0.5;
+        guess.elements.mean_motion += 1e-4;
+        guess.constants = sgp4::Constants::from_elements(&guess.elements).unwrap();
+        let before = residuals(&guess, 437.525e6, &measurements, &sites).unwrap();
+        assert!(before.iter().any(|r| r.abs() > 1e3));
+
+        let fit = fit_orbit(
+            &guess,
+            437.525e6,
+            &measurements,
+            &sites,
+            &FitParameter::DEFAULT,
+        )
+        .unwrap();
+        assert!(fit.rms < 1.0, "{fit:?}");
+        assert!((fit.frequency - frequency).abs() < 1.0, "{}", fit.frequency);
+        let elements = &fit.satellite.elements;
+        assert!((elements.mean_anomaly - truth.elements.mean_anomaly).abs() < 1e-3);
+        assert!((elements.mean_motion - truth.elements.mean_motion).abs() < 1e-6);
+    }
+
+    #[test]
+    fn fit_needs_known_sites() {
+        let measurement = parse_measurement("54729.518 437525000 5 1234")
+            .unwrap()
+            .unwrap();
+        let error = fit_orbit(
+            &iss(),
+            437.525e6,
+            &[measurement],
+            &HashMap::new(),
+            &FitParameter::DEFAULT,
+        )
+        .unwrap_err();
+        assert!(error.to_string().contains("1234"), "{error}");
+    }
+
+    #[test]
+    fn parameters_parse_from_short_names() {
+        assert_eq!("raan".parse(), Ok(FitParameter::RightAscension));
+        assert_eq!("MM".parse(), Ok(FitParameter::MeanMotion));
+        assert_eq!(FitParameter::Frequency.to_string(), "Frequency");
+    }
+}
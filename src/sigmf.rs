@@ -0,0 +1,402 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Loading [SigMF](https://sigmf.org) recordings as spectrograms.
+
+use std::{
+    collections::HashSet,
+    io::SeekFrom,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result, anyhow, ensure};
+use chrono::{DateTime, Duration, Utc};
+use serde::Deserialize;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+use crate::{
+    iq::{FftParams, Integrator, IqReader, SampleFormat},
+    spectrogram::{LoadOptions, RawStrfSpectrum, Spectrogram, channel_range, overlaps_time_range},
+};
+
+const META_EXTENSION: &str = "sigmf-meta";
+const DATA_EXTENSION: &str = "sigmf-data";
+
+#[derive(Debug, Deserialize)]
+struct Meta {
+    global: Global,
+    #[serde(default)]
+    captures: Vec<Capture>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Global {
+    #[serde(rename = "core:datatype")]
+    datatype: String,
+    #[serde(rename = "core:sample_rate")]
+    sample_rate: Option<f64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Capture {
+    #[serde(rename = "core:sample_start", default)]
+    sample_start: u64,
+    #[serde(rename = "core:frequency")]
+    frequency: Option<f64>,
+    #[serde(rename = "core:datetime")]
+    datetime: Option<String>,
+}
+
+/// Whether the path refers to either half of a SigMF recording.
+pub fn is_sigmf(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext == META_EXTENSION || ext == DATA_EXTENSION)
+}
+
+/// Loads a SigMF recording and channelizes it into a spectrogram.
+///
+/// `path` may point to either the `.sigmf-meta` or the `.sigmf-data` file. Each capture segment
+/// starts a new spectrum at its `core:datetime` (or at its sample offset if it has none), so
+/// discontinuities in the recording show up as gaps in the spectrogram.
+///
+/// With `options.time_range`, the samples of whole spectra outside of the range are skipped.
+/// Returns `None` if there are no spectra in the range.
+pub async fn load_sigmf(path: &Path, options: LoadOptions) -> Result<Option<Spectrogram>> {
+    let meta_path = path.with_extension(META_EXTENSION);
+    let data_path = data_path(path);
+
+    let meta = tokio::fs::read_to_string(&meta_path)
+        .await
+        .context(format!("Failed to read {:?}", meta_path))?;
+    let meta: Meta = serde_json::from_str(&meta).context("Failed to parse SigMF metadata")?;
+
+    let format = SampleFormat::from_sigmf(&meta.global.datatype)
+        .ok_or_else(|| anyhow!("Unsupported SigMF datatype: {}", meta.global.datatype))?;
+    let samp_rate = meta
+        .global
+        .sample_rate
+        .ok_or_else(|| anyhow!("SigMF metadata has no core:sample_rate"))?;
+
+    let mut captures = meta.captures;
+    captures.sort_by_key(|c| c.sample_start);
+    let first = captures
+        .first()
+        .ok_or_else(|| anyhow!("SigMF metadata has no captures"))?;
+    let freq = first
+        .frequency
+        .ok_or_else(|| anyhow!("SigMF capture has no core:frequency"))?;
+    // Captures without a frequency keep the one of the previous capture
+    ensure!(
+        captures
+            .iter()
+            .all(|c| c.frequency.is_none_or(|f| f == freq)),
+        "SigMF captures with different center frequencies are not supported"
+    );
+
+    let params = FftParams {
+        freq,
+        samp_rate,
+        nchan: options.iq.nchan,
+        tint: options.iq.tint,
+    };
+    let full_params = params.spectrogram_params();
+    let (out_params, channels) = match options.freq_range {
+        Some(range) => {
+            let channels = channel_range(&full_params, range);
+            ensure!(
+                !channels.is_empty(),
+                "Frequency range filter excludes all channels: {:?}",
+                full_params
+            );
+            (full_params.select_channels(channels.clone()), channels)
+        }
+        None => (full_params, 0..params.nchan),
+    };
+
+    let mut file = tokio::fs::File::open(&data_path)
+        .await
+        .context(format!("Failed to open {:?}", data_path))?;
+    let total_samples = file.metadata().await?.len() / format.sample_size() as u64;
+    let spectrum_samples = (params.nchan * params.nint()) as u64;
+    let spectrum_length = spectrum_samples as f64 / samp_rate;
+
+    let mut spectra = Vec::new();
+    let mut segment_start: Option<(u64, DateTime<Utc>)> = None;
+    for (i, capture) in captures.iter().enumerate() {
+        let start_time = match (&capture.datetime, segment_start) {
+            (Some(datetime), _) => DateTime::parse_from_rfc3339(datetime)
+                .context(format!("Invalid core:datetime: {}", datetime))?
+                .with_timezone(&Utc),
+            (None, Some((prev_sample, prev_time))) => {
+                let offset = (capture.sample_start - prev_sample) as f64 / samp_rate;
+                prev_time + Duration::microseconds((offset * 1e6).round() as i64)
+            }
+            (None, None) => anyhow::bail!("SigMF recording has no core:datetime"),
+        };
+        segment_start = Some((capture.sample_start, start_time));
+
+        let mut start = capture.sample_start;
+        let mut start_time = start_time;
+        let mut end = captures
+            .get(i + 1)
+            .map_or(total_samples, |c| c.sample_start)
+            .min(total_samples);
+        if let Some((from, to)) = options.time_range {
+            // Skip the spectra that end before the range and stop after the last one in it
+            let spectra_until = |time: DateTime<Utc>| {
+                ((time - start_time).as_seconds_f64() / spectrum_length).max(0.0)
+            };
+            let skip = spectra_until(from).floor() as u64;
+            end = end.min(start + spectra_until(to).ceil() as u64 * spectrum_samples);
+            start += skip * spectrum_samples;
+            start_time +=
+                Duration::microseconds((skip as f64 * spectrum_length * 1e6).round() as i64);
+        }
+        if end <= start {
+            continue;
+        }
+
+        file.seek(SeekFrom::Start(start * format.sample_size() as u64))
+            .await?;
+        let segment = (&mut file).take((end - start) * format.sample_size() as u64);
+        let mut reader = IqReader::new(segment, format, spectrum_samples as usize);
+        let mut integrator = Integrator::new(params.clone(), start_time)?;
+        while let Some(samples) = reader.next_block().await? {
+            let channels = channels.clone();
+            let (returned, new) = tokio::task::spawn_blocking(move || {
+                let new: Vec<_> = integrator
+                    .push(&samples)
+                    .into_iter()
+                    .filter(|spec| {
+                        overlaps_time_range(options.time_range, spec.time, spec.length_s)
+                    })
+                    .map(|spec| RawStrfSpectrum {
+                        power_linear: spec.power_linear[channels.clone()].to_vec(),
+                        ..spec
+                    })
+                    .collect();
+                (integrator, new)
+            })
+            .await?;
+            integrator = returned;
+            spectra.extend(new);
+        }
+    }
+
+    log::debug!("Channelized {} spectra from {:?}", spectra.len(), data_path);
+    if spectra.is_empty() && options.time_range.is_some() {
+        return Ok(None);
+    }
+    tokio::task::spawn_blocking(move || Spectrogram::from_raw(spectra, out_params).map(Some))
+        .await?
+}
+
+/// Removes the paths that refer to a SigMF recording that is already in `paths` (e.g. when both its
+/// `.sigmf-meta` and `.sigmf-data` files are given), keeping the first one.
+pub fn dedup_recordings(paths: &[PathBuf]) -> Vec<PathBuf> {
+    let mut seen = HashSet::new();
+    paths
+        .iter()
+        .filter(|path| !is_sigmf(path) || seen.insert(path.with_extension(META_EXTENSION)))
+        .cloned()
+        .collect()
+}
+
+/// Returns the `.sigmf-data` path belonging to a SigMF recording.
+pub fn data_path(path: &Path) -> PathBuf {
+    path.with_extension(DATA_EXTENSION)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::spectrogram::IqOptions;
+
+    fn write_recording(dir: &Path, meta: &str, nsamples: usize, tone_hz: f64) -> PathBuf {
+        let samp_rate = 1024.0;
+        let mut data = Vec::with_capacity(nsamples * 4);
+        for i in 0..nsamples {
+            let phase = 2.0 * std::f64::consts::PI * tone_hz * i as f64 / samp_rate;
+            data.extend(((phase.cos() * 16000.0) as i16).to_le_bytes());
+            data.extend(((phase.sin() * 16000.0) as i16).to_le_bytes());
+        }
+        let meta_path = dir.join("rec.sigmf-meta");
+        std::fs::write(&meta_path, meta).unwrap();
+        std::fs::write(data_path(&meta_path), data).unwrap();
+        meta_path
+    }
+
+    fn options() -> LoadOptions {
+        LoadOptions {
+            iq: IqOptions {
+                nchan: 64,
+                tint: 0.5,
+            },
+            ..Default::default()
+        }
+    }
+
+    const META: &str = r#"{
+        "global": {"core:datatype": "ci16_le", "core:sample_rate": 1024, "core:version": "1.0.0"},
+        "captures": [{"core:sample_start": 0, "core:frequency": 437000000,
+                      "core:datetime": "2024-01-01T00:00:00Z"}],
+        "annotations": []
+    }"#;
+
+    #[tokio::test]
+    async fn load_sigmf_uses_metadata_and_channelizes() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = write_recording(dir.path(), META, 2048, 160.0);
+
+        let spec = load_sigmf(&data_path(&path), options())
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(spec.nchan, 64);
+        assert_eq!(spec.nslices, 4);
+        assert!((spec.freq - 437e6).abs() < 1.0);
+        assert!((spec.bw - 1024.0).abs() < 1e-3);
+        assert_eq!(spec.start_time().to_rfc3339(), "2024-01-01T00:00:00+00:00");
+        assert_eq!((spec.end_time() - spec.start_time()).num_seconds(), 2);
+
+        // 160 Hz at 16 Hz/channel, offset by the 32 channels below the center
+        let peak = spec
+            .data()
+            .row(0)
+            .iter()
+            .enumerate()
+            .max_by(|a, b| a.1.total_cmp(b.1))
+            .unwrap()
+            .0;
+        assert_eq!(peak, 42);
+    }
+
+    #[tokio::test]
+    async fn load_sigmf_applies_freq_range() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = write_recording(dir.path(), META, 2048, 160.0);
+
+        let spec = load_sigmf(
+            &path,
+            LoadOptions {
+                freq_range: Some((437_000_000, 437_000_512)),
+                ..options()
+            },
+        )
+        .await
+        .unwrap()
+        .unwrap();
+
+        assert_eq!(spec.nchan, 32);
+        assert!((spec.freq - 437_000_256.0).abs() < 1.0);
+    }
+
+    #[tokio::test]
+    async fn load_sigmf_starts_new_spectra_at_each_capture() {
+        let meta = r#"{
+            "global": {"core:datatype": "ci16_le", "core:sample_rate": 1024},
+            "captures": [
+                {"core:sample_start": 0, "core:frequency": 437e6,
+                 "core:datetime": "2024-01-01T00:00:00Z"},
+                {"core:sample_start": 1024, "core:frequency": 437e6,
+                 "core:datetime": "2024-01-01T00:01:00Z"}
+            ]
+        }"#;
+        let dir = tempfile::tempdir().unwrap();
+        let path = write_recording(dir.path(), meta, 2048, 0.0);
+
+        let spec = load_sigmf(&path, options()).await.unwrap().unwrap();
+
+        assert_eq!(spec.nslices, 4);
+        assert_eq!((spec.timestamps[2] - spec.start_time()).num_seconds(), 60);
+    }
+
+    #[tokio::test]
+    async fn load_sigmf_skips_spectra_outside_of_the_time_range() {
+        let meta = r#"{
+            "global": {"core:datatype": "ci16_le", "core:sample_rate": 1024},
+            "captures": [
+                {"core:sample_start": 0, "core:frequency": 437e6,
+                 "core:datetime": "2024-01-01T00:00:00Z"},
+                {"core:sample_start": 1024, "core:frequency": 437e6,
+                 "core:datetime": "2024-01-01T00:01:00Z"}
+            ]
+        }"#;
+        let dir = tempfile::tempdir().unwrap();
+        let path = write_recording(dir.path(), meta, 2048, 0.0);
+        let time = |s: &str| DateTime::parse_from_rfc3339(s).unwrap().to_utc();
+        let load = |time_range| {
+            load_sigmf(
+                &path,
+                LoadOptions {
+                    time_range: Some(time_range),
+                    ..options()
+                },
+            )
+        };
+
+        let spec = load((
+            time("2024-01-01T00:00:00.7Z"),
+            time("2024-01-01T00:01:00.2Z"),
+        ))
+        .await
+        .unwrap()
+        .unwrap();
+        assert_eq!(
+            spec.timestamps,
+            vec![time("2024-01-01T00:00:00.5Z"), time("2024-01-01T00:01:00Z")]
+        );
+
+        let none = load((time("2024-01-01T00:00:02Z"), time("2024-01-01T00:00:59Z")))
+            .await
+            .unwrap();
+        assert!(none.is_none());
+    }
+
+    #[tokio::test]
+    async fn load_sigmf_keeps_frequency_of_previous_capture() {
+        let meta = r#"{
+            "global": {"core:datatype": "ci16_le", "core:sample_rate": 1024},
+            "captures": [
+                {"core:sample_start": 0, "core:frequency": 437e6,
+                 "core:datetime": "2024-01-01T00:00:00Z"},
+                {"core:sample_start": 1024, "core:datetime": "2024-01-01T00:01:00Z"}
+            ]
+        }"#;
+        let dir = tempfile::tempdir().unwrap();
+        let path = write_recording(dir.path(), meta, 2048, 0.0);
+        let spec = load_sigmf(&path, options()).await.unwrap().unwrap();
+        assert_eq!(spec.nslices, 4);
+        assert!((spec.freq - 437e6).abs() < 1.0);
+
+        let retuned = meta.replace(
+            r#""core:sample_start": 1024,"#,
+            r#""core:sample_start": 1024, "core:frequency": 438e6,"#,
+        );
+        let path = write_recording(dir.path(), &retuned, 2048, 0.0);
+        assert!(load_sigmf(&path, options()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn both_halves_of_a_recording_load_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = write_recording(dir.path(), META, 2048, 0.0);
+        let other = dir.path().join("other.bin");
+        let paths = [path.clone(), other.clone(), data_path(&path)];
+        assert_eq!(dedup_recordings(&paths), vec![path.clone(), other]);
+
+        let spec = crate::spectrogram::load(&[path.clone(), data_path(&path)], options())
+            .await
+            .unwrap();
+        assert_eq!(spec.nslices, 4);
+    }
+
+    #[tokio::test]
+    async fn load_sigmf_rejects_unsupported_datatype() {
+        let meta = META.replace("ci16_le", "rf32_le");
+        let dir = tempfile::tempdir().unwrap();
+        let path = write_recording(dir.path(), &meta, 2048, 0.0);
+
+        assert!(load_sigmf(&path, options()).await.is_err());
+    }
+}
@@ -1,8 +1,51 @@
+use std::ops::Range;
+
 use itertools::Itertools;
-use ndarray::{ArrayView1, s};
-use ndarray_stats::QuantileExt;
+use ndarray::{Array1, ArrayView1, s};
+use serde::{Deserialize, Serialize};
+use strum::{Display, VariantArray};
+
+use crate::{
+    coord::data_absolute,
+    spectrogram::{Spectrogram, percentile_of},
+    util::to_index,
+};
+
+mod blind;
+mod stack;
+mod track;
+
+pub use blind::{BlindSearchOptions, Candidate, blind_search};
+pub use stack::{StackPeak, StackedSpectrum, stack_along, stack_pass};
+pub use track::{Track, TrackInterpolation, reject_outliers};
 
-use crate::{coord::data_absolute, spectrogram::Spectrogram, util::to_index};
+/// A signal found by [`find_signals`] or marked by hand.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Signal {
+    pub point: data_absolute::Point,
+    /// Significance of the peak in standard deviations above the mean of the track window, or
+    /// `None` for signals that were marked by hand
+    pub sigma: Option<f32>,
+    /// Which tone of a multi-tone signal this is, see [`ToneOptions`]. Tones are numbered by
+    /// frequency, starting at 0 for the lowest.
+    #[serde(default)]
+    pub tone: usize,
+    /// Whether the signal was rejected as an outlier, see [`reject_outliers`]. Rejected signals
+    /// are kept so they can be restored, but not saved.
+    #[serde(default)]
+    pub rejected: bool,
+}
+
+impl Signal {
+    pub fn marked(point: data_absolute::Point) -> Signal {
+        Signal {
+            point,
+            sigma: None,
+            tone: 0,
+            rejected: false,
+        }
+    }
+}
 
 #[derive(Debug, Clone, Copy)]
 pub enum SignalDetectionMethod {
@@ -12,119 +55,692 @@ pub enum SignalDetectionMethod {
     /// from the mean (over the track window) by more than the threshold, the point is marked as a
     /// signal.
     FitTrace { sigma: f32 },
+    /// Like [`FitTrace`](Self::FitTrace), but refines the frequency of the peak by fitting a
+    /// parabola to the linear power of the peak channel and its neighbours.
+    Parabolic { sigma: f32 },
+    /// Like [`Parabolic`](Self::Parabolic), but fits the power in dB, which is exact for peaks
+    /// with a Gaussian shape.
+    Gaussian { sigma: f32 },
+    /// Like [`FitTrace`](Self::FitTrace), but estimates the frequency as the centroid of the power
+    /// above the mean of the track window, over the channels around the peak that are above it.
+    Centroid { sigma: f32 },
+}
+
+/// The kinds of [`SignalDetectionMethod`] without their parameters, e.g. to pick one in the GUI.
+#[derive(
+    Debug, Default, Display, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, VariantArray,
+)]
+pub enum SignalDetectionKind {
+    #[default]
+    #[strum(to_string = "Peak channel")]
+    FitTrace,
+    Parabolic,
+    Gaussian,
+    Centroid,
+}
+
+impl SignalDetectionKind {
+    pub fn with_sigma(self, sigma: f32) -> SignalDetectionMethod {
+        match self {
+            SignalDetectionKind::FitTrace => SignalDetectionMethod::FitTrace { sigma },
+            SignalDetectionKind::Parabolic => SignalDetectionMethod::Parabolic { sigma },
+            SignalDetectionKind::Gaussian => SignalDetectionMethod::Gaussian { sigma },
+            SignalDetectionKind::Centroid => SignalDetectionMethod::Centroid { sigma },
+        }
+    }
+}
+
+impl SignalDetectionMethod {
+    /// The detection threshold in standard deviations above the mean of the track window.
+    pub fn sigma(&self) -> f32 {
+        match *self {
+            SignalDetectionMethod::FitTrace { sigma }
+            | SignalDetectionMethod::Parabolic { sigma }
+            | SignalDetectionMethod::Gaussian { sigma }
+            | SignalDetectionMethod::Centroid { sigma } => sigma,
+        }
+    }
+
+    /// Estimates the fractional channel of the peak found at channel `peak` of `data` (in dB).
+    fn refine_peak(&self, data: ArrayView1<f32>, masked: Option<&[bool]>, peak: usize) -> f32 {
+        let usable = |i: usize| i < data.len() && !masked.is_some_and(|masked| masked[i]);
+        let neighbours = (peak > 0 && usable(peak - 1) && usable(peak + 1))
+            .then(|| [data[peak - 1], data[peak], data[peak + 1]]);
+        let offset = match self {
+            SignalDetectionMethod::FitTrace { .. } => 0.0,
+            SignalDetectionMethod::Parabolic { .. } => {
+                neighbours.map_or(0.0, |db| parabola_vertex(db.map(db_to_linear)))
+            }
+            SignalDetectionMethod::Gaussian { .. } => neighbours.map_or(0.0, parabola_vertex),
+            SignalDetectionMethod::Centroid { .. } => {
+                return centroid(data, &usable, peak).unwrap_or(peak as f32);
+            }
+        };
+        peak as f32 + offset
+    }
+}
+
+/// How many tones [`find_signals`] looks for in each spectrum, e.g. for FSK or a carrier with a
+/// subcarrier.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ToneOptions {
+    /// Most peaks per spectrum
+    pub count: usize,
+    /// Minimum distance (in Hz) between the peaks of a spectrum
+    pub min_separation: f32,
+    /// Detection threshold in standard deviations for all but the strongest peak of a spectrum,
+    /// e.g. for subcarriers that are much weaker than the carrier. `None` uses the threshold of
+    /// the [`SignalDetectionMethod`].
+    pub secondary_sigma: Option<f32>,
+}
+
+impl ToneOptions {
+    /// The threshold of each peak of a spectrum, strongest first, given the threshold `sigma` of
+    /// the detection method.
+    fn thresholds(&self, sigma: f32) -> Vec<f32> {
+        let secondary = self.secondary_sigma.unwrap_or(sigma);
+        std::iter::once(sigma)
+            .chain(std::iter::repeat_n(secondary, self.count.saturating_sub(1)))
+            .collect()
+    }
 }
 
-/// Finds signals in a spectrogram.
+impl Default for ToneOptions {
+    fn default() -> Self {
+        Self {
+            count: 1,
+            min_separation: 1e3,
+            secondary_sigma: None,
+        }
+    }
+}
+
+/// Finds signals in a spectrogram, in a window of `track_bw` around the track through the track
+/// points. Spectra and channels that are flagged as RFI are skipped.
+///
+/// With more than one tone, each spectrum can have several signals, which are grouped into tones
+/// by their offset from the track, see [`group_tones`].
 pub fn find_signals(
     spectrogram: &Spectrogram,
     track_points: &[data_absolute::Point],
     track_bw: f32,
+    interpolation: TrackInterpolation,
     method: SignalDetectionMethod,
-) -> anyhow::Result<Vec<data_absolute::Point>> {
-    let data = spectrogram.data();
-    let (nt, nf) = data.dim();
+    tones: ToneOptions,
+) -> anyhow::Result<Vec<Signal>> {
+    let Some(track) = Track::new(track_points, interpolation) else {
+        return Ok(Vec::new());
+    };
+    let (nt, nf) = (spectrogram.nslices, spectrogram.nchan);
     let t_scale = nt as f32 / spectrogram.length().as_seconds_f32();
     let bw = spectrogram.bw;
     let f_scale = nf as f32 / bw;
     let half_bw_idx = (track_bw * 0.5 * f_scale) as usize;
-    let track_points = track_points
-        .iter()
-        .map(|p| {
-            (
-                // TODO: This will clamp x/y to bounds individually -> might change slope
-                // for out-of-bounds points
-                to_index(p.0.x * t_scale, nt),
-                to_index((p.0.y + bw / 2.0) * f_scale, nf),
-            )
+    let min_separation = (tones.min_separation * f_scale).round() as usize;
+    let thresholds = tones.thresholds(method.sigma());
+    // TODO: This will clamp the track to the bounds, which might change its slope for
+    // out-of-bounds track points
+    let t_range = to_index(track.start() * t_scale, nt)..(to_index(track.end() * t_scale, nt) + 1);
+    let centers = t_range
+        .clone()
+        .map(|t_idx| {
+            let freq = track.freq_at(t_idx as f32 / t_scale);
+            to_index((freq + bw / 2.0) * f_scale, nf)
         })
         .collect_vec();
-    let t_range = track_points.first().unwrap().0..(track_points.last().unwrap().0 + 1);
-    let data = data.slice(s![t_range.clone(), ..]).to_owned();
+    // Only read the part of the spectrogram that the track windows can reach
+    let (f_min, f_max) = centers
+        .iter()
+        .fold((nf, 0), |(lo, hi), &f_idx| (lo.min(f_idx), hi.max(f_idx)));
+    let f_offset = f_min.saturating_sub(half_bw_idx);
+    let data = spectrogram.tile(t_range.clone(), f_offset..(f_max + half_bw_idx).min(nf - 1));
+    let mask = spectrogram.mask();
 
-    let signals = track_points
-        .into_iter()
-        .map(|(t_idx, f_idx)| (t_idx - t_range.start, f_idx))
-        .tuple_windows()
-        .flat_map(|(a, b)| -> anyhow::Result<Vec<data_absolute::Point>> {
-            let slope = (b.1 as f32 - a.1 as f32) / (b.0 as f32 - a.0 as f32);
-            let signals_nested: anyhow::Result<Vec<Vec<data_absolute::Point>>> = (a.0..=b.0)
-                .map(|t_idx| {
-                    let center_f = (a.1 as f32 + slope * (t_idx - a.0) as f32).round() as usize;
-                    let f_range =
-                        center_f.saturating_sub(half_bw_idx)..(center_f + half_bw_idx).min(nf - 1);
-                    let slice = data.slice(s![t_idx, f_range.clone()]);
-
-                    let slice_signals = match method {
-                        SignalDetectionMethod::FitTrace { sigma } => find_signals_ft(slice, sigma),
-                    }?;
-
-                    let signals_abs = slice_signals
-                        .iter()
-                        .map(|&f_idx| {
-                            data_absolute::Point::new(
-                                (t_idx + t_range.start) as f32 / t_scale,
-                                (f_idx + f_range.start) as f32 / f_scale - bw / 2.0,
-                            )
-                        })
-                        .collect();
-                    Ok(signals_abs)
+    let t_start = t_range.start;
+    let signals: anyhow::Result<Vec<Vec<(Signal, f32)>>> = t_range
+        .zip(centers)
+        .map(|(t_idx, center_f)| {
+            if mask.is_some_and(|mask| mask.slices[t_idx]) {
+                return Ok(Vec::new());
+            }
+            let f_range =
+                center_f.saturating_sub(half_bw_idx)..(center_f + half_bw_idx).min(nf - 1);
+            let slice = data.slice(s![
+                t_idx - t_start,
+                f_range.start - f_offset..f_range.end - f_offset
+            ]);
+            let masked = mask.map(|mask| &mask.channels[f_range.clone()]);
+
+            let slice_signals =
+                find_peaks_ft(slice, masked, 0..slice.len(), &thresholds, min_separation)?;
+
+            let t = t_idx as f32 / t_scale;
+            let signals_abs = slice_signals
+                .iter()
+                .map(|&(f_idx, sigma)| {
+                    let f_idx = method.refine_peak(slice, masked, f_idx);
+                    let freq = (f_idx + f_range.start as f32) / f_scale - bw / 2.0;
+                    let signal = Signal {
+                        point: data_absolute::Point::new(t, freq),
+                        sigma: Some(sigma),
+                        tone: 0,
+                        rejected: false,
+                    };
+                    (signal, freq - track.freq_at(t))
                 })
                 .collect();
-            let signals = signals_nested?.into_iter().flatten().collect_vec();
-            Ok(signals)
+            Ok(signals_abs)
         })
-        .flatten()
+        .collect();
+    Ok(group_tones(signals?))
+}
+
+/// Numbers the tones of the signals of each spectrum, which come with their offset from the
+/// track. The offset of each tone is the median over the spectra in which the most tones were
+/// found, ordered by frequency, and each signal belongs to the tone with the nearest offset.
+fn group_tones(spectra: Vec<Vec<(Signal, f32)>>) -> Vec<Signal> {
+    let count = spectra.iter().map(Vec::len).max().unwrap_or(0);
+    let mut tone_offsets: Vec<Vec<f32>> = vec![Vec::new(); count];
+    for spectrum in spectra.iter().filter(|spectrum| spectrum.len() == count) {
+        let offsets = spectrum
+            .iter()
+            .map(|&(_, offset)| offset)
+            .sorted_by(f32::total_cmp);
+        for (tone, offset) in offsets.enumerate() {
+            tone_offsets[tone].push(offset);
+        }
+    }
+    let tone_offsets = tone_offsets
+        .into_iter()
+        .map(|offsets| percentile_of(offsets, 50.0))
         .collect_vec();
+
+    spectra
+        .into_iter()
+        .flatten()
+        .map(|(signal, offset)| Signal {
+            tone: tone_offsets
+                .iter()
+                .position_min_by(|a, b| (*a - offset).abs().total_cmp(&(*b - offset).abs()))
+                .unwrap_or(0),
+            ..signal
+        })
+        .collect()
+}
+
+/// Options for [`follow_signal`].
+#[derive(Debug, Clone, Copy)]
+pub struct FollowOptions {
+    pub method: SignalDetectionMethod,
+    /// Width (in Hz) of the window around the predicted frequency that is searched while the
+    /// signal isn't locked
+    pub search_bw: f32,
+    /// How fast (in Hz/s) the signal may drift away from the predicted curve while it is locked
+    pub max_drift: f32,
+    /// How long (in seconds) the signal may be missing before the lock is lost, and the search
+    /// falls back to the predicted curve
+    pub lock_timeout: f32,
+}
+
+/// Follows a signal along a predicted frequency curve, e.g. a satellite's Doppler curve, instead
+/// of the track points of [`find_signals`].
+///
+/// `prediction` is sorted by time and in the same coordinates as the track points, and `offset` (in
+/// Hz) is added to it. Spectra outside of the prediction are skipped. Once the signal is found,
+/// each spectrum is searched around the last detection, moved along the slope of the prediction,
+/// within the drift limit. If the signal isn't found for longer than the lock timeout, the search
+/// starts again from the predicted curve.
+pub fn follow_signal(
+    spectrogram: &Spectrogram,
+    prediction: &[data_absolute::Point],
+    offset: f32,
+    options: FollowOptions,
+) -> anyhow::Result<Vec<Signal>> {
+    let (Some(first), Some(last)) = (prediction.first(), prediction.last()) else {
+        return Ok(Vec::new());
+    };
+    let nf = spectrogram.nchan;
+    let bw = spectrogram.bw;
+    let f_scale = nf as f32 / bw;
+    let predicted = |t: f32| {
+        let i = prediction.partition_point(|p| p.0.x <= t);
+        let (a, b) = (
+            prediction[i.saturating_sub(1)],
+            prediction[i.min(prediction.len() - 1)],
+        );
+        let frac = if b.0.x > a.0.x {
+            (t - a.0.x) / (b.0.x - a.0.x)
+        } else {
+            0.0
+        };
+        a.0.y + frac * (b.0.y - a.0.y)
+    };
+    let start_time = spectrogram.start_time();
+    let mask = spectrogram.mask();
+
+    let mut signals = Vec::new();
+    // Time and offset from the predicted curve of the last detection
+    let mut lock: Option<(f32, f32)> = None;
+    for (t_idx, timestamp) in spectrogram.timestamps.iter().enumerate() {
+        let t = (*timestamp - start_time).as_seconds_f32();
+        if t < first.0.x || t > last.0.x {
+            continue;
+        }
+        if let Some((t_last, _)) = lock
+            && t - t_last > options.lock_timeout
+        {
+            log::debug!("Lost lock on the signal at {t:.0} s");
+            lock = None;
+        }
+        if mask.is_some_and(|mask| mask.slices[t_idx]) {
+            continue;
+        }
+        // The noise statistics are always taken over the search window, but while locked, only
+        // peaks within the drift limit are accepted
+        let (center, half_drift) = match lock {
+            Some((t_last, locked_offset)) => (
+                predicted(t) + locked_offset,
+                (options.max_drift * (t - t_last)).max(1.0 / f_scale),
+            ),
+            None => (predicted(t) + offset, options.search_bw / 2.0),
+        };
+        let channel = |freq: f32| to_index((freq + bw / 2.0) * f_scale, nf);
+        let half_bw = options.search_bw / 2.0;
+        let f_range = channel(center - half_bw)..channel(center + half_bw) + 1;
+        if f_range.len() < 3 {
+            continue;
+        }
+        let peak_range = channel(center - half_drift).max(f_range.start) - f_range.start
+            ..(channel(center + half_drift) + 1).min(f_range.end) - f_range.start;
+
+        let data = spectrogram.tile(t_idx..t_idx + 1, f_range.clone());
+        let slice = data.row(0);
+        let masked = mask.map(|mask| &mask.channels[f_range.clone()]);
+        let peaks = find_peaks_ft(slice, masked, peak_range, &[options.method.sigma()], 0)?;
+        if let Some(&(f_idx, sigma)) = peaks.first() {
+            let f_idx = options.method.refine_peak(slice, masked, f_idx);
+            let freq = (f_idx + f_range.start as f32) / f_scale - bw / 2.0;
+            lock = Some((t, freq - predicted(t)));
+            signals.push(Signal {
+                point: data_absolute::Point::new(t, freq),
+                sigma: Some(sigma),
+                tone: 0,
+                rejected: false,
+            });
+        }
+    }
     Ok(signals)
 }
 
-fn find_signals_ft(data: ArrayView1<f32>, sigma_threshold: f32) -> anyhow::Result<Vec<usize>> {
+/// Returns the channel of each peak with its significance, strongest first. `masked` flags the
+/// channels of `data` that are left out.
+///
+/// Peaks are only looked for in the channels `peak_range` of `data`, while the mean and standard
+/// deviation are taken over all of it. Up to one peak per entry of `sigma_thresholds` is looked
+/// for, each at least `min_separation` channels from the stronger ones, and kept if it passes
+/// its own threshold. The statistics leave out all of the peak channels, so that the other tones
+/// don't count as noise.
+fn find_peaks_ft(
+    data: ArrayView1<f32>,
+    masked: Option<&[bool]>,
+    peak_range: Range<usize>,
+    sigma_thresholds: &[f32],
+    min_separation: usize,
+) -> anyhow::Result<Vec<(usize, f32)>> {
     // fit_trace works on non-log data, so we need to convert back here
-    let data = data.mapv(|v| 10.0_f32.powf(v / 10.0));
-    let max_idx = data.argmax()?;
-    let max = data[max_idx];
-    let sum = data.sum() - max;
-    let sq_sum = data.mapv(|v| v * v).sum() - max * max;
-    let mean = sum / (data.len() as f32 - 1.0);
-    let std_dev = ((sq_sum / (data.len() as f32 - 1.0)) - (mean * mean)).sqrt();
-    let sigma = (max - mean) / std_dev;
-    if sigma > sigma_threshold {
-        Ok(vec![max_idx])
-    } else {
-        Ok(Vec::new())
+    let (indices, data): (Vec<usize>, Vec<f32>) = data
+        .iter()
+        .enumerate()
+        .filter(|&(i, _)| !masked.is_some_and(|masked| masked[i]))
+        .map(|(i, &v)| (i, db_to_linear(v)))
+        .unzip();
+    let data = Array1::from(data);
+    let candidates = indices.partition_point(|&i| i < peak_range.start)
+        ..indices.partition_point(|&i| i < peak_range.end);
+    if candidates.is_empty() {
+        return Ok(Vec::new());
     }
+
+    let mut excluded = vec![false; data.len()];
+    let mut maxima = Vec::new();
+    while maxima.len() < sigma_thresholds.len() {
+        let Some(max_idx) = candidates
+            .clone()
+            .filter(|&i| !excluded[i])
+            .max_by(|&a, &b| data[a].total_cmp(&data[b]))
+        else {
+            break;
+        };
+        maxima.push(max_idx);
+        for (i, &chan) in indices.iter().enumerate() {
+            excluded[i] |= chan.abs_diff(indices[max_idx]) <= min_separation;
+        }
+    }
+
+    let (n, sum, sq_sum) = data
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| !maxima.contains(i))
+        .fold((0.0, 0.0, 0.0), |(n, sum, sq_sum), (_, &v)| {
+            (n + 1.0, sum + v, sq_sum + v * v)
+        });
+    let mean = sum / n;
+    let std_dev = (sq_sum / n - mean * mean).sqrt();
+    let peaks = maxima
+        .into_iter()
+        .zip(sigma_thresholds)
+        .map(|(max_idx, &threshold)| {
+            (
+                indices[max_idx],
+                (data[max_idx] - mean) / std_dev,
+                threshold,
+            )
+        })
+        .filter(|&(_, sigma, threshold)| sigma > threshold)
+        .map(|(channel, sigma, _)| (channel, sigma))
+        .collect();
+    Ok(peaks)
+}
+
+fn db_to_linear(db: f32) -> f32 {
+    10.0_f32.powf(db / 10.0)
+}
+
+/// Offset of the vertex of the parabola through `(-1, a)`, `(0, b)` and `(1, c)`, limited to half
+/// a channel. 0 if the parabola has no maximum.
+fn parabola_vertex([a, b, c]: [f32; 3]) -> f32 {
+    let curvature = a - 2.0 * b + c;
+    if curvature >= 0.0 {
+        return 0.0;
+    }
+    (0.5 * (a - c) / curvature).clamp(-0.5, 0.5)
+}
+
+/// The centroid of the linear power above the mean of `data` (in dB), over the usable channels
+/// around `peak` that are above the mean. `None` if nothing is above it.
+fn centroid(data: ArrayView1<f32>, usable: &impl Fn(usize) -> bool, peak: usize) -> Option<f32> {
+    let (sum, n) = (0..data.len())
+        .filter(|&i| usable(i))
+        .fold((0.0, 0), |(sum, n), i| (sum + db_to_linear(data[i]), n + 1));
+    let mean = sum / n as f32;
+    let excess = |i: usize| Some(db_to_linear(data[i]) - mean).filter(|&e| usable(i) && e > 0.0);
+
+    let (mut weighted, mut total) = (0.0, 0.0);
+    let below = (0..peak).rev().map_while(|i| excess(i).map(|e| (i, e)));
+    let above = (peak..data.len()).map_while(|i| excess(i).map(|e| (i, e)));
+    for (i, excess) in below.chain(above) {
+        weighted += i as f32 * excess;
+        total += excess;
+    }
+    (total > 0.0).then(|| weighted / total)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::spectrogram::{RawStrfSpectrum, SpectrogramParams};
+    use chrono::{DateTime, Duration, Utc};
     use ndarray::arr1;
 
     #[test]
-    fn flat_data_yields_no_signal() {
+    fn find_peaks_ft_ignores_flat_data() {
         // All bins at 0 dB → std_dev = 0 → sigma = NaN → no signal
         let data = arr1(&[0.0f32; 10]);
-        let result = find_signals_ft(data.view(), 5.0).unwrap();
+        let result = find_peaks_ft(data.view(), None, 0..data.len(), &[5.0], 0).unwrap();
         assert!(result.is_empty());
     }
 
     #[test]
-    fn strong_peak_is_detected_at_correct_index() {
+    fn find_peaks_ft_detects_strong_peak_at_correct_index() {
         // 9 bins at 0 dB, 1 bin at 30 dB → sigma = ∞ → signal detected
         let mut data = vec![0.0f32; 10];
         data[5] = 30.0;
         let data = arr1(&data);
-        let result = find_signals_ft(data.view(), 5.0).unwrap();
-        assert_eq!(result, vec![5]);
+        let result = find_peaks_ft(data.view(), None, 0..data.len(), &[5.0], 0).unwrap();
+        assert_eq!(result.iter().map(|s| s.0).collect_vec(), vec![5]);
     }
 
     #[test]
-    fn threshold_filters_weak_peaks() {
+    fn find_peaks_ft_filters_weak_peaks() {
         // Use data with real variance where max is only slightly above mean
         // Values in dB → linear: vary around 1.0 with small spread
         let data = arr1(&[0.0f32, 0.5, -0.3, 0.2, -0.1, 0.4, -0.2, 0.3, 0.1, 0.6]);
         // With high threshold (20 sigma), this moderate peak should not be detected
-        let result = find_signals_ft(data.view(), 20.0).unwrap();
+        let result = find_peaks_ft(data.view(), None, 0..data.len(), &[20.0], 0).unwrap();
         assert!(result.is_empty());
+        // The significance of detected peaks is returned
+        let result = find_peaks_ft(data.view(), None, 0..data.len(), &[1.0], 0).unwrap();
+        assert_eq!(result[0].0, 9);
+        assert!(result[0].1 > 1.0 && result[0].1 < 20.0, "{}", result[0].1);
+    }
+
+    #[test]
+    fn each_peak_has_its_own_threshold() {
+        // A strong carrier at channel 2 and a weak subcarrier at channel 12 over a noisy floor
+        let mut data = [0.0f32, 0.5, -0.3, 0.2, -0.1, 0.4, -0.2, 0.3, 0.1, 0.6].repeat(2);
+        data[2] = 30.0;
+        data[12] = 6.0;
+        let data = arr1(&data);
+        let find = |thresholds: &[f32]| {
+            find_peaks_ft(data.view(), None, 0..data.len(), thresholds, 0)
+                .unwrap()
+                .iter()
+                .map(|s| s.0)
+                .collect_vec()
+        };
+
+        assert_eq!(find(&[5.0, 1e6]), vec![2]);
+        assert_eq!(find(&[5.0, 5.0]), vec![2, 12]);
+        // A weaker peak is kept even if a stronger one misses its threshold
+        assert_eq!(find(&[1e6, 5.0]), vec![12]);
+    }
+
+    /// A peak of 20 dB over a 0 dB floor at fractional channel `center`, with the given shape.
+    fn peak(center: f32, shape: impl Fn(f32) -> f32) -> Array1<f32> {
+        (0..16)
+            .map(|i| 20.0 * shape(i as f32 - center))
+            .collect::<Array1<f32>>()
+    }
+
+    fn refine(method: SignalDetectionMethod, data: &Array1<f32>) -> f32 {
+        let peaks = find_peaks_ft(data.view(), None, 0..data.len(), &[method.sigma()], 0).unwrap();
+        method.refine_peak(data.view(), None, peaks[0].0)
+    }
+
+    #[test]
+    fn refinement_finds_fractional_peaks() {
+        let sigma = 2.0;
+        // Gaussian in linear power, i.e. a parabola in dB
+        let gaussian = peak(7.3, |x| 1.0 - x * x / 4.0);
+        assert_eq!(
+            refine(SignalDetectionMethod::FitTrace { sigma }, &gaussian),
+            7.0
+        );
+        let refined = refine(SignalDetectionMethod::Gaussian { sigma }, &gaussian);
+        assert!((refined - 7.3).abs() < 1e-3, "{refined}");
+
+        // A parabola in linear power
+        let parabola = peak(7.6, |x| (1.0 - x * x / 8.0).max(0.0))
+            .mapv(|p| 10.0 * (1.0 + 99.0 * p / 20.0).log10());
+        let refined = refine(SignalDetectionMethod::Parabolic { sigma }, &parabola);
+        assert!((refined - 7.6).abs() < 1e-3, "{refined}");
+
+        // Symmetric peak between two channels
+        let flat_top = peak(7.5, |x| if x.abs() < 1.0 { 1.0 } else { 0.0 });
+        let refined = refine(SignalDetectionMethod::Centroid { sigma }, &flat_top);
+        assert!((refined - 7.5).abs() < 1e-3, "{refined}");
+    }
+
+    #[test]
+    fn find_peaks_ft_ignores_masked_channels() {
+        // A carrier at 40 dB in channel 2 hides the signal at 30 dB unless it is masked
+        let mut data = vec![0.0f32; 10];
+        data[2] = 40.0;
+        data[7] = 30.0;
+        let data = arr1(&data);
+        let mut masked = [false; 10];
+        masked[2] = true;
+        let peaks = |masked| find_peaks_ft(data.view(), masked, 0..data.len(), &[5.0], 0).unwrap();
+        assert_eq!(peaks(None)[0].0, 2);
+        assert_eq!(peaks(Some(&masked))[0].0, 7);
+        // The masked carrier next to the peak doesn't pull it over
+        let method = SignalDetectionMethod::Centroid { sigma: 5.0 };
+        let mut data = vec![0.0f32; 10];
+        data[3] = 35.0;
+        data[4] = 30.0;
+        let data = arr1(&data);
+        let mut masked = [false; 10];
+        masked[3] = true;
+        assert!(method.refine_peak(data.view(), None, 4) < 3.5);
+        assert_eq!(method.refine_peak(data.view(), Some(&masked), 4), 4.0);
+    }
+
+    /// Pseudo-random noise between 0 and 1 dB.
+    pub(super) fn noise(i: usize, f: usize) -> f32 {
+        let mut x = (i as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15)
+            ^ (f as u64).wrapping_mul(0xC2B2_AE3D_27D4_EB4F);
+        x ^= x >> 31;
+        x = x.wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        x ^= x >> 29;
+        (x % 1000) as f32 / 1000.0
+    }
+
+    /// A spectrogram of 1 s spectra with 1 kHz channels, with 20 dB signals at the frequencies
+    /// (relative to the centre) that `signals` returns for each second.
+    pub(super) fn make_spec(nslices: usize, signals: impl Fn(f32) -> Vec<f32>) -> Spectrogram {
+        let nchan = 128;
+        let start = DateTime::<Utc>::from_timestamp(1_700_000_000, 0).unwrap();
+        let spectra = (0..nslices)
+            .map(|i| {
+                let channels = signals(i as f32)
+                    .into_iter()
+                    .map(|f| (f / 1e3 + nchan as f32 / 2.0).round() as usize)
+                    .collect_vec();
+                RawStrfSpectrum {
+                    time: start + Duration::seconds(i as i64),
+                    length_s: 1.0,
+                    power_linear: (0..nchan)
+                        .map(|f| {
+                            let signal = if channels.contains(&f) { 20.0 } else { 0.0 };
+                            10f32.powf((noise(i, f) + signal) / 10.0)
+                        })
+                        .collect(),
+                }
+            })
+            .collect();
+        let params = SpectrogramParams {
+            freq: 437e6,
+            bw: nchan as f32 * 1e3,
+            nchan,
+        };
+        Spectrogram::from_raw(spectra, params).unwrap()
+    }
+
+    #[test]
+    fn follows_signal_along_prediction() {
+        // The signal is 5 kHz above the prediction and drifts away from it by 10 Hz/s. It fades
+        // for 40 s, and a stronger signal appears next to it for a while.
+        let predicted = |t: f32| -30e3 + 300.0 * t;
+        let truth = |t: f32| predicted(t) + 5e3 + 10.0 * t;
+        let spec = make_spec(200, |t| {
+            let mut signals = Vec::new();
+            if !(100.0..140.0).contains(&t) {
+                signals.push(truth(t));
+            }
+            if (40.0..60.0).contains(&t) {
+                signals.push(truth(t) + 4e3);
+            }
+            signals
+        });
+        let prediction = (0..200)
+            .step_by(10)
+            .map(|t| data_absolute::Point::new(t as f32, predicted(t as f32)))
+            .collect_vec();
+        let options = FollowOptions {
+            method: SignalDetectionMethod::FitTrace { sigma: 5.0 },
+            search_bw: 40e3,
+            max_drift: 50.0,
+            lock_timeout: 20.0,
+        };
+
+        let signals = follow_signal(&spec, &prediction, 0.0, options).unwrap();
+        for signal in &signals {
+            let (t, f) = (signal.point.0.x, signal.point.0.y);
+            assert!((f - truth(t)).abs() <= 1e3, "{t} s: {f} Hz");
+            assert!(!(100.0..140.0).contains(&t), "{t} s");
+        }
+        let times = signals.iter().map(|s| s.point.0.x).collect_vec();
+        // All but the last spectrum, which is outside the prediction
+        assert_eq!(times.len(), 200 - 40 - 9);
+        assert!(times.contains(&140.0));
+    }
+
+    #[test]
+    fn doppler_interpolation_follows_s_curve() {
+        let truth = |t: f32| -20e3 * ((t - 100.0) / 20.0).tanh();
+        let spec = make_spec(200, |t| vec![truth(t)]);
+        let track_points = [0.0, 90.0, 110.0, 199.0]
+            .map(|t| data_absolute::Point::new(t, truth(t)))
+            .to_vec();
+        let method = SignalDetectionMethod::FitTrace { sigma: 5.0 };
+        let find = |interpolation| {
+            find_signals(
+                &spec,
+                &track_points,
+                6e3,
+                interpolation,
+                method,
+                ToneOptions::default(),
+            )
+            .unwrap()
+        };
+
+        let signals = find(TrackInterpolation::Doppler);
+        assert_eq!(signals.len(), 200);
+        for signal in &signals {
+            let (t, f) = (signal.point.0.x, signal.point.0.y);
+            assert!((f - truth(t)).abs() <= 1e3, "{t} s: {f} Hz");
+        }
+        // The straight lines cut the corners of the S-curve and miss the signal there
+        assert!(find(TrackInterpolation::Linear).len() < 150);
+    }
+
+    #[test]
+    fn finds_both_tones_of_fsk_signal() {
+        // Two tones 8 kHz apart, and the upper one fades out halfway
+        let carrier = |t: f32| -10e3 + 50.0 * t;
+        let spec = make_spec(100, |t| {
+            let mut signals = vec![carrier(t) - 4e3];
+            if t < 50.0 {
+                signals.push(carrier(t) + 4e3);
+            }
+            signals
+        });
+        let track_points = [0.0, 99.0]
+            .map(|t| data_absolute::Point::new(t, carrier(t)))
+            .to_vec();
+        let tones = ToneOptions {
+            count: 2,
+            min_separation: 3e3,
+            secondary_sigma: None,
+        };
+        let method = SignalDetectionMethod::FitTrace { sigma: 5.0 };
+        let signals = find_signals(
+            &spec,
+            &track_points,
+            20e3,
+            TrackInterpolation::Linear,
+            method,
+            tones,
+        )
+        .unwrap();
+
+        assert_eq!(signals.len(), 150);
+        for signal in &signals {
+            let (t, f) = (signal.point.0.x, signal.point.0.y);
+            let offset = if signal.tone == 0 { -4e3 } else { 4e3 };
+            assert!((f - carrier(t) - offset).abs() <= 1e3, "{t} s: {f} Hz");
+        }
+        assert_eq!(signals.iter().filter(|s| s.tone == 1).count(), 50);
     }
 }
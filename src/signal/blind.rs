@@ -0,0 +1,435 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Blind search for signals over the whole spectrogram, for when it isn't known where (or whether)
+//! an object transmits.
+//!
+//! Each spectrum is thresholded with a cell-averaging CFAR (constant false alarm rate) detector,
+//! detections are grouped into connected components, and components that continue each other
+//! after a gap are linked into candidate traces.
+
+use ndarray::ArrayView1;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use super::db_to_linear;
+use crate::{
+    coord::data_absolute,
+    spectrogram::{STREAM_CHUNK_SLICES, Spectrogram},
+};
+
+/// Options for [`blind_search`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BlindSearchOptions {
+    /// Detection threshold in standard deviations above the mean of the training cells
+    pub threshold: f32,
+    /// Channels on each side of a cell that are left out of its training cells, so a signal that
+    /// is wider than one channel doesn't raise its own noise estimate
+    pub guard: usize,
+    /// Channels on each side of a cell (beyond the guard channels) that its noise is estimated
+    /// from
+    pub train: usize,
+    /// Longest gap (in seconds) between the end of a trace and the start of a component that
+    /// continues it
+    pub max_gap: f32,
+    /// How far (in channels) a component may start from where a trace is extrapolated to, to
+    /// continue it
+    pub max_jump: f32,
+    /// Traces with fewer detections are dropped
+    pub min_cells: usize,
+}
+
+impl Default for BlindSearchOptions {
+    fn default() -> Self {
+        Self {
+            threshold: 5.0,
+            guard: 2,
+            train: 16,
+            max_gap: 30.0,
+            max_jump: 5.0,
+            min_cells: 5,
+        }
+    }
+}
+
+/// A candidate signal trace found by [`blind_search`].
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Candidate {
+    /// Time of the first and last detection, in seconds since the start of the spectrogram
+    pub start: f32,
+    pub end: f32,
+    /// Lowest and highest frequency of the detections, relative to the center frequency (in Hz)
+    pub freq_min: f32,
+    pub freq_max: f32,
+    /// Drift rate (in Hz/s) of a straight line fitted to the detections
+    pub drift: f32,
+    /// Significance of the strongest detection
+    pub sigma: f32,
+    /// Number of detections
+    pub cells: usize,
+}
+
+impl Candidate {
+    pub fn duration(&self) -> f32 {
+        self.end - self.start
+    }
+
+    pub fn freq_span(&self) -> f32 {
+        self.freq_max - self.freq_min
+    }
+
+    /// The time and frequency range of the trace.
+    pub fn bounds(&self) -> data_absolute::Rectangle {
+        data_absolute::Rectangle::new(
+            data_absolute::Point::new(self.start, self.freq_min),
+            data_absolute::Size::new(self.duration(), self.freq_span()),
+        )
+    }
+}
+
+/// Searches the whole spectrogram for signal traces, reading it in chunks of spectra. Spectra and
+/// channels that are flagged as RFI are skipped. The candidates are sorted by start time.
+///
+/// This is CPU-heavy for large inputs, so async callers should run it in `spawn_blocking`.
+pub fn blind_search(spectrogram: &Spectrogram, options: &BlindSearchOptions) -> Vec<Candidate> {
+    let (nslices, nchan) = (spectrogram.nslices, spectrogram.nchan);
+    let f_scale = nchan as f32 / spectrogram.bw;
+    let freq = |chan: usize| chan as f32 / f_scale - spectrogram.bw / 2.0;
+    let start_time = spectrogram.start_time();
+    let mask = spectrogram.mask();
+
+    let mut components = Components::default();
+    let mut previous: Vec<Run> = Vec::new();
+    for start in (0..nslices).step_by(STREAM_CHUNK_SLICES) {
+        let rows = start..(start + STREAM_CHUNK_SLICES).min(nslices);
+        let tile = spectrogram.tile(rows.clone(), 0..nchan);
+        let significance: Vec<Vec<f32>> = rows
+            .clone()
+            .into_par_iter()
+            .map(|slice| {
+                if mask.is_some_and(|mask| mask.slices[slice]) {
+                    return Vec::new();
+                }
+                let masked = mask.map(|mask| mask.channels.as_slice());
+                let spectrum = tile.row(slice - start);
+                cfar(spectrum, masked, options.guard, options.train)
+            })
+            .collect();
+
+        for (slice, significance) in rows.zip(significance) {
+            let t = (spectrogram.timestamps[slice] - start_time).as_seconds_f32();
+            let mut current = Vec::new();
+            for (run_start, run_end) in runs(&significance, options.threshold) {
+                let mut stats = Stats::empty();
+                for (chan, &sigma) in (run_start..).zip(&significance[run_start..=run_end]) {
+                    stats.add(t, freq(chan), sigma);
+                }
+                let label = components.push(stats);
+                // Runs in consecutive spectra are connected if they touch, including diagonally
+                for run in previous
+                    .iter()
+                    .filter(|run| run.start <= run_end + 1 && run_start <= run.end + 1)
+                {
+                    components.union(label, run.label);
+                }
+                current.push(Run {
+                    start: run_start,
+                    end: run_end,
+                    label,
+                });
+            }
+            previous = current;
+        }
+    }
+
+    let mut traces = link(
+        components.into_stats(),
+        options.max_gap,
+        options.max_jump / f_scale,
+    );
+    traces.retain(|trace| trace.cells >= options.min_cells);
+    traces.iter().map(Stats::candidate).collect()
+}
+
+/// Significance of each channel of a spectrum (in dB), in standard deviations of the linear power
+/// of its training cells above their mean. NaN for masked channels and channels with fewer than
+/// `train` unmasked training cells (e.g. at the band edges).
+fn cfar(
+    spectrum: ArrayView1<f32>,
+    masked: Option<&[bool]>,
+    guard: usize,
+    train: usize,
+) -> Vec<f32> {
+    let n = spectrum.len();
+    let usable = |i: usize| !masked.is_some_and(|masked| masked[i]);
+    // Prefix sums of the count, power and squared power of the usable channels
+    let mut prefix = vec![(0usize, 0f64, 0f64); n + 1];
+    for (i, &db) in spectrum.iter().enumerate() {
+        let (count, sum, sq_sum) = prefix[i];
+        prefix[i + 1] = if usable(i) {
+            let linear = db_to_linear(db) as f64;
+            (count + 1, sum + linear, sq_sum + linear * linear)
+        } else {
+            (count, sum, sq_sum)
+        };
+    }
+    let window = |range: std::ops::Range<usize>| {
+        let (c0, s0, q0) = prefix[range.start];
+        let (c1, s1, q1) = prefix[range.end];
+        (c1 - c0, s1 - s0, q1 - q0)
+    };
+
+    (0..n)
+        .map(|i| {
+            if !usable(i) {
+                return f32::NAN;
+            }
+            let below = window(i.saturating_sub(guard + train)..i.saturating_sub(guard));
+            let above = window((i + guard + 1).min(n)..(i + guard + train + 1).min(n));
+            let count = below.0 + above.0;
+            if count < train.max(2) {
+                return f32::NAN;
+            }
+            let mean = (below.1 + above.1) / count as f64;
+            let variance = (below.2 + above.2) / count as f64 - mean * mean;
+            if variance <= 0.0 {
+                return f32::NAN;
+            }
+            ((db_to_linear(spectrum[i]) as f64 - mean) / variance.sqrt()) as f32
+        })
+        .collect()
+}
+
+/// Runs of consecutive channels above the threshold, as inclusive channel ranges.
+fn runs(significance: &[f32], threshold: f32) -> Vec<(usize, usize)> {
+    let mut runs: Vec<(usize, usize)> = Vec::new();
+    for (chan, _) in significance
+        .iter()
+        .enumerate()
+        .filter(|&(_, &s)| s > threshold)
+    {
+        match runs.last_mut() {
+            Some((_, end)) if *end + 1 == chan => *end = chan,
+            _ => runs.push((chan, chan)),
+        }
+    }
+    runs
+}
+
+/// A run of detections in one spectrum, labelled with its component.
+struct Run {
+    start: usize,
+    end: usize,
+    label: usize,
+}
+
+/// Connected components of detections, as a union-find forest whose roots hold the statistics.
+#[derive(Default)]
+struct Components {
+    parent: Vec<usize>,
+    stats: Vec<Stats>,
+}
+
+impl Components {
+    fn push(&mut self, stats: Stats) -> usize {
+        self.parent.push(self.parent.len());
+        self.stats.push(stats);
+        self.parent.len() - 1
+    }
+
+    fn find(&mut self, mut label: usize) -> usize {
+        while self.parent[label] != label {
+            self.parent[label] = self.parent[self.parent[label]];
+            label = self.parent[label];
+        }
+        label
+    }
+
+    fn union(&mut self, a: usize, b: usize) {
+        let (a, b) = (self.find(a), self.find(b));
+        if a != b {
+            let stats = self.stats[b];
+            self.stats[a].merge(&stats);
+            self.parent[b] = a;
+        }
+    }
+
+    /// The statistics of each component.
+    fn into_stats(mut self) -> Vec<Stats> {
+        let roots: Vec<usize> = (0..self.parent.len())
+            .filter(|&label| self.find(label) == label)
+            .collect();
+        roots.into_iter().map(|label| self.stats[label]).collect()
+    }
+}
+
+/// Statistics of a group of detections, with the sums for a least-squares line fit of frequency
+/// over time.
+#[derive(Debug, Clone, Copy)]
+struct Stats {
+    start: f32,
+    end: f32,
+    freq_min: f32,
+    freq_max: f32,
+    sigma: f32,
+    cells: usize,
+    sum_t: f64,
+    sum_f: f64,
+    sum_tt: f64,
+    sum_tf: f64,
+}
+
+impl Stats {
+    fn empty() -> Stats {
+        Stats {
+            start: f32::INFINITY,
+            end: f32::NEG_INFINITY,
+            freq_min: f32::INFINITY,
+            freq_max: f32::NEG_INFINITY,
+            sigma: f32::NEG_INFINITY,
+            cells: 0,
+            sum_t: 0.0,
+            sum_f: 0.0,
+            sum_tt: 0.0,
+            sum_tf: 0.0,
+        }
+    }
+
+    fn add(&mut self, t: f32, f: f32, sigma: f32) {
+        let (t_sum, f_sum) = (t as f64, f as f64);
+        self.merge(&Stats {
+            start: t,
+            end: t,
+            freq_min: f,
+            freq_max: f,
+            sigma,
+            cells: 1,
+            sum_t: t_sum,
+            sum_f: f_sum,
+            sum_tt: t_sum * t_sum,
+            sum_tf: t_sum * f_sum,
+        });
+    }
+
+    fn merge(&mut self, other: &Stats) {
+        self.start = self.start.min(other.start);
+        self.end = self.end.max(other.end);
+        self.freq_min = self.freq_min.min(other.freq_min);
+        self.freq_max = self.freq_max.max(other.freq_max);
+        self.sigma = self.sigma.max(other.sigma);
+        self.cells += other.cells;
+        self.sum_t += other.sum_t;
+        self.sum_f += other.sum_f;
+        self.sum_tt += other.sum_tt;
+        self.sum_tf += other.sum_tf;
+    }
+
+    /// Slope of the fitted line, or 0 if all detections are at the same time.
+    fn drift(&self) -> f32 {
+        let n = self.cells as f64;
+        let denominator = n * self.sum_tt - self.sum_t * self.sum_t;
+        if denominator.abs() < 1e-9 * n * n {
+            return 0.0;
+        }
+        ((n * self.sum_tf - self.sum_t * self.sum_f) / denominator) as f32
+    }
+
+    /// Frequency of the fitted line at time `t`.
+    fn freq_at(&self, t: f32) -> f32 {
+        let n = self.cells as f64;
+        (self.sum_f / n) as f32 + self.drift() * (t - (self.sum_t / n) as f32)
+    }
+
+    fn candidate(&self) -> Candidate {
+        Candidate {
+            start: self.start,
+            end: self.end,
+            freq_min: self.freq_min,
+            freq_max: self.freq_max,
+            drift: self.drift(),
+            sigma: self.sigma,
+            cells: self.cells,
+        }
+    }
+}
+
+/// Links components into traces: each component (by start time) continues the trace that ended
+/// at most `max_gap` seconds before it and whose extrapolated frequency is closest to, and at
+/// most `max_jump` Hz from, its own. Components that don't continue a trace start a new one.
+fn link(mut components: Vec<Stats>, max_gap: f32, max_jump: f32) -> Vec<Stats> {
+    components.sort_by(|a, b| a.start.total_cmp(&b.start));
+    let mut traces: Vec<Stats> = Vec::new();
+    for component in components {
+        let best = traces
+            .iter()
+            .enumerate()
+            .filter(|(_, trace)| {
+                let gap = component.start - trace.end;
+                gap > 0.0 && gap <= max_gap
+            })
+            .map(|(i, trace)| {
+                let jump =
+                    (trace.freq_at(component.start) - component.freq_at(component.start)).abs();
+                (i, jump)
+            })
+            .filter(|&(_, jump)| jump <= max_jump)
+            .min_by(|a, b| a.1.total_cmp(&b.1));
+        match best {
+            Some((i, _)) => traces[i].merge(&component),
+            None => traces.push(component),
+        }
+    }
+    traces.sort_by(|a, b| a.start.total_cmp(&b.start));
+    traces
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::signal::tests::{make_spec, noise};
+
+    #[test]
+    fn finds_drifting_and_interrupted_traces() {
+        // A signal drifting by 100 Hz/s for 60 s, with a 10 s dropout, and a steady one from
+        // 120 s on
+        let spec = make_spec(200, |t| {
+            let mut signals = Vec::new();
+            if t < 60.0 && !(25.0..35.0).contains(&t) {
+                signals.push(-30e3 + (t / 10.0).floor() * 1e3);
+            }
+            if t >= 120.0 {
+                signals.push(40e3);
+            }
+            signals
+        });
+        let candidates = blind_search(&spec, &BlindSearchOptions::default());
+
+        assert_eq!(candidates.len(), 2, "{candidates:?}");
+        let (drifting, steady) = (&candidates[0], &candidates[1]);
+        assert_eq!((drifting.start, drifting.end), (0.0, 59.0));
+        assert_eq!(drifting.cells, 50);
+        assert!((drifting.drift - 100.0).abs() < 10.0, "{}", drifting.drift);
+        assert_eq!((drifting.freq_min, drifting.freq_span()), (-30e3, 5e3));
+        assert_eq!((steady.start, steady.end), (120.0, 199.0));
+        assert!((steady.freq_min - 40e3).abs() < 1.0, "{}", steady.freq_min);
+        assert_eq!(steady.freq_span(), 0.0);
+        assert_eq!(steady.drift, 0.0);
+    }
+
+    #[test]
+    fn cfar_ignores_masked_channels() {
+        let spectrum = ndarray::Array1::from_iter((0..64).map(|f| match f {
+            20 => 20.0,
+            40 => 30.0,
+            _ => noise(0, f),
+        }));
+        let mut masked = vec![false; 64];
+        masked[40] = true;
+
+        let significance = cfar(spectrum.view(), Some(&masked), 2, 16);
+        assert!(significance[40].is_nan());
+        assert_eq!(runs(&significance, 5.0), vec![(20, 20)]);
+        // Without the mask, the carrier at 40 is detected as well
+        let significance = cfar(spectrum.view(), None, 2, 16);
+        assert_eq!(runs(&significance, 5.0), vec![(20, 20), (40, 40)]);
+    }
+}
@@ -0,0 +1,190 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Incoherent stacking of spectra along a predicted Doppler curve, for signals that are too weak to
+//! be seen in single spectra.
+//!
+//! Each spectrum is shifted so that the predicted frequency lines up, and the linear power is
+//! averaged over all of them. The noise averages down while the signal stays at a fixed offset from
+//! the prediction, so it stands out in the stacked spectrum.
+
+use ndarray::ArrayView1;
+use serde::{Deserialize, Serialize};
+
+use super::{SignalDetectionMethod, Track, TrackInterpolation, db_to_linear};
+use crate::{
+    coord::data_absolute,
+    orbit::PassPrediction,
+    spectrogram::{MAD_TO_SIGMA, Spectrogram, percentile_of},
+};
+
+/// The de-dopplered spectrum that [`stack_along`] returns.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StackedSpectrum {
+    /// Width of the channels in Hz
+    pub channel_width: f32,
+    /// Mean power of each channel in dB, NaN for channels without data. The middle channel is at
+    /// the predicted frequency.
+    pub power: Vec<f32>,
+    /// Number of spectra that were stacked
+    pub spectra: usize,
+}
+
+/// The strongest channel of a [`StackedSpectrum`].
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct StackPeak {
+    /// Offset of the transmitter from the predicted frequency in Hz
+    pub offset: f32,
+    /// Power of the peak above the median of the stacked spectrum in dB
+    pub snr: f32,
+    /// Significance of the peak in robust standard deviations of the stacked spectrum
+    pub sigma: f32,
+}
+
+impl StackedSpectrum {
+    /// Offset of channel `i` from the predicted frequency in Hz.
+    pub fn offset(&self, i: f32) -> f32 {
+        (i - (self.power.len() / 2) as f32) * self.channel_width
+    }
+
+    /// The strongest channel, refined to a fraction of a channel. `None` if no channel has any
+    /// data.
+    pub fn peak(&self) -> Option<StackPeak> {
+        let (peak, &peak_db) = self
+            .power
+            .iter()
+            .enumerate()
+            .filter(|(_, p)| !p.is_nan())
+            .max_by(|(_, a), (_, b)| a.total_cmp(b))?;
+        let linear: Vec<f32> = self
+            .power
+            .iter()
+            .filter(|p| !p.is_nan())
+            .map(|&p| db_to_linear(p))
+            .collect();
+        let median = percentile_of(linear.clone(), 50.0);
+        let deviations = linear.iter().map(|p| (p - median).abs()).collect();
+        let std_dev = percentile_of(deviations, 50.0) * MAD_TO_SIGMA;
+        let empty: Vec<bool> = self.power.iter().map(|p| p.is_nan()).collect();
+        let method = SignalDetectionMethod::Parabolic { sigma: 0.0 };
+        let channel = method.refine_peak(ArrayView1::from(&self.power), Some(&empty), peak);
+        Some(StackPeak {
+            offset: self.offset(channel),
+            snr: peak_db - 10.0 * median.log10(),
+            sigma: (db_to_linear(peak_db) - median) / std_dev,
+        })
+    }
+}
+
+/// Stacks the spectra along the predicted frequency curves (relative to the center frequency, like
+/// [`follow_signal`](super::follow_signal)), over `span` Hz around the prediction. Spectra and
+/// channels that are flagged as RFI are skipped. `None` if no spectrum is covered by a curve.
+pub fn stack_along(
+    spectrogram: &Spectrogram,
+    curves: &[Vec<data_absolute::Point>],
+    span: f32,
+) -> Option<StackedSpectrum> {
+    let nf = spectrogram.nchan;
+    let f_scale = nf as f32 / spectrogram.bw;
+    let half = (span / 2.0 * f_scale).round() as isize;
+    let width = 2 * half as usize + 1;
+    let start_time = spectrogram.start_time();
+    let mask = spectrogram.mask();
+
+    let mut sums = vec![0.0f64; width];
+    let mut counts = vec![0usize; width];
+    let mut spectra = 0;
+    for track in curves
+        .iter()
+        .filter_map(|curve| Track::new(curve, TrackInterpolation::Linear))
+    {
+        for (t_idx, timestamp) in spectrogram.timestamps.iter().enumerate() {
+            let t = (*timestamp - start_time).as_seconds_f32();
+            if t < track.start() || t > track.end() || mask.is_some_and(|mask| mask.slices[t_idx]) {
+                continue;
+            }
+            let center = ((track.freq_at(t) + spectrogram.bw / 2.0) * f_scale).round() as isize;
+            let channels = (center - half).max(0)..(center + half + 1).min(nf as isize);
+            if channels.is_empty() {
+                continue;
+            }
+            let data = spectrogram.tile(
+                t_idx..t_idx + 1,
+                channels.start as usize..channels.end as usize,
+            );
+            for (chan, &power) in channels.clone().zip(data.row(0)) {
+                if mask.is_some_and(|mask| mask.channels[chan as usize]) {
+                    continue;
+                }
+                let i = (chan - center + half) as usize;
+                sums[i] += db_to_linear(power) as f64;
+                counts[i] += 1;
+            }
+            spectra += 1;
+        }
+    }
+    if spectra == 0 {
+        return None;
+    }
+
+    let power = sums
+        .iter()
+        .zip(&counts)
+        .map(|(&sum, &count)| match count {
+            0 => f32::NAN,
+            _ => 10.0 * (sum / count as f64).log10() as f32,
+        })
+        .collect();
+    Some(StackedSpectrum {
+        channel_width: 1.0 / f_scale,
+        power,
+        spectra,
+    })
+}
+
+/// Stacks the spectra along the predicted frequency of a transmitter during a pass, see
+/// [`stack_along`]. `times` are the prediction times, in seconds since the start of the
+/// spectrogram.
+pub fn stack_pass(
+    spectrogram: &Spectrogram,
+    times: ArrayView1<f64>,
+    pass: &PassPrediction,
+    transmitter: usize,
+    span: f32,
+) -> Option<StackedSpectrum> {
+    let curve = pass.curve(times, transmitter, spectrogram.freq as f64)?;
+    stack_along(spectrogram, &[curve], span)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::signal::tests::make_spec;
+
+    #[test]
+    fn stacking_lines_up_the_doppler_curve() {
+        // The transmitter is 3 kHz above the prediction, which sweeps over 40 kHz
+        let predicted = |t: f32| -20e3 * ((t - 50.0) / 15.0).tanh();
+        let spec = make_spec(100, |t| vec![predicted(t) + 3e3]);
+        let curve = (0..100)
+            .map(|t| data_absolute::Point::new(t as f32, predicted(t as f32)))
+            .collect();
+
+        let stacked = stack_along(&spec, &[curve], 20e3).unwrap();
+        assert_eq!(stacked.power.len(), 21);
+        assert_eq!(stacked.spectra, 100);
+        let peak = stacked.peak().unwrap();
+        assert!((peak.offset - 3e3).abs() <= 500.0, "{peak:?}");
+        assert!(peak.snr > 15.0, "{peak:?}");
+        assert!(peak.sigma > 10.0, "{peak:?}");
+    }
+
+    #[test]
+    fn nothing_to_stack_outside_the_curve() {
+        let spec = make_spec(10, |_| Vec::new());
+        let curve = vec![
+            data_absolute::Point::new(100.0, 0.0),
+            data_absolute::Point::new(200.0, 0.0),
+        ];
+        assert_eq!(stack_along(&spec, &[curve], 10e3), None);
+    }
+}
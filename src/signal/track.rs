@@ -0,0 +1,323 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Interpolation of the track through the trackpoints that [`find_signals`](super::find_signals)
+//! searches along.
+
+use serde::{Deserialize, Serialize};
+use strum::{Display, VariantArray};
+
+use crate::{
+    coord::data_absolute,
+    spectrogram::{MAD_TO_SIGMA, percentile_of},
+};
+
+/// Steps of the grid that [`Shape::fit_doppler`] searches per parameter and refinement
+const DOPPLER_GRID: usize = 32;
+const DOPPLER_REFINEMENTS: usize = 4;
+/// Most refits of [`reject_outliers`], in case the rejections don't settle
+const OUTLIER_ITERATIONS: usize = 10;
+
+/// How the track is interpolated between the trackpoints.
+#[derive(
+    Debug, Default, Display, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, VariantArray,
+)]
+pub enum TrackInterpolation {
+    /// Straight lines between consecutive trackpoints
+    #[default]
+    Linear,
+    /// A natural cubic spline through the trackpoints
+    Spline,
+    /// The S-curve `a + b * tanh((t - t0) / tau)` that fits the trackpoints best, which is close
+    /// to the Doppler curve of a pass. Needs at least three trackpoints, with fewer it is linear.
+    Doppler,
+}
+
+/// The track through a set of trackpoints, see [`TrackInterpolation`].
+#[derive(Debug, Clone)]
+pub struct Track {
+    points: Vec<data_absolute::Point>,
+    shape: Shape,
+}
+
+#[derive(Debug, Clone)]
+enum Shape {
+    Linear,
+    /// Second derivatives at the trackpoints
+    Spline(Vec<f32>),
+    Doppler {
+        a: f32,
+        b: f32,
+        t0: f32,
+        tau: f32,
+    },
+}
+
+impl Track {
+    /// The track through `points`, which are sorted by time. `None` if there are fewer than two.
+    pub fn new(
+        points: &[data_absolute::Point],
+        interpolation: TrackInterpolation,
+    ) -> Option<Track> {
+        if points.len() < 2 {
+            return None;
+        }
+        let shape = match interpolation {
+            _ if points.len() == 2 => Shape::Linear,
+            TrackInterpolation::Linear => Shape::Linear,
+            TrackInterpolation::Spline => Shape::Spline(spline_derivatives(points)),
+            TrackInterpolation::Doppler => Shape::fit_doppler(points).unwrap_or(Shape::Linear),
+        };
+        Some(Track {
+            points: points.to_vec(),
+            shape,
+        })
+    }
+
+    /// Time of the first trackpoint.
+    pub fn start(&self) -> f32 {
+        self.points[0].0.x
+    }
+
+    /// Time of the last trackpoint.
+    pub fn end(&self) -> f32 {
+        self.points[self.points.len() - 1].0.x
+    }
+
+    /// The frequency of the track at time `t`, which is clamped to the trackpoints.
+    pub fn freq_at(&self, t: f32) -> f32 {
+        let t = t.clamp(self.start(), self.end());
+        if let Shape::Doppler { a, b, t0, tau } = self.shape {
+            return a + b * ((t - t0) / tau).tanh();
+        }
+        let i = self
+            .points
+            .partition_point(|p| p.0.x <= t)
+            .clamp(1, self.points.len() - 1);
+        let (p, q) = (self.points[i - 1].0, self.points[i].0);
+        let h = q.x - p.x;
+        if h <= 0.0 {
+            return q.y;
+        }
+        let frac = (t - p.x) / h;
+        let linear = p.y + frac * (q.y - p.y);
+        match &self.shape {
+            Shape::Spline(m) => {
+                // Cubic Hermite form of the spline segment with curvatures m[i - 1], m[i]
+                let cubic = |x: f32| x * x * x - x;
+                linear + h * h / 6.0 * (m[i - 1] * cubic(1.0 - frac) + m[i] * cubic(frac))
+            }
+            _ => linear,
+        }
+    }
+
+    /// `n` points on the track, evenly spaced in time from the first to the last trackpoint.
+    pub fn sample(&self, n: usize) -> impl Iterator<Item = data_absolute::Point> + '_ {
+        let step = (self.end() - self.start()) / (n.max(2) - 1) as f32;
+        (0..n.max(2)).map(move |i| {
+            let t = self.start() + i as f32 * step;
+            data_absolute::Point::new(t, self.freq_at(t))
+        })
+    }
+}
+
+/// Flags the points that don't follow a pass: fits a Doppler curve (see
+/// [`TrackInterpolation::Doppler`]) to the points, flags those whose residual is more than
+/// `threshold` robust standard deviations (or at most `tolerance` Hz) from it, and refits to the
+/// rest until the flags don't change. Flagged points are checked against each refit as well, so
+/// they can come back. Nothing is flagged if fewer than four points are left for a fit.
+pub fn reject_outliers(
+    points: &[data_absolute::Point],
+    threshold: f32,
+    tolerance: f32,
+) -> Vec<bool> {
+    let mut order: Vec<usize> = (0..points.len()).collect();
+    order.sort_by(|&a, &b| points[a].0.x.total_cmp(&points[b].0.x));
+    let mut rejected = vec![false; points.len()];
+    for _ in 0..OUTLIER_ITERATIONS {
+        let kept: Vec<data_absolute::Point> = order
+            .iter()
+            .filter(|&&i| !rejected[i])
+            .map(|&i| points[i])
+            .collect();
+        if kept.len() < 4 {
+            break;
+        }
+        let Some(Shape::Doppler { a, b, t0, tau }) = Shape::fit_doppler(&kept) else {
+            break;
+        };
+        let residual =
+            |p: &data_absolute::Point| (a + b * ((p.0.x - t0) / tau).tanh() - p.0.y).abs();
+        let sigma = percentile_of(kept.iter().map(residual).collect(), 50.0) * MAD_TO_SIGMA;
+        let limit = (threshold * sigma).max(tolerance);
+        let next: Vec<bool> = points.iter().map(|p| residual(p) > limit).collect();
+        if next == rejected {
+            break;
+        }
+        rejected = next;
+    }
+    rejected
+}
+
+/// Second derivatives of the natural cubic spline through `points`, from its tridiagonal system.
+fn spline_derivatives(points: &[data_absolute::Point]) -> Vec<f32> {
+    let n = points.len();
+    let x = |i: usize| points[i].0.x as f64;
+    let y = |i: usize| points[i].0.y as f64;
+    let h = |i: usize| (x(i + 1) - x(i)).max(f64::EPSILON);
+    // Thomas algorithm for the interior points, the ends have zero curvature
+    let mut diag = vec![1.0; n];
+    let mut rhs = vec![0.0; n];
+    for i in 1..n - 1 {
+        let lower = h(i - 1);
+        diag[i] = 2.0 * (h(i - 1) + h(i));
+        rhs[i] = 6.0 * ((y(i + 1) - y(i)) / h(i) - (y(i) - y(i - 1)) / h(i - 1));
+        if i > 1 {
+            let factor = lower / diag[i - 1];
+            diag[i] -= factor * h(i - 1);
+            rhs[i] -= factor * rhs[i - 1];
+        }
+    }
+    let mut m = vec![0.0; n];
+    for i in (1..n - 1).rev() {
+        let upper = if i + 1 < n - 1 { h(i) * m[i + 1] } else { 0.0 };
+        m[i] = (rhs[i] - upper) / diag[i];
+    }
+    m.into_iter().map(|m| m as f32).collect()
+}
+
+impl Shape {
+    /// Fits `a + b * tanh((t - t0) / tau)` to the points by least squares. `a` and `b` are solved
+    /// for directly, and `t0` and `tau` are searched on a grid that is refined around the best
+    /// fit. `None` if there are fewer than three points.
+    fn fit_doppler(points: &[data_absolute::Point]) -> Option<Shape> {
+        if points.len() < 3 {
+            return None;
+        }
+        let (start, end) = (points[0].0.x, points[points.len() - 1].0.x);
+        let span = end - start;
+        if span <= 0.0 {
+            return None;
+        }
+        // Sum of squared residuals and the linear parameters for given t0 and tau
+        let fit = |t0: f32, tau: f32| -> Option<(f32, f32, f32)> {
+            let xs: Vec<f32> = points.iter().map(|p| ((p.0.x - t0) / tau).tanh()).collect();
+            let n = points.len() as f32;
+            let mean_x = xs.iter().sum::<f32>() / n;
+            let mean_y = points.iter().map(|p| p.0.y).sum::<f32>() / n;
+            let (mut cov, mut var) = (0.0, 0.0);
+            for (x, p) in xs.iter().zip(points) {
+                cov += (x - mean_x) * (p.0.y - mean_y);
+                var += (x - mean_x) * (x - mean_x);
+            }
+            if var < 1e-6 {
+                return None;
+            }
+            let b = cov / var;
+            let a = mean_y - b * mean_x;
+            let residual = xs
+                .iter()
+                .zip(points)
+                .map(|(x, p)| (a + b * x - p.0.y).powi(2))
+                .sum();
+            Some((residual, a, b))
+        };
+
+        let mut t0_range = (start, end);
+        let mut ln_tau_range = ((span / 100.0).ln(), (span * 2.0).ln());
+        let mut best: Option<(f32, Shape)> = None;
+        let step =
+            |(lo, hi): (f32, f32), i: usize| lo + (hi - lo) * i as f32 / (DOPPLER_GRID - 1) as f32;
+        for _ in 0..DOPPLER_REFINEMENTS {
+            let mut best_grid = None;
+            for i in 0..DOPPLER_GRID {
+                for j in 0..DOPPLER_GRID {
+                    let (t0, ln_tau) = (step(t0_range, i), step(ln_tau_range, j));
+                    let tau = ln_tau.exp();
+                    let Some((residual, a, b)) = fit(t0, tau) else {
+                        continue;
+                    };
+                    if best.as_ref().is_none_or(|(best, _)| residual < *best) {
+                        best = Some((residual, Shape::Doppler { a, b, t0, tau }));
+                        best_grid = Some((t0, ln_tau));
+                    }
+                }
+            }
+            // Zoom in on the best fit so far, to a few steps of the current grid around it
+            let Some((t0, ln_tau)) = best_grid else {
+                break;
+            };
+            let t0_step = 2.0 * (t0_range.1 - t0_range.0) / (DOPPLER_GRID - 1) as f32;
+            let tau_step = 2.0 * (ln_tau_range.1 - ln_tau_range.0) / (DOPPLER_GRID - 1) as f32;
+            t0_range = (t0 - t0_step, t0 + t0_step);
+            ln_tau_range = (ln_tau - tau_step, ln_tau + tau_step);
+        }
+        best.map(|(_, shape)| shape)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn points(points: &[(f32, f32)]) -> Vec<data_absolute::Point> {
+        points
+            .iter()
+            .map(|&(t, f)| data_absolute::Point::new(t, f))
+            .collect()
+    }
+
+    #[test]
+    fn spline_passes_through_trackpoints() {
+        let points = points(&[(0.0, 0.0), (10.0, 5.0), (20.0, 20.0), (40.0, 10.0)]);
+        let linear = Track::new(&points, TrackInterpolation::Linear).unwrap();
+        let spline = Track::new(&points, TrackInterpolation::Spline).unwrap();
+        for p in &points {
+            assert!((spline.freq_at(p.0.x) - p.0.y).abs() < 1e-3);
+        }
+        assert_eq!(linear.freq_at(15.0), 12.5);
+        // The spline bends through the peak instead of cutting the corner
+        assert!(spline.freq_at(25.0) > linear.freq_at(25.0));
+        // It is clamped outside of the trackpoints
+        assert_eq!(spline.freq_at(-5.0), 0.0);
+        assert!(Track::new(&points[..1], TrackInterpolation::Spline).is_none());
+    }
+
+    #[test]
+    fn rejects_spikes_off_the_doppler_curve() {
+        let truth = |t: f32| 2e3 - 15e3 * ((t - 300.0) / 80.0).tanh();
+        let mut points = points(
+            &(0..600)
+                .step_by(3)
+                .map(|t| {
+                    let t = t as f32;
+                    // Some scatter of up to 300 Hz
+                    (t, truth(t) + 300.0 * (t * 0.37).sin())
+                })
+                .collect::<Vec<_>>(),
+        );
+        // Spikes that got into the track window, also at the start where they pull the fit most
+        let spikes = [0, 1, 50, 51, 120, 199];
+        for &i in &spikes {
+            points[i].0.y += 5e3;
+        }
+
+        let rejected = reject_outliers(&points, 3.0, 500.0);
+        let flagged: Vec<usize> = (0..points.len()).filter(|&i| rejected[i]).collect();
+        assert_eq!(flagged, spikes);
+        // Too few points for a fit
+        assert_eq!(reject_outliers(&points[..3], 3.0, 500.0), vec![false; 3]);
+    }
+
+    #[test]
+    fn doppler_fits_s_curve_from_few_points() {
+        let truth = |t: f32| 1e3 - 8e3 * ((t - 250.0) / 60.0).tanh();
+        let points = points(&[0.0, 200.0, 300.0, 600.0].map(|t| (t, truth(t))));
+        let doppler = Track::new(&points, TrackInterpolation::Doppler).unwrap();
+        let linear = Track::new(&points, TrackInterpolation::Linear).unwrap();
+        for t in [100.0, 230.0, 250.0, 270.0, 450.0] {
+            let error = (doppler.freq_at(t) - truth(t)).abs();
+            assert!(error < 100.0, "{t} s: {error} Hz");
+        }
+        assert!((linear.freq_at(100.0) - truth(100.0)).abs() > 500.0);
+    }
+}
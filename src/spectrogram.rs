@@ -2,14 +2,16 @@
 
 use std::{
     io::SeekFrom,
+    ops::Range,
     path::{Path, PathBuf},
-    sync::LazyLock,
+    sync::{Arc, LazyLock},
 };
 
 use anyhow::{Context, Result, anyhow, bail, ensure};
 use chrono::{DateTime, Duration, Utc};
 use futures_util::{StreamExt, TryStreamExt};
-use ndarray::{ArcArray2, ArrayView2};
+use itertools::{Itertools, izip};
+use ndarray::{ArcArray2, Array2, ArrayView1, ArrayView2, Axis, CowArray, Ix2, s};
 use rayon::prelude::*;
 use regex::Regex;
 use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt, BufReader};
@@ -17,11 +19,37 @@ use uuid::Uuid;
 
 use crate::coord::data_absolute;
 
+mod error;
+mod export;
+mod follow;
+mod mapped;
+mod noise;
+mod pipeline;
+mod pyramid;
+mod rebin;
+mod rfi;
+mod stitch;
+mod summary;
+
+pub use error::StrfError;
+use error::tolerate;
+pub use export::{ExportFormat, export, save_fits, save_npy, save_npz};
+pub use follow::Follower;
+use mapped::MappedStrf;
+pub use noise::{FloorEstimator, FloorUnits, NoiseFloor};
+pub(crate) use pipeline::{MAD_TO_SIGMA, percentile_of};
+pub use pipeline::{Pipeline, Stage};
+pub use pyramid::{Decimation, Level, Pyramid};
+pub use rfi::{Mask, RfiOptions};
+pub use stitch::Overlap;
+pub use summary::{FileOverlap, FileSummary, SetSummary};
+
 static HEADER_RE: LazyLock<Regex> = LazyLock::new(|| {
     Regex::new(r"(?s)HEADER\s+UTC_START\s+(\S+)\s+FREQ\s+([0-9.]+)\s+Hz\s+BW\s+([0-9.]+)\s+Hz\s+LENGTH\s+([0-9.]+)\s+s\s+NCHAN\s+(\d+)\s+(?:NSUB\s+\d+\s+)?END").unwrap()
 });
 
 /// Raw spectrum read from a strf `.bin` file, including its per-spectrum timestamp.
+#[derive(Clone)]
 pub struct RawStrfSpectrum {
     pub time: DateTime<Utc>,
     pub length_s: f32,
@@ -30,86 +58,242 @@ pub struct RawStrfSpectrum {
 }
 
 /// Parameters shared by all spectra in a strf `.bin` file.
-#[derive(PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug)]
 pub struct SpectrogramParams {
     pub freq: f32,
     pub bw: f32,
     pub nchan: usize,
 }
 
+impl SpectrogramParams {
+    /// Returns the parameters describing only the given subset of channels.
+    pub fn select_channels(&self, channels: std::ops::Range<usize>) -> SpectrogramParams {
+        let chan_width = self.bw / self.nchan as f32;
+        let start_freq = self.freq - self.bw / 2.0;
+        let nchan = channels.len();
+        SpectrogramParams {
+            freq: start_freq + (channels.start as f32 + nchan as f32 / 2.0) * chan_width,
+            bw: nchan as f32 * chan_width,
+            nchan,
+        }
+    }
+}
+
+/// Options that control how spectrogram files are loaded.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct LoadOptions {
+    /// Frequency range to load in Hz (channels outside this range are skipped)
+    pub freq_range: Option<(u64, u64)>,
+    /// How to turn raw IQ recordings (e.g. SigMF) into spectra
+    pub iq: IqOptions,
+    /// Memory-map strf `.bin` files instead of reading them into RAM.
+    ///
+    /// Power values are then converted to dB on access, so only the tiles that are actually used
+    /// need to fit into memory.
+    pub mmap: bool,
+    /// Stitch files that cover different frequency bands side by side, combining overlapping
+    /// channels as given. If `None`, such files can't be loaded together.
+    pub stitch: Option<Overlap>,
+    /// Time range to load (spectra that don't overlap it are skipped)
+    pub time_range: Option<TimeRange>,
+    /// Skip truncated and unparseable spectra in strf `.bin` files with a warning instead of
+    /// failing, see [`Spectrogram::load_warnings`]
+    pub lenient: bool,
+}
+
+/// A UTC time range `(start, end)`, with the end excluded.
+pub type TimeRange = (DateTime<Utc>, DateTime<Utc>);
+
+/// Whether a spectrum starting at `start` overlaps `time_range` (always true if there is none).
+pub fn overlaps_time_range(
+    time_range: Option<TimeRange>,
+    start: DateTime<Utc>,
+    length_s: f32,
+) -> bool {
+    time_range.is_none_or(|(from, to)| start < to && spectrum_end(start, length_s) > from)
+}
+
+fn spectrum_end(start: DateTime<Utc>, length_s: f32) -> DateTime<Utc> {
+    start + Duration::microseconds((length_s * 1e6) as i64)
+}
+
+/// Channelization settings for recordings that store raw IQ samples instead of spectra.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct IqOptions {
+    /// Number of frequency channels
+    pub nchan: usize,
+    /// Integration time per spectrum in seconds
+    pub tint: f32,
+}
+
+impl Default for IqOptions {
+    fn default() -> Self {
+        Self {
+            nchan: 4096,
+            tint: 1.0,
+        }
+    }
+}
+
+// Required for use as `Subscription::run_with` data
+impl std::hash::Hash for LoadOptions {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.freq_range.hash(state);
+        self.iq.nchan.hash(state);
+        self.iq.tint.to_bits().hash(state);
+        self.mmap.hash(state);
+        self.stitch.hash(state);
+        self.time_range.hash(state);
+        self.lenient.hash(state);
+    }
+}
+
 /// Loads a single spectrogram file, dispatching on extension.
 ///
-/// This is simply a wrapper around `load_strf_file`, but we might add a different format in the
-/// future.
-pub async fn load_single(path: PathBuf, freq_range: Option<(u64, u64)>) -> Result<Spectrogram> {
-    let spec = load_strf_file(&path, freq_range).await;
-    log::debug!("Loaded {}", path.display());
+/// SigMF recordings (`.sigmf-meta`/`.sigmf-data`) are channelized according to `options.iq`;
+/// everything else is read as a strf `.bin` file (memory-mapped if `options.mmap` is set).
+///
+/// Returns `None` if the file has no spectra in `options.time_range`.
+pub async fn load_single(path: PathBuf, options: LoadOptions) -> Result<Option<Spectrogram>> {
+    if let Some((from, to)) = options.time_range {
+        ensure!(from < to, "Time range ends before it starts: {from} - {to}");
+    }
+    let spec = if crate::sigmf::is_sigmf(&path) {
+        crate::sigmf::load_sigmf(&path, options).await
+    } else if options.mmap {
+        load_strf_mapped(&path, options).await
+    } else {
+        load_strf_file(&path, options).await
+    };
+    match &spec {
+        Ok(Some(_)) => log::debug!("Loaded {}", path.display()),
+        Ok(None) => log::debug!("Skipped {} (outside of the time range)", path.display()),
+        Err(_) => {}
+    }
     spec.context(format!("Failed to load file {:?}", path))
 }
 
 /// Loads a spectrogram from the given file paths.
-pub async fn load(paths: &[PathBuf], freq_range: Option<(u64, u64)>) -> Result<Spectrogram> {
+pub async fn load(paths: &[PathBuf], options: LoadOptions) -> Result<Spectrogram> {
     if paths.is_empty() {
         bail!("No files provided");
     }
 
+    let paths = crate::sigmf::dedup_recordings(paths);
     log::debug!("Loading {} spectrogram files", paths.len());
 
-    let mut spectrograms: Vec<_> = futures_util::stream::iter(paths.iter().cloned())
-        .map(|path| load_single(path, freq_range))
+    let spectrograms: Vec<_> = futures_util::stream::iter(paths)
+        .map(|path| load_single(path, options))
         .buffer_unordered(8)
         .try_collect()
         .await?;
+    let mut spectrograms: Vec<_> = spectrograms.into_iter().flatten().collect();
+    ensure!(
+        !spectrograms.is_empty(),
+        "None of the files have spectra in the time range"
+    );
 
     log::debug!("Joining {} spectrograms", spectrograms.len());
     spectrograms.sort_by_key(|s| s.start_time());
-    Spectrogram::concatenate(spectrograms)
+    Spectrogram::combine(spectrograms, options.stitch)
 }
 
-async fn load_strf_file(path: &Path, freq_range: Option<(u64, u64)>) -> Result<Spectrogram> {
-    let (mut spectra, params) = load_strf_raw(path, freq_range).await?;
-    spectra.sort_unstable_by_key(|spec| spec.time);
+async fn load_strf_file(path: &Path, options: LoadOptions) -> Result<Option<Spectrogram>> {
+    let read = load_strf_raw(path, options).await?;
+    if read.spectra.is_empty() {
+        return Ok(None);
+    }
+    tokio::task::spawn_blocking(move || {
+        let mut spec = Spectrogram::from_raw(read.spectra, read.params)?;
+        spec.load_warnings = read.warnings;
+        Ok(Some(spec))
+    })
+    .await?
+}
 
-    let nchan = params.nchan;
-    let (data, timestamps, lengths) = tokio::task::spawn_blocking(move || {
-        let nslices = spectra.len();
-        let timestamps: Vec<_> = spectra.iter().map(|s| s.time).collect();
-        let lengths: Vec<_> = spectra.iter().map(|s| s.length_s).collect();
+async fn load_strf_mapped(path: &Path, options: LoadOptions) -> Result<Option<Spectrogram>> {
+    let path = path.to_path_buf();
+    tokio::task::spawn_blocking(move || MappedStrf::open(&path, options)).await?
+}
 
-        let mut data = Vec::with_capacity(nslices * nchan);
-        for spec in spectra {
-            data.extend(spec.power_linear);
+/// Number of spectra that are converted at once when streaming through a spectrogram.
+pub const STREAM_CHUNK_SLICES: usize = 1024;
+
+/// Writes a spectrogram to the given file path in the strf `.bin` format.
+pub async fn save_strf(spectrogram: &Spectrogram, path: &Path) -> Result<()> {
+    write_strf(spectrogram, path, false).await
+}
+
+/// Like [`save_strf`], but writes cells that are flagged as RFI (see [`Spectrogram::mask`]) with
+/// the lowest power of the spectrogram, the same as channels that [`Overlap::Blank`] blanks.
+pub async fn save_strf_blanked(spectrogram: &Spectrogram, path: &Path) -> Result<()> {
+    write_strf(spectrogram, path, true).await
+}
+
+async fn write_strf(spectrogram: &Spectrogram, path: &Path, blank: bool) -> Result<()> {
+    let mask = spectrogram.mask().filter(|_| blank);
+    let mut writer = StrfWriter::create(path, &spectrogram.params(), spectrogram.nslices).await?;
+    for start in (0..spectrogram.nslices).step_by(STREAM_CHUNK_SLICES) {
+        let rows = start..(start + STREAM_CHUNK_SLICES).min(spectrogram.nslices);
+        let mut tile = spectrogram.tile(rows.clone(), 0..spectrogram.nchan);
+        if let Some(mask) = mask {
+            let blank = spectrogram.power_bounds.0;
+            let mut owned = tile.into_owned();
+            for (i, mut slice) in rows.clone().zip(owned.outer_iter_mut()) {
+                for (chan, value) in slice.iter_mut().enumerate() {
+                    if mask.is_masked(i, chan) {
+                        *value = blank;
+                    }
+                }
+            }
+            tile = owned.into();
         }
-        data.par_iter_mut()
-            .for_each(|v| *v = 10.0 * (*v + 1e-12f32).log10());
+        for (i, slice) in rows.zip(tile.outer_iter()) {
+            writer
+                .write_spectrum(spectrogram.timestamps[i], spectrogram.lengths[i], slice)
+                .await?;
+        }
+    }
+    writer.finish().await
+}
 
-        (data, timestamps, lengths)
-    })
-    .await?;
-    let data = ArcArray2::from_shape_vec((timestamps.len(), params.nchan), data)
-        .context("Failed to shape data array")?;
-    Ok(Spectrogram {
-        id: Uuid::new_v4(),
-        nchan: params.nchan,
-        nslices: timestamps.len(),
-        freq: params.freq,
-        bw: params.bw,
-        power_bounds: (
-            data.iter().cloned().fold(f32::INFINITY, f32::min),
-            data.iter().cloned().fold(f32::NEG_INFINITY, f32::max),
-        ),
-        data,
-        timestamps,
-        lengths,
-    })
+/// Writes spectra to a strf `.bin` file one at a time, so the whole spectrogram never has to be in
+/// memory.
+pub struct StrfWriter {
+    writer: tokio::io::BufWriter<tokio::fs::File>,
+    params: SpectrogramParams,
+    nsub: usize,
 }
 
-/// Writes a spectrogram to the given file path in the strf `.bin` format.
-pub async fn save_strf(spectrogram: &Spectrogram, path: &Path) -> Result<()> {
-    let mut file = tokio::fs::File::create(path).await?;
-    let mut writer = tokio::io::BufWriter::new(&mut file);
+impl StrfWriter {
+    /// Creates the output file. `nsub` is only written to the headers (STRF uses it as the number
+    /// of spectra per file).
+    pub async fn create(path: &Path, params: &SpectrogramParams, nsub: usize) -> Result<Self> {
+        let file = tokio::fs::File::create(path).await?;
+        Ok(Self {
+            writer: tokio::io::BufWriter::new(file),
+            params: SpectrogramParams {
+                freq: params.freq,
+                bw: params.bw,
+                nchan: params.nchan,
+            },
+            nsub,
+        })
+    }
 
-    let header = |start: DateTime<Utc>, length_s: f32| {
+    /// Appends one spectrum of dB values.
+    pub async fn write_spectrum(
+        &mut self,
+        start: DateTime<Utc>,
+        length_s: f32,
+        power_db: ArrayView1<'_, f32>,
+    ) -> Result<()> {
+        ensure!(
+            power_db.len() == self.params.nchan,
+            "Spectrum has {} channels, expected {}",
+            power_db.len(),
+            self.params.nchan
+        );
         let mut start = start.to_rfc3339_opts(chrono::SecondsFormat::Millis, true);
         // Remove trailing Z for compatibility with STRF
         start.pop();
@@ -124,28 +308,22 @@ NCHAN        {}
 NSUB         {}
 END
 "#,
-            start,
-            spectrogram.freq,
-            spectrogram.bw,
-            length_s,
-            spectrogram.nchan,
-            spectrogram.nslices
+            start, self.params.freq, self.params.bw, length_s, self.params.nchan, self.nsub
         );
-        format!("{:256}", header)
-    };
-
-    for (i, slice) in spectrogram.data().outer_iter().enumerate() {
-        writer
-            .write_all(header(spectrogram.timestamps[i], spectrogram.lengths[i]).as_bytes())
+        self.writer
+            .write_all(format!("{:256}", header).as_bytes())
             .await?;
-        for &value in slice.iter() {
+        for &value in power_db.iter() {
             let linear_value = 10f32.powf(value / 10.0);
-            writer.write_f32_le(linear_value).await?;
+            self.writer.write_f32_le(linear_value).await?;
         }
+        Ok(())
     }
 
-    writer.flush().await?;
-    Ok(())
+    pub async fn finish(mut self) -> Result<()> {
+        self.writer.flush().await?;
+        Ok(())
+    }
 }
 
 /// Applies a frequency range filter to the spectrogram parameters.
@@ -169,26 +347,33 @@ fn apply_freq_range(
             0,
         );
     };
-    let chan_width = header.bw / header.nchan as f32;
-    let start_freq = header.freq - header.bw / 2.0;
-    let range_start = (((min_freq as f32 - start_freq).clamp(0.0, header.bw) / chan_width).floor()
+    let full = SpectrogramParams {
+        freq: header.freq,
+        bw: header.bw,
+        nchan: header.nchan,
+    };
+    let channels = channel_range(&full, (min_freq, max_freq));
+    let skip_before = channels.start * 4;
+    let skip_after = (header.nchan - channels.end) * 4;
+    (full.select_channels(channels), skip_before, skip_after)
+}
+
+/// Returns the channels of a spectrum with `params` that overlap `[min_freq, max_freq)` (in Hz).
+///
+/// The range is empty if the frequency range doesn't overlap the spectrum at all.
+pub fn channel_range(
+    params: &SpectrogramParams,
+    (min_freq, max_freq): (u64, u64),
+) -> std::ops::Range<usize> {
+    let chan_width = params.bw / params.nchan as f32;
+    let start_freq = params.freq - params.bw / 2.0;
+    let range_start = (((min_freq as f32 - start_freq).clamp(0.0, params.bw) / chan_width).floor()
         as usize)
-        .min(header.nchan);
-    let range_end = (((max_freq as f32 - start_freq).clamp(0.0, header.bw) / chan_width).ceil()
+        .min(params.nchan);
+    let range_end = (((max_freq as f32 - start_freq).clamp(0.0, params.bw) / chan_width).ceil()
         as usize)
-        .min(header.nchan);
-    let nchan = range_end.saturating_sub(range_start);
-    let skip_before = range_start * 4;
-    let skip_after = (header.nchan - range_end) * 4;
-    (
-        SpectrogramParams {
-            freq: start_freq + (range_start as f32 + nchan as f32 / 2.0) * chan_width,
-            bw: nchan as f32 * chan_width,
-            nchan,
-        },
-        skip_before,
-        skip_after,
-    )
+        .min(params.nchan);
+    range_start..range_end.max(range_start)
 }
 
 async fn read_spectrum<F>(
@@ -196,7 +381,7 @@ async fn read_spectrum<F>(
     byte_len: usize,
     skip_before: usize,
     skip_after: usize,
-) -> Result<Vec<f32>>
+) -> std::io::Result<Vec<f32>>
 where
     F: tokio::io::AsyncRead + tokio::io::AsyncSeek + Unpin,
 {
@@ -208,19 +393,157 @@ where
     Ok(power)
 }
 
+/// Spectra read from a strf `.bin` file, see [`load_strf_raw`].
+pub struct StrfRead {
+    pub spectra: Vec<RawStrfSpectrum>,
+    pub params: SpectrogramParams,
+    /// Byte offset just after the last complete spectrum
+    pub end: u64,
+    /// Problems that were skipped in lenient mode
+    pub warnings: Vec<StrfError>,
+}
+
 /// Reads all spectra from a strf `.bin` file with their per-spectrum timestamps.
-pub async fn load_strf_raw(
+///
+/// With a `time_range`, only the spectra overlapping it are read, which may be none at all.
+/// Problems with the file are returned as [`StrfError`]s, unless `options.lenient` is set.
+pub async fn load_strf_raw(path: &Path, options: LoadOptions) -> Result<StrfRead> {
+    let mut read = load_strf_raw_from(path, 0, options).await?;
+    let file_size = tokio::fs::metadata(path)
+        .await
+        .map_err(|e| StrfError::io(path, 0, e))?
+        .len();
+    if read.end < file_size {
+        let error = StrfError::Truncated {
+            path: path.to_path_buf(),
+            offset: read.end,
+            len: file_size - read.end,
+        };
+        tolerate(error, options.lenient, &mut read.warnings)?;
+    }
+    if read.spectra.is_empty() && options.time_range.is_none() {
+        Err(StrfError::Empty {
+            path: path.to_path_buf(),
+        })?;
+    }
+    Ok(read)
+}
+
+/// Size of a header + spectrum block with `nchan` channels in bytes.
+fn block_len(nchan: usize) -> u64 {
+    (HEADER_SIZE + nchan * 4) as u64
+}
+
+/// How to continue after reading the header of a block.
+enum Block {
+    Spectrum(Header),
+    /// Skip this many bytes to the next block (lenient mode only)
+    Skip(u64),
+}
+
+/// Checks the header of the block at `offset` against the first header of the file.
+///
+/// In lenient mode, blocks with an unparseable header are skipped assuming they have the same size
+/// as the others, and blocks with different parameters are skipped according to their header.
+fn check_block(
     path: &Path,
-    freq_range: Option<(u64, u64)>,
-) -> Result<(Vec<RawStrfSpectrum>, SpectrogramParams)> {
-    let file = tokio::fs::File::open(path).await?;
-    let file_size = file.metadata().await?.len() as usize;
-    let mut reader = tokio::io::BufReader::new(file);
+    offset: u64,
+    first: &Header,
+    header: Result<Header, StrfError>,
+    lenient: bool,
+    warnings: &mut Vec<StrfError>,
+) -> Result<Block, StrfError> {
+    let (error, len) = match header {
+        Ok(header) if header.same_params(first) => return Ok(Block::Spectrum(header)),
+        Ok(header) => (
+            inconsistent_params(path, offset, first, &header),
+            block_len(header.nchan),
+        ),
+        Err(error @ StrfError::InvalidHeader { .. }) => (error, block_len(first.nchan)),
+        Err(error) => return Err(error),
+    };
+    tolerate(error, lenient, warnings)?;
+    Ok(Block::Skip(len))
+}
 
-    let first_header = parse_header(&mut reader)
+fn inconsistent_params(path: &Path, offset: u64, first: &Header, header: &Header) -> StrfError {
+    StrfError::InconsistentParams {
+        path: path.to_path_buf(),
+        offset,
+        expected: first.params(),
+        found: header.params(),
+    }
+}
+
+/// Reads and parses the header at the current position of `reader`, which is at `offset`.
+async fn parse_header<R: tokio::io::AsyncRead + Unpin>(
+    reader: &mut R,
+    path: &Path,
+    offset: u64,
+) -> Result<Header, StrfError> {
+    let mut buf = [0u8; HEADER_SIZE];
+    reader
+        .read_exact(&mut buf)
         .await
-        .context("Failed to parse header")?;
-    let (params, skip_before, skip_after) = apply_freq_range(&first_header, freq_range);
+        .map_err(|e| StrfError::io(path, offset, e))?;
+    parse_header_bytes(&buf).map_err(|e| StrfError::InvalidHeader {
+        path: path.to_path_buf(),
+        offset,
+        reason: format!("{e:#}"),
+    })
+}
+
+/// Finds the first of `n_blocks` evenly spaced blocks from `offset` that ends after `from`.
+async fn find_block<R: tokio::io::AsyncRead + tokio::io::AsyncSeek + Unpin>(
+    reader: &mut R,
+    path: &Path,
+    first: &Header,
+    offset: u64,
+    n_blocks: u64,
+    from: DateTime<Utc>,
+) -> Result<u64, StrfError> {
+    let block_offset = |block: u64| offset + block * block_len(first.nchan);
+    let (mut lo, mut hi) = (0, n_blocks);
+    while lo < hi {
+        let mid = (lo + hi) / 2;
+        reader
+            .seek(SeekFrom::Start(block_offset(mid)))
+            .await
+            .map_err(|e| StrfError::io(path, block_offset(mid), e))?;
+        let header = parse_header(reader, path, block_offset(mid)).await?;
+        if !header.same_params(first) {
+            return Err(inconsistent_params(path, block_offset(mid), first, &header));
+        }
+        if spectrum_end(header.start_time, header.length) <= from {
+            lo = mid + 1;
+        } else {
+            hi = mid;
+        }
+    }
+    Ok(block_offset(lo))
+}
+
+/// Reads the complete spectra starting at byte `offset` of a strf `.bin` file, which may still be
+/// growing. The returned [`StrfRead::end`] is the offset just after the last complete spectrum, so
+/// that the next call can continue from there. A partially written spectrum at the end of the file
+/// is not considered a problem here.
+///
+/// With a time range, blocks before it are skipped by a binary search over the headers (rffft
+/// writes spectra in order), and reading stops at the first spectrum after it.
+pub async fn load_strf_raw_from(
+    path: &Path,
+    offset: u64,
+    options: LoadOptions,
+) -> Result<StrfRead> {
+    let io_error = |offset| move |e| StrfError::io(path, offset, e);
+    let file = tokio::fs::File::open(path)
+        .await
+        .with_context(|| format!("Failed to open {}", path.display()))?;
+    let file_size = file.metadata().await.map_err(io_error(0))?.len();
+    let mut reader = tokio::io::BufReader::new(file);
+
+    let first_header = parse_header(&mut reader, path, 0).await?;
+    let (params, skip_before, skip_after) = apply_freq_range(&first_header, options.freq_range);
     ensure!(
         params.nchan > 0,
         "Frequency range filter excludes all channels: {:?}",
@@ -232,39 +555,82 @@ pub async fn load_strf_raw(
         first_header.nchan
     );
 
-    let data_block_size = first_header.nchan * 4;
-    let n_blocks = file_size / (data_block_size + HEADER_SIZE);
-    let mut spectra = Vec::with_capacity(n_blocks);
+    let block_size = block_len(first_header.nchan);
+    let n_blocks = file_size.saturating_sub(offset) / block_size;
+    let mut pos = offset;
+    if let Some((from, _)) = options.time_range {
+        match find_block(&mut reader, path, &first_header, offset, n_blocks, from).await {
+            Ok(found) => pos = found,
+            Err(error) if options.lenient => {
+                log::warn!("Can't seek to the time range ({error}), reading the whole file");
+            }
+            Err(error) => Err(error)?,
+        }
+    }
 
+    let mut spectra =
+        Vec::with_capacity(((offset + n_blocks * block_size - pos) / block_size) as usize);
+    let mut warnings = Vec::new();
     let byte_len = params.nchan * 4;
-    let power = read_spectrum(&mut reader, byte_len, skip_before, skip_after)
+    reader
+        .seek(SeekFrom::Start(pos))
         .await
-        .context("Failed to read first spectrum")?;
-    spectra.push(RawStrfSpectrum {
-        time: first_header.start_time,
-        length_s: first_header.length,
-        power_linear: power,
-    });
-
-    while spectra.len() < n_blocks {
-        let header = parse_header(&mut reader).await?;
-        ensure!(
-            first_header.freq == header.freq
-                && first_header.bw == header.bw
-                && first_header.nchan == header.nchan,
-            "Inconsistent spectrogram parameters detected"
-        );
-        let power = read_spectrum(&mut reader, byte_len, skip_before, skip_after)
-            .await
-            .context("Failed to read spectrum")?;
-        spectra.push(RawStrfSpectrum {
-            time: header.start_time,
-            length_s: header.length,
-            power_linear: power,
-        });
+        .map_err(io_error(pos))?;
+    while pos + block_size <= file_size {
+        let header = parse_header(&mut reader, path, pos).await;
+        let header = match check_block(
+            path,
+            pos,
+            &first_header,
+            header,
+            options.lenient,
+            &mut warnings,
+        )? {
+            Block::Spectrum(header) => header,
+            Block::Skip(len) => {
+                if pos + len > file_size {
+                    break;
+                }
+                pos += len;
+                reader
+                    .seek(SeekFrom::Start(pos))
+                    .await
+                    .map_err(io_error(pos))?;
+                continue;
+            }
+        };
+        if options
+            .time_range
+            .is_some_and(|(_, to)| header.start_time >= to)
+        {
+            // Pretend to have read the remaining complete blocks
+            pos += (file_size - pos) / block_size * block_size;
+            break;
+        }
+        if overlaps_time_range(options.time_range, header.start_time, header.length) {
+            let power = read_spectrum(&mut reader, byte_len, skip_before, skip_after)
+                .await
+                .map_err(io_error(pos))?;
+            spectra.push(RawStrfSpectrum {
+                time: header.start_time,
+                length_s: header.length,
+                power_linear: power,
+            });
+        } else {
+            reader
+                .seek(SeekFrom::Current((first_header.nchan * 4) as i64))
+                .await
+                .map_err(io_error(pos))?;
+        }
+        pos += block_size;
     }
 
-    Ok((spectra, params))
+    Ok(StrfRead {
+        spectra,
+        params,
+        end: pos,
+        warnings,
+    })
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -275,20 +641,70 @@ struct Header {
     length: f32, // s
     nchan: usize,
 }
+
+impl Header {
+    fn params(&self) -> SpectrogramParams {
+        SpectrogramParams {
+            freq: self.freq,
+            bw: self.bw,
+            nchan: self.nchan,
+        }
+    }
+
+    fn same_params(&self, other: &Header) -> bool {
+        self.freq == other.freq && self.bw == other.bw && self.nchan == other.nchan
+    }
+}
 const HEADER_SIZE: usize = 256;
 
+/// Spacing between spectra beyond this fraction of a spectrum's length counts as a gap.
+const GAP_TOLERANCE: f32 = 0.5;
+
+/// Where the power values of a spectrogram live.
+#[derive(Clone)]
+enum Storage {
+    /// dB values, fully loaded into memory
+    Memory(ArcArray2<f32>),
+    /// dB values in memory, in blocks of [`STREAM_CHUNK_SLICES`] slices (the last one can be
+    /// shorter). Spectrograms that grow are stored like this, so that appending only copies the
+    /// last block, even if the others are shared with clones.
+    Chunked(Vec<ArcArray2<f32>>),
+    /// Memory-mapped strf `.bin` files (linear power), converted to dB on access
+    Mapped(Arc<MappedStrf>),
+}
+
+impl PartialEq for Storage {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Storage::Memory(a), Storage::Memory(b)) => a == b,
+            (Storage::Chunked(a), Storage::Chunked(b)) => a == b,
+            (Storage::Mapped(a), Storage::Mapped(b)) => Arc::ptr_eq(a, b),
+            _ => false,
+        }
+    }
+}
+
 #[derive(Clone, PartialEq)]
 pub struct Spectrogram {
     pub id: Uuid,
     pub nchan: usize,
     pub nslices: usize,
-    pub freq: f32,                // Hz
-    pub bw: f32,                  // Hz
-    pub power_bounds: (f32, f32), // dB
-    pub data: ArcArray2<f32>,     // dB
+    pub freq: f32, // Hz
+    pub bw: f32,   // Hz
+    /// Minimum and maximum power of all cells in dB
+    pub power_bounds: (f32, f32),
+    storage: Storage,
     // TODO: Replace with ArcArray1?
     pub timestamps: Vec<DateTime<Utc>>,
     pub lengths: Vec<f32>,
+    /// Time ranges without any spectra
+    gaps: Vec<Range<DateTime<Utc>>>,
+    /// Decimated levels for rendering, see [`Spectrogram::build_pyramid`]
+    pyramid: Option<Arc<Pyramid>>,
+    /// Problems that were skipped while loading in lenient mode
+    load_warnings: Vec<StrfError>,
+    /// Cells that are flagged as RFI, see [`Spectrogram::set_mask`]
+    mask: Option<Arc<Mask>>,
 }
 
 impl std::fmt::Debug for Spectrogram {
@@ -306,6 +722,88 @@ impl std::fmt::Debug for Spectrogram {
 }
 
 impl Spectrogram {
+    /// Builds a spectrogram from raw (linear power) spectra, sorting them by time.
+    ///
+    /// This is CPU-heavy for large inputs, so async callers should run it in `spawn_blocking`.
+    pub fn from_raw(
+        mut spectra: Vec<RawStrfSpectrum>,
+        params: SpectrogramParams,
+    ) -> Result<Spectrogram> {
+        ensure!(
+            !spectra.is_empty(),
+            "No spectra to build a spectrogram from"
+        );
+        spectra.sort_unstable_by_key(|spec| spec.time);
+
+        let nslices = spectra.len();
+        let timestamps: Vec<_> = spectra.iter().map(|s| s.time).collect();
+        let lengths: Vec<_> = spectra.iter().map(|s| s.length_s).collect();
+
+        let mut data = Vec::with_capacity(nslices * params.nchan);
+        for spec in spectra {
+            ensure!(
+                spec.power_linear.len() == params.nchan,
+                "Spectrum at {} has {} channels, expected {}",
+                spec.time,
+                spec.power_linear.len(),
+                params.nchan
+            );
+            data.extend(spec.power_linear);
+        }
+        data.par_iter_mut()
+            .for_each(|v| *v = 10.0 * (*v + 1e-12f32).log10());
+
+        let data = ArcArray2::from_shape_vec((nslices, params.nchan), data)
+            .context("Failed to shape data array")?;
+        Ok(Spectrogram::new(
+            params,
+            Storage::Memory(data),
+            timestamps,
+            lengths,
+        ))
+    }
+
+    fn new(
+        params: SpectrogramParams,
+        storage: Storage,
+        timestamps: Vec<DateTime<Utc>>,
+        lengths: Vec<f32>,
+    ) -> Spectrogram {
+        let mut spectrogram = Spectrogram {
+            id: Uuid::new_v4(),
+            nchan: params.nchan,
+            nslices: timestamps.len(),
+            freq: params.freq,
+            bw: params.bw,
+            power_bounds: (0.0, 0.0),
+            storage,
+            gaps: find_gaps(&timestamps, &lengths),
+            timestamps,
+            lengths,
+            pyramid: None,
+            load_warnings: Vec::new(),
+            mask: None,
+        };
+        spectrogram.power_bounds = spectrogram.compute_power_bounds();
+        spectrogram
+    }
+
+    /// Computes the minimum and maximum power.
+    ///
+    /// Memory-mapped spectrograms track them while their files are mapped, see
+    /// [`MappedStrf::open`].
+    fn compute_power_bounds(&self) -> (f32, f32) {
+        let minmax = |(min, max): (f32, f32), &v: &f32| (min.min(v), max.max(v));
+        match &self.storage {
+            Storage::Memory(data) => data.iter().fold((f32::INFINITY, f32::NEG_INFINITY), minmax),
+            Storage::Chunked(chunks) => chunks
+                .iter()
+                .flatten()
+                .fold((f32::INFINITY, f32::NEG_INFINITY), minmax),
+            Storage::Mapped(mapped) => mapped.power_bounds(),
+        }
+    }
+
     pub fn concatenate(components: Vec<Spectrogram>) -> Result<Spectrogram> {
         if components.is_empty() {
             bail!("No spectrograms to concatenate");
@@ -326,21 +824,37 @@ impl Spectrogram {
         let freq = first.freq;
         let bw = first.bw;
 
-        let mut data_flat = Vec::with_capacity(nslices * nchan);
         let mut timestamps = Vec::with_capacity(nslices);
         let mut lengths = Vec::with_capacity(nslices);
         let mut power_bounds = (f32::INFINITY, f32::NEG_INFINITY);
-
-        for spec in components {
-            data_flat.extend_from_slice(spec.data.as_slice().unwrap());
-            timestamps.extend(spec.timestamps);
-            lengths.extend(spec.lengths);
+        for spec in &components {
+            timestamps.extend_from_slice(&spec.timestamps);
+            lengths.extend_from_slice(&spec.lengths);
             power_bounds.0 = power_bounds.0.min(spec.power_bounds.0);
             power_bounds.1 = power_bounds.1.max(spec.power_bounds.1);
         }
 
-        let data = ArcArray2::from_shape_vec((nslices, nchan), data_flat)
-            .context("Failed to concatenate spectrograms")?;
+        let all_mapped = components
+            .iter()
+            .all(|s| matches!(s.storage, Storage::Mapped(_)));
+        let storage = if all_mapped {
+            let parts = components
+                .iter()
+                .filter_map(|s| match &s.storage {
+                    Storage::Mapped(mapped) => Some(mapped.as_ref()),
+                    Storage::Memory(_) | Storage::Chunked(_) => None,
+                })
+                .collect::<Vec<_>>();
+            Storage::Mapped(Arc::new(MappedStrf::concatenate(&parts)))
+        } else {
+            let mut data_flat = Vec::with_capacity(nslices * nchan);
+            for spec in &components {
+                data_flat.extend(spec.data().iter());
+            }
+            let data = ArcArray2::from_shape_vec((nslices, nchan), data_flat)
+                .context("Failed to concatenate spectrograms")?;
+            Storage::Memory(data)
+        };
 
         Ok(Spectrogram {
             id: Uuid::new_v4(),
@@ -349,16 +863,73 @@ impl Spectrogram {
             nchan,
             nslices,
             power_bounds,
-            data,
+            storage,
+            gaps: find_gaps(&timestamps, &lengths),
             timestamps,
             lengths,
+            pyramid: None,
+            load_warnings: components
+                .iter()
+                .flat_map(|s| s.load_warnings.iter().cloned())
+                .collect(),
+            mask: None,
         })
     }
 
-    pub fn data(&self) -> ArrayView2<'_, f32> {
-        self.data.view()
+    /// Returns all power values (in dB).
+    ///
+    /// For memory-mapped spectrograms, this converts the entire spectrogram, so prefer
+    /// [`Spectrogram::tile`] if you don't need all of it.
+    pub fn data(&self) -> CowArray<'_, f32, Ix2> {
+        self.tile(0..self.nslices, 0..self.nchan)
+    }
+
+    /// Returns the power values (in dB) of the given slices and channels.
+    ///
+    /// This borrows for in-memory spectrograms (unless the tile spans several blocks of a grown
+    /// one) and only reads the requested region from disk for memory-mapped ones.
+    pub fn tile(&self, slices: Range<usize>, channels: Range<usize>) -> CowArray<'_, f32, Ix2> {
+        match &self.storage {
+            Storage::Memory(data) => data.slice(s![slices, channels]).into(),
+            Storage::Chunked(chunks) => {
+                let mut parts = (slices.start / STREAM_CHUNK_SLICES
+                    ..slices.end.div_ceil(STREAM_CHUNK_SLICES))
+                    .map(|i| {
+                        let offset = i * STREAM_CHUNK_SLICES;
+                        let rows = slices.start.max(offset) - offset
+                            ..slices.end.min(offset + STREAM_CHUNK_SLICES) - offset;
+                        chunks[i].slice(s![rows, channels.clone()])
+                    })
+                    .collect_vec();
+                match parts.len() {
+                    0 => Array2::zeros((0, channels.len())).into(),
+                    1 => parts.remove(0).into(),
+                    _ => ndarray::concatenate(Axis(0), &parts)
+                        .expect("blocks have the same number of channels")
+                        .into(),
+                }
+            }
+            Storage::Mapped(mapped) => mapped.tile(slices, channels).into(),
+        }
     }
 
+    /// Returns a single power value (in dB).
+    pub fn value(&self, slice: usize, channel: usize) -> f32 {
+        match &self.storage {
+            Storage::Memory(data) => data[(slice, channel)],
+            Storage::Chunked(chunks) => {
+                chunks[slice / STREAM_CHUNK_SLICES][(slice % STREAM_CHUNK_SLICES, channel)]
+            }
+            Storage::Mapped(mapped) => mapped.tile(slice..slice + 1, channel..channel + 1)[(0, 0)],
+        }
+    }
+
+    /// Whether the power values are memory-mapped from disk instead of held in memory.
+    pub fn is_mapped(&self) -> bool {
+        matches!(self.storage, Storage::Mapped(_))
+    }
+
+    /// Replaces the power values (in dB), loading the spectrogram into memory.
     pub fn set_data(&mut self, data: ArcArray2<f32>) -> anyhow::Result<()> {
         ensure!(
             data.dim() == (self.nslices, self.nchan),
@@ -369,10 +940,187 @@ impl Spectrogram {
             data.dim().1
         );
 
-        self.data = data;
+        self.storage = Storage::Memory(data);
+        self.pyramid = None;
+        Ok(())
+    }
+
+    /// Appends spectra (in linear power) that were recorded after the current end, e.g. while
+    /// following a directory that rffft is still writing to.
+    ///
+    /// Spectra that don't start after the current last one are skipped. See
+    /// [`Spectrogram::splice_from`] for how the spectrogram is extended.
+    pub fn append(&mut self, mut spectra: Vec<RawStrfSpectrum>) -> Result<()> {
+        spectra.sort_unstable_by_key(|spec| spec.time);
+        let end = self.timestamps[self.nslices - 1];
+        let total = spectra.len();
+        spectra.retain(|spec| spec.time > end);
+        if spectra.len() < total {
+            log::warn!(
+                "Skipping {} spectra that don't start after the end of the spectrogram",
+                total - spectra.len()
+            );
+        }
+        if spectra.is_empty() {
+            return Ok(());
+        }
+        let tail = Spectrogram::from_raw(spectra, self.params())?;
+        self.splice_from(self.nslices, &tail)
+    }
+
+    /// Replaces the slices from `first` onwards with those of `tail`, appending the ones that go
+    /// past the current end, e.g. to add the processed version of spectra that were appended.
+    ///
+    /// The id stays the same, and the pyramid, if it was built, is updated in place, so only the
+    /// new slices have to be uploaded for drawing. Memory-mapped spectrograms can't be changed.
+    pub fn splice_from(&mut self, first: usize, tail: &Spectrogram) -> Result<()> {
+        ensure!(
+            !self.is_mapped(),
+            "Cannot append to a memory-mapped spectrogram"
+        );
+        ensure!(
+            tail.params() == self.params(),
+            "Cannot append spectra with different parameters: {:?} vs {:?}",
+            tail.params(),
+            self.params()
+        );
+        ensure!(
+            first == 0 || tail.start_time() > self.timestamps[first - 1],
+            "The spectra to append start at {}, before slice {}",
+            tail.start_time(),
+            first
+        );
+        self.splice(first, tail.data().view(), &tail.timestamps, &tail.lengths)?;
+        if let Some(mut pyramid) = self.pyramid.take() {
+            Arc::make_mut(&mut pyramid).extend(self, first);
+            self.pyramid = Some(pyramid);
+        }
+        Ok(())
+    }
+
+    /// A copy of the slices from `first` onwards, which has to be before the end, e.g. to process
+    /// only the spectra that were appended.
+    pub fn slices_from(&self, first: usize) -> Spectrogram {
+        let data = self.tile(first..self.nslices, 0..self.nchan).into_owned();
+        let mut tail = Spectrogram::new(
+            self.params(),
+            Storage::Memory(data.into_shared()),
+            self.timestamps[first..].to_vec(),
+            self.lengths[first..].to_vec(),
+        );
+        tail.mask = self.mask.as_ref().map(|mask| {
+            Arc::new(Mask {
+                slices: mask.slices[first..].to_vec(),
+                channels: mask.channels.clone(),
+            })
+        });
+        tail
+    }
+
+    /// Replaces the slices from `first` onwards with the given dB values, appending the ones that
+    /// go past the current end.
+    fn splice(
+        &mut self,
+        first: usize,
+        data: ArrayView2<f32>,
+        timestamps: &[DateTime<Utc>],
+        lengths: &[f32],
+    ) -> Result<()> {
+        ensure!(
+            first + data.nrows() >= self.nslices && first <= self.nslices,
+            "Cannot shrink a spectrogram"
+        );
+        if let Storage::Memory(stored) = &self.storage {
+            // Splitting into blocks doesn't copy, the blocks share the data
+            let blocks = (0..self.nslices)
+                .step_by(STREAM_CHUNK_SLICES)
+                .map(|start| {
+                    let end = (start + STREAM_CHUNK_SLICES).min(self.nslices);
+                    stored.clone().slice_move(s![start..end, ..])
+                })
+                .collect();
+            self.storage = Storage::Chunked(blocks);
+        }
+        let Storage::Chunked(chunks) = &mut self.storage else {
+            bail!("Cannot modify a memory-mapped spectrogram");
+        };
+
+        chunks.truncate(first.div_ceil(STREAM_CHUNK_SLICES));
+        if let Some(last) = chunks.last_mut() {
+            let keep = first - (first - 1) / STREAM_CHUNK_SLICES * STREAM_CHUNK_SLICES;
+            *last = last.clone().slice_move(s![..keep, ..]);
+        }
+        // Fill up the last block first. Only that block is copied if it's shared with a clone.
+        let mut rest = data;
+        if let Some(last) = chunks.last_mut()
+            && last.nrows() < STREAM_CHUNK_SLICES
+        {
+            let n = (STREAM_CHUNK_SLICES - last.nrows()).min(rest.nrows());
+            let mut owned = std::mem::replace(last, ArcArray2::zeros((0, 0))).into_owned();
+            owned
+                .append(Axis(0), rest.slice(s![..n, ..]))
+                .context("Failed to append spectra")?;
+            *last = owned.into_shared();
+            rest = rest.slice_move(s![n.., ..]);
+        }
+        chunks.extend(
+            rest.axis_chunks_iter(Axis(0), STREAM_CHUNK_SLICES)
+                .map(|block| block.to_shared()),
+        );
+
+        self.timestamps.truncate(first);
+        self.timestamps.extend_from_slice(timestamps);
+        self.lengths.truncate(first);
+        self.lengths.extend_from_slice(lengths);
+        self.nslices = self.timestamps.len();
+        if let Some(mask) = &mut self.mask {
+            Arc::make_mut(mask).slices.resize(self.nslices, false);
+        }
+
+        let minmax = |(min, max): (f32, f32), &v: &f32| (min.min(v), max.max(v));
+        self.power_bounds = data.iter().fold(self.power_bounds, minmax);
+
+        // Gaps can only have changed around the replaced slices
+        let from = first.saturating_sub(1);
+        let from_time = self.timestamps[from];
+        self.gaps.retain(|gap| gap.end <= from_time);
+        self.gaps
+            .extend(find_gaps(&self.timestamps[from..], &self.lengths[from..]));
         Ok(())
     }
 
+    /// Builds the level-of-detail pyramid used to render zoomed-out views.
+    ///
+    /// This is CPU-heavy for large inputs, so async callers should run it in `spawn_blocking`.
+    pub fn build_pyramid(&mut self) {
+        self.pyramid = Some(Arc::new(Pyramid::build(self, Pyramid::DEFAULT_MIN_SIZE)));
+    }
+
+    /// Returns the level-of-detail pyramid, if it was built.
+    pub fn pyramid(&self) -> Option<&Pyramid> {
+        self.pyramid.as_deref()
+    }
+
+    /// Returns the time ranges in which no spectra were recorded, e.g. due to dropouts while
+    /// recording or missing files.
+    pub fn gaps(&self) -> &[Range<DateTime<Utc>>] {
+        &self.gaps
+    }
+
+    /// Returns the problems in the files that were skipped while loading in lenient mode.
+    pub fn load_warnings(&self) -> &[StrfError] {
+        &self.load_warnings
+    }
+
+    /// Returns the files that [`Spectrogram::load_warnings`] refer to, in order of appearance.
+    pub fn degraded_files(&self) -> Vec<&Path> {
+        self.load_warnings
+            .iter()
+            .map(StrfError::path)
+            .unique()
+            .collect()
+    }
+
     pub fn length(&self) -> Duration {
         self.end_time() - self.start_time()
     }
@@ -410,20 +1158,26 @@ impl Spectrogram {
     }
 }
 
+/// Finds the spaces between consecutive spectra that are noticeably longer than the spectra.
+fn find_gaps(timestamps: &[DateTime<Utc>], lengths: &[f32]) -> Vec<Range<DateTime<Utc>>> {
+    izip!(timestamps, lengths)
+        .tuple_windows()
+        .filter_map(|((&start, &length), (&next, _))| {
+            let end = start + Duration::microseconds((length * 1e6) as i64);
+            let spacing = (next - end).as_seconds_f32();
+            (spacing > length * GAP_TOLERANCE).then_some(end..next)
+        })
+        .collect()
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct SpectrogramBounds {
     pub time_range: std::ops::Range<DateTime<Utc>>,
     pub freq_range: std::ops::Range<f32>,
 }
 
-async fn parse_header<R: tokio::io::AsyncRead + Unpin>(reader: &mut R) -> Result<Header> {
-    let mut buf = [0u8; HEADER_SIZE];
-    reader
-        .read_exact(&mut buf)
-        .await
-        .context("Failed to read header")?;
-
-    let text = std::str::from_utf8(&buf)?.trim_end_matches('\0').trim();
+fn parse_header_bytes(buf: &[u8]) -> Result<Header> {
+    let text = std::str::from_utf8(buf)?.trim_end_matches('\0').trim();
 
     let caps = HEADER_RE
         .captures(text)
@@ -452,7 +1206,6 @@ async fn parse_header<R: tokio::io::AsyncRead + Unpin>(reader: &mut R) -> Result
 mod tests {
     use super::*;
     use chrono::NaiveDate;
-    use ndarray::s;
 
     fn test_start() -> DateTime<Utc> {
         NaiveDate::from_ymd_opt(2024, 1, 1)
@@ -476,11 +1229,15 @@ mod tests {
             nchan,
             nslices,
             power_bounds: (min, max),
-            data: data.into(),
+            storage: Storage::Memory(data.into()),
             timestamps: (0..nslices)
                 .map(|i| start + Duration::milliseconds((1.0 * 1000.0) as i64 * i as i64))
                 .collect(),
             lengths: vec![1.0; nslices],
+            gaps: Vec::new(),
+            pyramid: None,
+            load_warnings: Vec::new(),
+            mask: None,
         }
     }
 
@@ -545,6 +1302,121 @@ mod tests {
         );
     }
 
+    #[test]
+    fn concatenate_reports_gaps_between_files() {
+        let start = test_start();
+        let s1 = make_spec(start, 10, 16, 1.0);
+        let s2 = make_spec(s1.end_time(), 5, 16, 1.0);
+        let s3 = make_spec(s2.end_time() + Duration::seconds(30), 5, 16, 1.0);
+        let gap = s2.end_time()..s3.start_time();
+
+        let result = Spectrogram::concatenate(vec![s1, s2, s3]).unwrap();
+        assert_eq!(result.gaps(), &[gap]);
+    }
+
+    #[test]
+    fn find_gaps_tolerates_jitter() {
+        let start = test_start();
+        let timestamps = [0, 1010, 2000, 5000, 6000].map(|ms| start + Duration::milliseconds(ms));
+        let gaps = find_gaps(&timestamps, &[1.0; 5]);
+        assert_eq!(
+            gaps,
+            vec![(start + Duration::seconds(3))..(start + Duration::seconds(5))]
+        );
+    }
+
+    fn raw_spectra(start: DateTime<Utc>, n: usize, nchan: usize) -> Vec<RawStrfSpectrum> {
+        (0..n)
+            .map(|i| RawStrfSpectrum {
+                time: start + Duration::seconds(i as i64),
+                length_s: 1.0,
+                power_linear: vec![(i + 1) as f32; nchan],
+            })
+            .collect()
+    }
+
+    #[test]
+    fn append_matches_building_at_once() {
+        let start = test_start();
+        let spectra = raw_spectra(start, 12, 4);
+        let params = SpectrogramParams {
+            freq: 100e6,
+            bw: 100e3,
+            nchan: 4,
+        };
+        let mut spec = Spectrogram::from_raw(spectra[..5].to_vec(), params.clone()).unwrap();
+        let id = spec.id;
+        // Keep a clone around so the data is shared when appending
+        let before = spec.clone();
+        spec.append(spectra[3..].to_vec()).unwrap();
+
+        let expected = Spectrogram::from_raw(spectra, params).unwrap();
+        assert_eq!(spec.id, id);
+        assert_eq!(spec.nslices, 12);
+        assert_eq!(spec.timestamps, expected.timestamps);
+        assert_eq!(spec.data(), expected.data());
+        assert_eq!(spec.power_bounds, expected.power_bounds);
+        assert_eq!(before.nslices, 5);
+    }
+
+    #[test]
+    fn append_copies_only_the_last_block() {
+        let n = STREAM_CHUNK_SLICES + 10;
+        let spectra = raw_spectra(test_start(), n + 5, 4);
+        let params = SpectrogramParams {
+            freq: 100e6,
+            bw: 100e3,
+            nchan: 4,
+        };
+        let mut spec = Spectrogram::from_raw(spectra[..n].to_vec(), params.clone()).unwrap();
+        spec.append(spectra[n..n + 2].to_vec()).unwrap();
+        let before = spec.clone();
+        spec.append(spectra[n + 2..].to_vec()).unwrap();
+
+        let (Storage::Chunked(old), Storage::Chunked(new)) = (&before.storage, &spec.storage)
+        else {
+            panic!("Appending should split the data into blocks");
+        };
+        assert_eq!(old[0].as_ptr(), new[0].as_ptr());
+        assert_ne!(old[1].as_ptr(), new[1].as_ptr());
+        assert_eq!(before.nslices, n + 2);
+
+        let expected = Spectrogram::from_raw(spectra, params).unwrap();
+        assert_eq!(spec.data(), expected.data());
+        let across = STREAM_CHUNK_SLICES - 3..STREAM_CHUNK_SLICES + 3;
+        assert_eq!(spec.tile(across.clone(), 1..3), expected.tile(across, 1..3));
+        assert_eq!(spec.value(n + 4, 2), expected.value(n + 4, 2));
+    }
+
+    #[test]
+    fn splice_from_replaces_the_end() {
+        let start = test_start();
+        let mut spec = make_spec(start, 6, 4, 1.0);
+        let id = spec.id;
+        let tail = make_spec(spec.timestamps[4], 4, 4, 100.0);
+        spec.splice_from(4, &tail).unwrap();
+
+        assert_eq!(spec.id, id);
+        assert_eq!(spec.nslices, 8);
+        assert_eq!(spec.value(3, 0), 0.0);
+        assert_eq!(spec.value(4, 0), tail.value(0, 0));
+        assert_eq!(spec.power_bounds, (0.0, tail.value(0, 0)));
+        assert_eq!(spec.slices_from(4).data(), tail.data());
+        assert_eq!(spec.slices_from(4).timestamps, tail.timestamps);
+        // The spectra have to start after the kept ones
+        assert!(spec.splice_from(4, &make_spec(start, 2, 4, 1.0)).is_err());
+    }
+
+    #[test]
+    fn append_updates_gaps() {
+        let start = test_start();
+        let mut spec = make_spec(start, 5, 4, 1.0);
+        spec.append(raw_spectra(spec.end_time() + Duration::seconds(10), 3, 4))
+            .unwrap();
+        assert_eq!(spec.gaps().len(), 1);
+        assert!(spec.append(raw_spectra(spec.end_time(), 2, 8)).is_err());
+    }
+
     #[test]
     fn concatenate_mismatched_nchan_errors() {
         let start = test_start();
@@ -574,7 +1446,7 @@ mod tests {
         let path = dir.path().join("test.bin");
         save_strf(&spec, &path).await.unwrap();
 
-        let loaded = load(&[path], None).await.unwrap();
+        let loaded = load(&[path], LoadOptions::default()).await.unwrap();
 
         assert_eq!(loaded.nslices, spec.nslices);
         assert_eq!(loaded.nchan, spec.nchan);
@@ -603,7 +1475,7 @@ mod tests {
         save_strf(&s1, &path1).await.unwrap();
         save_strf(&s2, &path2).await.unwrap();
 
-        let loaded = load(&[path1, path2], None).await.unwrap();
+        let loaded = load(&[path1, path2], LoadOptions::default()).await.unwrap();
 
         assert_eq!(loaded.nslices, s1.nslices + s2.nslices);
         assert_eq!(loaded.nchan, s1.nchan);
@@ -623,13 +1495,311 @@ mod tests {
         save_strf(&s2, &path2).await.unwrap();
 
         // Pass in reverse order — should produce the same result
-        let loaded = load(&[path2.clone(), path1.clone()], None).await.unwrap();
-        let loaded_fwd = load(&[path1, path2], None).await.unwrap();
+        let loaded = load(&[path2.clone(), path1.clone()], LoadOptions::default())
+            .await
+            .unwrap();
+        let loaded_fwd = load(&[path1, path2], LoadOptions::default()).await.unwrap();
 
         assert_eq!(loaded.nslices, loaded_fwd.nslices);
         assert_eq!(loaded.start_time(), loaded_fwd.start_time());
     }
 
+    fn mmap_options(freq_range: Option<(u64, u64)>) -> LoadOptions {
+        LoadOptions {
+            freq_range,
+            mmap: true,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn tile_returns_requested_region() {
+        let mut spec = make_spec(test_start(), 4, 8, 1.0);
+        let data = ArcArray2::from_shape_fn((4, 8), |(t, f)| (t * 8 + f) as f32);
+        spec.set_data(data).unwrap();
+
+        let tile = spec.tile(1..3, 2..5);
+        assert_eq!(tile.dim(), (2, 3));
+        assert_eq!(tile[(0, 0)], 10.0);
+        assert_eq!(tile[(1, 2)], 20.0);
+        assert_eq!(spec.value(3, 7), 31.0);
+    }
+
+    #[tokio::test]
+    async fn load_mapped_matches_in_memory_load() {
+        let start = test_start();
+        let s1 = make_spec(start, 5, 16, 100.0);
+        let s2 = make_spec(s1.end_time(), 5, 16, 200.0);
+
+        let dir = tempfile::tempdir().unwrap();
+        let path1 = dir.path().join("part1.bin");
+        let path2 = dir.path().join("part2.bin");
+        save_strf(&s1, &path1).await.unwrap();
+        save_strf(&s2, &path2).await.unwrap();
+        let paths = [path1, path2];
+
+        let memory = load(&paths, LoadOptions::default()).await.unwrap();
+        let mapped = load(&paths, mmap_options(None)).await.unwrap();
+
+        assert!(!memory.is_mapped());
+        assert!(mapped.is_mapped());
+        assert_eq!(mapped.params(), memory.params());
+        assert_eq!(mapped.timestamps, memory.timestamps);
+        assert_eq!(mapped.lengths, memory.lengths);
+        assert_eq!(mapped.power_bounds, memory.power_bounds);
+        assert_eq!(mapped.data(), memory.data());
+        assert_eq!(mapped.tile(3..7, 4..9), memory.tile(3..7, 4..9));
+    }
+
+    #[tokio::test]
+    async fn load_mapped_finds_exact_power_bounds() {
+        // More spectra than could be looked at individually, with the extremes in single cells
+        let mut spec = make_spec(test_start(), 600, 16, 100.0);
+        let mut data = spec.data().to_owned();
+        data[(301, 3)] = 0.0;
+        data[(599, 15)] = 50.0;
+        spec.storage = Storage::Memory(data.into());
+        spec.power_bounds = spec.compute_power_bounds();
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("test.bin");
+        save_strf(&spec, &path).await.unwrap();
+
+        let mapped = load(&[path], mmap_options(None)).await.unwrap();
+        assert!(mapped.is_mapped());
+        assert!((mapped.power_bounds.0 - 0.0).abs() < 1e-3, "{:?}", mapped);
+        assert!((mapped.power_bounds.1 - 50.0).abs() < 1e-3, "{:?}", mapped);
+    }
+
+    #[tokio::test]
+    async fn load_mapped_with_freq_range_matches_in_memory_load() {
+        let spec = make_spec(test_start(), 3, 16, 100.0);
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("test.bin");
+        save_strf(&spec, &path).await.unwrap();
+
+        let freq_range = Some((436_975_000, 437_025_000));
+        let memory = load(
+            std::slice::from_ref(&path),
+            LoadOptions {
+                freq_range,
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        let mapped = load(&[path], mmap_options(freq_range)).await.unwrap();
+
+        assert_eq!(mapped.params(), memory.params());
+        assert_eq!(mapped.data(), memory.data());
+    }
+
+    #[tokio::test]
+    async fn save_strf_from_mapped_roundtrip() {
+        let spec = make_spec(test_start(), 4, 16, 100.0);
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("in.bin");
+        let out = dir.path().join("out.bin");
+        save_strf(&spec, &path).await.unwrap();
+        let mapped = load(&[path], mmap_options(None)).await.unwrap();
+        save_strf(&mapped, &out).await.unwrap();
+
+        let loaded = load(&[out], LoadOptions::default()).await.unwrap();
+        assert_eq!(loaded.timestamps, spec.timestamps);
+        for (&a, &b) in spec.data().iter().zip(loaded.data().iter()) {
+            assert!((a - b).abs() < 0.01, "dB mismatch: {a} vs {b}");
+        }
+    }
+
+    #[tokio::test]
+    async fn save_strf_blanked_writes_masked_cells_at_the_floor() {
+        let mut spec = make_spec(test_start(), 4, 16, 100.0);
+        spec.power_bounds.0 = 0.0;
+        let mut mask = Mask::new(4, 16);
+        mask.slices[1] = true;
+        mask.channels[3] = true;
+        spec.set_mask(Some(mask)).unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("blanked.bin");
+        save_strf_blanked(&spec, &path).await.unwrap();
+        let loaded = load(&[path], LoadOptions::default()).await.unwrap();
+        assert!(loaded.value(1, 0).abs() < 0.01);
+        assert!(loaded.value(0, 3).abs() < 0.01);
+        assert!((loaded.value(0, 0) - 20.0).abs() < 0.01);
+    }
+
+    #[tokio::test]
+    async fn load_with_time_range_keeps_overlapping_spectra_and_skips_files() {
+        let start = test_start();
+        let s1 = make_spec(start, 10, 16, 100.0);
+        let s2 = make_spec(start + Duration::seconds(60), 10, 16, 200.0);
+
+        let dir = tempfile::tempdir().unwrap();
+        let paths = [dir.path().join("part1.bin"), dir.path().join("part2.bin")];
+        save_strf(&s1, &paths[0]).await.unwrap();
+        save_strf(&s2, &paths[1]).await.unwrap();
+
+        // Starts halfway through the third spectrum, ends halfway through the sixth
+        let time_range = (
+            start + Duration::milliseconds(2500),
+            start + Duration::milliseconds(5500),
+        );
+        for mmap in [false, true] {
+            let options = LoadOptions {
+                time_range: Some(time_range),
+                mmap,
+                ..Default::default()
+            };
+            let loaded = load(&paths, options).await.unwrap();
+            assert_eq!(
+                loaded.timestamps,
+                s1.timestamps[2..6].to_vec(),
+                "mmap: {mmap}"
+            );
+            assert_eq!(loaded.nchan, s1.nchan);
+
+            let outside = (start + Duration::seconds(20), start + Duration::seconds(30));
+            let result = load(
+                &paths,
+                LoadOptions {
+                    time_range: Some(outside),
+                    ..options
+                },
+            )
+            .await;
+            assert!(result.is_err(), "mmap: {mmap}");
+        }
+    }
+
+    #[tokio::test]
+    async fn load_rejects_empty_time_range() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("test.bin");
+        save_strf(&make_spec(test_start(), 3, 16, 100.0), &path)
+            .await
+            .unwrap();
+
+        let options = LoadOptions {
+            time_range: Some((test_start() + Duration::seconds(1), test_start())),
+            ..Default::default()
+        };
+        assert!(load(&[path], options).await.is_err());
+    }
+
+    /// Writes `nslices` spectra with `nchan` channels to a `.bin` file and returns its bytes.
+    async fn bin_bytes(dir: &Path, start: DateTime<Utc>, nslices: usize, nchan: usize) -> Vec<u8> {
+        let path = dir.join("scratch.bin");
+        save_strf(&make_spec(start, nslices, nchan, 100.0), &path)
+            .await
+            .unwrap();
+        std::fs::read(path).unwrap()
+    }
+
+    /// Loads `path` in strict and lenient mode (both in memory and memory-mapped), checking that
+    /// strict mode fails with `expected` and lenient mode skips it.
+    async fn check_damaged(path: &Path, expected: &StrfError) -> Spectrogram {
+        let mut lenient = None;
+        for mmap in [false, true] {
+            let options = LoadOptions {
+                mmap,
+                ..Default::default()
+            };
+            let error = load(&[path.to_path_buf()], options).await.unwrap_err();
+            assert_eq!(
+                error.downcast_ref::<StrfError>(),
+                Some(expected),
+                "mmap: {mmap}"
+            );
+
+            let options = LoadOptions {
+                lenient: true,
+                ..options
+            };
+            let spec = load(&[path.to_path_buf()], options).await.unwrap();
+            assert_eq!(spec.load_warnings(), std::slice::from_ref(expected));
+            assert_eq!(spec.degraded_files(), vec![path]);
+            lenient = Some(spec);
+        }
+        lenient.unwrap()
+    }
+
+    #[tokio::test]
+    async fn lenient_load_drops_truncated_last_spectrum() {
+        let dir = tempfile::tempdir().unwrap();
+        let bytes = bin_bytes(dir.path(), test_start(), 3, 16).await;
+        let block = HEADER_SIZE + 16 * 4;
+        let path = dir.path().join("truncated.bin");
+        std::fs::write(&path, &bytes[..2 * block + 100]).unwrap();
+
+        let expected = StrfError::Truncated {
+            path: path.clone(),
+            offset: 2 * block as u64,
+            len: 100,
+        };
+        let spec = check_damaged(&path, &expected).await;
+        assert_eq!(spec.nslices, 2);
+    }
+
+    #[tokio::test]
+    async fn lenient_load_skips_garbled_header() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut bytes = bin_bytes(dir.path(), test_start(), 4, 16).await;
+        let block = HEADER_SIZE + 16 * 4;
+        bytes[block..block + 6].copy_from_slice(b"GARBLE");
+        let path = dir.path().join("garbled.bin");
+        std::fs::write(&path, &bytes).unwrap();
+
+        let StrfError::InvalidHeader { offset, .. } = load_strf_raw(&path, LoadOptions::default())
+            .await
+            .err()
+            .and_then(|e| e.downcast::<StrfError>().ok())
+            .unwrap()
+        else {
+            panic!("expected an invalid header");
+        };
+        assert_eq!(offset, block as u64);
+
+        let options = LoadOptions {
+            lenient: true,
+            ..Default::default()
+        };
+        for mmap in [false, true] {
+            let spec = load(std::slice::from_ref(&path), LoadOptions { mmap, ..options })
+                .await
+                .unwrap();
+            assert_eq!(spec.nslices, 3);
+            assert_eq!(spec.timestamps[1], test_start() + Duration::seconds(2));
+            assert_eq!(spec.load_warnings().len(), 1);
+        }
+    }
+
+    #[tokio::test]
+    async fn lenient_load_resyncs_after_nchan_change() {
+        let dir = tempfile::tempdir().unwrap();
+        let start = test_start();
+        let mut bytes = bin_bytes(dir.path(), start, 2, 16).await;
+        bytes.extend(bin_bytes(dir.path(), start + Duration::seconds(2), 1, 8).await);
+        bytes.extend(bin_bytes(dir.path(), start + Duration::seconds(3), 2, 16).await);
+        let path = dir.path().join("nchan.bin");
+        std::fs::write(&path, &bytes).unwrap();
+
+        let offset = 2 * (HEADER_SIZE + 16 * 4) as u64;
+        let expected = StrfError::InconsistentParams {
+            path: path.clone(),
+            offset,
+            expected: make_spec(start, 1, 16, 1.0).params(),
+            found: make_spec(start, 1, 8, 1.0).params(),
+        };
+        let spec = check_damaged(&path, &expected).await;
+        assert_eq!(
+            spec.timestamps,
+            [0, 1, 3, 4].map(|s| start + Duration::seconds(s)).to_vec()
+        );
+    }
+
     // Header for apply_freq_range unit tests: freq=500_000 Hz, bw=1_000 Hz, nchan=10
     // chan_width=100 Hz, channels span [499_500, 500_500) Hz
     fn test_header() -> Header {
@@ -733,7 +1903,15 @@ mod tests {
         let path = dir.path().join("test.bin");
         save_strf(&spec, &path).await.unwrap();
 
-        let loaded = load(&[path], Some((436_950_000, 437_000_000))).await.unwrap();
+        let loaded = load(
+            &[path],
+            LoadOptions {
+                freq_range: Some((436_950_000, 437_000_000)),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
 
         assert_eq!(loaded.nchan, 8);
         assert!((loaded.bw - 50_000.0).abs() < 1.0);
@@ -752,8 +1930,18 @@ mod tests {
         let path = dir.path().join("test.bin");
         save_strf(&spec, &path).await.unwrap();
 
-        let full = load(&[path.clone()], None).await.unwrap();
-        let filtered = load(&[path], Some((436_950_000, 437_000_000))).await.unwrap();
+        let full = load(std::slice::from_ref(&path), LoadOptions::default())
+            .await
+            .unwrap();
+        let filtered = load(
+            &[path],
+            LoadOptions {
+                freq_range: Some((436_950_000, 437_000_000)),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
 
         let full_data = full.data();
         let filt_data = filtered.data();
@@ -761,7 +1949,10 @@ mod tests {
             for ch in 0..8 {
                 let expected = full_data[[row, ch]];
                 let got = filt_data[[row, ch]];
-                assert!((expected - got).abs() < 0.01, "row={row} ch={ch}: {expected} vs {got}");
+                assert!(
+                    (expected - got).abs() < 0.01,
+                    "row={row} ch={ch}: {expected} vs {got}"
+                );
             }
         }
     }
@@ -776,7 +1967,14 @@ mod tests {
         save_strf(&spec, &path).await.unwrap();
 
         // Range entirely outside the spectrum [436_950_000, 437_050_000)
-        let result = load(&[path], Some((100_000, 200_000))).await;
+        let result = load(
+            &[path],
+            LoadOptions {
+                freq_range: Some((100_000, 200_000)),
+                ..Default::default()
+            },
+        )
+        .await;
         assert!(result.is_err());
     }
 }
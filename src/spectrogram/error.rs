@@ -0,0 +1,97 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Problems with the contents of strf `.bin` files.
+
+use std::path::{Path, PathBuf};
+
+use super::SpectrogramParams;
+
+/// A problem reading a strf `.bin` file, with the file and the byte offset of the affected block.
+///
+/// The loading functions return these wrapped in [`anyhow::Error`], so they can be recovered with
+/// `downcast_ref`. In lenient mode (see [`super::LoadOptions::lenient`]), the affected spectra are
+/// skipped instead and the problems are kept in [`super::Spectrogram::load_warnings`].
+#[derive(Debug, Clone, PartialEq, thiserror::Error)]
+pub enum StrfError {
+    #[error("{}: {kind} at byte {offset}", path.display())]
+    Io {
+        path: PathBuf,
+        offset: u64,
+        kind: std::io::ErrorKind,
+    },
+    #[error("{}: invalid header at byte {offset}: {reason}", path.display())]
+    InvalidHeader {
+        path: PathBuf,
+        offset: u64,
+        reason: String,
+    },
+    /// A spectrum whose frequency, bandwidth or channel count differs from the first one in the file
+    #[error(
+        "{}: spectrum at byte {offset} has {found:?}, expected {expected:?}",
+        path.display()
+    )]
+    InconsistentParams {
+        path: PathBuf,
+        offset: u64,
+        expected: SpectrogramParams,
+        found: SpectrogramParams,
+    },
+    /// The last spectrum was only partially written
+    #[error(
+        "{}: truncated spectrum at byte {offset} (only {len} bytes left)",
+        path.display()
+    )]
+    Truncated {
+        path: PathBuf,
+        offset: u64,
+        len: u64,
+    },
+    #[error("{}: no complete spectra", path.display())]
+    Empty { path: PathBuf },
+}
+
+impl StrfError {
+    pub(super) fn io(path: &Path, offset: u64, error: std::io::Error) -> Self {
+        StrfError::Io {
+            path: path.to_path_buf(),
+            offset,
+            kind: error.kind(),
+        }
+    }
+
+    /// The file the problem occurred in.
+    pub fn path(&self) -> &Path {
+        match self {
+            StrfError::Io { path, .. }
+            | StrfError::InvalidHeader { path, .. }
+            | StrfError::InconsistentParams { path, .. }
+            | StrfError::Truncated { path, .. }
+            | StrfError::Empty { path } => path,
+        }
+    }
+
+    /// The byte offset of the affected block, if the problem concerns a single block.
+    pub fn offset(&self) -> Option<u64> {
+        match self {
+            StrfError::Io { offset, .. }
+            | StrfError::InvalidHeader { offset, .. }
+            | StrfError::InconsistentParams { offset, .. }
+            | StrfError::Truncated { offset, .. } => Some(*offset),
+            StrfError::Empty { .. } => None,
+        }
+    }
+}
+
+/// Returns `error` in strict mode. In lenient mode, logs it and keeps it in `warnings` instead.
+pub(super) fn tolerate(
+    error: StrfError,
+    lenient: bool,
+    warnings: &mut Vec<StrfError>,
+) -> Result<(), StrfError> {
+    if !lenient {
+        return Err(error);
+    }
+    log::warn!("Skipping: {error}");
+    warnings.push(error);
+    Ok(())
+}
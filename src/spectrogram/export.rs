@@ -0,0 +1,529 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Exporting spectrograms for analysis in other tools, as FITS images (e.g. for astropy) or NumPy
+//! `.npy`/`.npz` arrays.
+//!
+//! Unlike [`super::save_strf`], these write with blocking I/O, so async callers should run them in
+//! `spawn_blocking`.
+
+use std::{
+    fmt::Display,
+    fs::File,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use anyhow::{Context, Result, bail, ensure};
+use chrono::{DateTime, Utc};
+use clap::ValueEnum;
+use strum::Display;
+
+use super::{
+    STREAM_CHUNK_SLICES, Spectrogram, Storage, TimeRange, channel_range, overlaps_time_range,
+};
+
+/// FITS files consist of blocks of this many bytes.
+const FITS_BLOCK: usize = 2880;
+/// FITS headers consist of cards of this many characters.
+const FITS_CARD: usize = 80;
+
+/// Formats that spectrograms can be exported to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Display)]
+#[strum(serialize_all = "lowercase")]
+pub enum ExportFormat {
+    /// FITS image with WCS time and frequency axes, and the exact timestamps in a table
+    Fits,
+    /// NumPy array of the power, with the axes in separate `.npy` files next to it
+    Npy,
+    /// NumPy archive with the power and the axes
+    Npz,
+}
+
+impl ExportFormat {
+    /// Guesses the format from the file extension.
+    pub fn from_path(path: &Path) -> Option<ExportFormat> {
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "fits" | "fit" | "fts" => Some(ExportFormat::Fits),
+            "npy" => Some(ExportFormat::Npy),
+            "npz" => Some(ExportFormat::Npz),
+            _ => None,
+        }
+    }
+}
+
+/// Writes a spectrogram to `path` in the given format.
+pub fn export(spectrogram: &Spectrogram, path: &Path, format: ExportFormat) -> Result<()> {
+    match format {
+        ExportFormat::Fits => save_fits(spectrogram, path),
+        ExportFormat::Npy => save_npy(spectrogram, path),
+        ExportFormat::Npz => save_npz(spectrogram, path),
+    }
+}
+
+impl Spectrogram {
+    /// Returns the spectra that overlap `time_range` and the channels that overlap `freq_range` (in
+    /// Hz), copied into memory.
+    pub fn crop(
+        &self,
+        time_range: Option<TimeRange>,
+        freq_range: Option<(u64, u64)>,
+    ) -> Result<Spectrogram> {
+        let overlaps =
+            |&i: &usize| overlaps_time_range(time_range, self.timestamps[i], self.lengths[i]);
+        let first = (0..self.nslices).find(overlaps);
+        let last = (0..self.nslices).rfind(overlaps);
+        let (Some(first), Some(last)) = (first, last) else {
+            bail!("The spectrogram has no spectra in the time range");
+        };
+        let slices = first..last + 1;
+        let channels =
+            freq_range.map_or(0..self.nchan, |range| channel_range(&self.params(), range));
+        ensure!(
+            !channels.is_empty(),
+            "The spectrogram has no channels in the frequency range"
+        );
+
+        let data = self.tile(slices.clone(), channels.clone()).into_owned();
+        let mut cropped = Spectrogram::new(
+            self.params().select_channels(channels.clone()),
+            Storage::Memory(data.into_shared()),
+            self.timestamps[slices.clone()].to_vec(),
+            self.lengths[slices.clone()].to_vec(),
+        );
+        cropped.mask = self
+            .mask()
+            .map(|mask| Arc::new(mask.select(slices, channels)));
+        Ok(cropped)
+    }
+
+    /// Centre frequencies of the channels in Hz.
+    pub fn channel_freqs(&self) -> Vec<f64> {
+        let chan_width = self.bw as f64 / self.nchan as f64;
+        let start_freq = self.freq as f64 - self.bw as f64 / 2.0;
+        (0..self.nchan)
+            .map(|chan| start_freq + (chan as f64 + 0.5) * chan_width)
+            .collect()
+    }
+}
+
+/// Writes the power values (in dB) row by row, i.e. with one spectrum per row.
+fn write_power<W: Write + ?Sized>(
+    spectrogram: &Spectrogram,
+    writer: &mut W,
+    to_bytes: fn(f32) -> [u8; 4],
+) -> std::io::Result<()> {
+    for start in (0..spectrogram.nslices).step_by(STREAM_CHUNK_SLICES) {
+        let rows = start..(start + STREAM_CHUNK_SLICES).min(spectrogram.nslices);
+        let tile = spectrogram.tile(rows, 0..spectrogram.nchan);
+        let bytes: Vec<u8> = tile.iter().flat_map(|&v| to_bytes(v)).collect();
+        writer.write_all(&bytes)?;
+    }
+    Ok(())
+}
+
+fn create(path: &Path) -> Result<BufWriter<File>> {
+    let file =
+        File::create(path).with_context(|| format!("Failed to create {}", path.display()))?;
+    Ok(BufWriter::new(file))
+}
+
+/// Writes a spectrogram as a FITS file.
+///
+/// The primary HDU is a 2D image of the power in dB, with one spectrum per row (`NAXIS1` is
+/// frequency, `NAXIS2` is time). Its WCS frequency axis is exact, the time axis assumes evenly
+/// spaced spectra of the median length. The exact start times and lengths of the spectra (in
+/// seconds since `DATEREF`) are in the `TIMES` binary table extension.
+pub fn save_fits(spectrogram: &Spectrogram, path: &Path) -> Result<()> {
+    let mut writer = create(path)?;
+    let date_ref = fits_time(spectrogram.start_time());
+    let chan_width = spectrogram.bw as f64 / spectrogram.nchan as f64;
+    let mut lengths = spectrogram.lengths.clone();
+    lengths.sort_unstable_by(f32::total_cmp);
+    let spectrum_length = lengths[lengths.len() / 2];
+
+    let mut header = FitsHeader::default();
+    header.logical("SIMPLE", true, "Standard FITS");
+    header.value("BITPIX", -32, "32-bit IEEE floats");
+    header.value("NAXIS", 2, "");
+    header.value("NAXIS1", spectrogram.nchan, "Channels");
+    header.value("NAXIS2", spectrogram.nslices, "Spectra");
+    header.logical("EXTEND", true, "TIMES extension follows");
+    header.string("BUNIT", "dB", "Power");
+    header.string("ORIGIN", "rstrf", "");
+    header.string("TIMESYS", "UTC", "");
+    header.string("DATEREF", &date_ref, "Time axis reference");
+    header.string("DATE-OBS", &date_ref, "Start of the first spectrum");
+    header.string(
+        "DATE-END",
+        &fits_time(spectrogram.end_time()),
+        "End of the last spectrum",
+    );
+    header.string("CTYPE1", "FREQ", "");
+    header.string("CUNIT1", "Hz", "");
+    header.float("CRPIX1", 1.0, "");
+    header.float(
+        "CRVAL1",
+        spectrogram.channel_freqs()[0],
+        "Centre of the first channel",
+    );
+    header.float("CDELT1", chan_width, "Channel width");
+    header.string("CTYPE2", "TIME", "");
+    header.string("CUNIT2", "s", "");
+    header.float("CRPIX2", 1.0, "");
+    header.float("CRVAL2", 0.0, "Seconds since DATEREF");
+    header.float("CDELT2", spectrum_length as f64, "Median length, see TIMES");
+    writer.write_all(&header.finish())?;
+    write_power(spectrogram, &mut writer, f32::to_be_bytes)?;
+    pad_fits_block(&mut writer, spectrogram.nslices * spectrogram.nchan * 4)?;
+
+    // Rows of the TIMES table: start time (f64) and length (f32)
+    const ROW_SIZE: usize = 8 + 4;
+    let mut header = FitsHeader::default();
+    header.string("XTENSION", "BINTABLE", "Binary table");
+    header.value("BITPIX", 8, "");
+    header.value("NAXIS", 2, "");
+    header.value("NAXIS1", ROW_SIZE, "Bytes per row");
+    header.value("NAXIS2", spectrogram.nslices, "Spectra");
+    header.value("PCOUNT", 0, "");
+    header.value("GCOUNT", 1, "");
+    header.value("TFIELDS", 2, "");
+    header.string("TTYPE1", "TIME", "Start of the spectrum since DATEREF");
+    header.string("TFORM1", "1D", "");
+    header.string("TUNIT1", "s", "");
+    header.string("TTYPE2", "LENGTH", "Length of the spectrum");
+    header.string("TFORM2", "1E", "");
+    header.string("TUNIT2", "s", "");
+    header.string("EXTNAME", "TIMES", "");
+    header.string("TIMESYS", "UTC", "");
+    header.string("DATEREF", &date_ref, "");
+    writer.write_all(&header.finish())?;
+    for (&time, &length) in spectrogram.timestamps.iter().zip(&spectrogram.lengths) {
+        let offset = (time - spectrogram.start_time()).as_seconds_f64();
+        writer.write_all(&offset.to_be_bytes())?;
+        writer.write_all(&length.to_be_bytes())?;
+    }
+    pad_fits_block(&mut writer, spectrogram.nslices * ROW_SIZE)?;
+
+    writer.flush()?;
+    Ok(())
+}
+
+fn fits_time(time: DateTime<Utc>) -> String {
+    time.format("%Y-%m-%dT%H:%M:%S%.6f").to_string()
+}
+
+/// Pads a data unit of `len` bytes with zeros to a whole FITS block.
+fn pad_fits_block(writer: &mut impl Write, len: usize) -> std::io::Result<()> {
+    let padding = len.next_multiple_of(FITS_BLOCK) - len;
+    writer.write_all(&vec![0; padding])
+}
+
+/// A FITS header, built from fixed-format keyword cards.
+#[derive(Default)]
+struct FitsHeader(String);
+
+impl FitsHeader {
+    /// Adds a card whose value is right-aligned to column 30, as required for numbers and logicals.
+    fn value(&mut self, key: &str, value: impl Display, comment: &str) {
+        self.card(key, format!("{value:>20}"), comment);
+    }
+
+    fn float(&mut self, key: &str, value: f64, comment: &str) {
+        // FITS requires a decimal point and an upper-case exponent
+        let mut value = format!("{value:?}").to_uppercase();
+        if !value.contains('.') {
+            let exponent = value.find('E').unwrap_or(value.len());
+            value.insert_str(exponent, ".0");
+        }
+        self.value(key, value, comment);
+    }
+
+    fn logical(&mut self, key: &str, value: bool, comment: &str) {
+        self.value(key, if value { "T" } else { "F" }, comment);
+    }
+
+    fn string(&mut self, key: &str, value: &str, comment: &str) {
+        let quoted = format!("'{:<8}'", value.replace('\'', "''"));
+        self.card(key, format!("{quoted:<20}"), comment);
+    }
+
+    fn card(&mut self, key: &str, value: String, comment: &str) {
+        let mut card = format!("{key:<8}= {value}");
+        if !comment.is_empty() {
+            card.push_str(" / ");
+            card.push_str(comment);
+        }
+        card.truncate(FITS_CARD);
+        self.0.push_str(&format!("{card:<FITS_CARD$}"));
+    }
+
+    /// Ends the header and pads it with spaces to a whole FITS block.
+    fn finish(mut self) -> Vec<u8> {
+        self.0.push_str(&format!("{:<FITS_CARD$}", "END"));
+        let len = self.0.len().next_multiple_of(FITS_BLOCK);
+        format!("{:<len$}", self.0).into_bytes()
+    }
+}
+
+/// Writes a spectrogram as NumPy `.npy` files.
+///
+/// `path` gets the power in dB as a `float32` array of shape `(spectra, channels)`. The axes are
+/// written next to it, with the suffixes `_times` (start times as `datetime64[us]`), `_lengths`
+/// (spectrum lengths in seconds as `float32`) and `_freqs` (channel centre frequencies in Hz as
+/// `float64`).
+pub fn save_npy(spectrogram: &Spectrogram, path: &Path) -> Result<()> {
+    let mut writer = create(path)?;
+    write_npy_power(spectrogram, &mut writer)?;
+    writer.flush()?;
+    for (name, write_axis) in NPY_AXES {
+        let axis_path = npy_axis_path(path, name);
+        let mut writer = create(&axis_path)?;
+        write_axis(spectrogram, &mut writer)?;
+        writer.flush()?;
+    }
+    Ok(())
+}
+
+/// Writes a spectrogram as a NumPy `.npz` archive with the arrays `power`, `times`, `lengths`
+/// and `freqs`, see [`save_npy`].
+pub fn save_npz(spectrogram: &Spectrogram, path: &Path) -> Result<()> {
+    let mut zip = zip::ZipWriter::new(create(path)?);
+    // Like `numpy.savez`, store the arrays uncompressed
+    let power_size = spectrogram.nslices * spectrogram.nchan * 4;
+    let options = zip::write::SimpleFileOptions::default()
+        .compression_method(zip::CompressionMethod::Stored)
+        .large_file(power_size > u32::MAX as usize);
+    zip.start_file("power.npy", options)?;
+    write_npy_power(spectrogram, &mut zip)?;
+    for (name, write_axis) in NPY_AXES {
+        zip.start_file(format!("{name}.npy"), options)?;
+        write_axis(spectrogram, &mut zip)?;
+    }
+    zip.finish()?.flush()?;
+    Ok(())
+}
+
+type WriteAxis = fn(&Spectrogram, &mut dyn Write) -> std::io::Result<()>;
+
+/// The axes that are written alongside the power, and how to write them.
+const NPY_AXES: [(&str, WriteAxis); 3] = [
+    ("times", write_npy_times),
+    ("lengths", write_npy_lengths),
+    ("freqs", write_npy_freqs),
+];
+
+fn npy_axis_path(path: &Path, name: &str) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!("{stem}_{name}.npy"))
+}
+
+fn write_npy_power(spectrogram: &Spectrogram, writer: &mut dyn Write) -> std::io::Result<()> {
+    write_npy_header(writer, "<f4", &[spectrogram.nslices, spectrogram.nchan])?;
+    write_power(spectrogram, writer, f32::to_le_bytes)
+}
+
+fn write_npy_times(spectrogram: &Spectrogram, writer: &mut dyn Write) -> std::io::Result<()> {
+    write_npy_header(writer, "<M8[us]", &[spectrogram.nslices])?;
+    let bytes: Vec<u8> = spectrogram
+        .timestamps
+        .iter()
+        .flat_map(|time| time.timestamp_micros().to_le_bytes())
+        .collect();
+    writer.write_all(&bytes)
+}
+
+fn write_npy_lengths(spectrogram: &Spectrogram, writer: &mut dyn Write) -> std::io::Result<()> {
+    write_npy_header(writer, "<f4", &[spectrogram.nslices])?;
+    let bytes: Vec<u8> = spectrogram
+        .lengths
+        .iter()
+        .flat_map(|length| length.to_le_bytes())
+        .collect();
+    writer.write_all(&bytes)
+}
+
+fn write_npy_freqs(spectrogram: &Spectrogram, writer: &mut dyn Write) -> std::io::Result<()> {
+    write_npy_header(writer, "<f8", &[spectrogram.nchan])?;
+    let bytes: Vec<u8> = spectrogram
+        .channel_freqs()
+        .iter()
+        .flat_map(|freq| freq.to_le_bytes())
+        .collect();
+    writer.write_all(&bytes)
+}
+
+/// Writes a version 1.0 `.npy` header for a C-order array.
+fn write_npy_header(writer: &mut dyn Write, descr: &str, shape: &[usize]) -> std::io::Result<()> {
+    const MAGIC: &[u8] = b"\x93NUMPY\x01\x00";
+    let shape = match shape {
+        [len] => format!("({len},)"),
+        _ => format!(
+            "({})",
+            shape
+                .iter()
+                .map(usize::to_string)
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    };
+    let mut header = format!("{{'descr': '{descr}', 'fortran_order': False, 'shape': {shape}, }}");
+    // The data has to start at a multiple of 64 bytes, and the header ends with a newline
+    let len = (MAGIC.len() + 2 + header.len() + 1).next_multiple_of(64) - MAGIC.len() - 2;
+    header = format!("{header:<0$}\n", len - 1);
+    writer.write_all(MAGIC)?;
+    writer.write_all(&(len as u16).to_le_bytes())?;
+    writer.write_all(header.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::spectrogram::{RawStrfSpectrum, SpectrogramParams};
+    use chrono::Duration;
+    use std::io::Read;
+
+    fn make_spec(offsets_s: &[i64], nchan: usize) -> Spectrogram {
+        let start = DateTime::<Utc>::from_timestamp(1_700_000_000, 0).unwrap();
+        let spectra = offsets_s
+            .iter()
+            .enumerate()
+            .map(|(i, &offset)| RawStrfSpectrum {
+                time: start + Duration::seconds(offset),
+                length_s: 1.0,
+                power_linear: (0..nchan).map(|f| (i * nchan + f + 1) as f32).collect(),
+            })
+            .collect();
+        let params = SpectrogramParams {
+            freq: 437e6,
+            bw: nchan as f32 * 1e3,
+            nchan,
+        };
+        Spectrogram::from_raw(spectra, params).unwrap()
+    }
+
+    /// Parses a `.npy` file into its header and data.
+    fn read_npy(bytes: &[u8]) -> (&str, &[u8]) {
+        assert_eq!(&bytes[..8], b"\x93NUMPY\x01\x00");
+        let len = u16::from_le_bytes([bytes[8], bytes[9]]) as usize;
+        assert_eq!((10 + len) % 64, 0);
+        let header = std::str::from_utf8(&bytes[10..10 + len]).unwrap();
+        assert!(header.ends_with('\n'));
+        (header, &bytes[10 + len..])
+    }
+
+    #[test]
+    fn crop_selects_overlapping_spectra_and_channels() {
+        let spec = make_spec(&[0, 1, 2, 3, 4], 10);
+        let from = spec.timestamps[1] + Duration::milliseconds(500);
+        let to = spec.timestamps[3];
+        let cropped = spec
+            .crop(Some((from, to)), Some((436_998_500, 437_001_500)))
+            .unwrap();
+
+        assert_eq!(cropped.timestamps, spec.timestamps[1..3].to_vec());
+        assert_eq!(cropped.nchan, 4);
+        assert!((cropped.freq - 437e6).abs() < 1.0);
+        assert_eq!(cropped.value(0, 0), spec.value(1, 3));
+
+        let outside = spec.end_time() + Duration::seconds(1);
+        let later = outside + Duration::seconds(1);
+        assert!(spec.crop(Some((outside, later)), None).is_err());
+        assert!(spec.crop(None, Some((1, 2))).is_err());
+    }
+
+    #[test]
+    fn save_fits_writes_image_and_times_table() {
+        let spec = make_spec(&[0, 1, 5], 4);
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("out.fits");
+        save_fits(&spec, &path).unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+
+        // Header, image, table header, table
+        assert_eq!(bytes.len(), 4 * FITS_BLOCK);
+        let header = std::str::from_utf8(&bytes[..FITS_BLOCK]).unwrap();
+        assert!(header.starts_with(&format!("SIMPLE  = {:>20} / Standard FITS ", "T")));
+        assert!(header.contains(&format!("{:<8}= {:>20}", "NAXIS1", 4)));
+        assert!(header.contains(&format!("{:<8}= {:>20}", "NAXIS2", 3)));
+        assert!(header.contains("DATEREF = '2023-11-14T22:13:20.000000'"));
+        assert!(header.contains(&format!("{:<8}= {:>20}", "CDELT1", "1000.0")));
+        assert!(header.contains("END     "));
+
+        let first = f32::from_be_bytes(bytes[FITS_BLOCK..FITS_BLOCK + 4].try_into().unwrap());
+        assert_eq!(first, spec.value(0, 0));
+
+        let table = &bytes[3 * FITS_BLOCK..];
+        let time =
+            |row: usize| f64::from_be_bytes(table[row * 12..row * 12 + 8].try_into().unwrap());
+        assert_eq!([time(0), time(1), time(2)], [0.0, 1.0, 5.0]);
+    }
+
+    #[test]
+    fn save_npy_writes_power_and_axes() {
+        let spec = make_spec(&[0, 1, 2], 4);
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("out.npy");
+        save_npy(&spec, &path).unwrap();
+
+        let bytes = std::fs::read(&path).unwrap();
+        let (header, data) = read_npy(&bytes);
+        assert!(header.starts_with("{'descr': '<f4', 'fortran_order': False, 'shape': (3, 4), }"));
+        assert_eq!(data.len(), 3 * 4 * 4);
+        assert_eq!(
+            f32::from_le_bytes(data[4..8].try_into().unwrap()),
+            spec.value(0, 1)
+        );
+
+        let bytes = std::fs::read(dir.path().join("out_times.npy")).unwrap();
+        let (header, data) = read_npy(&bytes);
+        assert!(header.contains("'descr': '<M8[us]'") && header.contains("'shape': (3,)"));
+        let time = i64::from_le_bytes(data[8..16].try_into().unwrap());
+        assert_eq!(time, spec.timestamps[1].timestamp_micros());
+
+        let bytes = std::fs::read(dir.path().join("out_freqs.npy")).unwrap();
+        let (_, data) = read_npy(&bytes);
+        let freq = f64::from_le_bytes(data[..8].try_into().unwrap());
+        assert_eq!(freq, 437e6 - 1500.0);
+        assert!(dir.path().join("out_lengths.npy").exists());
+    }
+
+    #[test]
+    fn save_npz_stores_all_arrays() {
+        let spec = make_spec(&[0, 1], 2);
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("out.npz");
+        save_npz(&spec, &path).unwrap();
+
+        let mut archive = zip::ZipArchive::new(File::open(&path).unwrap()).unwrap();
+        let mut names: Vec<_> = archive.file_names().collect();
+        names.sort_unstable();
+        assert_eq!(
+            names,
+            ["freqs.npy", "lengths.npy", "power.npy", "times.npy"]
+        );
+        let mut bytes = Vec::new();
+        archive
+            .by_name("power.npy")
+            .unwrap()
+            .read_to_end(&mut bytes)
+            .unwrap();
+        let (header, data) = read_npy(&bytes);
+        assert!(header.contains("'shape': (2, 2)"));
+        assert_eq!(data.len(), 2 * 2 * 4);
+    }
+
+    #[test]
+    fn export_format_from_extension() {
+        assert_eq!(
+            ExportFormat::from_path(Path::new("a/b.FITS")),
+            Some(ExportFormat::Fits)
+        );
+        assert_eq!(
+            ExportFormat::from_path(Path::new("b.npz")),
+            Some(ExportFormat::Npz)
+        );
+        assert_eq!(ExportFormat::from_path(Path::new("b.bin")), None);
+    }
+}
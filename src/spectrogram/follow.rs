@@ -0,0 +1,181 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Following a directory of strf `.bin` files that rffft is still writing to.
+
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result, ensure};
+
+use super::{HEADER_SIZE, LoadOptions, RawStrfSpectrum, SpectrogramParams, load_strf_raw_from};
+
+/// Keeps track of how far each `.bin` file in a directory has been read, so that repeated polls
+/// only return the spectra that were written since the last one.
+pub struct Follower {
+    dir: PathBuf,
+    /// Only the frequency range, time range and lenient mode are used
+    options: LoadOptions,
+    /// Byte offset just after the last complete spectrum read from each file
+    offsets: BTreeMap<PathBuf, u64>,
+    params: Option<SpectrogramParams>,
+}
+
+impl Follower {
+    pub fn new(dir: PathBuf, options: LoadOptions) -> Self {
+        Self {
+            dir,
+            options,
+            offsets: BTreeMap::new(),
+            params: None,
+        }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// The parameters shared by all spectra read so far, if any were read.
+    pub fn params(&self) -> Option<&SpectrogramParams> {
+        self.params.as_ref()
+    }
+
+    /// The files that spectra were read from so far, sorted by name.
+    pub fn files(&self) -> Vec<PathBuf> {
+        self.offsets
+            .iter()
+            .filter(|&(_, &offset)| offset > 0)
+            .map(|(path, _)| path.clone())
+            .collect()
+    }
+
+    /// Reads the complete spectra that were written since the last poll, sorted by time.
+    ///
+    /// A spectrum that is only partially written is left for a later poll.
+    pub async fn poll(&mut self) -> Result<Vec<RawStrfSpectrum>> {
+        let mut paths = Vec::new();
+        let mut entries = tokio::fs::read_dir(&self.dir)
+            .await
+            .with_context(|| format!("Failed to read directory {}", self.dir.display()))?;
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            if path.extension().is_some_and(|ext| ext == "bin") {
+                paths.push(path);
+            }
+        }
+        paths.sort();
+
+        let mut spectra = Vec::new();
+        for path in paths {
+            let size = tokio::fs::metadata(&path).await?.len();
+            let offset = self.offsets.get(&path).copied().unwrap_or(0);
+            if size < HEADER_SIZE as u64 || size <= offset {
+                continue;
+            }
+            let read = load_strf_raw_from(&path, offset, self.options)
+                .await
+                .with_context(|| format!("Failed to read {}", path.display()))?;
+            let (new, params) = (read.spectra, read.params);
+            // Skipped blocks have to be accounted for even if there are no new spectra
+            self.offsets.insert(path.clone(), read.end);
+            if new.is_empty() {
+                continue;
+            }
+            match &self.params {
+                Some(expected) => ensure!(
+                    *expected == params,
+                    "{} has different parameters ({:?}) than the files before it ({:?})",
+                    path.display(),
+                    params,
+                    expected
+                ),
+                None => self.params = Some(params),
+            }
+            log::debug!("Read {} new spectra from {}", new.len(), path.display());
+            spectra.extend(new);
+        }
+        spectra.sort_unstable_by_key(|spec| spec.time);
+        Ok(spectra)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::spectrogram::StrfWriter;
+    use chrono::{DateTime, Duration, NaiveDate, Utc};
+    use ndarray::Array1;
+
+    fn test_start() -> DateTime<Utc> {
+        NaiveDate::from_ymd_opt(2024, 1, 1)
+            .unwrap()
+            .and_hms_opt(0, 0, 0)
+            .unwrap()
+            .and_utc()
+    }
+
+    async fn write_bin(path: &Path, start: DateTime<Utc>, nslices: usize, nchan: usize) {
+        let params = SpectrogramParams {
+            freq: 100e6,
+            bw: 100e3,
+            nchan,
+        };
+        let mut writer = StrfWriter::create(path, &params, nslices).await.unwrap();
+        for i in 0..nslices {
+            let time = start + Duration::seconds(i as i64);
+            writer
+                .write_spectrum(time, 1.0, Array1::zeros(nchan).view())
+                .await
+                .unwrap();
+        }
+        writer.finish().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn poll_returns_only_new_complete_spectra() {
+        let scratch = tempfile::tempdir().unwrap();
+        let full = scratch.path().join("full.bin");
+        write_bin(&full, test_start(), 5, 16).await;
+        let bytes = std::fs::read(&full).unwrap();
+        let block = HEADER_SIZE + 16 * 4;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("2024-01-01T00:00:00_000000.bin");
+        let mut follower = Follower::new(dir.path().to_path_buf(), LoadOptions::default());
+        assert!(follower.poll().await.unwrap().is_empty());
+        assert!(follower.params().is_none());
+
+        // Two and a half spectra written so far
+        std::fs::write(&path, &bytes[..2 * block + block / 2]).unwrap();
+        let spectra = follower.poll().await.unwrap();
+        assert_eq!(spectra.len(), 2);
+        assert_eq!(follower.files(), vec![path.clone()]);
+
+        std::fs::write(&path, &bytes).unwrap();
+        let spectra = follower.poll().await.unwrap();
+        assert_eq!(
+            spectra.iter().map(|s| s.time).collect::<Vec<_>>(),
+            (2..5)
+                .map(|i| test_start() + Duration::seconds(i))
+                .collect::<Vec<_>>()
+        );
+        assert!(follower.poll().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn poll_picks_up_new_files_and_rejects_mismatched_ones() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut follower = Follower::new(dir.path().to_path_buf(), LoadOptions::default());
+        write_bin(&dir.path().join("a.bin"), test_start(), 3, 16).await;
+        assert_eq!(follower.poll().await.unwrap().len(), 3);
+
+        let next = test_start() + Duration::seconds(3);
+        write_bin(&dir.path().join("b.bin"), next, 2, 16).await;
+        assert_eq!(follower.poll().await.unwrap().len(), 2);
+        assert_eq!(follower.files().len(), 2);
+
+        write_bin(&dir.path().join("c.bin"), next, 2, 8).await;
+        assert!(follower.poll().await.is_err());
+    }
+}
@@ -0,0 +1,220 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Memory-mapped strf `.bin` files, for spectrograms that don't fit into RAM.
+
+use std::{ops::Range, path::Path, sync::Arc};
+
+use anyhow::{Context, Result, ensure};
+use memmap2::Mmap;
+use ndarray::Array2;
+use rayon::prelude::*;
+
+use super::{
+    Block, HEADER_SIZE, LoadOptions, Spectrogram, Storage, StrfError, apply_freq_range, block_len,
+    check_block, overlaps_time_range, parse_header_bytes, spectrum_end, tolerate,
+};
+
+struct MappedFile {
+    mmap: Mmap,
+    /// Number of channels skipped at the start of each spectrum (from the frequency range filter)
+    channel_offset: usize,
+}
+
+/// Spectra backed by one or more memory-mapped `.bin` files.
+pub(super) struct MappedStrf {
+    files: Vec<Arc<MappedFile>>,
+    /// (file index, byte offset of the block) of each spectrum, sorted by time
+    slices: Vec<(usize, usize)>,
+    /// Minimum and maximum power of all spectra in dB
+    power_bounds: (f32, f32),
+}
+
+impl MappedStrf {
+    /// Maps a strf `.bin` file and reads all of its headers into a spectrogram.
+    ///
+    /// Only spectra overlapping `options.time_range` are included. Returns `None` if there are
+    /// none.
+    pub(super) fn open(path: &Path, options: LoadOptions) -> Result<Option<Spectrogram>> {
+        let file = std::fs::File::open(path)
+            .with_context(|| format!("Failed to open {}", path.display()))?;
+        // SAFETY: We only ever read from the map. If the file is truncated by another process while
+        // it is mapped, reads may fault; rffft only ever appends to its output files.
+        let mmap = unsafe { Mmap::map(&file) }.context("Failed to memory-map file")?;
+        let header_at = |offset: usize| {
+            let bytes =
+                mmap.get(offset..offset + HEADER_SIZE)
+                    .ok_or_else(|| StrfError::Truncated {
+                        path: path.to_path_buf(),
+                        offset: offset as u64,
+                        len: (mmap.len() - offset) as u64,
+                    })?;
+            parse_header_bytes(bytes).map_err(|e| StrfError::InvalidHeader {
+                path: path.to_path_buf(),
+                offset: offset as u64,
+                reason: format!("{e:#}"),
+            })
+        };
+
+        let first_header = header_at(0)?;
+        let (params, skip_before, _) = apply_freq_range(&first_header, options.freq_range);
+        ensure!(
+            params.nchan > 0,
+            "Frequency range filter excludes all channels: {:?}",
+            params
+        );
+
+        let block_size = block_len(first_header.nchan) as usize;
+        let n_blocks = mmap.len() / block_size;
+        if let (Some((from, to)), Some(last)) = (options.time_range, n_blocks.checked_sub(1)) {
+            // rffft writes spectra in order, so the first and last header give the file's span.
+            // If the last one is damaged, we find out below.
+            if let Ok(last) = header_at(last * block_size)
+                && (first_header.start_time >= to
+                    || spectrum_end(last.start_time, last.length) <= from)
+            {
+                log::debug!(
+                    "Skipping {}, which ends before {} or starts after {}",
+                    path.display(),
+                    from,
+                    to
+                );
+                return Ok(None);
+            }
+        }
+
+        let mut blocks = Vec::with_capacity(n_blocks);
+        let mut warnings = Vec::new();
+        let mut offset = 0;
+        while offset + block_size <= mmap.len() {
+            let header = header_at(offset);
+            match check_block(
+                path,
+                offset as u64,
+                &first_header,
+                header,
+                options.lenient,
+                &mut warnings,
+            )? {
+                Block::Spectrum(header) => {
+                    if overlaps_time_range(options.time_range, header.start_time, header.length) {
+                        blocks.push((header.start_time, header.length, offset));
+                    }
+                    offset += block_size;
+                }
+                Block::Skip(len) => offset += len as usize,
+            }
+        }
+        if offset < mmap.len() {
+            let error = StrfError::Truncated {
+                path: path.to_path_buf(),
+                offset: offset as u64,
+                len: (mmap.len() - offset) as u64,
+            };
+            tolerate(error, options.lenient, &mut warnings)?;
+        }
+        if blocks.is_empty() {
+            if options.time_range.is_some() {
+                return Ok(None);
+            }
+            Err(StrfError::Empty {
+                path: path.to_path_buf(),
+            })?;
+        }
+        blocks.sort_unstable_by_key(|&(time, _, _)| time);
+        log::debug!(
+            "Mapped {} spectra ({}/{} channels) from {}",
+            blocks.len(),
+            params.nchan,
+            first_header.nchan,
+            path.display()
+        );
+
+        let channel_offset = skip_before / 4;
+        // Read every spectrum once so that the bounds are exact, e.g. for blanking with the minimum
+        let (min, max) = blocks
+            .par_iter()
+            .map(|&(_, _, offset)| {
+                let start = offset + HEADER_SIZE + channel_offset * 4;
+                mmap[start..start + params.nchan * 4]
+                    .chunks_exact(4)
+                    .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+                    .fold((f32::INFINITY, f32::NEG_INFINITY), |(lo, hi), v| {
+                        (lo.min(v), hi.max(v))
+                    })
+            })
+            .reduce(
+                || (f32::INFINITY, f32::NEG_INFINITY),
+                |a, b| (a.0.min(b.0), a.1.max(b.1)),
+            );
+        let mapped = MappedStrf {
+            files: vec![Arc::new(MappedFile {
+                mmap,
+                channel_offset,
+            })],
+            slices: blocks.iter().map(|&(_, _, offset)| (0, offset)).collect(),
+            power_bounds: (to_db(min), to_db(max)),
+        };
+        let timestamps = blocks.iter().map(|&(time, _, _)| time).collect();
+        let lengths = blocks.iter().map(|&(_, length, _)| length).collect();
+        let mut spectrogram = Spectrogram::new(
+            params,
+            Storage::Mapped(Arc::new(mapped)),
+            timestamps,
+            lengths,
+        );
+        spectrogram.load_warnings = warnings;
+        Ok(Some(spectrogram))
+    }
+
+    /// Joins mapped spectra in order. The files stay mapped only once.
+    pub(super) fn concatenate(parts: &[&MappedStrf]) -> MappedStrf {
+        let mut files = Vec::new();
+        let mut slices = Vec::new();
+        let mut power_bounds = (f32::INFINITY, f32::NEG_INFINITY);
+        for part in parts {
+            power_bounds.0 = power_bounds.0.min(part.power_bounds.0);
+            power_bounds.1 = power_bounds.1.max(part.power_bounds.1);
+            let file_offset = files.len();
+            files.extend(part.files.iter().cloned());
+            slices.extend(
+                part.slices
+                    .iter()
+                    .map(|&(file, block)| (file + file_offset, block)),
+            );
+        }
+        MappedStrf {
+            files,
+            slices,
+            power_bounds,
+        }
+    }
+
+    /// Minimum and maximum power in dB, as found while mapping the files.
+    pub(super) fn power_bounds(&self) -> (f32, f32) {
+        self.power_bounds
+    }
+
+    /// Reads the given slices and channels, converted to dB.
+    pub(super) fn tile(&self, slices: Range<usize>, channels: Range<usize>) -> Array2<f32> {
+        let nslices = slices.len();
+        let nchan = channels.len();
+        let mut data = vec![0f32; nslices * nchan];
+        if nchan > 0 {
+            data.par_chunks_mut(nchan)
+                .zip(self.slices[slices].par_iter())
+                .for_each(|(out, &(file, offset))| {
+                    let file = &self.files[file];
+                    let start = offset + HEADER_SIZE + (file.channel_offset + channels.start) * 4;
+                    let bytes = &file.mmap[start..start + nchan * 4];
+                    for (v, b) in out.iter_mut().zip(bytes.chunks_exact(4)) {
+                        *v = to_db(f32::from_le_bytes([b[0], b[1], b[2], b[3]]));
+                    }
+                });
+        }
+        Array2::from_shape_vec((nslices, nchan), data).expect("tile shape matches buffer")
+    }
+}
+
+fn to_db(linear: f32) -> f32 {
+    10.0 * (linear + 1e-12f32).log10()
+}
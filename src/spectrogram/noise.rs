@@ -0,0 +1,265 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Estimating the noise floor around each cell of a spectrogram from a window over frequency and
+//! time, e.g. to flatten it before looking for weak signals (see `rsmedfilt`).
+
+use anyhow::{Result, ensure};
+use clap::ValueEnum;
+use ndarray::{Array2, ArrayView2};
+use rayon::prelude::*;
+use strum::Display;
+
+/// Ratio of the interquartile range to the standard deviation of Gaussian noise.
+const IQR_TO_SIGMA: f32 = 1.349;
+
+/// Sigma clipping stops after this many iterations, even if it hasn't converged.
+const MAX_CLIP_ITERATIONS: usize = 10;
+
+/// How the noise floor is estimated from the values in a window.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum, Display)]
+#[strum(serialize_all = "kebab-case")]
+pub enum FloorEstimator {
+    /// Median of the window
+    #[default]
+    Median,
+    /// A (usually lower) percentile of the window, which strong signals bias less
+    Percentile,
+    /// Mean of the window after iteratively rejecting outliers
+    SigmaClip,
+    /// Minimum of the window
+    Min,
+}
+
+/// The units of the values after subtracting the noise floor.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum, Display)]
+#[strum(serialize_all = "lowercase")]
+pub enum FloorUnits {
+    /// Difference to the floor in dB
+    #[default]
+    Db,
+    /// Difference to the floor in units of the local noise sigma, estimated from the interquartile
+    /// range of the window
+    Sigma,
+}
+
+/// A noise floor estimate over a window of `2 * half_slices + 1` spectra and
+/// `2 * half_chans + 1` channels around each cell.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NoiseFloor {
+    pub estimator: FloorEstimator,
+    /// Channels on each side of a cell that are part of its window
+    pub half_chans: usize,
+    /// Spectra before and after a cell that are part of its window
+    pub half_slices: usize,
+    /// Percentile (0 to 100) for [`FloorEstimator::Percentile`]
+    pub percentile: f32,
+    /// Rejection threshold in standard deviations for [`FloorEstimator::SigmaClip`]
+    pub clip_sigma: f32,
+}
+
+impl Default for NoiseFloor {
+    fn default() -> Self {
+        Self {
+            estimator: FloorEstimator::default(),
+            half_chans: 0,
+            half_slices: 0,
+            percentile: 25.0,
+            clip_sigma: 3.0,
+        }
+    }
+}
+
+impl NoiseFloor {
+    pub fn validate(&self) -> Result<()> {
+        ensure!(
+            (0.0..=100.0).contains(&self.percentile),
+            "The percentile must be between 0 and 100"
+        );
+        ensure!(self.clip_sigma > 0.0, "The clipping sigma must be positive");
+        Ok(())
+    }
+
+    /// Subtracts the noise floor from `data` (spectra × channels, in dB).
+    ///
+    /// At the edges of `data`, the first and last spectra and channels are repeated. To filter a
+    /// spectrogram in chunks, pass `half_slices` extra spectra on either side of each chunk and
+    /// drop their results.
+    ///
+    /// This is CPU-heavy, so async callers should run it in `spawn_blocking`.
+    pub fn subtract(&self, data: ArrayView2<f32>, units: FloorUnits) -> Array2<f32> {
+        let values: Vec<f32> = (0..data.nrows())
+            .into_par_iter()
+            .flat_map_iter(|slice| self.subtract_spectrum(data, slice, units))
+            .collect();
+        Array2::from_shape_vec(data.dim(), values).expect("One value per cell")
+    }
+
+    fn subtract_spectrum(
+        &self,
+        data: ArrayView2<f32>,
+        slice: usize,
+        units: FloorUnits,
+    ) -> Vec<f32> {
+        let (nslices, nchan) = data.dim();
+        let half_slices = self.half_slices as isize;
+        let rows: Vec<usize> = (-half_slices..=half_slices)
+            .map(|offset| (slice as isize + offset).clamp(0, nslices as isize - 1) as usize)
+            .collect();
+        let column = |chan: isize| {
+            let chan = chan.clamp(0, nchan as isize - 1) as usize;
+            rows.iter().map(move |&row| data[[row, chan]])
+        };
+
+        let half_chans = self.half_chans as isize;
+        let mut window: Vec<f32> = (-half_chans..=half_chans).flat_map(column).collect();
+        window.sort_unstable_by(f32::total_cmp);
+        let mut result = Vec::with_capacity(nchan);
+        for chan in 0..nchan as isize {
+            let mut value = data[[slice, chan as usize]] - self.estimate(&window);
+            if units == FloorUnits::Sigma {
+                let sigma = (quantile(&window, 75.0) - quantile(&window, 25.0)) / IQR_TO_SIGMA;
+                if sigma > 0.0 {
+                    value /= sigma;
+                }
+            }
+            result.push(value);
+
+            // Slide the window one channel up, keeping it sorted
+            for old in column(chan - half_chans) {
+                let index = window.partition_point(|v| v.total_cmp(&old).is_lt());
+                window.remove(index);
+            }
+            for new in column(chan + half_chans + 1) {
+                let index = window.partition_point(|v| v.total_cmp(&new).is_lt());
+                window.insert(index, new);
+            }
+        }
+        result
+    }
+
+    /// Estimates the floor from the sorted values of a window.
+    fn estimate(&self, sorted: &[f32]) -> f32 {
+        match self.estimator {
+            FloorEstimator::Median => quantile(sorted, 50.0),
+            FloorEstimator::Percentile => quantile(sorted, self.percentile),
+            FloorEstimator::SigmaClip => sigma_clipped_mean(sorted, self.clip_sigma),
+            FloorEstimator::Min => sorted[0],
+        }
+    }
+}
+
+/// The given percentile (0 to 100) of sorted values, rounded to the nearest rank.
+fn quantile(sorted: &[f32], percentile: f32) -> f32 {
+    sorted[(percentile / 100.0 * (sorted.len() - 1) as f32).round() as usize]
+}
+
+/// The mean of sorted values after repeatedly dropping those more than `clip_sigma` standard
+/// deviations from the mean of the rest.
+fn sigma_clipped_mean(sorted: &[f32], clip_sigma: f32) -> f32 {
+    let (mut lo, mut hi) = (0, sorted.len());
+    let mut mean = 0.0;
+    for _ in 0..MAX_CLIP_ITERATIONS {
+        let kept = &sorted[lo..hi];
+        let n = kept.len() as f32;
+        mean = kept.iter().sum::<f32>() / n;
+        let std = (kept.iter().map(|v| (v - mean).powi(2)).sum::<f32>() / n).sqrt();
+        let (min, max) = (mean - clip_sigma * std, mean + clip_sigma * std);
+        let bounds = (
+            sorted.partition_point(|&v| v < min),
+            sorted.partition_point(|&v| v <= max),
+        );
+        if bounds == (lo, hi) || bounds.0 >= bounds.1 {
+            break;
+        }
+        (lo, hi) = bounds;
+    }
+    mean
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ndarray::s;
+
+    /// Deterministic "noise" with an interquartile range of about 11.
+    fn noise(i: usize, f: usize) -> f32 {
+        ((i * 13 + f * 37) % 23) as f32
+    }
+
+    #[test]
+    fn estimators_on_window() {
+        let sorted = [1.0, 2.0, 2.0, 3.0, 3.0, 3.0, 4.0, 4.0, 5.0, 50.0];
+        let floor = |estimator, percentile| {
+            NoiseFloor {
+                estimator,
+                percentile,
+                ..Default::default()
+            }
+            .estimate(&sorted)
+        };
+
+        assert_eq!(floor(FloorEstimator::Median, 0.0), 3.0);
+        assert_eq!(floor(FloorEstimator::Percentile, 10.0), 2.0);
+        assert_eq!(floor(FloorEstimator::Min, 0.0), 1.0);
+        // With 10 values, no value can be more than 3 sigma from the mean
+        let clipped = NoiseFloor {
+            estimator: FloorEstimator::SigmaClip,
+            clip_sigma: 2.0,
+            ..Default::default()
+        };
+        assert!((clipped.estimate(&sorted) - 3.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn time_window_keeps_broadband_bursts() {
+        // A burst over all channels in spectrum 5
+        let data = Array2::from_shape_fn((10, 32), |(i, f)| {
+            (f % 3) as f32 + if i == 5 { 10.0 } else { 0.0 }
+        });
+        let mut floor = NoiseFloor {
+            half_chans: 3,
+            ..Default::default()
+        };
+
+        let result = floor.subtract(data.view(), FloorUnits::Db);
+        assert!(result[[5, 10]].abs() < 1e-6);
+
+        floor.half_slices = 2;
+        let result = floor.subtract(data.view(), FloorUnits::Db);
+        assert!((result[[5, 10]] - 10.0).abs() < 1e-6);
+        assert!(result[[2, 10]].abs() < 1e-6);
+    }
+
+    #[test]
+    fn sigma_units_are_independent_of_scale() {
+        let data = Array2::from_shape_fn((8, 64), |(i, f)| noise(i, f));
+        let floor = NoiseFloor {
+            half_chans: 8,
+            half_slices: 1,
+            ..Default::default()
+        };
+
+        let result = floor.subtract(data.view(), FloorUnits::Sigma);
+        let scaled = floor.subtract((&data * 3.0 + 7.0).view(), FloorUnits::Sigma);
+        for (a, b) in result.iter().zip(&scaled) {
+            assert!((a - b).abs() < 1e-4);
+        }
+        assert!(result.iter().all(|v| v.abs() < 3.0));
+    }
+
+    #[test]
+    fn chunks_with_overlap_match_whole() {
+        let data = Array2::from_shape_fn((20, 16), |(i, f)| noise(i, f) + i as f32);
+        let floor = NoiseFloor {
+            estimator: FloorEstimator::SigmaClip,
+            half_chans: 2,
+            half_slices: 3,
+            ..Default::default()
+        };
+        let whole = floor.subtract(data.view(), FloorUnits::Db);
+
+        // Spectra 8..12 with 3 spectra of context on either side
+        let chunk = floor.subtract(data.slice(s![5..15, ..]), FloorUnits::Db);
+        assert_eq!(chunk.slice(s![3..7, ..]), whole.slice(s![8..12, ..]));
+    }
+}
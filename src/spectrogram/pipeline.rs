@@ -0,0 +1,644 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Chains of processing stages that are applied to a spectrogram, e.g. to flatten the noise floor
+//! before looking for weak signals.
+//!
+//! Pipelines are written as stages separated by commas or newlines, each with optional
+//! parameters, e.g. `bandpass, background(window=20000), clip(min=-3), snr`. See [`Stage::HELP`].
+
+use std::{fmt, str::FromStr};
+
+use anyhow::{Context, Result, anyhow, bail, ensure};
+use itertools::Itertools;
+use ndarray::{Array1, Array2, Axis};
+use rayon::prelude::*;
+use serde_with::{DeserializeFromStr, SerializeDisplay};
+
+use super::{RfiOptions, Spectrogram, Storage};
+
+/// Scale factor from the median absolute deviation to the standard deviation of Gaussian noise.
+pub(crate) const MAD_TO_SIGMA: f32 = 1.4826;
+
+/// Default window of the background stage in Hz (the same as `rsmedfilt`'s).
+const DEFAULT_BACKGROUND_WINDOW_HZ: f32 = 20e3;
+
+/// One step of a [`Pipeline`].
+///
+/// Apart from [`Stage::Rebin`], all stages work on the power values in dB. The RFI mask set by
+/// [`Stage::Flag`] is kept by all stages except [`Stage::Rebin`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum Stage {
+    /// Subtracts a running percentile over `window_hz` of each spectrum, like `rsmedfilt` does
+    /// with the median.
+    Background { window_hz: f32, percentile: f32 },
+    /// Subtracts a percentile of each channel over all spectra, flattening the receiver's
+    /// bandpass.
+    Bandpass { percentile: f32 },
+    /// Subtracts the median level of each spectrum, smoothed with a running median over
+    /// `window_s` seconds, to remove gain variations over time.
+    Detrend { window_s: f32 },
+    /// Integrates spectra and merges channels, see [`Spectrogram::rebin_to`].
+    Rebin {
+        tint: Option<f32>,
+        chan_width: Option<f32>,
+    },
+    /// Limits the values to `[min, max]`.
+    Clip { min: Option<f32>, max: Option<f32> },
+    /// Converts each spectrum to units of its noise sigma above the median, with the sigma
+    /// estimated from the median absolute deviation.
+    Snr,
+    /// Flags RFI, adding to the spectrogram's mask without changing the power values.
+    Flag(RfiOptions),
+}
+
+impl Stage {
+    /// A summary of the stages and their parameters, e.g. for `--help`.
+    pub const HELP: &'static str = "\
+Stages (separated by commas or newlines, parameters in parentheses):
+  background(window=HZ, percentile=P)  Subtract a running percentile over WINDOW Hz of each
+                                       spectrum [default: window=20000, percentile=50]
+  bandpass(percentile=P)               Subtract a percentile of each channel over time
+                                       [default: percentile=50]
+  detrend(window=S)                    Subtract the median level of each spectrum, smoothed over
+                                       S seconds [default: window=0]
+  rebin(tint=S, chan_width=HZ)         Integrate spectra and merge channels (like rsrebin)
+  clip(min=MIN, max=MAX)               Limit the values to [MIN, MAX]
+  snr                                  Convert each spectrum to units of its noise sigma
+  flag(kurtosis=K, carrier=C,          Mask RFI: channels with outlying spectral kurtosis or a
+       impulse=I)                      persistent carrier, and spectra with broadband bursts,
+                                       with thresholds in sigma (0 disables a test)
+                                       [default: kurtosis=5, carrier=5, impulse=5]";
+
+    /// Whether the stage works on each spectrum on its own, so that the result for a spectrum
+    /// doesn't change when others are added.
+    pub fn is_per_spectrum(&self) -> bool {
+        match *self {
+            Stage::Background { .. } | Stage::Clip { .. } | Stage::Snr => true,
+            // Without smoothing, only the median of each spectrum is subtracted
+            Stage::Detrend { window_s } => window_s == 0.0,
+            Stage::Bandpass { .. } | Stage::Rebin { .. } | Stage::Flag(_) => false,
+        }
+    }
+
+    fn validate(&self) -> Result<()> {
+        match *self {
+            Stage::Background {
+                window_hz,
+                percentile,
+            } => {
+                ensure!(window_hz > 0.0, "The window must be positive");
+                validate_percentile(percentile)
+            }
+            Stage::Bandpass { percentile } => validate_percentile(percentile),
+            Stage::Detrend { window_s } => {
+                ensure!(window_s >= 0.0, "The window can't be negative");
+                Ok(())
+            }
+            Stage::Rebin { tint, chan_width } => {
+                ensure!(
+                    tint.is_some() || chan_width.is_some(),
+                    "Nothing to do, pass tint and/or chan_width"
+                );
+                ensure!(
+                    tint.is_none_or(|t| t > 0.0) && chan_width.is_none_or(|w| w > 0.0),
+                    "tint and chan_width must be positive"
+                );
+                Ok(())
+            }
+            Stage::Clip { min, max } => {
+                ensure!(
+                    min.is_some() || max.is_some(),
+                    "Nothing to do, pass min and/or max"
+                );
+                if let (Some(min), Some(max)) = (min, max) {
+                    ensure!(min <= max, "min is larger than max");
+                }
+                Ok(())
+            }
+            Stage::Snr => Ok(()),
+            Stage::Flag(RfiOptions {
+                kurtosis,
+                carrier,
+                impulse,
+            }) => {
+                ensure!(
+                    kurtosis >= 0.0 && carrier >= 0.0 && impulse >= 0.0,
+                    "The thresholds can't be negative"
+                );
+                Ok(())
+            }
+        }
+    }
+
+    /// Applies the stage, returning a new (in-memory) spectrogram.
+    ///
+    /// This is CPU-heavy, so async callers should run it in `spawn_blocking`.
+    pub fn apply(&self, spectrogram: &Spectrogram) -> Result<Spectrogram> {
+        match *self {
+            Stage::Rebin { tint, chan_width } => return spectrogram.rebin_to(tint, chan_width),
+            Stage::Flag(options) => {
+                let mut mask = options.flag(spectrogram);
+                let (slices, channels) = mask.count();
+                log::info!("Flagged {slices} spectra and {channels} channels as RFI");
+                if let Some(previous) = spectrogram.mask() {
+                    mask.union(previous);
+                }
+                let mut result = spectrogram.clone();
+                result.set_mask(Some(mask))?;
+                return Ok(result);
+            }
+            _ => {}
+        }
+
+        let mut data = spectrogram.data().as_standard_layout().into_owned();
+        match *self {
+            Stage::Background {
+                window_hz,
+                percentile,
+            } => {
+                let chan_width = spectrogram.bw / spectrogram.nchan as f32;
+                let half = (window_hz / chan_width / 2.0).round() as usize;
+                for_each_spectrum(&mut data, |spectrum| {
+                    let floor = running_percentile(spectrum, half, percentile);
+                    for (value, floor) in spectrum.iter_mut().zip(floor) {
+                        *value -= floor;
+                    }
+                });
+            }
+            Stage::Bandpass { percentile } => {
+                let levels: Vec<f32> = (0..data.ncols())
+                    .into_par_iter()
+                    .map(|chan| percentile_of(data.column(chan).to_vec(), percentile))
+                    .collect();
+                data -= &Array1::from(levels);
+            }
+            Stage::Detrend { window_s } => {
+                let levels: Vec<f32> = data
+                    .outer_iter()
+                    .map(|spectrum| percentile_of(spectrum.to_vec(), 50.0))
+                    .collect();
+                let length = percentile_of(spectrogram.lengths.clone(), 50.0);
+                let half = (window_s / length / 2.0).round() as usize;
+                let trend = running_percentile(&levels, half, 50.0);
+                data -= &Array1::from(trend).insert_axis(Axis(1));
+            }
+            Stage::Clip { min, max } => {
+                let (min, max) = (min.unwrap_or(f32::MIN), max.unwrap_or(f32::MAX));
+                data.mapv_inplace(|v| v.clamp(min, max));
+            }
+            Stage::Snr => for_each_spectrum(&mut data, |spectrum| {
+                let median = percentile_of(spectrum.to_vec(), 50.0);
+                let deviations = spectrum.iter().map(|v| (v - median).abs()).collect();
+                let sigma = percentile_of(deviations, 50.0) * MAD_TO_SIGMA;
+                for value in spectrum.iter_mut() {
+                    *value -= median;
+                    if sigma > 0.0 {
+                        *value /= sigma;
+                    }
+                }
+            }),
+            Stage::Rebin { .. } | Stage::Flag(_) => unreachable!(),
+        }
+        Ok(spectrogram.with_data(data))
+    }
+}
+
+fn validate_percentile(percentile: f32) -> Result<()> {
+    ensure!(
+        (0.0..=100.0).contains(&percentile),
+        "The percentile must be between 0 and 100"
+    );
+    Ok(())
+}
+
+impl FromStr for Stage {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim();
+        let (name, args) = match s.split_once('(') {
+            Some((name, args)) => {
+                let args = args
+                    .strip_suffix(')')
+                    .ok_or_else(|| anyhow!("Missing ')' in {s:?}"))?;
+                (name.trim(), args)
+            }
+            None => (s, ""),
+        };
+        let mut params = Params::parse(name, args)?;
+        let stage = match name {
+            "background" => Stage::Background {
+                window_hz: params
+                    .take("window")
+                    .unwrap_or(DEFAULT_BACKGROUND_WINDOW_HZ),
+                percentile: params.take("percentile").unwrap_or(50.0),
+            },
+            "bandpass" => Stage::Bandpass {
+                percentile: params.take("percentile").unwrap_or(50.0),
+            },
+            "detrend" => Stage::Detrend {
+                window_s: params.take("window").unwrap_or(0.0),
+            },
+            "rebin" => Stage::Rebin {
+                tint: params.take("tint"),
+                chan_width: params.take("chan_width"),
+            },
+            "clip" => Stage::Clip {
+                min: params.take("min"),
+                max: params.take("max"),
+            },
+            "snr" => Stage::Snr,
+            "flag" => {
+                let default = RfiOptions::default();
+                Stage::Flag(RfiOptions {
+                    kurtosis: params.take("kurtosis").unwrap_or(default.kurtosis),
+                    carrier: params.take("carrier").unwrap_or(default.carrier),
+                    impulse: params.take("impulse").unwrap_or(default.impulse),
+                })
+            }
+            _ => bail!(
+                "Unknown stage {name:?}, expected background, bandpass, detrend, rebin, clip, snr \
+                 or flag"
+            ),
+        };
+        params.finish()?;
+        stage
+            .validate()
+            .with_context(|| format!("Invalid {name}"))?;
+        Ok(stage)
+    }
+}
+
+impl fmt::Display for Stage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Only print the parameters that are set
+        let optional = |params: &[(&str, Option<f32>)]| {
+            params
+                .iter()
+                .filter_map(|(key, value)| value.map(|v| format!("{key}={v}")))
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        match *self {
+            Stage::Background {
+                window_hz,
+                percentile,
+            } => write!(f, "background(window={window_hz}, percentile={percentile})"),
+            Stage::Bandpass { percentile } => write!(f, "bandpass(percentile={percentile})"),
+            Stage::Detrend { window_s } => write!(f, "detrend(window={window_s})"),
+            Stage::Rebin { tint, chan_width } => write!(
+                f,
+                "rebin({})",
+                optional(&[("tint", tint), ("chan_width", chan_width)])
+            ),
+            Stage::Clip { min, max } => {
+                write!(f, "clip({})", optional(&[("min", min), ("max", max)]))
+            }
+            Stage::Snr => write!(f, "snr"),
+            Stage::Flag(RfiOptions {
+                kurtosis,
+                carrier,
+                impulse,
+            }) => write!(
+                f,
+                "flag(kurtosis={kurtosis}, carrier={carrier}, impulse={impulse})"
+            ),
+        }
+    }
+}
+
+/// The `key=value` parameters of a stage.
+struct Params<'a> {
+    stage: &'a str,
+    values: Vec<(&'a str, f32)>,
+}
+
+impl<'a> Params<'a> {
+    fn parse(stage: &'a str, args: &'a str) -> Result<Self> {
+        let values =
+            args.split(',')
+                .map(str::trim)
+                .filter(|arg| !arg.is_empty())
+                .map(|arg| {
+                    let (key, value) = arg
+                        .split_once('=')
+                        .ok_or_else(|| anyhow!("Expected key=value in {stage}, got {arg:?}"))?;
+                    let value = value.trim().parse().with_context(|| {
+                        format!("Invalid value for {key} in {stage}: {value:?}")
+                    })?;
+                    Ok((key.trim(), value))
+                })
+                .collect::<Result<_>>()?;
+        Ok(Self { stage, values })
+    }
+
+    fn take(&mut self, key: &str) -> Option<f32> {
+        let index = self.values.iter().position(|(k, _)| *k == key)?;
+        Some(self.values.remove(index).1)
+    }
+
+    /// Fails if there are parameters that the stage doesn't know.
+    fn finish(self) -> Result<()> {
+        match self.values.first() {
+            Some((key, _)) => bail!("Unknown parameter {key:?} for {}", self.stage),
+            None => Ok(()),
+        }
+    }
+}
+
+/// A chain of [`Stage`]s that are applied one after the other.
+///
+/// It is (de)serialized as its text form, e.g. `bandpass(percentile=50), snr`.
+#[derive(Debug, Clone, Default, PartialEq, SerializeDisplay, DeserializeFromStr)]
+pub struct Pipeline {
+    pub stages: Vec<Stage>,
+}
+
+impl Pipeline {
+    pub fn is_empty(&self) -> bool {
+        self.stages.is_empty()
+    }
+
+    /// Whether all stages work on each spectrum on its own (see [`Stage::is_per_spectrum`]), so
+    /// that spectra which are appended later can be processed without the earlier ones.
+    pub fn is_per_spectrum(&self) -> bool {
+        self.stages.iter().all(Stage::is_per_spectrum)
+    }
+
+    /// Applies all stages in order. An empty pipeline returns the spectrogram as it is.
+    ///
+    /// This is CPU-heavy, so async callers should run it in `spawn_blocking`.
+    pub fn apply(&self, spectrogram: &Spectrogram) -> Result<Spectrogram> {
+        let mut result = spectrogram.clone();
+        for stage in &self.stages {
+            log::debug!("Applying {stage}");
+            result = stage
+                .apply(&result)
+                .with_context(|| format!("Failed to apply {stage}"))?;
+        }
+        Ok(result)
+    }
+}
+
+impl FromStr for Pipeline {
+    type Err = anyhow::Error;
+
+    /// Parses stages separated by commas or newlines. `#` starts a comment that runs to the end of
+    /// the line.
+    fn from_str(s: &str) -> Result<Self> {
+        let mut stages = Vec::new();
+        let mut stage = String::new();
+        let mut depth = 0;
+        for line in s.lines() {
+            let line = line.split('#').next().unwrap_or_default();
+            for c in line.chars().chain(['\n']) {
+                match c {
+                    '(' => depth += 1,
+                    ')' => depth -= 1,
+                    _ => {}
+                }
+                if (c == ',' && depth == 0) || c == '\n' {
+                    if !stage.trim().is_empty() {
+                        stages.push(stage.parse()?);
+                    }
+                    stage.clear();
+                } else {
+                    stage.push(c);
+                }
+            }
+        }
+        Ok(Pipeline { stages })
+    }
+}
+
+impl fmt::Display for Pipeline {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.stages.iter().map(Stage::to_string).join(", "))
+    }
+}
+
+impl Spectrogram {
+    /// A copy of the spectrogram with other power values of the same shape.
+    fn with_data(&self, data: Array2<f32>) -> Spectrogram {
+        let mut result = Spectrogram::new(
+            self.params(),
+            Storage::Memory(data.into_shared()),
+            self.timestamps.clone(),
+            self.lengths.clone(),
+        );
+        result.load_warnings = self.load_warnings.clone();
+        result.mask = self.mask.clone();
+        result
+    }
+}
+
+/// Calls `f` with each spectrum (row) of `data` in parallel.
+fn for_each_spectrum(data: &mut Array2<f32>, f: impl Fn(&mut [f32]) + Send + Sync) {
+    let nchan = data.ncols();
+    data.as_slice_mut()
+        .expect("Data is in standard layout")
+        .par_chunks_mut(nchan)
+        .for_each(f);
+}
+
+/// The given percentile (0 to 100) of the values, rounded to the nearest rank.
+pub(crate) fn percentile_of(mut values: Vec<f32>, percentile: f32) -> f32 {
+    let rank = (percentile / 100.0 * (values.len() - 1) as f32).round() as usize;
+    *values.select_nth_unstable_by(rank, f32::total_cmp).1
+}
+
+/// The percentile (0 to 100) of a window of `2 * half + 1` values around each value. At the edges,
+/// the first and last values are repeated (like `mode="nearest"` in scipy).
+pub(super) fn running_percentile(values: &[f32], half: usize, percentile: f32) -> Vec<f32> {
+    let last = values.len() as isize - 1;
+    let at = |i: isize| values[i.clamp(0, last) as usize];
+    let half = half as isize;
+    let rank = (percentile / 100.0 * (2 * half) as f32).round() as usize;
+
+    let mut window: Vec<f32> = (-half..=half).map(at).collect();
+    window.sort_unstable_by(f32::total_cmp);
+    let mut result = Vec::with_capacity(values.len());
+    for i in 0..=last {
+        result.push(window[rank]);
+        // Slide the window one value to the right, keeping it sorted
+        let (old, new) = (at(i - half), at(i + half + 1));
+        let index = window.partition_point(|v| v.total_cmp(&old).is_lt());
+        window.remove(index);
+        let index = window.partition_point(|v| v.total_cmp(&new).is_lt());
+        window.insert(index, new);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::spectrogram::{RawStrfSpectrum, SpectrogramParams};
+    use chrono::{DateTime, Duration, Utc};
+
+    /// A spectrogram with the given power (in dB) per slice and channel.
+    fn make_spec(
+        power_db: impl Fn(usize, usize) -> f32,
+        nslices: usize,
+        nchan: usize,
+    ) -> Spectrogram {
+        let start = DateTime::<Utc>::from_timestamp(1_700_000_000, 0).unwrap();
+        let spectra = (0..nslices)
+            .map(|i| RawStrfSpectrum {
+                time: start + Duration::seconds(i as i64),
+                length_s: 1.0,
+                power_linear: (0..nchan)
+                    .map(|f| 10f32.powf(power_db(i, f) / 10.0))
+                    .collect(),
+            })
+            .collect();
+        let params = SpectrogramParams {
+            freq: 437e6,
+            bw: nchan as f32 * 1e3,
+            nchan,
+        };
+        Spectrogram::from_raw(spectra, params).unwrap()
+    }
+
+    #[test]
+    fn pipeline_parses_and_prints() {
+        let pipeline: Pipeline =
+            "bandpass,\n background(window=5000) # comment\nclip(min=-3, max=20),snr"
+                .parse()
+                .unwrap();
+        assert_eq!(
+            pipeline.stages,
+            vec![
+                Stage::Bandpass { percentile: 50.0 },
+                Stage::Background {
+                    window_hz: 5000.0,
+                    percentile: 50.0
+                },
+                Stage::Clip {
+                    min: Some(-3.0),
+                    max: Some(20.0)
+                },
+                Stage::Snr,
+            ]
+        );
+        assert_eq!(
+            pipeline.to_string(),
+            "bandpass(percentile=50), background(window=5000, percentile=50), clip(min=-3, max=20), snr"
+        );
+        assert_eq!(pipeline.to_string().parse::<Pipeline>().unwrap(), pipeline);
+        assert!("".parse::<Pipeline>().unwrap().is_empty());
+    }
+
+    #[test]
+    fn pipeline_rejects_invalid_stages() {
+        for invalid in [
+            "median",
+            "background(window=-1)",
+            "background(percentile=101)",
+            "bandpass(width=3)",
+            "clip",
+            "clip(min=5, max=1)",
+            "rebin",
+            "snr(",
+            "detrend(window=abc)",
+        ] {
+            assert!(invalid.parse::<Pipeline>().is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn per_spectrum_pipelines_process_appended_spectra_alone() {
+        let spec = make_spec(|i, f| ((i * 7 + f * 37) % 23) as f32, 6, 64);
+        let pipeline: Pipeline = "background(window=9000), detrend, snr".parse().unwrap();
+        assert!(pipeline.is_per_spectrum());
+        let full = pipeline.apply(&spec).unwrap();
+        let tail = pipeline.apply(&spec.slices_from(4)).unwrap();
+        assert_eq!(tail.data(), full.slices_from(4).data());
+
+        for global in ["bandpass", "detrend(window=10)", "flag", "rebin(tint=2)"] {
+            let pipeline: Pipeline = format!("snr, {global}").parse().unwrap();
+            assert!(!pipeline.is_per_spectrum(), "{global}");
+        }
+    }
+
+    #[test]
+    fn running_percentile_matches_sorting() {
+        let values: Vec<f32> = (0..50).map(|i| ((i * 37) % 23) as f32).collect();
+        let half = 3;
+        let result = running_percentile(&values, half, 25.0);
+        for (i, &value) in result.iter().enumerate() {
+            let window: Vec<f32> = (i as isize - 3..=i as isize + 3)
+                .map(|j| values[j.clamp(0, 49) as usize])
+                .collect();
+            assert_eq!(value, percentile_of(window, 25.0), "at {i}");
+        }
+    }
+
+    #[test]
+    fn background_removes_sloped_floor_but_keeps_signal() {
+        let spec = make_spec(
+            |_, f| f as f32 * 0.1 + if f == 32 { 10.0 } else { 0.0 },
+            4,
+            64,
+        );
+        let stage: Stage = "background(window=9000)".parse().unwrap();
+        let result = stage.apply(&spec).unwrap();
+
+        assert_ne!(result.id, spec.id);
+        // The signal raises the floor around it by one step
+        assert!((result.value(1, 32) - 9.9).abs() < 1e-3);
+        assert!(result.value(1, 20).abs() < 1e-3);
+    }
+
+    #[test]
+    fn bandpass_and_detrend_flatten_offsets() {
+        let spec = make_spec(|i, f| (f % 4) as f32 + i as f32, 5, 16);
+
+        let flat = Stage::Bandpass { percentile: 50.0 }.apply(&spec).unwrap();
+        for i in 0..5 {
+            assert!((flat.value(i, 1) - flat.value(i, 2)).abs() < 1e-3);
+        }
+        let detrended = Stage::Detrend { window_s: 0.0 }.apply(&spec).unwrap();
+        for f in 0..16 {
+            assert!((detrended.value(0, f) - detrended.value(4, f)).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn flag_sets_a_mask_that_later_stages_keep() {
+        let spec = make_spec(
+            |_, f| ((f * 37) % 23) as f32 / 23.0 + if f == 7 { 20.0 } else { 0.0 },
+            8,
+            64,
+        );
+        let pipeline: Pipeline = "flag(kurtosis=0, impulse=0), snr".parse().unwrap();
+        assert_eq!(
+            pipeline.to_string(),
+            "flag(kurtosis=0, carrier=5, impulse=0), snr"
+        );
+        let result = pipeline.apply(&spec).unwrap();
+
+        let mask = result.mask().unwrap();
+        assert!(mask.channels[7]);
+        assert_eq!(mask.count(), (0, 1));
+        assert!(spec.mask().is_none());
+    }
+
+    #[test]
+    fn snr_scales_by_noise_sigma() {
+        let spec = make_spec(
+            |_, f| ((f * 37) % 23) as f32 + if f == 5 { 100.0 } else { 0.0 },
+            2,
+            64,
+        );
+        let pipeline: Pipeline = "snr, clip(max=5)".parse().unwrap();
+        let result = pipeline.apply(&spec).unwrap();
+
+        let spectrum = result.data().row(0).to_vec();
+        assert!(percentile_of(spectrum.clone(), 50.0).abs() < 1e-4);
+        let deviations = spectrum.iter().map(|v| v.abs()).collect();
+        assert!((percentile_of(deviations, 50.0) * MAD_TO_SIGMA - 1.0).abs() < 1e-4);
+        assert_eq!(result.value(0, 5), 5.0);
+        assert_eq!(result.power_bounds.1, 5.0);
+    }
+}
@@ -0,0 +1,389 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Level-of-detail pyramid of decimated spectrograms, so zoomed-out views don't need every spectrum
+//! on the GPU.
+
+use std::ops::Range;
+
+use chrono::{DateTime, Utc};
+use ndarray::{Array2, ArrayView2, s};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use strum::{Display, EnumIter, IntoStaticStr, VariantArray};
+
+use super::{STREAM_CHUNK_SLICES, Spectrogram, SpectrogramParams, Storage};
+
+/// Levels built from memory-mapped spectrograms are held in memory, so the first level skips
+/// ahead until it fits into this many bytes (per decimation method).
+const MAX_MAPPED_LEVEL_BYTES: usize = 256 << 20;
+
+/// How neighbouring cells are combined when decimating a spectrogram.
+#[derive(
+    Debug,
+    Default,
+    Display,
+    Clone,
+    Copy,
+    Serialize,
+    Deserialize,
+    PartialEq,
+    Eq,
+    EnumIter,
+    IntoStaticStr,
+    VariantArray,
+)]
+pub enum Decimation {
+    /// Keep the strongest cell, so narrow signals stay visible when zoomed out
+    #[default]
+    Max,
+    /// Average the linear power, which represents the noise floor more faithfully
+    Mean,
+}
+
+/// One decimated level of a [`Pyramid`].
+#[derive(Clone, PartialEq)]
+pub struct Level {
+    /// Number of original slices per slice of this level
+    pub time_factor: usize,
+    /// Number of original channels per channel of this level
+    pub freq_factor: usize,
+    max: Spectrogram,
+    mean: Spectrogram,
+}
+
+impl Level {
+    pub fn spectrogram(&self, decimation: Decimation) -> &Spectrogram {
+        match decimation {
+            Decimation::Max => &self.max,
+            Decimation::Mean => &self.mean,
+        }
+    }
+}
+
+/// Decimated copies of a spectrogram at successively coarser resolutions.
+///
+/// Each level halves the slices and channels of the previous one, as long as that axis is still
+/// larger than the minimum size. Both max- and mean-decimated versions are kept for each level.
+#[derive(Clone, PartialEq)]
+pub struct Pyramid {
+    levels: Vec<Level>,
+    min_size: usize,
+}
+
+impl Pyramid {
+    /// Axes are no longer halved once they are at most this long.
+    pub const DEFAULT_MIN_SIZE: usize = 1024;
+
+    /// Builds the pyramid, reading the source spectrogram in tiles.
+    ///
+    /// This is CPU-heavy for large inputs, so async callers should run it in `spawn_blocking`.
+    pub fn build(spectrogram: &Spectrogram, min_size: usize) -> Pyramid {
+        let mut pyramid = Pyramid {
+            levels: Vec::new(),
+            min_size,
+        };
+        pyramid.add_levels(spectrogram);
+        pyramid
+    }
+
+    /// Updates the pyramid after the slices of `spectrogram` from `first` onwards were replaced or
+    /// appended.
+    ///
+    /// Only the decimated slices covering changed spectra are recomputed, and coarser levels are
+    /// added once the spectrogram has grown enough. The levels keep their ids.
+    pub fn extend(&mut self, spectrogram: &Spectrogram, first: usize) {
+        // First slice of the source that changed. Only the last slice of each level can be
+        // partially filled, so this is at most one slice before the old end of each level.
+        let mut changed_from = first;
+        for i in 0..self.levels.len() {
+            let (coarser, finer) = self.levels.split_at_mut(i);
+            let level = &mut finer[0];
+            let (src_max, src_mean, prev_factors) = match coarser.last() {
+                None => (spectrogram, spectrogram, (1, 1)),
+                Some(prev) => (&prev.max, &prev.mean, (prev.time_factor, prev.freq_factor)),
+            };
+            let time_step = level.time_factor / prev_factors.0;
+            let freq_step = level.freq_factor / prev_factors.1;
+            let first = changed_from / time_step;
+            let rows = first..src_max.nslices.div_ceil(time_step);
+            for (dst, src, decimation) in [
+                (&mut level.max, src_max, Decimation::Max),
+                (&mut level.mean, src_mean, Decimation::Mean),
+            ] {
+                let (data, timestamps, lengths) =
+                    decimate_rows(src, time_step, freq_step, decimation, rows.clone());
+                dst.splice(first, data.view(), &timestamps, &lengths)
+                    .expect("pyramid levels are held in memory");
+            }
+            changed_from = first;
+        }
+        self.add_levels(spectrogram);
+    }
+
+    /// Adds levels below the coarsest one until both axes are at most `min_size` long.
+    fn add_levels(&mut self, spectrogram: &Spectrogram) {
+        let step = |n: usize| if n > self.min_size { 2 } else { 1 };
+
+        let (mut time_factor, mut freq_factor) = match self.levels.last() {
+            Some(last) => (
+                last.time_factor * step(last.max.nslices),
+                last.freq_factor * step(last.max.nchan),
+            ),
+            None => (step(spectrogram.nslices), step(spectrogram.nchan)),
+        };
+        if self.levels.is_empty() && spectrogram.is_mapped() {
+            let level_bytes = |tf: usize, ff: usize| {
+                spectrogram.nslices.div_ceil(tf) * spectrogram.nchan.div_ceil(ff) * 4
+            };
+            while level_bytes(time_factor, freq_factor) > MAX_MAPPED_LEVEL_BYTES {
+                let tf = time_factor * step(spectrogram.nslices / time_factor);
+                let ff = freq_factor * step(spectrogram.nchan / freq_factor);
+                if (tf, ff) == (time_factor, freq_factor) {
+                    break;
+                }
+                (time_factor, freq_factor) = (tf, ff);
+            }
+        }
+
+        loop {
+            let (src_max, src_mean, prev_factors) = match self.levels.last() {
+                None => (spectrogram, spectrogram, (1, 1)),
+                Some(prev) => (&prev.max, &prev.mean, (prev.time_factor, prev.freq_factor)),
+            };
+            if (time_factor, freq_factor) == prev_factors {
+                break;
+            }
+            let (time_step, freq_step) =
+                (time_factor / prev_factors.0, freq_factor / prev_factors.1);
+            let level = Level {
+                time_factor,
+                freq_factor,
+                max: decimate(src_max, time_step, freq_step, Decimation::Max),
+                mean: decimate(src_mean, time_step, freq_step, Decimation::Mean),
+            };
+            log::debug!(
+                "Built pyramid level {}x{} ({} slices, {} channels)",
+                level.time_factor,
+                level.freq_factor,
+                level.max.nslices,
+                level.max.nchan
+            );
+            time_factor = level.time_factor * step(level.max.nslices);
+            freq_factor = level.freq_factor * step(level.max.nchan);
+            self.levels.push(level);
+        }
+    }
+
+    /// Returns the levels from finest to coarsest.
+    pub fn levels(&self) -> &[Level] {
+        &self.levels
+    }
+
+    /// Picks the coarsest level that still has at least one slice and one channel per pixel,
+    /// given how many original slices and channels fall onto one pixel.
+    ///
+    /// Returns `None` if the full resolution spectrogram should be used.
+    pub fn select(&self, slices_per_pixel: f32, channels_per_pixel: f32) -> Option<&Level> {
+        self.levels.iter().rev().find(|level| {
+            level.time_factor as f32 <= slices_per_pixel.max(1.0)
+                && level.freq_factor as f32 <= channels_per_pixel.max(1.0)
+        })
+    }
+}
+
+/// Combines blocks of `time_factor` slices by `freq_factor` channels into single cells.
+///
+/// Each decimated slice starts at the first slice of its block and lasts until the end of the
+/// last one, so gaps inside a block are absorbed while gaps between blocks are preserved.
+fn decimate(
+    spectrogram: &Spectrogram,
+    time_factor: usize,
+    freq_factor: usize,
+    decimation: Decimation,
+) -> Spectrogram {
+    let (data, timestamps, lengths) = decimate_rows(
+        spectrogram,
+        time_factor,
+        freq_factor,
+        decimation,
+        0..spectrogram.nslices.div_ceil(time_factor),
+    );
+    Spectrogram::new(
+        SpectrogramParams {
+            nchan: data.ncols(),
+            ..spectrogram.params()
+        },
+        Storage::Memory(data.into_shared()),
+        timestamps,
+        lengths,
+    )
+}
+
+/// Computes the given decimated slices, see [`decimate`].
+fn decimate_rows(
+    spectrogram: &Spectrogram,
+    time_factor: usize,
+    freq_factor: usize,
+    decimation: Decimation,
+    rows: Range<usize>,
+) -> (Array2<f32>, Vec<DateTime<Utc>>, Vec<f32>) {
+    let nchan = spectrogram.nchan.div_ceil(freq_factor);
+    let rows_per_chunk = (STREAM_CHUNK_SLICES / time_factor).max(1);
+    let slices = rows.start * time_factor..(rows.end * time_factor).min(spectrogram.nslices);
+
+    let mut data = Vec::with_capacity(rows.len() * nchan);
+    for first in slices.clone().step_by(rows_per_chunk * time_factor) {
+        let last = (first + rows_per_chunk * time_factor).min(slices.end);
+        let tile = spectrogram.tile(first..last, 0..spectrogram.nchan);
+        let chunk: Vec<Vec<f32>> = (0..(last - first).div_ceil(time_factor))
+            .into_par_iter()
+            .map(|row| {
+                let block = tile.slice(s![
+                    row * time_factor..((row + 1) * time_factor).min(tile.nrows()),
+                    ..
+                ]);
+                (0..nchan)
+                    .map(|chan| {
+                        let cells = block.slice(s![
+                            ..,
+                            chan * freq_factor..((chan + 1) * freq_factor).min(spectrogram.nchan)
+                        ]);
+                        combine(cells, decimation)
+                    })
+                    .collect()
+            })
+            .collect();
+        data.extend(chunk.into_iter().flatten());
+    }
+
+    let (timestamps, lengths) = slices
+        .clone()
+        .step_by(time_factor)
+        .map(|first| {
+            let last = (first + time_factor).min(slices.end) - 1;
+            let start = spectrogram.timestamps[first];
+            let length =
+                (spectrogram.timestamps[last] - start).as_seconds_f32() + spectrogram.lengths[last];
+            (start, length)
+        })
+        .unzip();
+
+    let data = Array2::from_shape_vec((rows.len(), nchan), data)
+        .expect("decimated data matches its shape");
+    (data, timestamps, lengths)
+}
+
+/// Combines a block of cells (in dB) into one.
+pub(super) fn combine(cells: ArrayView2<f32>, decimation: Decimation) -> f32 {
+    match decimation {
+        Decimation::Max => cells.fold(f32::NEG_INFINITY, |acc, &v| acc.max(v)),
+        Decimation::Mean => {
+            // Average in linear power, not in dB
+            let sum: f32 = cells.iter().map(|v| 10.0_f32.powf(v / 10.0)).sum();
+            10.0 * (sum / cells.len() as f32).log10()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+    use ndarray::ArcArray2;
+
+    fn make_spec(nslices: usize, nchan: usize) -> Spectrogram {
+        let start = DateTime::<Utc>::from_timestamp(1_700_000_000, 0).unwrap();
+        let data = ArcArray2::from_shape_fn((nslices, nchan), |(t, f)| (t * nchan + f) as f32);
+        Spectrogram::new(
+            SpectrogramParams {
+                freq: 437e6,
+                bw: 100e3,
+                nchan,
+            },
+            Storage::Memory(data),
+            (0..nslices)
+                .map(|i| start + Duration::seconds(i as i64))
+                .collect(),
+            vec![1.0; nslices],
+        )
+    }
+
+    #[test]
+    fn build_halves_until_min_size() {
+        let spec = make_spec(40, 12);
+        let pyramid = Pyramid::build(&spec, 8);
+
+        let shapes: Vec<_> = pyramid
+            .levels()
+            .iter()
+            .map(|l| {
+                let s = l.spectrogram(Decimation::Max);
+                (l.time_factor, l.freq_factor, s.nslices, s.nchan)
+            })
+            .collect();
+        assert_eq!(shapes, vec![(2, 2, 20, 6), (4, 2, 10, 6), (8, 2, 5, 6)]);
+        assert!(Pyramid::build(&spec, 64).levels().is_empty());
+    }
+
+    #[test]
+    fn decimate_combines_blocks() {
+        let spec = make_spec(3, 4);
+        let max = decimate(&spec, 2, 2, Decimation::Max);
+        assert_eq!(max.data(), ndarray::arr2(&[[5.0, 7.0], [9.0, 11.0]]));
+
+        let mean = decimate(&spec, 2, 2, Decimation::Mean);
+        let linear = [0.0f32, 1.0, 4.0, 5.0].map(|v| 10.0_f32.powf(v / 10.0));
+        let expected = 10.0 * (linear.iter().sum::<f32>() / 4.0).log10();
+        assert!((mean.value(0, 0) - expected).abs() < 1e-4);
+        assert!(mean.value(0, 0) < max.value(0, 0));
+    }
+
+    #[test]
+    fn decimate_spans_block_times() {
+        let mut spec = make_spec(5, 2);
+        // Leave a gap before the last slice
+        spec.timestamps[4] += Duration::seconds(10);
+        let dec = decimate(&spec, 2, 1, Decimation::Max);
+
+        assert_eq!(
+            dec.timestamps,
+            vec![spec.timestamps[0], spec.timestamps[2], spec.timestamps[4]]
+        );
+        assert_eq!(dec.lengths, vec![2.0, 2.0, 1.0]);
+        assert_eq!(dec.end_time(), spec.end_time());
+        assert_ne!(dec.id, spec.id);
+    }
+
+    #[test]
+    fn extend_matches_rebuild() {
+        let mut pyramid = Pyramid::build(&make_spec(41, 12), 8);
+        let spec = make_spec(80, 12);
+        pyramid.extend(&spec, 41);
+
+        let rebuilt = Pyramid::build(&spec, 8);
+        assert_eq!(pyramid.levels().len(), rebuilt.levels().len());
+        for (level, expected) in pyramid.levels().iter().zip(rebuilt.levels()) {
+            assert_eq!(level.time_factor, expected.time_factor);
+            for decimation in [Decimation::Max, Decimation::Mean] {
+                let (a, b) = (
+                    level.spectrogram(decimation),
+                    expected.spectrogram(decimation),
+                );
+                assert_eq!(a.data(), b.data());
+                assert_eq!(a.timestamps, b.timestamps);
+                assert_eq!(a.lengths, b.lengths);
+            }
+        }
+    }
+
+    #[test]
+    fn select_picks_coarsest_fitting_level() {
+        let spec = make_spec(64, 64);
+        let pyramid = Pyramid::build(&spec, 8);
+
+        assert!(pyramid.select(1.0, 1.0).is_none());
+        assert!(pyramid.select(1.5, 100.0).is_none());
+        assert_eq!(pyramid.select(5.0, 3.0).map(|l| l.time_factor), Some(2));
+        assert_eq!(pyramid.select(100.0, 100.0).map(|l| l.time_factor), Some(8));
+    }
+}
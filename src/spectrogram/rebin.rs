@@ -0,0 +1,212 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Rebinning spectrograms to a coarser time and frequency resolution, e.g. to bring out weak,
+//! slowly drifting signals or to shrink files.
+
+use std::ops::Range;
+
+use anyhow::{Result, ensure};
+use ndarray::{Array2, s};
+use rayon::prelude::*;
+
+use super::{Spectrogram, SpectrogramParams, Storage, pyramid};
+
+impl Spectrogram {
+    /// Integrates `time_factor` consecutive spectra and merges `freq_factor` neighbouring channels,
+    /// averaging in linear power.
+    ///
+    /// Spectra are only integrated within contiguous stretches of data, so the last spectrum
+    /// before a gap (and at the end) may cover fewer than `time_factor` spectra. Its timestamp and
+    /// length always span exactly the spectra it was integrated from. Channels that don't fill a
+    /// whole bin at the upper edge are dropped.
+    pub fn rebin(&self, time_factor: usize, freq_factor: usize) -> Result<Spectrogram> {
+        ensure!(
+            time_factor > 0 && freq_factor > 0,
+            "Rebinning factors must be at least 1"
+        );
+        ensure!(
+            freq_factor <= self.nchan,
+            "Cannot merge {} channels of a spectrogram with only {}",
+            freq_factor,
+            self.nchan
+        );
+
+        let nchan = self.nchan / freq_factor;
+        let chan_width = self.bw / self.nchan as f32;
+        let bw = (nchan * freq_factor) as f32 * chan_width;
+        let params = SpectrogramParams {
+            freq: self.freq - self.bw / 2.0 + bw / 2.0,
+            bw,
+            nchan,
+        };
+
+        let blocks = self.time_blocks(time_factor);
+        let rows: Vec<Vec<f32>> = blocks
+            .par_iter()
+            .map(|block| {
+                let tile = self.tile(block.clone(), 0..nchan * freq_factor);
+                (0..nchan)
+                    .map(|chan| {
+                        let cells =
+                            tile.slice(s![.., chan * freq_factor..(chan + 1) * freq_factor]);
+                        pyramid::combine(cells, pyramid::Decimation::Mean)
+                    })
+                    .collect()
+            })
+            .collect();
+        let data = Array2::from_shape_vec((blocks.len(), nchan), rows.concat())?;
+
+        let (timestamps, lengths) = blocks
+            .iter()
+            .map(|block| {
+                let (first, last) = (block.start, block.end - 1);
+                let start = self.timestamps[first];
+                let length = (self.timestamps[last] - start).as_seconds_f32() + self.lengths[last];
+                (start, length)
+            })
+            .unzip();
+
+        Ok(Spectrogram::new(
+            params,
+            Storage::Memory(data.into_shared()),
+            timestamps,
+            lengths,
+        ))
+    }
+
+    /// Rebins to the given spectrum length (in seconds) and channel width (in Hz), see
+    /// [`Spectrogram::rebin`] and [`Spectrogram::rebin_factors`].
+    pub fn rebin_to(
+        &self,
+        spectrum_length: Option<f32>,
+        channel_width: Option<f32>,
+    ) -> Result<Spectrogram> {
+        let (time_factor, freq_factor) = self.rebin_factors(spectrum_length, channel_width);
+        log::debug!("Rebinning by {time_factor} in time and {freq_factor} in frequency");
+        self.rebin(time_factor, freq_factor)
+    }
+
+    /// The rebinning factors that come closest to the given spectrum length (in seconds) and
+    /// channel width (in Hz). Axes without a target get a factor of 1.
+    pub fn rebin_factors(
+        &self,
+        spectrum_length: Option<f32>,
+        channel_width: Option<f32>,
+    ) -> (usize, usize) {
+        let factor = |target: f32, current: f32| ((target / current).round() as usize).max(1);
+        // Spectrum lengths can differ slightly between files, so use the typical one
+        let mut lengths = self.lengths.clone();
+        lengths.sort_unstable_by(f32::total_cmp);
+        let length = lengths[lengths.len() / 2];
+
+        let time_factor = spectrum_length.map_or(1, |target| factor(target, length));
+        let freq_factor =
+            channel_width.map_or(1, |target| factor(target, self.bw / self.nchan as f32));
+        (time_factor, freq_factor)
+    }
+
+    /// Splits the slices into blocks of up to `time_factor`, without crossing gaps.
+    fn time_blocks(&self, time_factor: usize) -> Vec<Range<usize>> {
+        let mut breaks = self
+            .gaps
+            .iter()
+            .map(|gap| self.timestamps.partition_point(|t| *t < gap.end))
+            .peekable();
+        let mut blocks = Vec::with_capacity(self.nslices.div_ceil(time_factor));
+        let mut start = 0;
+        while start < self.nslices {
+            while breaks.next_if(|&b| b <= start).is_some() {}
+            let end = (start + time_factor)
+                .min(self.nslices)
+                .min(breaks.peek().copied().unwrap_or(usize::MAX));
+            blocks.push(start..end);
+            start = end;
+        }
+        blocks
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::spectrogram::RawStrfSpectrum;
+    use chrono::{DateTime, Duration, Utc};
+
+    fn make_spec(offsets_s: &[i64], nchan: usize) -> Spectrogram {
+        let start = DateTime::<Utc>::from_timestamp(1_700_000_000, 0).unwrap();
+        let spectra = offsets_s
+            .iter()
+            .enumerate()
+            .map(|(i, &offset)| RawStrfSpectrum {
+                time: start + Duration::seconds(offset),
+                length_s: 1.0,
+                power_linear: (0..nchan).map(|f| (i * nchan + f + 1) as f32).collect(),
+            })
+            .collect();
+        let params = SpectrogramParams {
+            freq: 437e6,
+            bw: nchan as f32 * 1e3,
+            nchan,
+        };
+        Spectrogram::from_raw(spectra, params).unwrap()
+    }
+
+    fn db(linear: f32) -> f32 {
+        10.0 * linear.log10()
+    }
+
+    #[test]
+    fn rebin_averages_linear_power() {
+        let spec = make_spec(&[0, 1, 2, 3], 5);
+        let rebinned = spec.rebin(2, 2).unwrap();
+
+        assert_eq!(rebinned.nslices, 2);
+        assert_eq!(rebinned.nchan, 2);
+        // The fifth channel doesn't fill a bin and is dropped
+        assert!((rebinned.bw - 4e3).abs() < 1e-3);
+        assert!((rebinned.freq - (437e6 - 500.0)).abs() < 1.0);
+        // Slices 0 and 1, channels 0 and 1: linear 1, 2, 6, 7
+        assert!((rebinned.value(0, 0) - db(4.0)).abs() < 1e-3);
+        assert_eq!(
+            rebinned.timestamps,
+            vec![spec.timestamps[0], spec.timestamps[2]]
+        );
+        assert_eq!(rebinned.lengths, vec![2.0, 2.0]);
+    }
+
+    #[test]
+    fn rebin_does_not_integrate_across_gaps() {
+        let spec = make_spec(&[0, 1, 2, 10, 11, 12, 13], 2);
+        assert_eq!(spec.gaps().len(), 1);
+        let rebinned = spec.rebin(2, 1).unwrap();
+
+        assert_eq!(
+            rebinned.timestamps,
+            [0, 2, 3, 5].map(|i| spec.timestamps[i]).to_vec()
+        );
+        assert_eq!(rebinned.lengths, vec![2.0, 1.0, 2.0, 2.0]);
+        assert_eq!(rebinned.gaps().len(), 1);
+        assert_eq!(rebinned.end_time(), spec.end_time());
+    }
+
+    #[test]
+    fn rebin_to_rounds_to_factors() {
+        let spec = make_spec(&[0, 1, 2, 3, 4, 5], 8);
+        let rebinned = spec.rebin_to(Some(3.2), Some(4e3)).unwrap();
+        assert_eq!(rebinned.nslices, 2);
+        assert_eq!(rebinned.nchan, 2);
+
+        let unchanged = spec.rebin_to(None, None).unwrap();
+        assert_eq!(unchanged.timestamps, spec.timestamps);
+        for (a, b) in unchanged.data().iter().zip(spec.data().iter()) {
+            assert!((a - b).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn rebin_rejects_invalid_factors() {
+        let spec = make_spec(&[0, 1], 4);
+        assert!(spec.rebin(0, 1).is_err());
+        assert!(spec.rebin(1, 5).is_err());
+    }
+}
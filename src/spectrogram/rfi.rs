@@ -0,0 +1,335 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Flagging radio frequency interference (RFI), i.e. channels with terrestrial carriers and spectra
+//! with broadband bursts, so it can be hidden in plots and ignored when looking for signals.
+
+use std::{ops::Range, sync::Arc};
+
+use anyhow::{Result, ensure};
+use ndarray::Axis;
+use uuid::Uuid;
+
+use super::{
+    STREAM_CHUNK_SLICES, Spectrogram,
+    pipeline::{MAD_TO_SIGMA, percentile_of, running_percentile},
+};
+
+/// Window (in spectra) of the running median that the level of each spectrum is compared to when
+/// looking for impulses.
+const IMPULSE_WINDOW_SLICES: usize = 32;
+
+/// Channels and spectra that are flagged as RFI. A cell is masked if its channel or its spectrum
+/// is flagged.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Mask {
+    pub slices: Vec<bool>,
+    pub channels: Vec<bool>,
+}
+
+impl Mask {
+    /// A mask of the given size without any flags.
+    pub fn new(nslices: usize, nchan: usize) -> Mask {
+        Mask {
+            slices: vec![false; nslices],
+            channels: vec![false; nchan],
+        }
+    }
+
+    pub fn is_masked(&self, slice: usize, chan: usize) -> bool {
+        self.slices[slice] || self.channels[chan]
+    }
+
+    /// Numbers of flagged spectra and channels.
+    pub fn count(&self) -> (usize, usize) {
+        let count = |flags: &[bool]| flags.iter().filter(|&&f| f).count();
+        (count(&self.slices), count(&self.channels))
+    }
+
+    /// Flags everything that is flagged in either mask.
+    pub fn union(&mut self, other: &Mask) {
+        for (a, b) in [
+            (&mut self.slices, &other.slices),
+            (&mut self.channels, &other.channels),
+        ] {
+            a.iter_mut().zip(b).for_each(|(a, b)| *a |= b);
+        }
+    }
+
+    /// The part of the mask for the given slices and channels.
+    pub fn select(&self, slices: Range<usize>, channels: Range<usize>) -> Mask {
+        Mask {
+            slices: self.slices[slices].to_vec(),
+            channels: self.channels[channels].to_vec(),
+        }
+    }
+
+    /// The mask for a decimated copy of the spectrogram, e.g. a pyramid level, where each cell
+    /// combines `time_factor` spectra and `freq_factor` channels. Cells are masked if any of the
+    /// cells they combine are, since a strong carrier would otherwise still dominate them.
+    pub fn decimate(&self, time_factor: usize, freq_factor: usize) -> Mask {
+        let decimate = |flags: &[bool], factor: usize| {
+            flags
+                .chunks(factor.max(1))
+                .map(|chunk| chunk.iter().any(|&f| f))
+                .collect()
+        };
+        Mask {
+            slices: decimate(&self.slices, time_factor),
+            channels: decimate(&self.channels, freq_factor),
+        }
+    }
+}
+
+/// Thresholds of the RFI tests, in robust standard deviations of the test statistic over all
+/// channels (or spectra). A threshold of 0 disables the test.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RfiOptions {
+    /// Flags channels whose spectral kurtosis over time deviates from that of the other channels,
+    /// e.g. because of intermittent or unusually steady signals.
+    pub kurtosis: f32,
+    /// Flags channels whose median power over time is above that of the neighbouring channels,
+    /// i.e. persistent carriers.
+    pub carrier: f32,
+    /// Flags spectra whose median power over all channels is above that of the neighbouring
+    /// spectra, i.e. broadband bursts.
+    pub impulse: f32,
+}
+
+impl Default for RfiOptions {
+    fn default() -> Self {
+        Self {
+            kurtosis: 5.0,
+            carrier: 5.0,
+            impulse: 5.0,
+        }
+    }
+}
+
+impl RfiOptions {
+    /// Runs the enabled tests on a spectrogram, reading it in chunks of spectra.
+    ///
+    /// The statistics of each channel are computed per chunk and combined with the median, so
+    /// satellite passes, which only take up a small part of a recording, don't get their channels
+    /// flagged.
+    ///
+    /// This is CPU-heavy for large inputs, so async callers should run it in `spawn_blocking`.
+    pub fn flag(&self, spectrogram: &Spectrogram) -> Mask {
+        let (nslices, nchan) = (spectrogram.nslices, spectrogram.nchan);
+        let mut kurtosis = vec![Vec::new(); nchan];
+        let mut levels = vec![Vec::new(); nchan];
+        let mut slice_levels = Vec::with_capacity(nslices);
+        for start in (0..nslices).step_by(STREAM_CHUNK_SLICES) {
+            let rows = start..(start + STREAM_CHUNK_SLICES).min(nslices);
+            let tile = spectrogram.tile(rows, 0..nchan);
+            for (chan, column) in tile.axis_iter(Axis(1)).enumerate() {
+                if let Some(sk) = spectral_kurtosis(column.iter().copied()) {
+                    kurtosis[chan].push(sk);
+                }
+                levels[chan].push(percentile_of(column.to_vec(), 50.0));
+            }
+            slice_levels.extend(
+                tile.outer_iter()
+                    .map(|spectrum| percentile_of(spectrum.to_vec(), 50.0)),
+            );
+        }
+
+        let mut mask = Mask::new(nslices, nchan);
+        if self.kurtosis > 0.0 && kurtosis.iter().all(|sk| !sk.is_empty()) {
+            let kurtosis: Vec<f32> = kurtosis
+                .into_iter()
+                .map(|sk| percentile_of(sk, 50.0))
+                .collect();
+            let median = percentile_of(kurtosis.clone(), 50.0);
+            let deviations: Vec<f32> = kurtosis.iter().map(|sk| (sk - median).abs()).collect();
+            flag_outliers(&mut mask.channels, &deviations, self.kurtosis);
+        }
+        if self.carrier > 0.0 {
+            let levels: Vec<f32> = levels
+                .into_iter()
+                .map(|levels| percentile_of(levels, 50.0))
+                .collect();
+            flag_outliers(
+                &mut mask.channels,
+                &excess(&levels, (nchan / 64).max(4)),
+                self.carrier,
+            );
+        }
+        if self.impulse > 0.0 {
+            flag_outliers(
+                &mut mask.slices,
+                &excess(&slice_levels, IMPULSE_WINDOW_SLICES / 2),
+                self.impulse,
+            );
+        }
+        mask
+    }
+}
+
+/// The spectral kurtosis estimator of power values (in dB) of one channel, which is 1 for
+/// Gaussian noise in a single FFT, and the same for all channels in averaged spectra. `None` if
+/// there are fewer than two values.
+fn spectral_kurtosis(power_db: impl Iterator<Item = f32>) -> Option<f32> {
+    let (mut n, mut s1, mut s2) = (0usize, 0f64, 0f64);
+    for db in power_db {
+        let linear = 10f64.powf(db as f64 / 10.0);
+        n += 1;
+        s1 += linear;
+        s2 += linear * linear;
+    }
+    if n < 2 || s1 <= 0.0 {
+        return None;
+    }
+    let n = n as f64;
+    Some(((n + 1.0) / (n - 1.0) * (n * s2 / (s1 * s1) - 1.0)) as f32)
+}
+
+/// How far each value is above the running median over `2 * half + 1` values around it.
+fn excess(values: &[f32], half: usize) -> Vec<f32> {
+    let baseline = running_percentile(values, half, 50.0);
+    values.iter().zip(baseline).map(|(v, b)| v - b).collect()
+}
+
+/// Flags the values that are more than `threshold` robust standard deviations (estimated from the
+/// median absolute value of `deviations`) above zero.
+fn flag_outliers(flags: &mut [bool], deviations: &[f32], threshold: f32) {
+    let sigma = percentile_of(deviations.iter().map(|d| d.abs()).collect(), 50.0) * MAD_TO_SIGMA;
+    for (flag, &deviation) in flags.iter_mut().zip(deviations) {
+        *flag |= deviation > 0.0 && deviation > threshold * sigma;
+    }
+}
+
+impl Spectrogram {
+    /// The RFI mask, see [`RfiOptions::flag`].
+    pub fn mask(&self) -> Option<&Mask> {
+        self.mask.as_deref()
+    }
+
+    /// Whether a cell is flagged as RFI.
+    pub fn is_masked(&self, slice: usize, chan: usize) -> bool {
+        self.mask
+            .as_ref()
+            .is_some_and(|mask| mask.is_masked(slice, chan))
+    }
+
+    /// Sets the RFI mask. Since this changes how the spectrogram is shown, it also gets a new id.
+    pub fn set_mask(&mut self, mask: Option<Mask>) -> Result<()> {
+        if let Some(mask) = &mask {
+            ensure!(
+                mask.slices.len() == self.nslices && mask.channels.len() == self.nchan,
+                "The mask has {}x{} cells, but the spectrogram has {}x{}",
+                mask.slices.len(),
+                mask.channels.len(),
+                self.nslices,
+                self.nchan
+            );
+        }
+        self.mask = mask.map(Arc::new);
+        self.id = Uuid::new_v4();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::spectrogram::{RawStrfSpectrum, SpectrogramParams};
+    use chrono::{DateTime, Duration, Utc};
+
+    /// Pseudo-random noise between 0 and 1 dB.
+    fn noise(i: usize, f: usize) -> f32 {
+        let mut x = (i as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15)
+            ^ (f as u64).wrapping_mul(0xC2B2_AE3D_27D4_EB4F);
+        x ^= x >> 31;
+        x = x.wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        x ^= x >> 29;
+        (x % 1000) as f32 / 1000.0
+    }
+
+    /// A spectrogram with pseudo-random noise and the given extra power (in dB).
+    fn make_spec(
+        extra_db: impl Fn(usize, usize) -> f32,
+        nslices: usize,
+        nchan: usize,
+    ) -> Spectrogram {
+        let start = DateTime::<Utc>::from_timestamp(1_700_000_000, 0).unwrap();
+        let spectra = (0..nslices)
+            .map(|i| RawStrfSpectrum {
+                time: start + Duration::seconds(i as i64),
+                length_s: 1.0,
+                power_linear: (0..nchan)
+                    .map(|f| 10f32.powf((noise(i, f) + extra_db(i, f)) / 10.0))
+                    .collect(),
+            })
+            .collect();
+        let params = SpectrogramParams {
+            freq: 437e6,
+            bw: nchan as f32 * 1e3,
+            nchan,
+        };
+        Spectrogram::from_raw(spectra, params).unwrap()
+    }
+
+    #[test]
+    fn flags_carriers_and_impulses_but_not_passes() {
+        let spec = make_spec(
+            |i, f| {
+                let carrier = if f == 20 { 15.0 } else { 0.0 };
+                let burst = if i == 50 { 10.0 } else { 0.0 };
+                // A short pass drifting over a few channels
+                let pass = if (70..80).contains(&i) && f == 40 + (i - 70) / 4 {
+                    20.0
+                } else {
+                    0.0
+                };
+                carrier + burst + pass
+            },
+            100,
+            64,
+        );
+        let options = RfiOptions {
+            kurtosis: 0.0,
+            ..Default::default()
+        };
+        let mask = options.flag(&spec);
+
+        assert!(mask.channels[20]);
+        assert!(mask.slices[50]);
+        assert_eq!(mask.count(), (1, 1));
+    }
+
+    #[test]
+    fn kurtosis_flags_intermittent_channels() {
+        // Channel 10 switches on and off every spectrum
+        let spec = make_spec(
+            |i, f| if f == 10 && i % 2 == 0 { 10.0 } else { 0.0 },
+            64,
+            32,
+        );
+        let options = RfiOptions {
+            carrier: 0.0,
+            impulse: 0.0,
+            ..Default::default()
+        };
+        let mask = options.flag(&spec);
+
+        assert!(mask.channels[10]);
+        assert_eq!(mask.count(), (0, 1));
+    }
+
+    #[test]
+    fn mask_decimates_and_changes_the_id() {
+        let mut mask = Mask::new(5, 8);
+        mask.slices[4] = true;
+        mask.channels[2] = true;
+        let decimated = mask.decimate(2, 4);
+        assert_eq!(decimated.slices, vec![false, false, true]);
+        assert_eq!(decimated.channels, vec![true, false]);
+
+        let mut spec = make_spec(|_, _| 0.0, 5, 8);
+        let id = spec.id;
+        spec.set_mask(Some(mask)).unwrap();
+        assert_ne!(spec.id, id);
+        assert!(spec.is_masked(4, 0) && spec.is_masked(0, 2) && !spec.is_masked(0, 0));
+        assert!(spec.set_mask(Some(Mask::new(4, 8))).is_err());
+    }
+}
@@ -0,0 +1,404 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Joining spectrograms of neighbouring frequency bands (e.g. from several rffft instances) into
+//! one wide spectrogram.
+
+use anyhow::{Context, Result, bail, ensure};
+use chrono::{DateTime, Utc};
+use clap::ValueEnum;
+use ndarray::{ArcArray2, Array1, Array2, s};
+use strum::Display;
+use uuid::Uuid;
+
+use super::{
+    GAP_TOLERANCE, STREAM_CHUNK_SLICES, Spectrogram, SpectrogramParams, Storage, find_gaps,
+};
+
+/// Bands whose channel widths differ by more than this fraction can't be stitched.
+const CHANNEL_WIDTH_TOLERANCE: f32 = 1e-3;
+
+/// Band edges that are further than this many channels off the common channel grid are logged.
+const MISALIGNMENT_WARN_CHANNELS: f64 = 0.1;
+
+/// How channels that are covered by more than one band are combined when stitching.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, ValueEnum, Display)]
+#[strum(serialize_all = "lowercase")]
+pub enum Overlap {
+    /// Average the linear power of all bands covering the channel
+    #[default]
+    Average,
+    /// Blank the channel, so the seam between the bands stays visible
+    Blank,
+}
+
+impl Spectrogram {
+    /// Joins spectrograms that may cover different frequency bands.
+    ///
+    /// Spectrograms with the same parameters are concatenated in time first. If that leaves more
+    /// than one band, they are stitched side by side with [`Spectrogram::stitch`], or an error is
+    /// returned if `overlap` is `None`.
+    pub fn combine(
+        spectrograms: Vec<Spectrogram>,
+        overlap: Option<Overlap>,
+    ) -> Result<Spectrogram> {
+        let mut bands: Vec<(SpectrogramParams, Vec<Spectrogram>)> = Vec::new();
+        for spec in spectrograms {
+            let params = spec.params();
+            match bands.iter_mut().find(|(p, _)| *p == params) {
+                Some((_, band)) => band.push(spec),
+                None => bands.push((params, vec![spec])),
+            }
+        }
+
+        let mut bands = bands
+            .into_iter()
+            .map(|(_, mut band)| {
+                band.sort_by_key(|s| s.start_time());
+                Spectrogram::concatenate(band)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        if bands.len() <= 1 {
+            return bands
+                .pop()
+                .ok_or_else(|| anyhow::anyhow!("No spectrograms to join"));
+        }
+        match overlap {
+            Some(overlap) => Spectrogram::stitch(bands, overlap),
+            None => bail!(
+                "Spectrograms cover {} different frequency bands, enable stitching to join them",
+                bands.len()
+            ),
+        }
+    }
+
+    /// Places spectrograms of neighbouring frequency bands side by side on a common channel grid.
+    ///
+    /// All bands need the same channel width. Spectra are matched by timestamp: spectra of
+    /// different bands that start less than half a spectrum apart end up in the same slice.
+    /// Channels that no band covers, and bands without a spectrum for a slice, are blanked, i.e.
+    /// set to the lowest power of all bands. Channels covered by several bands are handled as
+    /// given by `overlap`.
+    ///
+    /// The result is always held in memory.
+    pub fn stitch(mut bands: Vec<Spectrogram>, overlap: Overlap) -> Result<Spectrogram> {
+        ensure!(!bands.is_empty(), "No spectrograms to stitch");
+        if bands.len() == 1 {
+            return Ok(bands.remove(0));
+        }
+
+        let chan_width = bands[0].bw / bands[0].nchan as f32;
+        for band in &bands {
+            let width = band.bw / band.nchan as f32;
+            ensure!(
+                (width - chan_width).abs() <= chan_width * CHANNEL_WIDTH_TOLERANCE,
+                "Cannot stitch bands with different channel widths ({} Hz vs {} Hz)",
+                width,
+                chan_width
+            );
+        }
+
+        // Lay out the bands on a channel grid starting at the lowest band edge. Band edges are in
+        // f64, as f32 can't resolve narrow channels at VHF/UHF frequencies.
+        let low_edge = |band: &Spectrogram| band.freq as f64 - band.bw as f64 / 2.0;
+        let low = bands.iter().map(low_edge).fold(f64::INFINITY, f64::min);
+        let high = bands
+            .iter()
+            .map(|band| band.freq as f64 + band.bw as f64 / 2.0)
+            .fold(f64::NEG_INFINITY, f64::max);
+        let nchan = ((high - low) / chan_width as f64).round() as usize;
+        let offsets = bands
+            .iter()
+            .map(|band| {
+                let offset = (low_edge(band) - low) / chan_width as f64;
+                if (offset - offset.round()).abs() > MISALIGNMENT_WARN_CHANNELS {
+                    log::warn!(
+                        "Band at {} Hz is {:.2} channels off the common channel grid",
+                        band.freq,
+                        offset - offset.round()
+                    );
+                }
+                // The channel widths only match within the tolerance, so a wide band can end up
+                // with more channels than the rounded grid
+                let max_offset = nchan.checked_sub(band.nchan).with_context(|| {
+                    format!(
+                        "Band at {} Hz with {} channels doesn't fit on the common grid of {} \
+                         channels",
+                        band.freq, band.nchan, nchan
+                    )
+                })?;
+                Ok((offset.round() as usize).min(max_offset))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let (timestamps, lengths) = merge_timelines(&bands);
+        let nslices = timestamps.len();
+        // `count` is the number of bands covering each cell. Several spectra of one band can fall
+        // into the same slice; they are averaged first, so they don't count as overlapping bands.
+        let mut sum = Array2::<f32>::zeros((nslices, nchan));
+        let mut count = Array2::<u8>::zeros((nslices, nchan));
+        for (band, &offset) in bands.iter().zip(&offsets) {
+            let channels = offset..offset + band.nchan;
+            let mut add = |(slice, acc, n): (usize, Array1<f32>, u32)| {
+                sum.slice_mut(s![slice, channels.clone()])
+                    .zip_mut_with(&acc, |v, &a| *v += a / n as f32);
+                count
+                    .slice_mut(s![slice, channels.clone()])
+                    .mapv_inplace(|n| n.saturating_add(1));
+            };
+            // The band's timestamps are sorted, so spectra sharing a slice are consecutive
+            let mut pending: Option<(usize, Array1<f32>, u32)> = None;
+            for start in (0..band.nslices).step_by(STREAM_CHUNK_SLICES) {
+                let rows = start..(start + STREAM_CHUNK_SLICES).min(band.nslices);
+                let tile = band.tile(rows.clone(), 0..band.nchan);
+                for (i, row) in rows.zip(tile.outer_iter()) {
+                    let slice = timestamps.partition_point(|t| *t <= band.timestamps[i]) - 1;
+                    let linear = row.mapv(|db| 10f32.powf(db / 10.0));
+                    match &mut pending {
+                        Some((s, acc, n)) if *s == slice => {
+                            *acc += &linear;
+                            *n += 1;
+                        }
+                        _ => {
+                            if let Some(done) = pending.replace((slice, linear, 1)) {
+                                add(done);
+                            }
+                        }
+                    }
+                }
+            }
+            if let Some(done) = pending {
+                add(done);
+            }
+        }
+
+        let blank = bands
+            .iter()
+            .map(|band| band.power_bounds.0)
+            .fold(f32::INFINITY, f32::min);
+        let mut data = sum;
+        data.zip_mut_with(&count, |v, &n| {
+            *v = match (n, overlap) {
+                (0, _) | (2.., Overlap::Blank) => blank,
+                (n, _) => 10.0 * (*v / n as f32).log10(),
+            };
+        });
+        let power_bounds = data
+            .iter()
+            .fold((f32::INFINITY, f32::NEG_INFINITY), |(min, max), &v| {
+                (min.min(v), max.max(v))
+            });
+
+        log::debug!(
+            "Stitched {} bands into {} channels and {} slices",
+            bands.len(),
+            nchan,
+            nslices
+        );
+        Ok(Spectrogram {
+            id: Uuid::new_v4(),
+            freq: ((low + high) / 2.0) as f32,
+            bw: nchan as f32 * chan_width,
+            nchan,
+            nslices,
+            power_bounds,
+            storage: Storage::Memory(ArcArray2::from(data)),
+            gaps: find_gaps(&timestamps, &lengths),
+            timestamps,
+            lengths,
+            pyramid: None,
+            load_warnings: bands
+                .iter()
+                .flat_map(|band| band.load_warnings.iter().cloned())
+                .collect(),
+            mask: None,
+        })
+    }
+}
+
+/// Merges the timestamps of all bands into one timeline, putting spectra that start less than
+/// [`GAP_TOLERANCE`] spectrum lengths after the first spectrum of a slice into that slice.
+fn merge_timelines(bands: &[Spectrogram]) -> (Vec<DateTime<Utc>>, Vec<f32>) {
+    let mut all = bands
+        .iter()
+        .flat_map(|band| {
+            band.timestamps
+                .iter()
+                .copied()
+                .zip(band.lengths.iter().copied())
+        })
+        .collect::<Vec<_>>();
+    all.sort_unstable_by_key(|&(time, _)| time);
+
+    let mut timestamps: Vec<DateTime<Utc>> = Vec::new();
+    let mut lengths: Vec<f32> = Vec::new();
+    for (time, length) in all {
+        let same_slice = timestamps
+            .last()
+            .zip(lengths.last())
+            .is_some_and(|(start, len)| (time - *start).as_seconds_f32() < len * GAP_TOLERANCE);
+        if !same_slice {
+            timestamps.push(time);
+            lengths.push(length);
+        }
+    }
+    (timestamps, lengths)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::spectrogram::RawStrfSpectrum;
+    use chrono::{Duration, NaiveDate};
+
+    fn test_start() -> DateTime<Utc> {
+        NaiveDate::from_ymd_opt(2024, 1, 1)
+            .unwrap()
+            .and_hms_opt(0, 0, 0)
+            .unwrap()
+            .and_utc()
+    }
+
+    /// A band with 1 kHz channels starting at `low` Hz, with linear power `value(slice)`.
+    fn band(
+        low: f32,
+        nchan: usize,
+        start: DateTime<Utc>,
+        nslices: usize,
+        value: impl Fn(usize) -> f32,
+    ) -> Spectrogram {
+        let spectra = (0..nslices)
+            .map(|i| RawStrfSpectrum {
+                time: start + Duration::seconds(i as i64),
+                length_s: 1.0,
+                power_linear: vec![value(i); nchan],
+            })
+            .collect();
+        let params = SpectrogramParams {
+            freq: low + nchan as f32 * 500.0,
+            bw: nchan as f32 * 1e3,
+            nchan,
+        };
+        Spectrogram::from_raw(spectra, params).unwrap()
+    }
+
+    fn db(linear: f32) -> f32 {
+        10.0 * linear.log10()
+    }
+
+    #[test]
+    fn stitch_places_bands_side_by_side() {
+        let a = band(144.000e6, 8, test_start(), 4, |i| (i + 1) as f32 * 10.0);
+        let b = band(144.010e6, 8, test_start(), 4, |_| 1000.0);
+        let stitched = Spectrogram::stitch(vec![b, a], Overlap::Average).unwrap();
+
+        assert_eq!(stitched.nchan, 18);
+        assert_eq!(stitched.nslices, 4);
+        assert!((stitched.freq - 144.009e6).abs() < 1.0);
+        assert!((stitched.bw - 18e3).abs() < 1e-3);
+        assert!((stitched.value(2, 0) - db(30.0)).abs() < 1e-3);
+        assert!((stitched.value(2, 12) - db(1000.0)).abs() < 1e-3);
+        // Nothing covers the channels between the bands
+        assert_eq!(stitched.value(2, 8), stitched.power_bounds.0);
+        assert_eq!(stitched.value(2, 9), stitched.power_bounds.0);
+        assert!((stitched.power_bounds.0 - db(10.0)).abs() < 1e-3);
+    }
+
+    #[test]
+    fn stitch_combines_overlapping_channels() {
+        let a = band(144.000e6, 8, test_start(), 2, |_| 100.0);
+        let b = band(144.004e6, 8, test_start(), 2, |_| 300.0);
+
+        let averaged = Spectrogram::stitch(vec![a.clone(), b.clone()], Overlap::Average).unwrap();
+        assert_eq!(averaged.nchan, 12);
+        assert!((averaged.value(0, 2) - db(100.0)).abs() < 1e-3);
+        assert!((averaged.value(0, 5) - db(200.0)).abs() < 1e-3);
+        assert!((averaged.value(0, 10) - db(300.0)).abs() < 1e-3);
+
+        let blanked = Spectrogram::stitch(vec![a, b], Overlap::Blank).unwrap();
+        assert!((blanked.value(0, 5) - db(100.0)).abs() < 1e-3);
+        assert!((blanked.value(0, 10) - db(300.0)).abs() < 1e-3);
+    }
+
+    #[test]
+    fn stitch_aligns_spectra_by_timestamp() {
+        let a = band(144.000e6, 4, test_start(), 3, |_| 100.0);
+        let b = band(
+            144.004e6,
+            4,
+            test_start() + Duration::milliseconds(200),
+            4,
+            |_| 1000.0,
+        );
+        let stitched = Spectrogram::stitch(vec![a, b], Overlap::Average).unwrap();
+
+        assert_eq!(stitched.nslices, 4);
+        assert_eq!(stitched.start_time(), test_start());
+        assert!((stitched.value(2, 0) - db(100.0)).abs() < 1e-3);
+        assert!((stitched.value(2, 6) - db(1000.0)).abs() < 1e-3);
+        // Only the second band has a spectrum for the last slice
+        assert_eq!(stitched.value(3, 0), stitched.power_bounds.0);
+        assert!((stitched.value(3, 6) - db(1000.0)).abs() < 1e-3);
+    }
+
+    #[test]
+    fn stitch_averages_spectra_of_one_band_in_the_same_slice() {
+        let spectra = [(0, 100.0), (200, 300.0), (1000, 100.0)]
+            .into_iter()
+            .map(|(ms, value)| RawStrfSpectrum {
+                time: test_start() + Duration::milliseconds(ms),
+                length_s: 1.0,
+                power_linear: vec![value; 4],
+            })
+            .collect();
+        let params = SpectrogramParams {
+            freq: 144.002e6,
+            bw: 4e3,
+            nchan: 4,
+        };
+        let a = Spectrogram::from_raw(spectra, params).unwrap();
+        let b = band(144.004e6, 4, test_start(), 2, |_| 1000.0);
+
+        for overlap in [Overlap::Average, Overlap::Blank] {
+            let stitched = Spectrogram::stitch(vec![a.clone(), b.clone()], overlap).unwrap();
+            assert_eq!(stitched.nslices, 2);
+            assert!((stitched.value(0, 0) - db(200.0)).abs() < 1e-3);
+            assert!((stitched.value(1, 0) - db(100.0)).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn stitch_rejects_different_channel_widths() {
+        let a = band(144.000e6, 8, test_start(), 2, |_| 1.0);
+        let mut b = band(144.008e6, 8, test_start(), 2, |_| 1.0);
+        b.bw *= 2.0;
+        assert!(Spectrogram::stitch(vec![a, b], Overlap::Average).is_err());
+    }
+
+    #[test]
+    fn stitch_rejects_band_wider_than_the_grid() {
+        // The channel widths match within the tolerance, but the grid of the first band's width
+        // rounds down to one channel less than the wide band has
+        let a = band(144.100e6, 4, test_start(), 2, |_| 1.0);
+        let mut b = band(144.000e6, 1000, test_start(), 2, |_| 1.0);
+        b.bw *= 0.9991;
+        b.freq = 144.000e6 + b.bw / 2.0;
+        assert!(Spectrogram::stitch(vec![a, b], Overlap::Average).is_err());
+    }
+
+    #[test]
+    fn combine_concatenates_before_stitching() {
+        let a1 = band(144.000e6, 4, test_start(), 3, |_| 1.0);
+        let a2 = band(144.000e6, 4, test_start() + Duration::seconds(3), 3, |_| {
+            1.0
+        });
+        let b = band(144.004e6, 4, test_start(), 6, |_| 1.0);
+
+        let parts = vec![a2.clone(), b.clone(), a1.clone()];
+        assert!(Spectrogram::combine(parts, None).is_err());
+
+        let combined = Spectrogram::combine(vec![a2, b, a1], Some(Overlap::Average)).unwrap();
+        assert_eq!(combined.nslices, 6);
+        assert_eq!(combined.nchan, 8);
+        assert!(combined.gaps().is_empty());
+    }
+}
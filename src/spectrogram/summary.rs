@@ -0,0 +1,258 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Summaries of strf `.bin` files that only read the headers, e.g. to check what a night's worth
+//! of data contains before processing it.
+
+use std::{
+    io::SeekFrom,
+    ops::Range,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use tokio::io::AsyncSeekExt;
+
+use super::{
+    Block, GAP_TOLERANCE, SpectrogramParams, StrfError, block_len, check_block, find_gaps,
+    parse_header, spectrum_end,
+};
+
+/// What the headers of a strf `.bin` file say about it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileSummary {
+    pub path: PathBuf,
+    /// Parameters of the first spectrum
+    pub params: SpectrogramParams,
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    /// Number of readable spectra
+    pub nspectra: usize,
+    /// Median spectrum length in seconds
+    pub spectrum_length: f32,
+    pub gaps: Vec<Range<DateTime<Utc>>>,
+    /// Damaged spectra, which [`super::load`] only skips in lenient mode
+    pub problems: Vec<StrfError>,
+}
+
+impl FileSummary {
+    /// Reads all headers of a strf `.bin` file, seeking past the spectra.
+    ///
+    /// Damaged spectra are collected in [`FileSummary::problems`]. This only fails if the file
+    /// can't be read or its first header is unusable.
+    pub async fn read(path: &Path) -> Result<FileSummary> {
+        let mut file = tokio::fs::File::open(path)
+            .await
+            .with_context(|| format!("Failed to open {}", path.display()))?;
+        let file_size = file
+            .metadata()
+            .await
+            .map_err(|e| StrfError::io(path, 0, e))?
+            .len();
+        // Unbuffered, as a buffer would read ahead into the spectra after every seek
+        let first = parse_header(&mut file, path, 0).await?;
+        let block_size = block_len(first.nchan);
+
+        let mut spectra = Vec::new();
+        let mut problems = Vec::new();
+        let mut pos = 0;
+        while pos + block_size <= file_size {
+            file.seek(SeekFrom::Start(pos))
+                .await
+                .map_err(|e| StrfError::io(path, pos, e))?;
+            let header = parse_header(&mut file, path, pos).await;
+            match check_block(path, pos, &first, header, true, &mut problems)? {
+                Block::Spectrum(header) => {
+                    spectra.push((header.start_time, header.length));
+                    pos += block_size;
+                }
+                Block::Skip(len) => pos += len,
+            }
+        }
+        if pos < file_size {
+            problems.push(StrfError::Truncated {
+                path: path.to_path_buf(),
+                offset: pos,
+                len: file_size - pos,
+            });
+        }
+        if spectra.is_empty() {
+            Err(StrfError::Empty {
+                path: path.to_path_buf(),
+            })?;
+        }
+
+        spectra.sort_unstable_by_key(|&(time, _)| time);
+        let (timestamps, lengths): (Vec<_>, Vec<_>) = spectra.into_iter().unzip();
+        let mut sorted_lengths = lengths.clone();
+        sorted_lengths.sort_unstable_by(f32::total_cmp);
+        let last = timestamps.len() - 1;
+        Ok(FileSummary {
+            path: path.to_path_buf(),
+            params: first.params(),
+            start: timestamps[0],
+            end: spectrum_end(timestamps[last], lengths[last]),
+            nspectra: timestamps.len(),
+            spectrum_length: sorted_lengths[sorted_lengths.len() / 2],
+            gaps: find_gaps(&timestamps, &lengths),
+            problems,
+        })
+    }
+}
+
+/// Two files whose spectra overlap in time.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileOverlap {
+    pub first: PathBuf,
+    pub second: PathBuf,
+    pub range: Range<DateTime<Utc>>,
+}
+
+/// A summary of a set of files that are meant to be loaded together.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SetSummary {
+    /// The files, sorted by start time
+    pub files: Vec<FileSummary>,
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    /// Time ranges without spectra, both within and between files
+    pub gaps: Vec<Range<DateTime<Utc>>>,
+    pub overlaps: Vec<FileOverlap>,
+    /// Files whose parameters differ from those of the first file
+    pub inconsistent: Vec<PathBuf>,
+}
+
+impl SetSummary {
+    /// Summarizes the given files. Returns `None` if there are none.
+    pub fn new(mut files: Vec<FileSummary>) -> Option<SetSummary> {
+        files.sort_by_key(|file| file.start);
+        let first = files.first()?;
+        let start = first.start;
+        let inconsistent = files
+            .iter()
+            .filter(|file| file.params != first.params)
+            .map(|file| file.path.clone())
+            .collect();
+
+        let mut gaps = Vec::new();
+        let mut overlaps = Vec::new();
+        // The end of the data so far, and the file it is from
+        let mut covered: Option<(DateTime<Utc>, &FileSummary)> = None;
+        for file in &files {
+            gaps.extend(file.gaps.iter().cloned());
+            if let Some((end, previous)) = covered {
+                if file.start < end {
+                    overlaps.push(FileOverlap {
+                        first: previous.path.clone(),
+                        second: file.path.clone(),
+                        range: file.start..end.min(file.end),
+                    });
+                } else if (file.start - end).as_seconds_f32() > file.spectrum_length * GAP_TOLERANCE
+                {
+                    gaps.push(end..file.start);
+                }
+            }
+            if covered.is_none_or(|(end, _)| file.end > end) {
+                covered = Some((file.end, file));
+            }
+        }
+        gaps.sort_by_key(|gap| gap.start);
+
+        let end = covered.map_or(start, |(end, _)| end);
+        Some(SetSummary {
+            files,
+            start,
+            end,
+            gaps,
+            overlaps,
+            inconsistent,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::spectrogram::StrfWriter;
+    use chrono::{Duration, NaiveDate};
+    use ndarray::Array1;
+
+    fn test_start() -> DateTime<Utc> {
+        NaiveDate::from_ymd_opt(2024, 1, 1)
+            .unwrap()
+            .and_hms_opt(0, 0, 0)
+            .unwrap()
+            .and_utc()
+    }
+
+    async fn write_bin(path: &Path, offsets_s: &[i64], nchan: usize) {
+        let params = SpectrogramParams {
+            freq: 100e6,
+            bw: 100e3,
+            nchan,
+        };
+        let mut writer = StrfWriter::create(path, &params, offsets_s.len())
+            .await
+            .unwrap();
+        for &offset in offsets_s {
+            let time = test_start() + Duration::seconds(offset);
+            writer
+                .write_spectrum(time, 1.0, Array1::zeros(nchan).view())
+                .await
+                .unwrap();
+        }
+        writer.finish().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn file_summary_reads_headers_and_reports_problems() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("a.bin");
+        write_bin(&path, &[0, 1, 2, 10, 11], 16).await;
+        let mut bytes = std::fs::read(&path).unwrap();
+        bytes.truncate(bytes.len() - 10);
+        std::fs::write(&path, &bytes).unwrap();
+
+        let summary = FileSummary::read(&path).await.unwrap();
+        assert_eq!(summary.nspectra, 4);
+        assert_eq!(summary.params.nchan, 16);
+        assert_eq!(summary.start, test_start());
+        assert_eq!(summary.end, test_start() + Duration::seconds(11));
+        assert_eq!(summary.spectrum_length, 1.0);
+        assert_eq!(summary.gaps.len(), 1);
+        assert!(matches!(
+            summary.problems.as_slice(),
+            [StrfError::Truncated { .. }]
+        ));
+    }
+
+    #[tokio::test]
+    async fn set_summary_finds_gaps_overlaps_and_inconsistent_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let paths: Vec<_> = ["a.bin", "b.bin", "c.bin", "d.bin"]
+            .iter()
+            .map(|name| dir.path().join(name))
+            .collect();
+        write_bin(&paths[0], &[0, 1, 2], 16).await;
+        write_bin(&paths[1], &[2, 3, 4], 16).await;
+        write_bin(&paths[2], &[20, 21], 16).await;
+        write_bin(&paths[3], &[22, 23], 8).await;
+
+        let mut files = Vec::new();
+        for path in paths.iter().rev() {
+            files.push(FileSummary::read(path).await.unwrap());
+        }
+        let set = SetSummary::new(files).unwrap();
+
+        assert_eq!(set.files[0].path, paths[0]);
+        assert_eq!(set.start, test_start());
+        assert_eq!(set.end, test_start() + Duration::seconds(24));
+        assert_eq!(
+            set.gaps,
+            vec![test_start() + Duration::seconds(5)..test_start() + Duration::seconds(20)]
+        );
+        assert_eq!(set.overlaps.len(), 1);
+        assert_eq!(set.overlaps[0].second, paths[1]);
+        assert_eq!(set.inconsistent, vec![paths[3].clone()]);
+    }
+}
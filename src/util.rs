@@ -1,5 +1,8 @@
 use std::path::PathBuf;
 
+use anyhow::{Context, Result};
+use chrono::{DateTime, NaiveDateTime, Utc};
+
 use iced::{
     Point, Rectangle,
     keyboard::{Key, key::Named},
@@ -101,6 +104,15 @@ where
     ranges
 }
 
+/// Parses a UTC timestamp like `2026-02-19T00:00:01.5`, with or without a trailing `Z`.
+pub fn parse_utc(s: &str) -> Result<DateTime<Utc>> {
+    let s = s.trim().trim_end_matches('Z');
+    NaiveDateTime::parse_from_str(s, "%Y-%m-%dT%H:%M:%S%.f")
+        .or_else(|_| NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S%.f"))
+        .map(|t| t.and_utc())
+        .with_context(|| format!("Invalid UTC timestamp: {s}"))
+}
+
 #[derive(Clone)]
 pub struct DebugRgbaImage(pub RgbaImage);
 
@@ -184,6 +196,19 @@ mod tests {
         );
     }
 
+    #[test]
+    fn parse_utc_accepts_optional_z_and_fraction() {
+        let expected = chrono::NaiveDate::from_ymd_opt(2026, 2, 19)
+            .unwrap()
+            .and_hms_milli_opt(0, 0, 1, 500)
+            .unwrap()
+            .and_utc();
+        assert_eq!(parse_utc("2026-02-19T00:00:01.5").unwrap(), expected);
+        assert_eq!(parse_utc("2026-02-19T00:00:01.500Z").unwrap(), expected);
+        assert_eq!(parse_utc("2026-02-19 00:00:01.5").unwrap(), expected);
+        assert!(parse_utc("2026-02-19").is_err());
+    }
+
     #[test]
     fn minmax_empty_returns_nan() {
         let (lo, hi) = minmax(&arr1(&[]));